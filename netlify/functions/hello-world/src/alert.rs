@@ -0,0 +1,162 @@
+use aws_sdk_dynamodb::model::{AttributeValue, ReturnValue};
+
+/// consecutive failed runs before the email report escalates, unless `FAILURE_ALERT_THRESHOLD` is set
+const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+
+/// consecutive runs a pair is skipped for the same reason before it is reported, unless `SKIP_ALERT_THRESHOLD` is set
+const DEFAULT_SKIP_THRESHOLD: u32 = 5;
+
+/// item of the counter table holding the count
+const COUNTER_ID: &str = "consecutive_failures";
+
+pub fn failure_threshold() -> u32 {
+    option_env!("FAILURE_ALERT_THRESHOLD")
+        .and_then(|threshold| threshold.parse().ok())
+        .unwrap_or(DEFAULT_FAILURE_THRESHOLD)
+}
+
+pub fn skip_threshold() -> u32 {
+    option_env!("SKIP_ALERT_THRESHOLD")
+        .and_then(|threshold| threshold.parse().ok())
+        .unwrap_or(DEFAULT_SKIP_THRESHOLD)
+}
+
+/// Number of consecutive failed runs, as `DcaRunSummary::failed` has it, persisted across invocations.
+pub struct FailureCounter {
+    pub table: String,
+    client: aws_sdk_dynamodb::Client,
+}
+
+impl FailureCounter {
+    /// Counter in the `FAILURE_COUNTER_TABLE` table, `None` when it isn't configured.
+    pub async fn from_env() -> Option<FailureCounter> {
+        let table = option_env!("FAILURE_COUNTER_TABLE")?;
+        let config = aws_config::load_from_env().await;
+
+        Some(FailureCounter {
+            table: table.to_owned(),
+            client: aws_sdk_dynamodb::Client::new(&config),
+        })
+    }
+
+    /// Counts a failed run, or resets the count after a successful one, and
+    /// returns the consecutive failures including this run.
+    pub async fn record(&self, failed: bool) -> Result<u32, String> {
+        if !failed {
+            return self
+                .client
+                .put_item()
+                .table_name(&self.table)
+                .item("id", AttributeValue::S(COUNTER_ID.to_owned()))
+                .item("failures", AttributeValue::N(String::from("0")))
+                .send()
+                .await
+                .map(|_| 0)
+                .map_err(|e| format!("{:?}", e));
+        }
+
+        let updated = self
+            .client
+            .update_item()
+            .table_name(&self.table)
+            .key("id", AttributeValue::S(COUNTER_ID.to_owned()))
+            .update_expression("ADD failures :one")
+            .expression_attribute_values(":one", AttributeValue::N(String::from("1")))
+            .return_values(ReturnValue::UpdatedNew)
+            .send()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+
+        match updated.attributes.as_ref().and_then(|attributes| attributes.get("failures")) {
+            Some(AttributeValue::N(failures)) => failures.parse().map_err(|e| format!("{:?}", e)),
+            _ => Err(String::from("failure count missing from the update")),
+        }
+    }
+}
+
+/// Number of consecutive runs each plan pair was skipped for the same reason,
+/// one item per account and pair in the `SKIP_COUNTER_TABLE`.
+pub struct SkipCounter {
+    pub table: String,
+    client: aws_sdk_dynamodb::Client,
+}
+
+impl SkipCounter {
+    /// Counter in the `SKIP_COUNTER_TABLE` table, `None` when it isn't configured.
+    pub async fn from_env() -> Option<SkipCounter> {
+        let table = option_env!("SKIP_COUNTER_TABLE")?;
+        let config = aws_config::load_from_env().await;
+
+        Some(SkipCounter {
+            table: table.to_owned(),
+            client: aws_sdk_dynamodb::Client::new(&config),
+        })
+    }
+
+    /// Counts a skip of `pair` in `account`, starting over when the reason
+    /// changed, and returns the consecutive skips including this run. Reasons
+    /// differing only in their numbers count as the same.
+    pub async fn record_skip(&self, account: &str, pair: &str, reason: &str) -> Result<u32, String> {
+        let id = skip_id(account, pair);
+        let stored = self
+            .client
+            .get_item()
+            .table_name(&self.table)
+            .key("id", AttributeValue::S(id.clone()))
+            .send()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+
+        let kind = reason_kind(reason);
+        let previous = stored.item.as_ref().and_then(|item| match (item.get("reason"), item.get("skips")) {
+            (Some(AttributeValue::S(stored_kind)), Some(AttributeValue::N(skips))) if *stored_kind == kind => skips.parse().ok(),
+            _ => None,
+        });
+        let skips = previous.unwrap_or(0) + 1;
+
+        self.client
+            .put_item()
+            .table_name(&self.table)
+            .item("id", AttributeValue::S(id))
+            .item("reason", AttributeValue::S(kind))
+            .item("skips", AttributeValue::N(skips.to_string()))
+            .send()
+            .await
+            .map(|_| skips)
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    /// Starts the count of `pair` in `account` over after a buy.
+    pub async fn reset(&self, account: &str, pair: &str) -> Result<(), String> {
+        self.client
+            .delete_item()
+            .table_name(&self.table)
+            .key("id", AttributeValue::S(skip_id(account, pair)))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("{:?}", e))
+    }
+}
+
+fn skip_id(account: &str, pair: &str) -> String {
+    format!("skips/{}/{}", account, pair)
+}
+
+/// `reason` with every number replaced by `#`, e.g. `volume # is below the minimum order of #`.
+fn reason_kind(reason: &str) -> String {
+    let mut kind = String::new();
+    let mut in_number = false;
+
+    for c in reason.chars() {
+        let numeric = c.is_ascii_digit() || (in_number && c == '.');
+        if numeric && !in_number {
+            kind.push('#');
+        } else if !numeric {
+            kind.push(c);
+        }
+        in_number = numeric;
+    }
+
+    kind
+}
@@ -0,0 +1,145 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::kraken::Candle;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SimulatedBuy {
+    /// unix time of the candle bought in
+    pub time: i64,
+    /// candle close the buy executed at
+    pub price: Decimal,
+    pub amount: Decimal,
+    pub units: Decimal,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BacktestResult {
+    pub buys: Vec<SimulatedBuy>,
+    pub total_spent: Decimal,
+    pub total_units: Decimal,
+    /// total spent per unit, `None` without buys
+    pub average_cost: Option<Decimal>,
+    /// close of the last candle
+    pub final_price: Option<Decimal>,
+    /// total units at the final price
+    pub final_value: Decimal,
+    /// gain over the total spent in percent, `None` without buys
+    pub return_pct: Option<Decimal>,
+}
+
+/// Simulates buying `amount` quote currency at the close of every `interval`th
+/// candle, starting with the first, without fees. Candles without a positive
+/// close are passed over.
+pub fn backtest_dca(candles: &[Candle], amount: Decimal, interval: usize) -> BacktestResult {
+    let buys: Vec<SimulatedBuy> = candles
+        .iter()
+        .step_by(interval.max(1))
+        .filter(|candle| candle.close > Decimal::ZERO)
+        .map(|candle| SimulatedBuy {
+            time: candle.time,
+            price: candle.close,
+            amount,
+            units: amount / candle.close,
+        })
+        .collect();
+
+    let total_spent: Decimal = buys.iter().map(|buy| buy.amount).sum();
+    let total_units: Decimal = buys.iter().map(|buy| buy.units).sum();
+    let final_price = candles.last().map(|candle| candle.close);
+    let final_value = total_units * final_price.unwrap_or(Decimal::ZERO);
+
+    let (average_cost, return_pct) = if total_units.is_zero() || total_spent.is_zero() {
+        (None, None)
+    } else {
+        (
+            Some(total_spent / total_units),
+            Some((final_value - total_spent) / total_spent * Decimal::ONE_HUNDRED),
+        )
+    };
+
+    BacktestResult {
+        buys,
+        total_spent,
+        total_units,
+        average_cost,
+        final_price,
+        final_value,
+        return_pct,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    /// hourly candles closing at `closes`
+    fn candles(closes: &[&str]) -> Vec<Candle> {
+        closes
+            .iter()
+            .enumerate()
+            .map(|(i, close)| Candle {
+                time: i as i64 * 3600,
+                open: dec(close),
+                high: dec(close),
+                low: dec(close),
+                close: dec(close),
+                vwap: dec(close),
+                volume: Decimal::ONE,
+                count: 1,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn buys_every_interval_th_candle_from_the_first() {
+        let result = backtest_dca(&candles(&["100", "90", "50", "60", "200"]), dec("100"), 2);
+
+        let times: Vec<i64> = result.buys.iter().map(|buy| buy.time).collect();
+        assert_eq!(times, vec![0, 7200, 14400]);
+        assert_eq!(result.total_spent, dec("300"));
+        assert_eq!(result.total_units, dec("3.5"));
+        assert_eq!(result.final_price, Some(dec("200")));
+        assert_eq!(result.final_value, dec("700"));
+    }
+
+    #[test]
+    fn interval_zero_buys_every_candle() {
+        let result = backtest_dca(&candles(&["10", "20", "40"]), dec("40"), 0);
+
+        assert_eq!(result.buys.len(), 3);
+        assert_eq!(result.total_units, dec("7"));
+    }
+
+    #[test]
+    fn passes_over_candles_without_a_close() {
+        let result = backtest_dca(&candles(&["100", "0", "50"]), dec("100"), 1);
+
+        let prices: Vec<Decimal> = result.buys.iter().map(|buy| buy.price).collect();
+        assert_eq!(prices, vec![dec("100"), dec("50")]);
+        assert_eq!(result.total_spent, dec("200"));
+    }
+
+    #[test]
+    fn average_cost_and_return() {
+        let result = backtest_dca(&candles(&["100", "50", "75"]), dec("100"), 1);
+
+        // 1 + 2 + 4/3 units for 300
+        assert_eq!(result.average_cost.unwrap().round_dp(4), dec("69.2308"));
+        assert_eq!(result.return_pct.unwrap().round_dp(4), dec("8.3333"));
+    }
+
+    #[test]
+    fn no_buys_without_candles() {
+        let result = backtest_dca(&[], dec("100"), 1);
+
+        assert!(result.buys.is_empty());
+        assert_eq!(result.average_cost, None);
+        assert_eq!(result.return_pct, None);
+        assert_eq!(result.final_value, Decimal::ZERO);
+    }
+}
@@ -0,0 +1,129 @@
+//! Wall clock times of runs, journals and reports. With the `timestamps`
+//! feature they are chrono's `DateTime<Utc>`, without it plain nanoseconds
+//! since the epoch.
+
+#[cfg(not(feature = "timestamps"))]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "timestamps")]
+pub type Timestamp = chrono::DateTime<chrono::Utc>;
+
+/// Nanoseconds since the epoch, serialized as a number.
+#[cfg(not(feature = "timestamps"))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(transparent)]
+pub struct Timestamp(i64);
+
+#[cfg(not(feature = "timestamps"))]
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+#[cfg(feature = "timestamps")]
+pub fn now() -> Timestamp {
+    chrono::Utc::now()
+}
+
+#[cfg(not(feature = "timestamps"))]
+pub fn now() -> Timestamp {
+    let since_epoch = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    Timestamp(since_epoch.as_nanos() as i64)
+}
+
+#[cfg(feature = "timestamps")]
+pub fn from_unix(secs: i64) -> Option<Timestamp> {
+    chrono::TimeZone::timestamp_opt(&chrono::Utc, secs, 0).single()
+}
+
+#[cfg(not(feature = "timestamps"))]
+pub fn from_unix(secs: i64) -> Option<Timestamp> {
+    secs.checked_mul(NANOS_PER_SEC).map(Timestamp)
+}
+
+/// Time of Kraken's float seconds since the epoch, keeping the fraction down
+/// to nanoseconds.
+#[cfg(feature = "timestamps")]
+pub fn from_unix_f64(secs: f64) -> Option<Timestamp> {
+    let nanos = ((secs.fract() * 1e9).round() as u32).min(999_999_999);
+    chrono::TimeZone::timestamp_opt(&chrono::Utc, secs.trunc() as i64, nanos).single()
+}
+
+#[cfg(not(feature = "timestamps"))]
+pub fn from_unix_f64(secs: f64) -> Option<Timestamp> {
+    let nanos = (secs * 1e9).round();
+    Some(nanos).filter(|nanos| nanos.abs() < i64::MAX as f64).map(|nanos| Timestamp(nanos as i64))
+}
+
+/// Whole seconds since the epoch.
+#[cfg(feature = "timestamps")]
+pub fn unix(at: &Timestamp) -> i64 {
+    at.timestamp()
+}
+
+#[cfg(not(feature = "timestamps"))]
+pub fn unix(at: &Timestamp) -> i64 {
+    at.0.div_euclid(NANOS_PER_SEC)
+}
+
+/// Nanoseconds since the epoch.
+#[cfg(feature = "timestamps")]
+pub fn unix_nanos(at: &Timestamp) -> i64 {
+    at.timestamp_nanos()
+}
+
+#[cfg(not(feature = "timestamps"))]
+pub fn unix_nanos(at: &Timestamp) -> i64 {
+    at.0
+}
+
+/// `at` in RFC 3339, e.g. 2022-12-25T09:30:59+00:00.
+#[cfg(feature = "timestamps")]
+pub fn rfc3339(at: &Timestamp) -> String {
+    at.to_rfc3339()
+}
+
+#[cfg(not(feature = "timestamps"))]
+pub fn rfc3339(at: &Timestamp) -> String {
+    let (days, secs) = (unix(at).div_euclid(86_400), unix(at).rem_euclid(86_400));
+
+    // civil date of a day count, Howard Hinnant's `civil_from_days`
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}+00:00",
+        year,
+        month,
+        day,
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kraken_times_keep_their_fraction() {
+        let at = from_unix_f64(1_616_492_376.594_1).unwrap();
+
+        assert_eq!(unix(&at), 1_616_492_376);
+        assert_eq!(unix_nanos(&at) % 1_000_000_000 / 1_000_000, 594);
+    }
+
+    #[test]
+    fn rfc3339_is_utc() {
+        assert_eq!(rfc3339(&from_unix(0).unwrap()), "1970-01-01T00:00:00+00:00");
+        assert_eq!(rfc3339(&from_unix(1_671_960_659).unwrap()), "2022-12-25T09:30:59+00:00");
+        assert_eq!(rfc3339(&from_unix(951_782_400).unwrap()), "2000-02-29T00:00:00+00:00");
+    }
+}
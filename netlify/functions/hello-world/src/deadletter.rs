@@ -0,0 +1,162 @@
+#[cfg(test)]
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::clock::{self, Timestamp};
+use crate::summary::DcaRunSummary;
+
+/// A run that failed entirely, kept for inspection or a later `/resume`.
+#[derive(Serialize, Debug, Clone)]
+pub struct DeadLetter {
+    pub run_id: String,
+    pub failed_at: Timestamp,
+    /// the run's summary, errors included
+    pub summary: serde_json::Value,
+}
+
+impl DeadLetter {
+    pub fn of(summary: &DcaRunSummary) -> Result<DeadLetter, String> {
+        Ok(DeadLetter {
+            run_id: summary.run_id.clone(),
+            failed_at: clock::now(),
+            summary: serde_json::to_value(summary).map_err(|e| format!("{:?}", e))?,
+        })
+    }
+}
+
+/// Destination of failed runs, next to the alerting.
+#[async_trait]
+pub trait DeadLetterSink: Send + Sync {
+    async fn send(&self, letter: &DeadLetter) -> Result<(), String>;
+}
+
+/// Sends the run to `sink` if it failed entirely, returns whether it did.
+pub async fn send_if_failed(sink: &dyn DeadLetterSink, summary: &DcaRunSummary) -> Result<bool, String> {
+    if !summary.failed() {
+        return Ok(false);
+    }
+
+    sink.send(&DeadLetter::of(summary)?).await?;
+    Ok(true)
+}
+
+/// Keeps the letters in memory, for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryDeadLetters {
+    letters: Mutex<Vec<DeadLetter>>,
+}
+
+#[cfg(test)]
+impl MemoryDeadLetters {
+    pub fn letters(&self) -> Vec<DeadLetter> {
+        self.letters.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl DeadLetterSink for MemoryDeadLetters {
+    async fn send(&self, letter: &DeadLetter) -> Result<(), String> {
+        self.letters.lock().unwrap().push(letter.clone());
+        Ok(())
+    }
+}
+
+/// Sends every letter as a JSON message to an SQS queue.
+pub struct SqsDeadLetters {
+    pub queue_url: String,
+    client: aws_sdk_sqs::Client,
+}
+
+impl SqsDeadLetters {
+    /// Queue at `DEAD_LETTER_QUEUE_URL`, `None` when it isn't configured.
+    pub async fn from_env() -> Option<SqsDeadLetters> {
+        let queue_url = option_env!("DEAD_LETTER_QUEUE_URL")?;
+        let config = aws_config::load_from_env().await;
+
+        Some(SqsDeadLetters {
+            queue_url: queue_url.to_owned(),
+            client: aws_sdk_sqs::Client::new(&config),
+        })
+    }
+}
+
+#[async_trait]
+impl DeadLetterSink for SqsDeadLetters {
+    async fn send(&self, letter: &DeadLetter) -> Result<(), String> {
+        let body = serde_json::to_string(letter).map_err(|e| format!("{:?}", e))?;
+
+        self.client
+            .send_message()
+            .queue_url(&self.queue_url)
+            .message_body(body)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("{:?}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary::{tagged, Fill, OrderOutcome, RunError};
+
+    fn error(pair: &str) -> RunError {
+        RunError {
+            pair: Some(pair.to_owned()),
+            error: String::from("EOrder:Insufficient funds"),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_fully_failed_run_is_dead_lettered() {
+        let sink = MemoryDeadLetters::default();
+        let mut summary = DcaRunSummary::new("run-1", "live");
+        summary.errors.push(tagged("main", error("XBTUSD")));
+        summary.errors.push(tagged("main", error("ETHUSD")));
+
+        assert_eq!(send_if_failed(&sink, &summary).await, Ok(true));
+
+        let letters = sink.letters();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].run_id, "run-1");
+        assert_eq!(letters[0].summary["errors"].as_array().unwrap().len(), 2);
+        assert_eq!(letters[0].summary["errors"][0]["pair"], "XBTUSD");
+    }
+
+    #[tokio::test]
+    async fn a_partly_failed_run_is_not() {
+        let sink = MemoryDeadLetters::default();
+        let mut summary = DcaRunSummary::new("run-1", "live");
+        summary.errors.push(tagged("main", error("ETHUSD")));
+        summary.orders.push(tagged("main", OrderOutcome {
+            pair: String::from("XBTUSD"),
+            price: "30000".parse().unwrap(),
+            volume: "0.001".parse().unwrap(),
+            cost: "30".parse().unwrap(),
+            quote: String::from("ZUSD"),
+            fee: "0.078".parse().unwrap(),
+            fee_estimated: true,
+            txid: Some(vec![String::from("OABCDE-FGHIJ-KLMNOP")]),
+            validation: false,
+            description: String::from("buy 0.00100000 XBTUSD @ market"),
+            fill: Fill::Unconfirmed,
+            spread_pct: None,
+        }));
+
+        assert_eq!(send_if_failed(&sink, &summary).await, Ok(false));
+        assert!(sink.letters().is_empty());
+    }
+
+    #[tokio::test]
+    async fn an_empty_run_is_not() {
+        let sink = MemoryDeadLetters::default();
+
+        assert_eq!(send_if_failed(&sink, &DcaRunSummary::new("run-1", "live")).await, Ok(false));
+        assert!(sink.letters().is_empty());
+    }
+}
@@ -0,0 +1,96 @@
+use aws_sdk_ses::model::{Body, Content, Destination, Message};
+
+use crate::plan::LowFunds;
+use crate::summary::DcaRunSummary;
+
+/// Mails every run summary through SES.
+pub struct EmailReporter {
+    pub from: String,
+    pub to: String,
+    client: aws_sdk_ses::Client,
+}
+
+impl EmailReporter {
+    /// Reporter from `REPORT_EMAIL_FROM` to `REPORT_EMAIL_TO`, `None` unless both are configured.
+    pub async fn from_env() -> Option<EmailReporter> {
+        let from = option_env!("REPORT_EMAIL_FROM")?;
+        let to = option_env!("REPORT_EMAIL_TO")?;
+        let config = aws_config::load_from_env().await;
+
+        Some(EmailReporter {
+            from: from.to_owned(),
+            to: to.to_owned(),
+            client: aws_sdk_ses::Client::new(&config),
+        })
+    }
+
+    pub async fn send(&self, summary: &DcaRunSummary) -> Result<(), String> {
+        let subject = format!(
+            "DCA run {} ({}): {} order(s), {} error(s)",
+            summary.run_id,
+            summary.mode,
+            summary.orders.len(),
+            summary.errors.len()
+        );
+
+        self.send_email(subject, summary.to_string()).await
+    }
+
+    /// Escalates after `failures` consecutive failed runs, with the latest run's summary.
+    pub async fn send_outage(&self, failures: u32, summary: &DcaRunSummary) -> Result<(), String> {
+        let subject = format!("DCA bot is down: {} consecutive runs failed", failures);
+        let text = format!(
+            "The last {} DCA runs failed. No further reports are sent until a run succeeds.\n\n{}",
+            failures, summary
+        );
+
+        self.send_email(subject, text).await
+    }
+
+    /// Warns before a run of `account` that its free balance is running low.
+    pub async fn send_low_funds(&self, account: &str, low_funds: &LowFunds) -> Result<(), String> {
+        let subject = format!("DCA funds running low for {}: {} {} short", account, low_funds.shortfall, low_funds.quote);
+        let text = format!(
+            "[{}] {}. The run goes ahead, but buys are cut or skipped once the balance runs out. Top up to keep them going.",
+            account, low_funds
+        );
+
+        self.send_email(subject, text).await
+    }
+
+    /// Reports that `pair` of `account` was skipped `skips` runs in a row for `reason`.
+    pub async fn send_repeated_skip(&self, account: &str, pair: &str, skips: u32, reason: &str) -> Result<(), String> {
+        let subject = format!("DCA keeps skipping {} for {}: {} runs in a row", pair, account, skips);
+        let text = format!(
+            "[{}] {} was skipped in the last {} runs, each time because: {}. Buys of it won't resume until the plan or the account is fixed.",
+            account, pair, skips, reason
+        );
+
+        self.send_email(subject, text).await
+    }
+
+    async fn send_email(&self, subject: String, text: String) -> Result<(), String> {
+        let body = Body::builder()
+            .text(Content::builder().data(&text).build())
+            .html(Content::builder().data(format!("<pre>{}</pre>", escape_html(&text))).build())
+            .build();
+        let message = Message::builder()
+            .subject(Content::builder().data(subject).build())
+            .body(body)
+            .build();
+
+        self.client
+            .send_email()
+            .source(&self.from)
+            .destination(Destination::builder().to_addresses(&self.to).build())
+            .message(message)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("{:?}", e))
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
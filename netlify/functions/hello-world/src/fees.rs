@@ -0,0 +1,154 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::kraken::{self, AssetPair, KrakenClient};
+
+/// Kraken's spot schedule as `(30-day USD volume, taker %, maker %)`, for pairs
+/// whose AssetPairs entry has no schedule of its own
+const SPOT_TIERS: [(u64, &str, &str); 9] = [
+    (0, "0.26", "0.16"),
+    (50_000, "0.24", "0.14"),
+    (100_000, "0.22", "0.12"),
+    (250_000, "0.20", "0.10"),
+    (500_000, "0.18", "0.08"),
+    (1_000_000, "0.16", "0.06"),
+    (2_500_000, "0.14", "0.04"),
+    (5_000_000, "0.12", "0.02"),
+    (10_000_000, "0.10", "0.00"),
+];
+
+#[derive(Serialize, Debug)]
+pub struct FeeTierUpgrade {
+    pub pair: String,
+    /// 30-day volume so far
+    pub volume: Decimal,
+    /// 30-day volume the next tier starts at
+    pub threshold: Decimal,
+    pub volume_needed: Decimal,
+    /// fees in percent, now and at the next tier
+    pub taker_fee: Decimal,
+    pub next_taker_fee: Decimal,
+    pub maker_fee: Decimal,
+    pub next_maker_fee: Decimal,
+    /// taker fees saved on another `threshold` of volume at the next tier
+    pub projected_savings: Decimal,
+}
+
+fn spot_schedule(maker: bool) -> Vec<(Decimal, Decimal)> {
+    SPOT_TIERS
+        .iter()
+        .map(|(volume, taker_fee, maker_fee)| {
+            let fee = if maker { maker_fee } else { taker_fee };
+            (Decimal::from(*volume), fee.parse().unwrap())
+        })
+        .collect()
+}
+
+/// Fee at `volume` and the next tier's `(volume, fee)` on a schedule of `(volume, fee)` tiers.
+fn tiers(schedule: &[(Decimal, Decimal)], volume: Decimal) -> (Decimal, Option<(Decimal, Decimal)>) {
+    let mut sorted = schedule.to_vec();
+    sorted.sort_by_key(|&(volume, _)| volume);
+
+    let current = sorted.iter().rev().find(|(start, _)| *start <= volume).map_or(Decimal::ZERO, |(_, fee)| *fee);
+    let next = sorted.iter().find(|(start, _)| *start > volume).copied();
+
+    (current, next)
+}
+
+/// What reaching the next fee tier of `pair` takes from a 30-day `volume`,
+/// `None` at the lowest fee tier.
+pub fn next_tier(pair: &AssetPair, volume: Decimal) -> Option<FeeTierUpgrade> {
+    let taker_schedule = if pair.fees.is_empty() { spot_schedule(false) } else { pair.fees.clone() };
+    let maker_schedule = if pair.fees_maker.is_empty() { spot_schedule(true) } else { pair.fees_maker.clone() };
+
+    let (taker_fee, next_taker) = tiers(&taker_schedule, volume);
+    let (threshold, next_taker_fee) = next_taker?;
+    let (maker_fee, _) = tiers(&maker_schedule, volume);
+    let (next_maker_fee, _) = tiers(&maker_schedule, threshold);
+
+    Some(FeeTierUpgrade {
+        pair: pair.altname.clone(),
+        volume,
+        threshold,
+        volume_needed: threshold - volume,
+        taker_fee,
+        next_taker_fee,
+        maker_fee,
+        next_maker_fee,
+        projected_savings: threshold * (taker_fee - next_taker_fee) / Decimal::ONE_HUNDRED,
+    })
+}
+
+/// The next fee tier of `pair` (in any form `resolve_pair` accepts) from the account's 30-day volume.
+pub async fn fee_tier_upgrade(client: &KrakenClient, pair: &str) -> Result<Option<FeeTierUpgrade>, String> {
+    let key = kraken::resolve_pair(client, pair).await?;
+    let metadata = client.metadata().await?;
+    let info = metadata.pair_info(&key).ok_or_else(|| format!("unknown asset pair: {}", pair))?;
+
+    let trade_volume = kraken::trade_volume(client, &[&key]).await?;
+
+    Ok(next_tier(info, trade_volume.volume))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    fn pair(fees: serde_json::Value) -> AssetPair {
+        serde_json::from_value(serde_json::json!({
+            "altname": "XBTUSD",
+            "wsname": "XBT/USD",
+            "base": "XXBT",
+            "quote": "ZUSD",
+            "pair_decimals": 1,
+            "lot_decimals": 8,
+            "status": "online",
+            "ordermin": "0.0001",
+            "costmin": "0.5",
+            "fees": fees,
+            "fee_volume_currency": "ZUSD"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn tiers_finds_the_current_and_next_tier() {
+        let schedule = [(dec("100000"), dec("0.22")), (dec("0"), dec("0.26")), (dec("50000"), dec("0.24"))];
+
+        assert_eq!(tiers(&schedule, dec("60000")), (dec("0.24"), Some((dec("100000"), dec("0.22")))));
+        assert_eq!(tiers(&schedule, dec("50000")), (dec("0.24"), Some((dec("100000"), dec("0.22")))));
+        assert_eq!(tiers(&schedule, dec("100000")), (dec("0.22"), None));
+    }
+
+    #[test]
+    fn next_tier_falls_back_to_the_spot_schedule() {
+        let upgrade = next_tier(&pair(serde_json::json!([])), dec("10000")).unwrap();
+
+        assert_eq!(upgrade.threshold, dec("50000"));
+        assert_eq!(upgrade.volume_needed, dec("40000"));
+        assert_eq!((upgrade.taker_fee, upgrade.next_taker_fee), (dec("0.26"), dec("0.24")));
+        assert_eq!((upgrade.maker_fee, upgrade.next_maker_fee), (dec("0.16"), dec("0.14")));
+        assert_eq!(upgrade.projected_savings, dec("10"));
+    }
+
+    #[test]
+    fn next_tier_uses_the_pair_schedule() {
+        let fees = serde_json::json!([["0", "0.40"], ["25000", "0.35"]]);
+        let upgrade = next_tier(&pair(fees), dec("5000")).unwrap();
+
+        assert_eq!(upgrade.threshold, dec("25000"));
+        assert_eq!((upgrade.taker_fee, upgrade.next_taker_fee), (dec("0.40"), dec("0.35")));
+        // no maker schedule of its own
+        assert_eq!((upgrade.maker_fee, upgrade.next_maker_fee), (dec("0.16"), dec("0.16")));
+    }
+
+    #[test]
+    fn next_tier_is_none_at_the_lowest_fee() {
+        assert!(next_tier(&pair(serde_json::json!([])), dec("10000000")).is_none());
+        assert!(next_tier(&pair(serde_json::json!([])), dec("25000000")).is_none());
+    }
+}
@@ -0,0 +1,81 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::kraken::{self, KrakenClient};
+
+/// candle intervals `kraken::ohlc` accepts, in minutes
+pub const OHLC_INTERVALS: [u32; 9] = [1, 5, 15, 30, 60, 240, 1440, 10080, 21600];
+
+/// Condition a pair must meet for a run to buy it.
+#[async_trait]
+pub trait RunFilter: Send + Sync {
+    /// Reason to leave `pair` (a canonical AssetPairs key) out of this run, `None` to buy it.
+    async fn check(&self, client: &KrakenClient, pair: &str) -> Result<Option<String>, String>;
+}
+
+/// Buys only after a red period: the last closed candle closed below its open.
+pub struct RedCandle {
+    /// candle length in minutes
+    pub interval: u32,
+}
+
+#[async_trait]
+impl RunFilter for RedCandle {
+    async fn check(&self, client: &KrakenClient, pair: &str) -> Result<Option<String>, String> {
+        let candles = kraken::ohlc(client, pair, self.interval).await?;
+
+        // the last candle is the period still in progress
+        let closed = candles
+            .iter()
+            .rev()
+            .nth(1)
+            .ok_or_else(|| format!("no closed {}m candle for {}", self.interval, pair))?;
+
+        if closed.close < closed.open {
+            Ok(None)
+        } else {
+            Ok(Some(format!(
+                "last {}m candle was not red: opened at {}, closed at {}",
+                self.interval, closed.open, closed.close
+            )))
+        }
+    }
+}
+
+/// Filter as written in a plan, e.g. `{"red_candle": {"interval": 1440}}`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum FilterConfig {
+    RedCandle {
+        #[serde(default = "default_interval")]
+        interval: u32,
+    },
+}
+
+/// one candle a day
+fn default_interval() -> u32 {
+    1440
+}
+
+impl FilterConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            FilterConfig::RedCandle { interval } if !OHLC_INTERVALS.contains(interval) => {
+                Err(format!("red_candle filter has an invalid interval of {}m", interval))
+            }
+            FilterConfig::RedCandle { .. } => Ok(()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterConfig::RedCandle { .. } => "red_candle",
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn RunFilter> {
+        match *self {
+            FilterConfig::RedCandle { interval } => Box::new(RedCandle { interval }),
+        }
+    }
+}
@@ -0,0 +1,398 @@
+use std::cell::RefCell;
+#[cfg(test)]
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{self, Timestamp};
+
+/// What the run decided for a plan entry.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// pair and prices resolved at planning time
+    Prepared {
+        resolved_pair: String,
+        limit_price: Option<Decimal>,
+        max_price: Option<Decimal>,
+    },
+    /// amount lowered to keep the reserve
+    Adjusted { amount: Decimal, adjusted: Decimal },
+    Skipped { reason: String },
+    Failed { error: String },
+    Ordered {
+        price: Decimal,
+        volume: Decimal,
+        txid: Option<Vec<String>>,
+    },
+    /// order ended short of its volume
+    PartiallyFilled { exec_vol: Decimal, remaining: Decimal },
+    /// open limit moved closer to the market, `txid` replaces the order
+    Repriced { from: Decimal, to: Decimal, txid: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Decision {
+    pub at: Timestamp,
+    pub account: String,
+    /// pair as written in the plan
+    pub pair: String,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+/// User reference every order of run `run_id` is placed with, the same for
+/// every invocation so a resumed run finds the orders of the interrupted one.
+pub fn run_userref(run_id: &str) -> i32 {
+    // FNV-1a, stable across builds unlike the std hasher
+    let hash = run_id
+        .bytes()
+        .fold(0x811c_9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193));
+
+    // positive and never 0, which Kraken treats as no reference
+    (hash & 0x7fff_ffff).max(1) as i32
+}
+
+/// Collects one account's decisions as the run makes them.
+pub struct Recorder<'a> {
+    run_id: String,
+    account: String,
+    decisions: RefCell<Vec<Decision>>,
+    /// where `record_durably` writes ahead of the run's entry
+    sink: Option<&'a dyn JournalSink>,
+}
+
+impl<'a> Recorder<'a> {
+    pub fn new(run_id: &str, account: &str, sink: Option<&'a dyn JournalSink>) -> Recorder<'a> {
+        Recorder {
+            run_id: run_id.to_owned(),
+            account: account.to_owned(),
+            decisions: RefCell::new(Vec::new()),
+            sink,
+        }
+    }
+
+    fn decision(&self, pair: &str, action: Action) -> Decision {
+        Decision {
+            at: clock::now(),
+            account: self.account.clone(),
+            pair: pair.to_owned(),
+            action,
+        }
+    }
+
+    pub fn record(&self, pair: &str, action: Action) {
+        let decision = self.decision(pair, action);
+        self.decisions.borrow_mut().push(decision);
+    }
+
+    /// Records the decision and writes it to the sink right away, so a run cut
+    /// short (e.g. by the Lambda deadline) still leaves it behind. Meant for
+    /// submitted orders, before their fills are awaited.
+    pub async fn record_durably(&self, pair: &str, action: Action) {
+        let decision = self.decision(pair, action);
+        self.decisions.borrow_mut().push(decision.clone());
+
+        if let Some(sink) = self.sink {
+            if let Err(e) = sink.append_decision(&self.run_id, &decision).await {
+                log::warn!("[{}] decision for {} not journaled ahead of the run: {}", self.account, pair, e);
+            }
+        }
+    }
+
+    pub fn into_decisions(self) -> Vec<Decision> {
+        self.decisions.into_inner()
+    }
+}
+
+/// Everything one run decided, across all accounts.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JournalEntry {
+    pub run_id: String,
+    pub started_at: Timestamp,
+    pub finished_at: Timestamp,
+    pub decisions: Vec<Decision>,
+}
+
+/// Append-only destination of journal entries.
+#[async_trait]
+pub trait JournalSink: Send + Sync {
+    async fn append(&self, entry: &JournalEntry) -> Result<(), String>;
+
+    /// Writes a single decision of run `run_id` before the run's entry is complete.
+    async fn append_decision(&self, run_id: &str, decision: &Decision) -> Result<(), String>;
+
+    /// Decisions of run `run_id` written ahead with `append_decision`.
+    async fn decisions(&self, run_id: &str) -> Result<Vec<Decision>, String>;
+
+    /// Entries of the runs started at or after `since`.
+    async fn entries_since(&self, since: Timestamp) -> Result<Vec<JournalEntry>, String>;
+}
+
+/// Keeps the entries in memory, for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryJournal {
+    entries: Mutex<Vec<JournalEntry>>,
+    /// decisions written ahead, with their run id
+    decisions: Mutex<Vec<(String, Decision)>>,
+}
+
+#[cfg(test)]
+impl MemoryJournal {
+    pub fn entries(&self) -> Vec<JournalEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    pub fn decisions(&self) -> Vec<(String, Decision)> {
+        self.decisions.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl JournalSink for MemoryJournal {
+    async fn append(&self, entry: &JournalEntry) -> Result<(), String> {
+        self.entries.lock().unwrap().push(entry.clone());
+        Ok(())
+    }
+
+    async fn append_decision(&self, run_id: &str, decision: &Decision) -> Result<(), String> {
+        self.decisions.lock().unwrap().push((run_id.to_owned(), decision.clone()));
+        Ok(())
+    }
+
+    async fn decisions(&self, run_id: &str) -> Result<Vec<Decision>, String> {
+        Ok(self
+            .decisions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, _)| id == run_id)
+            .map(|(_, decision)| decision.clone())
+            .collect())
+    }
+
+    async fn entries_since(&self, since: Timestamp) -> Result<Vec<JournalEntry>, String> {
+        Ok(self.entries().into_iter().filter(|entry| entry.started_at >= since).collect())
+    }
+}
+
+/// Writes every entry to its own `<run_id>.json` object, and every decision
+/// written ahead to its own object under `<run_id>/`, so nothing is ever overwritten.
+pub struct S3Journal {
+    pub bucket: String,
+    client: aws_sdk_s3::Client,
+}
+
+impl S3Journal {
+    /// Journal in the `JOURNAL_BUCKET` bucket, `None` when it isn't configured.
+    pub async fn from_env() -> Option<S3Journal> {
+        let bucket = option_env!("JOURNAL_BUCKET")?;
+        let config = aws_config::load_from_env().await;
+
+        Some(S3Journal {
+            bucket: bucket.to_owned(),
+            client: aws_sdk_s3::Client::new(&config),
+        })
+    }
+}
+
+#[async_trait]
+impl JournalSink for S3Journal {
+    async fn append(&self, entry: &JournalEntry) -> Result<(), String> {
+        self.put(format!("journal/{}.json", entry.run_id), entry).await
+    }
+
+    async fn append_decision(&self, run_id: &str, decision: &Decision) -> Result<(), String> {
+        let key = format!(
+            "journal/{}/{}-{}-{}.json",
+            run_id,
+            decision.account,
+            decision.pair,
+            clock::unix_nanos(&decision.at)
+        );
+        self.put(key, decision).await
+    }
+
+    async fn decisions(&self, run_id: &str) -> Result<Vec<Decision>, String> {
+        let listed = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(format!("journal/{}/", run_id))
+            .send()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+
+        let mut decisions = Vec::new();
+        for key in listed.contents.unwrap_or_default().into_iter().filter_map(|object| object.key) {
+            decisions.push(self.get(&key).await?);
+        }
+
+        Ok(decisions)
+    }
+
+    async fn entries_since(&self, since: Timestamp) -> Result<Vec<JournalEntry>, String> {
+        let mut entries = Vec::new();
+        let mut continuation_token = None;
+
+        // the journal outgrows a single listing page
+        loop {
+            let listed = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix("journal/")
+                .delimiter("/")
+                .set_continuation_token(continuation_token)
+                .send()
+                .await
+                .map_err(|e| format!("{:?}", e))?;
+
+            for object in listed.contents.unwrap_or_default() {
+                let modified = object.last_modified.map_or(i64::MAX, |modified| modified.secs());
+                let key = match object.key {
+                    // an entry is written when its run finishes, so older objects can't be newer runs
+                    Some(key) if modified >= clock::unix(&since) => key,
+                    _ => continue,
+                };
+
+                let entry: JournalEntry = self.get(&key).await?;
+                if entry.started_at >= since {
+                    entries.push(entry);
+                }
+            }
+
+            continuation_token = listed.next_continuation_token;
+            if continuation_token.is_none() {
+                return Ok(entries);
+            }
+        }
+    }
+}
+
+impl S3Journal {
+    async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<T, String> {
+        let object = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        let body = object.body.collect().await.map_err(|e| format!("{:?}", e))?.into_bytes();
+
+        serde_json::from_slice(&body).map_err(|e| format!("invalid journal object {}: {}", key, e))
+    }
+
+    async fn put<T: Serialize>(&self, key: String, value: &T) -> Result<(), String> {
+        let body = serde_json::to_vec(value).map_err(|e| format!("{:?}", e))?;
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type("application/json")
+            .body(aws_sdk_s3::ByteStream::from(body))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("{:?}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(run_id: &str, started_at: Timestamp, decisions: Vec<Decision>) -> JournalEntry {
+        JournalEntry {
+            run_id: run_id.to_owned(),
+            started_at,
+            finished_at: clock::from_unix(clock::unix(&started_at) + 30).unwrap(),
+            decisions,
+        }
+    }
+
+    fn days_before(at: &Timestamp, days: i64) -> Timestamp {
+        clock::from_unix(clock::unix(at) - days * 86_400).unwrap()
+    }
+
+    #[tokio::test]
+    async fn durable_decisions_are_written_ahead() {
+        let journal = MemoryJournal::default();
+        let recorder = Recorder::new("run-1", "main", Some(&journal));
+
+        recorder.record("XBTUSD", Action::Skipped { reason: String::from("below ordermin") });
+        recorder
+            .record_durably("ETHUSD", Action::Ordered {
+                price: "2000".parse().unwrap(),
+                volume: "0.01".parse().unwrap(),
+                txid: Some(vec![String::from("OABCDE-FGHIJ-KLMNOP")]),
+            })
+            .await;
+
+        let ahead = JournalSink::decisions(&journal, "run-1").await.unwrap();
+        assert_eq!(ahead.len(), 1);
+        assert_eq!(ahead[0].pair, "ETHUSD");
+        assert_eq!(ahead[0].account, "main");
+        assert!(JournalSink::decisions(&journal, "run-2").await.unwrap().is_empty());
+
+        let decisions = recorder.into_decisions();
+        let pairs: Vec<&str> = decisions.iter().map(|decision| decision.pair.as_str()).collect();
+        assert_eq!(pairs, vec!["XBTUSD", "ETHUSD"]);
+        assert!(journal.entries().is_empty());
+    }
+
+    #[tokio::test]
+    async fn one_entry_per_run() {
+        let journal = MemoryJournal::default();
+        let recorder = Recorder::new("run-1", "main", None);
+        recorder.record("XBTUSD", Action::Failed { error: String::from("EOrder:Insufficient funds") });
+
+        let now = clock::now();
+        journal.append(&entry("run-0", days_before(&now, 2), Vec::new())).await.unwrap();
+        journal.append(&entry("run-1", now, recorder.into_decisions())).await.unwrap();
+
+        assert_eq!(journal.entries().len(), 2);
+        // nothing was written ahead without a sink
+        assert!(journal.decisions().is_empty());
+
+        let recent = journal.entries_since(days_before(&now, 1)).await.unwrap();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].run_id, "run-1");
+        assert!(matches!(recent[0].decisions[0].action, Action::Failed { .. }));
+    }
+
+    #[test]
+    fn decisions_round_trip_as_flat_json() {
+        let decision = Decision {
+            at: clock::now(),
+            account: String::from("main"),
+            pair: String::from("XBTUSD"),
+            action: Action::Adjusted {
+                amount: "50".parse().unwrap(),
+                adjusted: "42.5".parse().unwrap(),
+            },
+        };
+
+        let json = serde_json::to_value(&decision).unwrap();
+        assert_eq!(json["action"], "adjusted");
+        assert_eq!(json["adjusted"], "42.5");
+
+        let parsed: Decision = serde_json::from_value(json).unwrap();
+        assert!(matches!(parsed.action, Action::Adjusted { adjusted, .. } if adjusted == "42.5".parse::<Decimal>().unwrap()));
+    }
+
+    #[test]
+    fn run_userref_is_stable_and_positive() {
+        assert_eq!(run_userref("run-1"), run_userref("run-1"));
+        assert_ne!(run_userref("run-1"), run_userref("run-2"));
+        assert!(run_userref("").is_positive());
+    }
+}
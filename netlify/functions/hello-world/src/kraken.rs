@@ -1,18 +1,29 @@
 use core::fmt;
-use std::collections::HashMap;
-use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use reqwest;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::{Decimal, RoundingStrategy};
+#[cfg(feature = "timestamps")]
+use chrono::{DateTime, SecondsFormat, Utc};
 use crypto::digest::Digest;
 use crypto::hmac::Hmac;
 use crypto::mac::Mac;
 use crypto::sha2::{Sha256, Sha512};
-use serde::de::DeserializeOwned;
+use serde::de::{self, DeserializeOwned, Deserializer};
+use serde::ser::Serializer;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OrderType {
     Market,
     /// (price = limit price)
     Limit,
+    /// (price = limit price, only part of the volume is shown in the book)
+    Iceberg,
     /// (price = stop loss price)
     StopLoss,
     /// (price = take profit price)
@@ -21,6 +32,10 @@ pub enum OrderType {
     StopLossLimit,
     /// (price = take profit trigger price, price2 = triggered limit price)
     TakeProfitLimit,
+    /// (price = relative trailing offset)
+    TrailingStop,
+    /// (price = relative trailing offset, price2 = limit offset)
+    TrailingStopLimit,
     SettlePosition,
 }
 impl fmt::Display for OrderType {
@@ -28,16 +43,135 @@ impl fmt::Display for OrderType {
 		match self {
 			OrderType::Market => write!(f, "market"),
 			OrderType::Limit => write!(f, "limit"),
+			OrderType::Iceberg => write!(f, "iceberg"),
 			OrderType::StopLoss => write!(f, "stop-loss"),
 			OrderType::TakeProfit => write!(f, "take-profit"),
 			OrderType::StopLossLimit => write!(f, "stop-loss-limit"),
 			OrderType::TakeProfitLimit => write!(f, "take-profit-limit"),
+			OrderType::TrailingStop => write!(f, "trailing-stop"),
+			OrderType::TrailingStopLimit => write!(f, "trailing-stop-limit"),
 			OrderType::SettlePosition => write!(f, "settle-position")
 		}
 	}
 }
 
-#[derive(Debug, Clone, Copy)]
+impl FromStr for OrderType {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<OrderType, String> {
+		match s {
+			"market" => Ok(OrderType::Market),
+			"limit" => Ok(OrderType::Limit),
+			"iceberg" => Ok(OrderType::Iceberg),
+			"stop-loss" => Ok(OrderType::StopLoss),
+			"take-profit" => Ok(OrderType::TakeProfit),
+			"stop-loss-limit" => Ok(OrderType::StopLossLimit),
+			"take-profit-limit" => Ok(OrderType::TakeProfitLimit),
+			"trailing-stop" => Ok(OrderType::TrailingStop),
+			"trailing-stop-limit" => Ok(OrderType::TrailingStopLimit),
+			"settle-position" => Ok(OrderType::SettlePosition),
+			_ => Err(format!("unknown order type: {}", s)),
+		}
+	}
+}
+
+impl OrderType {
+	/// stop and take profit types, which wait for a price to trigger them
+	pub fn is_triggered(&self) -> bool {
+		matches!(
+			self,
+			OrderType::StopLoss
+				| OrderType::TakeProfit
+				| OrderType::StopLossLimit
+				| OrderType::TakeProfitLimit
+				| OrderType::TrailingStop
+				| OrderType::TrailingStopLimit
+		)
+	}
+}
+
+/// Order price as Kraken takes it: a plain decimal, or for trailing stops and
+/// `price2` an offset from the market (`+1.5`, `-1.5`, `#1.5`, `+2%`). Comma
+/// decimals, currency symbols and the like are refused when parsing instead of
+/// failing at Kraken.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Price {
+	/// `+`, `-` or `#` of a relative price
+	pub offset: Option<char>,
+	pub value: Decimal,
+	/// `value` is a percentage, relative prices only
+	pub percent: bool,
+}
+
+impl From<Decimal> for Price {
+	fn from(value: Decimal) -> Price {
+		Price {
+			offset: None,
+			value,
+			percent: false,
+		}
+	}
+}
+
+impl fmt::Display for Price {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if let Some(offset) = self.offset {
+			write!(f, "{}", offset)?;
+		}
+		write!(f, "{}", self.value.normalize())?;
+		if self.percent {
+			write!(f, "%")?;
+		}
+		Ok(())
+	}
+}
+
+impl FromStr for Price {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Price, String> {
+		let trimmed = s.trim();
+		let offset = trimmed.chars().next().filter(|c| matches!(c, '+' | '-' | '#'));
+		let unsigned = if offset.is_some() { &trimmed[1..] } else { trimmed };
+		let percent = unsigned.ends_with('%');
+		let number = unsigned.trim_end_matches('%');
+
+		if percent && offset.is_none() {
+			return Err(format!("invalid price {:?}: only a relative price (+, - or #) may be a percentage", s));
+		}
+		if number.contains(',') {
+			return Err(format!("invalid price {:?}: use '.' as the decimal separator, without thousands separators", s));
+		}
+		if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit() || c == '.') || number.matches('.').count() > 1 {
+			return Err(format!("invalid price {:?}: expected a number such as 154.20, without currency symbols", s));
+		}
+
+		let value = Decimal::from_str(number).map_err(|e| format!("invalid price {:?}: {}", s, e))?;
+		Ok(Price { offset, value, percent })
+	}
+}
+
+/// Price that triggers a stop or take profit order.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+	/// last trade price on Kraken (the default)
+	Last,
+	/// index price across exchanges, not moved by a wick on Kraken alone
+	Index,
+}
+
+impl fmt::Display for Trigger {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Trigger::Last => write!(f, "last"),
+			Trigger::Index => write!(f, "index"),
+		}
+	}
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
 pub enum OrderDirection {
     Buy,
     Sell,
@@ -52,6 +186,103 @@ impl fmt::Display for OrderDirection {
 	}
 }
 
+impl FromStr for OrderDirection {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<OrderDirection, String> {
+		match s {
+			"buy" => Ok(OrderDirection::Buy),
+			"sell" => Ok(OrderDirection::Sell),
+			_ => Err(format!("unknown order direction: {}", s)),
+		}
+	}
+}
+
+/// Order directions `add_order` accepts.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DirectionPolicy {
+	/// DCA only accumulates, a sell order is a misconfiguration
+	#[default]
+	BuyOnly,
+	/// buys and sells, e.g. for rebalancing
+	Both,
+}
+
+impl FromStr for DirectionPolicy {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<DirectionPolicy, String> {
+		match s {
+			"buy_only" => Ok(DirectionPolicy::BuyOnly),
+			"both" => Ok(DirectionPolicy::Both),
+			_ => Err(format!("unknown direction policy: {}", s)),
+		}
+	}
+}
+
+/// Deserializes a string field through the type's `FromStr`.
+fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+	where
+		D: Deserializer<'de>,
+		T: FromStr,
+		T::Err: fmt::Display
+{
+	String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+}
+
+/// Serializes a field through its `Display`, the counterpart of `deserialize_from_str`.
+fn serialize_display<S, T>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+		T: fmt::Display
+{
+	serializer.collect_str(value)
+}
+
+/// Deserializes a numeric field Kraken sends either as a JSON string or as a
+/// JSON number, so a change of representation doesn't break decoding.
+/// `Decimal` fields need no help, rust_decimal takes both already.
+fn deserialize_number_from_string_or_number<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+	where
+		D: Deserializer<'de>,
+		T: FromStr,
+		T::Err: fmt::Display
+{
+	#[derive(Deserialize)]
+	#[serde(untagged)]
+	enum Number {
+		Text(String),
+		Number(serde_json::Number),
+	}
+
+	let text = match Number::deserialize(deserializer)? {
+		Number::Text(text) => text,
+		Number::Number(number) => number.to_string(),
+	};
+
+	text.parse().map_err(de::Error::custom)
+}
+
+/// Currency an order's fee is preferably charged in.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FeePreference {
+	/// `fcib` order flag
+	Base,
+	/// `fciq` order flag
+	Quote,
+}
+
+impl FeePreference {
+	fn flag(self) -> &'static str {
+		match self {
+			FeePreference::Base => "fcib",
+			FeePreference::Quote => "fciq",
+		}
+	}
+}
+
 pub struct NewOrder { 
     /// asset pair
     pub pair: String,
@@ -59,20 +290,29 @@ pub struct NewOrder {
     pub order_direction: OrderDirection,
     pub order_type: OrderType,
     /// price (optional.  dependent upon ordertype)
-    pub price: Option<String>,
+    pub price: Option<Price>,
     /// secondary price (optional.  dependent upon ordertype)
-    pub price2: Option<String>,
+    pub price2: Option<Price>,
+    /// price triggering a stop or take profit order (optional, triggered types only)
+    pub trigger: Option<Trigger>,
     /// order volume in lots
     pub volume: Option<String>,
     /// amount of leverage desired (optional.  default = none)
     pub leverage: Option<String>,
-    /// comma delimited list of order flags (optional):
-    ///   + viqc = volume in quote currency (not available for leveraged orders)
-    ///   + fcib = prefer fee in base currency
-    ///   + fciq = prefer fee in quote currency
-    ///   + nompp = no market price protection
-    ///   + post = post only order (available when ordertype = limit)
+    /// only reduce an open margin position, never open or grow one (leveraged orders only)
+    pub reduce_only: bool,
+    /// comma delimited list of order flags (optional), checked against the
+    /// order by `add_order`:
+    ///   + viqc = volume in quote currency (market buys without leverage only)
+    ///   + fcib = prefer fee in base currency (any order, not with fciq)
+    ///   + fciq = prefer fee in quote currency (any order, not with fcib)
+    ///   + nompp = no market price protection (market orders only)
+    ///   + post = post only order (limit orders only)
     pub oflags: Option<String>,
+    /// fee currency preference, added to `oflags` (optional)
+    pub fee_currency: Option<FeePreference>,
+    /// Kraken's market price protection, `false` adds the `nompp` flag (market orders only)
+    pub market_price_protection: bool,
     /// scheduled start time (optional):
     ///   + 0 = now (default)
     ///   + +<n> = schedule start time <n> seconds from now
@@ -82,9 +322,13 @@ pub struct NewOrder {
     ///   + 0 = no expiration (default)
     ///   + +<n> = expire <n> seconds from now
     ///   + <n> = unix timestamp of expiration time
-    pub expiretm: Option<i64>,
+    pub expiretm: Option<String>,
     /// user reference id.  32-bit signed number.  (optional)
     pub userref: Option<String>,
+    /// time after which the order is rejected if the matching engine hasn't
+    /// taken it yet (optional, must be in the future)
+    #[cfg(feature = "timestamps")]
+    pub deadline: Option<DateTime<Utc>>,
     /// validate inputs only.  do not submit order (optional)
     pub validate: Option<bool>,
 }
@@ -94,130 +338,4445 @@ pub struct ApiResponse<T> {
 	result: Option<T>,
 	error: Vec<String>,
 }
+#[derive(Deserialize)]
 pub struct Account {
 	pub key: String,
 	pub secret: String,
+	/// static two-factor password of an API key set up with "2FA password"
+	/// in Kraken's key settings, sent as `otp` with every private request.
+	/// Keys with a nonce window need nothing here, the window is account side.
+	#[serde(default)]
+	pub otp: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct AddOrderResponse {
-	descr: HashMap<String, String>,
-	txid: Option<Vec<String>>
+/// Where signed requests take their nonces from.
+///
+/// Kraken refuses any nonce of an API key that isn't larger than the last one it
+/// accepted for that key, from whichever process. A source must therefore hand
+/// out strictly increasing values to every process sharing the key, atomically
+/// (two callers never get the same nonce), and never fall back below a value
+/// already handed out, across restarts too. Values are nanoseconds since the
+/// epoch, so a source can be swapped without going back.
+#[async_trait]
+pub trait NonceSource: Send + Sync {
+	async fn next(&self) -> Result<u64, String>;
+
+	/// Moves later nonces `ahead` past the clock, after Kraken refused one.
+	async fn bump(&self, ahead: Duration) -> Result<(), String>;
 }
 
-async fn private<T>(account: &Account, method: &str, params: &mut HashMap<String, String>) -> Result<ApiResponse<T>, reqwest::Error> 
-	where
-		T: DeserializeOwned
-{
-	let path = format!("/0/private/{}", method);
-    let url = format!("https://api.kraken.com{}", path);
-	let timestamp = ::std::time::UNIX_EPOCH.elapsed().unwrap();
-    let nonce = format!("{}{:09}", timestamp.as_secs(), timestamp.subsec_nanos());
+/// Strictly increasing nonces taken from the clock, in nanoseconds. Only safe
+/// while a single process uses the API key.
+struct NonceGen {
+	last: Mutex<u64>,
+}
 
-	params.insert("nonce".to_owned(), nonce.clone());
+impl NonceGen {
+	fn new() -> NonceGen {
+		NonceGen { last: Mutex::new(0) }
+	}
+}
 
-	let mut body = params.iter().fold(
-        String::new(),
-        |data, item| data + item.0 + "=" + item.1 + "&",
-    );
-    body.pop(); // remove last &
-	
-	let body_bytes = body.as_bytes();
-    let secret = base64::decode(&account.secret).unwrap();
-    let mut hmac = Hmac::new(Sha512::new(), &secret);
-    let mut body_hasher = Sha256::new();
+#[async_trait]
+impl NonceSource for NonceGen {
+	async fn next(&self) -> Result<u64, String> {
+		let now = ::std::time::UNIX_EPOCH.elapsed().unwrap().as_nanos() as u64;
+		let mut last = self.last.lock().unwrap();
+		*last = now.max(*last + 1);
+		Ok(*last)
+	}
 
-    body_hasher.input(nonce.as_bytes());
-    body_hasher.input(body_bytes);
+	async fn bump(&self, ahead: Duration) -> Result<(), String> {
+		let now = ::std::time::UNIX_EPOCH.elapsed().unwrap().as_nanos() as u64;
+		let mut last = self.last.lock().unwrap();
+		*last = now.max(*last) + ahead.as_nanos() as u64;
+		Ok(())
+	}
+}
 
-    hmac.input(path.as_bytes());
-    let mut out: [u8; 32] = [0; 32];
-    body_hasher.result(&mut out);
-    hmac.input(&out);
+/// Where the budget-to-volume math takes the price a buy is sized at.
+#[async_trait]
+pub trait PriceSource: Send + Sync {
+	/// Price of `pair` (canonical pair key) a market buy is sized at.
+	async fn price(&self, client: &KrakenClient, pair: &str) -> Result<Decimal, String>;
+}
 
-    let sign = base64::encode(hmac.result().code());
+/// The last trade of Kraken's ticker, see `last_price`. The default.
+pub struct TickerPrice;
 
-	let http_response = reqwest::Client::new()
-		.post(url)
-		.header("API-Key", &account.key)
-		.header("API-Sign", sign)
-		.form(params)
-		.send()
-		.await?
-		.error_for_status()?;
-	
-	let response = http_response
-        .json::<ApiResponse<T>>()
-        .await?;
-	
-	Ok(response)
+#[async_trait]
+impl PriceSource for TickerPrice {
+	async fn price(&self, client: &KrakenClient, pair: &str) -> Result<Decimal, String> {
+		last_price(client, pair).await
+	}
 }
 
-pub async fn balance(account: &Account) -> Result<HashMap<String, String>, String> {
-    let mut params = HashMap::new();
-    private(account, "Balance", &mut params)
-		.await
+/// Fixed prices by pair, to size buys without asking the market in a test.
+#[cfg(test)]
+pub struct FixedPrice {
+	pub prices: HashMap<String, Decimal>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl PriceSource for FixedPrice {
+	async fn price(&self, _client: &KrakenClient, pair: &str) -> Result<Decimal, String> {
+		self.prices.get(pair).copied().ok_or_else(|| format!("no fixed price for {}", pair))
+	}
+}
+
+const KRAKEN_API_URL: &str = "https://api.kraken.com";
+
+const DEFAULT_BALANCE_TTL: Duration = Duration::from_secs(5);
+
+const DEFAULT_TICKER_TTL: Duration = Duration::from_secs(2);
+
+/// Retries of one class of failure.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryClass {
+	pub max_retries: u32,
+	/// delay before the first retry, doubling for every further one
+	pub delay: Duration,
+}
+
+/// How often and how patiently each class of transient failure is retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+	/// `EAPI:Rate limit exceeded`, `EOrder:Rate limit exceeded`, `EGeneral:Too many requests`,
+	/// HTTP 429; waits as long as the response's rate limit headers ask when they do
+	pub rate_limit: RetryClass,
+	/// `EAPI:Invalid nonce`, retried with nonces bumped past the clock
+	pub nonce: RetryClass,
+	/// HTTP 5xx; never retried for AddOrder or EditOrder
+	pub server_error: RetryClass,
+	/// `EService:Unavailable`, `EService:Busy` during exchange congestion, waiting
+	/// longer than for rate limits; retried for orders too, Kraken refused them unprocessed
+	pub unavailable: RetryClass,
+	/// DNS, connect and TLS failures, and timeouts; timed out or cut off AddOrder
+	/// and EditOrder requests are never retried as they may have placed the order
+	pub connection: RetryClass,
+}
+
+impl Default for RetryPolicy {
+	fn default() -> RetryPolicy {
+		RetryPolicy {
+			rate_limit: RetryClass { max_retries: 4, delay: Duration::from_secs(2) },
+			nonce: RetryClass { max_retries: 1, delay: Duration::ZERO },
+			server_error: RetryClass { max_retries: 2, delay: Duration::from_millis(500) },
+			unavailable: RetryClass { max_retries: 3, delay: Duration::from_secs(5) },
+			connection: RetryClass { max_retries: 2, delay: Duration::from_secs(1) },
+		}
+	}
+}
+
+impl RetryPolicy {
+	fn class(&self, class: ErrorClass) -> RetryClass {
+		match class {
+			ErrorClass::RateLimit => self.rate_limit,
+			ErrorClass::Nonce => self.nonce,
+			ErrorClass::ServerError => self.server_error,
+			ErrorClass::Unavailable => self.unavailable,
+			ErrorClass::Connection | ErrorClass::Interrupted => self.connection,
+		}
+	}
+}
+
+/// timeout of any request whose category doesn't set its own
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long each category of request may take before it fails with
+/// `KrakenError::Timeout`. `Timeouts::uniform` gives every category one
+/// timeout, to override single ones over, e.g.
+/// `Timeouts { export: Duration::from_secs(300), ..Timeouts::uniform(Duration::from_secs(20)) }`.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+	/// public market data (Ticker, Depth, OHLC, AssetPairs, ...), 10 seconds by
+	/// default so a stalled price fetch fails fast
+	pub public: Duration,
+	/// private calls reading the account (Balance, OpenOrders, QueryOrders, ...),
+	/// `DEFAULT_TIMEOUT` by default
+	pub private_read: Duration,
+	/// private calls placing or canceling orders (AddOrder, AddOrderBatch,
+	/// EditOrder, CancelOrder, CancelAll), `DEFAULT_TIMEOUT` by default
+	pub private_write: Duration,
+	/// account history pages (TradesHistory, Ledgers, ClosedOrders,
+	/// QueryLedgers), 2 minutes by default as a large account's pages are slow
+	pub export: Duration,
+}
+
+impl Default for Timeouts {
+	fn default() -> Timeouts {
+		Timeouts {
+			public: Duration::from_secs(10),
+			export: Duration::from_secs(120),
+			..Timeouts::uniform(DEFAULT_TIMEOUT)
+		}
+	}
+}
+
+/// private methods changing orders, timed as `Timeouts::private_write`
+const WRITE_METHODS: [&str; 5] = ["AddOrder", "AddOrderBatch", "EditOrder", "CancelOrder", "CancelAll"];
+
+impl Timeouts {
+	pub fn uniform(timeout: Duration) -> Timeouts {
+		Timeouts {
+			public: timeout,
+			private_read: timeout,
+			private_write: timeout,
+			export: timeout,
+		}
+	}
+
+	fn private(&self, method: &str) -> Duration {
+		if PAGED_HISTORY_METHODS.contains(&method) {
+			self.export
+		} else if WRITE_METHODS.contains(&method) {
+			self.private_write
+		} else {
+			self.private_read
+		}
+	}
+}
+
+/// HTTP client sending every request through the HTTP(S) proxy at `url`, for
+/// `KrakenClient::with_http_client`. TLS is still tunneled end to end, Kraken's
+/// certificate is verified as without a proxy.
+pub fn proxied_http_client(url: &str) -> Result<reqwest::Client, String> {
+	let proxy = reqwest::Proxy::all(url).map_err(|e| format!("invalid proxy {}: {}", url, e))?;
+	reqwest::Client::builder()
+		.proxy(proxy)
+		.build()
 		.map_err(|e| format!("{:?}", e))
-		.and_then(
-			|response| if response.error.len() > 0 {
-				Err(format!("{:?}", response.error))
-			} else {
-				Ok(response.result.unwrap())
-			}
-		)
 }
 
-pub async fn add_order(account: &Account, order: NewOrder) -> Result<AddOrderResponse, String> {
-	let mut params = HashMap::new();
+/// Rate limit counters found in a response's headers. Kraken's REST API
+/// doesn't send them today, but a proxy in front of it may.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitInfo {
+	/// `x-ratelimit-limit`
+	pub limit: Option<u64>,
+	/// `x-ratelimit-remaining`
+	pub remaining: Option<u64>,
+	/// `x-ratelimit-reset`, until the counter is reset
+	pub reset: Option<Duration>,
+	/// `retry-after`, in seconds
+	pub retry_after: Option<Duration>,
+}
 
-	params.insert("pair".to_owned(), order.pair.to_string());
-	params.insert("type".to_owned(), order.order_direction.to_string());
-	params.insert("ordertype".to_owned(), order.order_type.to_string());
+impl RateLimitInfo {
+	/// Counters in `headers`, `None` when there are none.
+	pub fn from_headers(headers: &reqwest::header::HeaderMap) -> Option<RateLimitInfo> {
+		let number = |name: &str| {
+			headers
+				.get(name)
+				.and_then(|value| value.to_str().ok())
+				.and_then(|value| value.trim().parse::<u64>().ok())
+		};
 
-	if let Some(price) = order.price {
-		params.insert("price".to_owned(), price);
+		let info = RateLimitInfo {
+			limit: number("x-ratelimit-limit"),
+			remaining: number("x-ratelimit-remaining"),
+			reset: number("x-ratelimit-reset").map(Duration::from_secs),
+			retry_after: number("retry-after").map(Duration::from_secs),
+		};
+
+		if info.limit.is_none() && info.remaining.is_none() && info.reset.is_none() && info.retry_after.is_none() {
+			None
+		} else {
+			Some(info)
+		}
 	}
 
-	if let Some(price) = order.price2 {
-        params.insert("price2".to_owned(), price);
-    }
+	/// How long the headers ask to wait before a retry, if they say.
+	pub fn wait(&self) -> Option<Duration> {
+		match (self.retry_after, self.remaining, self.reset) {
+			(Some(retry_after), _, _) => Some(retry_after),
+			(None, Some(0), Some(reset)) => Some(reset),
+			_ => None,
+		}
+	}
+}
 
-	if let Some(volume) = order.volume {
-        params.insert("volume".to_owned(), volume);
-    }	
+pub struct KrakenClient {
+	pub account: Account,
+	/// HTTP client every request goes through, shared so connections are reused
+	http: reqwest::Client,
+	/// Kraken's REST API unless `with_base_url` points elsewhere
+	base_url: String,
+	/// in-process unless `with_nonce_source` sets a shared one
+	nonces: Box<dyn NonceSource>,
+	/// Kraken's ticker unless a test's `with_price_source` sets another
+	price_source: Box<dyn PriceSource>,
+	/// pair and asset metadata, fetched once and reused for the lifetime of the client
+	metadata: Mutex<Option<Arc<Metadata>>>,
+	/// pairs `resolve_pair` only found under a new name, by the name given, with the current altname
+	renamed_pairs: Mutex<HashMap<String, String>>,
+	/// results of `tradable_pairs` by quote, cleared on `refresh`
+	quote_pairs: Mutex<HashMap<String, Vec<String>>>,
+	/// when set, orders for any other pair are refused before they are sent
+	allowed_pairs: Option<HashSet<String>>,
+	/// check an order's leverage against the pair's allowed values before sending
+	check_leverage: bool,
+	/// order directions `add_order` lets through
+	direction_policy: DirectionPolicy,
+	/// log every private request's method and redacted params at debug level
+	debug_log_requests: bool,
+	/// last fetched balance and when it was fetched, dropped by `add_order`
+	balance: Mutex<Option<(Instant, HashMap<String, String>)>>,
+	/// how long `balance` serves the cached copy
+	balance_ttl: Duration,
+	/// last fetched ticker of each pair and when it was fetched
+	tickers: Mutex<HashMap<String, (Instant, TickerInfo)>>,
+	/// how long `last_price` serves a cached ticker
+	ticker_ttl: Duration,
+	retry_policy: RetryPolicy,
+	timeouts: Timeouts,
+	/// most bytes a history page may have, `None` to read it whole
+	max_body_size: Option<usize>,
+	/// rate limit headers of the last response, `None` when it had none
+	rate_limit: Mutex<Option<RateLimitInfo>>,
+	/// records every response body read whole, for debugging
+	#[cfg(feature = "record-raw")]
+	raw_sink: Option<Box<dyn crate::raw::RawSink>>,
+}
 
-    if let Some(leverage) = order.leverage {
-        params.insert("leverage".to_owned(), leverage);
-    }
+impl KrakenClient {
+	pub fn new(account: Account) -> KrakenClient {
+		KrakenClient {
+			account,
+			// proxies from HTTPS_PROXY/ALL_PROXY unless `with_http_client` sets another
+			http: reqwest::Client::new(),
+			base_url: String::from(KRAKEN_API_URL),
+			nonces: Box::new(NonceGen::new()),
+			price_source: Box::new(TickerPrice),
+			metadata: Mutex::new(None),
+			renamed_pairs: Mutex::new(HashMap::new()),
+			quote_pairs: Mutex::new(HashMap::new()),
+			allowed_pairs: None,
+			check_leverage: true,
+			direction_policy: DirectionPolicy::default(),
+			debug_log_requests: false,
+			balance: Mutex::new(None),
+			balance_ttl: DEFAULT_BALANCE_TTL,
+			tickers: Mutex::new(HashMap::new()),
+			ticker_ttl: DEFAULT_TICKER_TTL,
+			retry_policy: RetryPolicy::default(),
+			timeouts: Timeouts::default(),
+			max_body_size: None,
+			rate_limit: Mutex::new(None),
+			#[cfg(feature = "record-raw")]
+			raw_sink: None,
+		}
+	}
 
-    if let Some(oflags) = order.oflags {
-        params.insert("oflags".to_owned(), oflags);
-    }
+	/// Sends every request through `http`, typically a clone of one shared by
+	/// the clients of several accounts to pool their connections. Only the
+	/// connections are shared: nonces, caches and rate limit state stay with
+	/// each client.
+	pub fn with_http_client(mut self, http: reqwest::Client) -> KrakenClient {
+		self.http = http;
+		self
+	}
 
-    if let Some(userref) = order.userref {
-        params.insert("userref".to_owned(), userref);
-    }
+	/// Takes the nonces from `source`, for an API key shared between processes.
+	pub fn with_nonce_source(mut self, source: Box<dyn NonceSource>) -> KrakenClient {
+		self.nonces = source;
+		self
+	}
 
-    if let Some(starttm) = order.starttm {
-        params.insert("starttm".to_owned(), format!("{}", starttm));
-    }
+	/// Sizes market buys at the prices of `source` instead of Kraken's ticker.
+	#[cfg(test)]
+	pub fn with_price_source(mut self, source: Box<dyn PriceSource>) -> KrakenClient {
+		self.price_source = source;
+		self
+	}
 
-    if let Some(expiretm) = order.expiretm {
-        params.insert("expiretm".to_owned(), format!("{}", expiretm));
-    }
+	/// Reads history pages (TradesHistory, Ledgers, ClosedOrders, QueryLedgers)
+	/// chunk by chunk and refuses any larger than `bytes`, so paging a large
+	/// account stays within the Lambda's memory. Other responses are read whole.
+	pub fn with_max_body_size(mut self, bytes: usize) -> KrakenClient {
+		self.max_body_size = Some(bytes);
+		self
+	}
 
-    if order.validate.is_some() {
-        params.insert("validate".to_owned(), String::from("1"));
-    }
+	/// Records the body of every response read whole to `sink`. History pages
+	/// read under `with_max_body_size` are decoded from their own buffer and left out.
+	#[cfg(feature = "record-raw")]
+	pub fn with_raw_sink(mut self, sink: Box<dyn crate::raw::RawSink>) -> KrakenClient {
+		self.raw_sink = Some(sink);
+		self
+	}
 
-	private(account, "AddOrder", &mut params)
-		.await
-		.map_err(|e| format!("{:?}", e))
-		.and_then(
-			|response| if response.error.len() > 0 {
-				Err(format!("{:?}", response.error))
-			} else {
-				Ok(response.result.unwrap())
+	pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> KrakenClient {
+		self.retry_policy = retry_policy;
+		self
+	}
+
+	/// Sends every request to `url` instead of Kraken's REST API, e.g. a
+	/// recording proxy or a local stand-in. `url` has no trailing slash.
+	pub fn with_base_url(mut self, url: &str) -> KrakenClient {
+		self.base_url = url.trim_end_matches('/').to_owned();
+		self
+	}
+
+	pub fn with_timeouts(mut self, timeouts: Timeouts) -> KrakenClient {
+		self.timeouts = timeouts;
+		self
+	}
+
+	/// Caches the balance for `ttl` instead of the default 5 seconds.
+	pub fn with_balance_ttl(mut self, ttl: Duration) -> KrakenClient {
+		self.balance_ttl = ttl;
+		self
+	}
+
+	/// Caches the tickers `last_price` reads for `ttl` instead of the default 2 seconds.
+	pub fn with_ticker_ttl(mut self, ttl: Duration) -> KrakenClient {
+		self.ticker_ttl = ttl;
+		self
+	}
+
+	/// Fetches the balance again, replacing the cached copy.
+	pub async fn refresh_balance(&self) -> Result<HashMap<String, String>, String> {
+		let mut params = HashMap::new();
+		let balance: HashMap<String, String> = private(self, "Balance", &mut params)
+			.await
+			.map_err(|e| format!("{:?}", e))
+			.and_then(into_result)?;
+
+		*self.balance.lock().unwrap() = Some((Instant::now(), balance.clone()));
+
+		Ok(balance)
+	}
+
+	fn invalidate_balance(&self) {
+		*self.balance.lock().unwrap() = None;
+	}
+
+	/// Rate limit counters of the last response, `None` when it sent none.
+	pub fn rate_limit(&self) -> Option<RateLimitInfo> {
+		*self.rate_limit.lock().unwrap()
+	}
+
+	fn record_rate_limit(&self, headers: &reqwest::header::HeaderMap) {
+		*self.rate_limit.lock().unwrap() = RateLimitInfo::from_headers(headers);
+	}
+
+	/// Logs private requests at debug level, with `nonce` and `otp` masked.
+	pub fn with_request_logging(mut self) -> KrakenClient {
+		self.debug_log_requests = true;
+		self
+	}
+
+	/// Turns the local leverage check off, leaving validation to Kraken.
+	pub fn without_leverage_check(mut self) -> KrakenClient {
+		self.check_leverage = false;
+		self
+	}
+
+	/// Lets `add_order` through for the directions `policy` allows instead of buys only.
+	pub fn with_direction_policy(mut self, policy: DirectionPolicy) -> KrakenClient {
+		self.direction_policy = policy;
+		self
+	}
+
+	/// Whether `add_order` lets sell orders through.
+	pub fn allows_sells(&self) -> bool {
+		self.direction_policy == DirectionPolicy::Both
+	}
+
+	/// Restricts `add_order` to `pairs`, given as pair keys, altnames or wsnames.
+	pub fn with_allowed_pairs(mut self, pairs: HashSet<String>) -> KrakenClient {
+		self.allowed_pairs = Some(pairs.into_iter().map(|pair| pair.to_uppercase()).collect());
+		self
+	}
+
+	async fn check_pair_allowed(&self, pair: &str) -> Result<(), String> {
+		let allowed_pairs = match &self.allowed_pairs {
+			Some(allowed_pairs) => allowed_pairs,
+			None => return Ok(()),
+		};
+
+		let metadata = self.metadata().await?;
+		let mut names = vec![pair.to_uppercase()];
+		if let Some(info) = metadata.pair_info(pair) {
+			names.push(info.altname.clone());
+			names.extend(info.wsname.clone());
+		}
+
+		if names.iter().any(|name| allowed_pairs.contains(name)) {
+			Ok(())
+		} else {
+			Err(KrakenError::PairNotAllowed { pair: pair.to_owned() }.to_string())
+		}
+	}
+
+	fn check_direction(&self, order: &NewOrder) -> Result<(), String> {
+		match (self.direction_policy, order.order_direction) {
+			(DirectionPolicy::BuyOnly, OrderDirection::Sell) => {
+				Err(KrakenError::SellNotAllowed { pair: order.pair.clone() }.to_string())
 			}
-		)
-}
\ No newline at end of file
+			_ => Ok(()),
+		}
+	}
+
+	async fn check_order_leverage(&self, order: &NewOrder) -> Result<(), String> {
+		let leverage = match &order.leverage {
+			Some(leverage) if self.check_leverage && leverage != "none" => leverage,
+			_ => return Ok(()),
+		};
+
+		let metadata = self.metadata().await?;
+		let supported = match metadata.pair_info(&order.pair) {
+			Some(info) => SupportedTypes::of(info),
+			None => return Ok(()),
+		};
+		let allowed = supported.leverage(order.order_direction);
+
+		// Kraken accepts both "2" and "2:1"
+		let requested = leverage
+			.split(':')
+			.next()
+			.and_then(|value| value.trim().parse::<u32>().ok())
+			.ok_or_else(|| format!("invalid leverage: {}", leverage))?;
+
+		if allowed.contains(&requested) {
+			Ok(())
+		} else if allowed.is_empty() {
+			Err(format!("{} {} orders do not support leverage", order.pair, order.order_direction))
+		} else {
+			Err(format!(
+				"leverage {} is not available for {} {} orders, valid values: {:?}",
+				leverage, order.pair, order.order_direction, allowed
+			))
+		}
+	}
+
+	/// Pairs given under a name Kraken no longer uses, with the altname they resolved to.
+	pub fn pair_renames(&self) -> HashMap<String, String> {
+		self.renamed_pairs.lock().unwrap().clone()
+	}
+
+	/// Pair and asset metadata, fetched on first use and cached afterwards.
+	pub async fn metadata(&self) -> Result<Arc<Metadata>, String> {
+		if let Some(metadata) = self.metadata.lock().unwrap().as_ref() {
+			return Ok(metadata.clone());
+		}
+
+		self.refresh().await
+	}
+
+	/// Fetches pair and asset metadata again, replacing the cached copy.
+	pub async fn refresh(&self) -> Result<Arc<Metadata>, String> {
+		let metadata = Arc::new(Metadata {
+			pairs: asset_pairs(self).await?,
+			assets: assets(self).await?,
+		});
+
+		*self.metadata.lock().unwrap() = Some(metadata.clone());
+		self.quote_pairs.lock().unwrap().clear();
+
+		Ok(metadata)
+	}
+}
+
+/// Pair and asset metadata, serializable so a snapshot can be kept between runs.
+#[derive(Serialize, Deserialize)]
+pub struct Metadata {
+	/// AssetPairs keyed by pair name
+	pub pairs: HashMap<String, AssetPair>,
+	/// Assets keyed by asset id
+	pub assets: HashMap<String, AssetInfo>,
+}
+
+impl Metadata {
+	pub fn pair_info(&self, pair: &str) -> Option<&AssetPair> {
+		self.pairs.get(pair)
+	}
+
+	pub fn asset_info(&self, code: &str) -> Option<&AssetInfo> {
+		self.assets.get(code)
+	}
+
+	/// Asset id of `code`, given as an asset id (`ZUSD`) or altname (`USD`).
+	pub fn asset_id(&self, code: &str) -> Option<String> {
+		let wanted = code.trim().to_uppercase();
+
+		if self.assets.contains_key(&wanted) {
+			return Some(wanted);
+		}
+
+		self.assets
+			.iter()
+			.find(|(_, asset)| asset.altname == wanted)
+			.map(|(id, _)| id.clone())
+	}
+
+	/// Pairs added, removed or changed since `previous`, sorted by pair. Only
+	/// the fields a buy depends on count as a change.
+	pub fn diff(&self, previous: &Metadata) -> Vec<MetadataChange> {
+		let mut changes = Vec::new();
+
+		for (pair, info) in &self.pairs {
+			let before = match previous.pairs.get(pair) {
+				Some(before) => before,
+				None => {
+					changes.push(MetadataChange::Added { pair: pair.clone() });
+					continue;
+				}
+			};
+
+			let fields = [
+				("status", format_opt(&before.status), format_opt(&info.status)),
+				("ordermin", format_opt(&before.ordermin), format_opt(&info.ordermin)),
+				("costmin", format_opt(&before.costmin), format_opt(&info.costmin)),
+				("pair_decimals", before.pair_decimals.to_string(), info.pair_decimals.to_string()),
+				("lot_decimals", before.lot_decimals.to_string(), info.lot_decimals.to_string()),
+			];
+
+			for (field, from, to) in fields.iter() {
+				if from != to {
+					changes.push(MetadataChange::Changed {
+						pair: pair.clone(),
+						field,
+						from: from.clone(),
+						to: to.clone(),
+					});
+				}
+			}
+		}
+
+		for pair in previous.pairs.keys() {
+			if !self.pairs.contains_key(pair) {
+				changes.push(MetadataChange::Removed { pair: pair.clone() });
+			}
+		}
+
+		changes.sort_by(|a, b| a.pair().cmp(b.pair()));
+		changes
+	}
+}
+
+fn format_opt<T: fmt::Display>(value: &Option<T>) -> String {
+	match value {
+		Some(value) => value.to_string(),
+		None => String::from("none"),
+	}
+}
+
+/// One difference between two metadata snapshots.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum MetadataChange {
+	Added { pair: String },
+	Removed { pair: String },
+	Changed {
+		pair: String,
+		/// `AssetPair` field name
+		field: &'static str,
+		from: String,
+		to: String,
+	},
+}
+
+impl MetadataChange {
+	pub fn pair(&self) -> &str {
+		match self {
+			MetadataChange::Added { pair } | MetadataChange::Removed { pair } | MetadataChange::Changed { pair, .. } => pair,
+		}
+	}
+}
+
+impl fmt::Display for MetadataChange {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			MetadataChange::Added { pair } => write!(f, "{} was added", pair),
+			MetadataChange::Removed { pair } => write!(f, "{} was removed", pair),
+			MetadataChange::Changed { pair, field, from, to } => write!(f, "{} {} changed from {} to {}", pair, field, from, to),
+		}
+	}
+}
+
+/// Asset class as Kraken names it, `currency` for everything spot trades today.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub enum AssetClass {
+	#[default]
+	Currency,
+	Forex,
+	/// a class Kraken added after this was written, kept as sent
+	Other(String),
+}
+
+impl fmt::Display for AssetClass {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			AssetClass::Currency => write!(f, "currency"),
+			AssetClass::Forex => write!(f, "forex"),
+			AssetClass::Other(aclass) => write!(f, "{}", aclass),
+		}
+	}
+}
+
+impl FromStr for AssetClass {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<AssetClass, String> {
+		match s {
+			"currency" => Ok(AssetClass::Currency),
+			"forex" => Ok(AssetClass::Forex),
+			_ => Ok(AssetClass::Other(s.to_owned())),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AssetInfo {
+	/// asset class
+	#[serde(default, serialize_with = "serialize_display", deserialize_with = "deserialize_from_str")]
+	pub aclass: AssetClass,
+	/// alternate name
+	pub altname: String,
+	/// scaling decimal places for record keeping
+	pub decimals: u32,
+	/// scaling decimal places for output display
+	pub display_decimals: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AssetPair {
+	/// alternate pair name (e.g. XBTUSD)
+	pub altname: String,
+	/// websocket pair name (e.g. XBT/USD), not set for every pair
+	pub wsname: Option<String>,
+	/// asset id of the base component
+	pub base: String,
+	/// asset id of the quote component
+	pub quote: String,
+	/// scaling decimal places for the pair
+	pub pair_decimals: u32,
+	/// scaling decimal places for the volume
+	pub lot_decimals: u32,
+	/// trading status (online, cancel_only, post_only, limit_only, reduce_only, delisted)
+	pub status: Option<String>,
+	/// minimum order volume for the pair
+	pub ordermin: Option<Decimal>,
+	/// minimum order cost (in quote currency)
+	pub costmin: Option<Decimal>,
+	/// leverage amounts available when buying
+	#[serde(default)]
+	pub leverage_buy: Vec<u32>,
+	/// leverage amounts available when selling
+	#[serde(default)]
+	pub leverage_sell: Vec<u32>,
+	/// taker fee schedule array in [<volume>, <percent fee>] tuples
+	#[serde(default)]
+	pub fees: Vec<(Decimal, Decimal)>,
+	/// maker fee schedule array in [<volume>, <percent fee>] tuples, not set for every pair
+	#[serde(default)]
+	pub fees_maker: Vec<(Decimal, Decimal)>,
+	/// currency the fee schedule's volume is counted in, not set for every pair
+	pub fee_volume_currency: Option<String>,
+}
+
+impl AssetPair {
+	/// Fee for an order of `cost` at the base (lowest volume) taker tier.
+	pub fn estimated_fee(&self, cost: Decimal) -> Decimal {
+		let percent = self.fees.first().map(|(_, percent)| *percent).unwrap_or(Decimal::ZERO);
+		cost * percent / Decimal::ONE_HUNDRED
+	}
+}
+
+/// Orders a pair takes, from its `AssetPairs` entry.
+#[derive(Debug, Clone)]
+pub struct SupportedTypes {
+	/// order types the pair's trading status accepts
+	pub order_types: Vec<OrderType>,
+	/// only post only limit orders are accepted
+	pub post_only: bool,
+	/// leverage amounts available when buying, empty on spot only pairs
+	pub leverage_buy: Vec<u32>,
+	/// leverage amounts available when selling, empty on spot only pairs
+	pub leverage_sell: Vec<u32>,
+}
+
+impl SupportedTypes {
+	pub fn of(info: &AssetPair) -> SupportedTypes {
+		let margin = !info.leverage_buy.is_empty() || !info.leverage_sell.is_empty();
+
+		let order_types = match info.status.as_deref().unwrap_or("online") {
+			"online" => {
+				let mut order_types = vec![
+					OrderType::Market,
+					OrderType::Limit,
+					OrderType::Iceberg,
+					OrderType::StopLoss,
+					OrderType::TakeProfit,
+					OrderType::StopLossLimit,
+					OrderType::TakeProfitLimit,
+					OrderType::TrailingStop,
+					OrderType::TrailingStopLimit,
+				];
+				// only margin positions can be settled
+				if margin {
+					order_types.push(OrderType::SettlePosition);
+				}
+				order_types
+			}
+			"post_only" | "limit_only" => vec![OrderType::Limit],
+			// cancel_only, reduce_only, delisted
+			_ => Vec::new(),
+		};
+
+		SupportedTypes {
+			order_types,
+			post_only: info.status.as_deref() == Some("post_only"),
+			leverage_buy: info.leverage_buy.clone(),
+			leverage_sell: info.leverage_sell.clone(),
+		}
+	}
+
+	pub fn allows(&self, order_type: OrderType, post_only: bool) -> bool {
+		self.order_types.contains(&order_type) && (post_only || !self.post_only)
+	}
+
+	/// Leverage amounts available for orders in `direction`.
+	pub fn leverage(&self, direction: OrderDirection) -> &[u32] {
+		match direction {
+			OrderDirection::Buy => &self.leverage_buy,
+			OrderDirection::Sell => &self.leverage_sell,
+		}
+	}
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ServerTime {
+	/// unix timestamp
+	pub unixtime: i64,
+	/// RFC 1123 time format
+	pub rfc1123: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExchangeStatus {
+	/// operational, full trading available
+	Online,
+	/// existing orders are canceled, new orders cannot be created
+	Maintenance,
+	/// existing orders can be canceled, new orders cannot be created
+	CancelOnly,
+	/// only new post limit orders can be submitted
+	PostOnly,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SystemStatus {
+	pub status: ExchangeStatus,
+	/// current timestamp (RFC3339)
+	pub timestamp: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TickerInfo {
+	/// best ask
+	pub a: PriceLevel,
+	/// best bid
+	pub b: PriceLevel,
+	/// last trade closed
+	pub c: LastTrade,
+}
+
+impl TickerInfo {
+	pub fn ask_price(&self) -> Decimal {
+		self.a.price
+	}
+
+	pub fn bid_price(&self) -> Decimal {
+		self.b.price
+	}
+
+	pub fn last_price(&self) -> Decimal {
+		self.c.price
+	}
+}
+
+/// Ticker array(<price>, <whole lot volume>, <lot volume>), malformed arrays fail
+/// to deserialize naming the expected length.
+#[derive(Deserialize, Debug, Clone)]
+pub struct PriceLevel {
+	pub price: Decimal,
+	pub whole_lot_volume: Decimal,
+	pub lot_volume: Decimal,
+}
+
+/// Ticker array(<price>, <lot volume>)
+#[derive(Deserialize, Debug, Clone)]
+pub struct LastTrade {
+	pub price: Decimal,
+	pub lot_volume: Decimal,
+}
+
+/// Kraken transaction id of an order or trade
+pub type TxId = String;
+
+/// Fill state of an order, as far as it got while being watched.
+#[derive(Debug, Clone)]
+pub struct ExecutedTrade {
+	pub txid: TxId,
+	pub pair: String,
+	/// order status when watching stopped: closed, canceled or expired, or
+	/// still pending/open when the timeout elapsed first
+	pub status: String,
+	/// executed volume
+	pub volume: Decimal,
+	/// volume the order was placed for
+	pub ordered: Decimal,
+	/// average execution price
+	pub price: Decimal,
+	pub cost: Decimal,
+	pub fee: Decimal,
+}
+
+impl ExecutedTrade {
+	fn from_order(txid: &str, order: &OpenOrder) -> Result<ExecutedTrade, String> {
+		Ok(ExecutedTrade {
+			txid: txid.to_owned(),
+			pair: order.descr.pair.clone(),
+			status: order.status.clone(),
+			volume: parse_decimal(&order.vol_exec)?,
+			ordered: parse_decimal(&order.vol)?,
+			price: parse_decimal(&order.price)?,
+			cost: parse_decimal(&order.cost)?,
+			fee: parse_decimal(&order.fee)?,
+		})
+	}
+
+	pub fn is_terminal(&self) -> bool {
+		is_terminal(&self.status)
+	}
+}
+
+fn is_terminal(status: &str) -> bool {
+	status == "closed" || status == "canceled" || status == "expired"
+}
+
+fn parse_decimal(value: &str) -> Result<Decimal, String> {
+	Decimal::from_str(value).map_err(|e| format!("{:?}", e))
+}
+
+/// `price * volume`, failing instead of panicking on overflow and instead of
+/// rounding a product of non-zero factors down to zero.
+pub fn safe_cost(price: Decimal, volume: Decimal) -> Result<Decimal, KrakenError> {
+	let out_of_range = || KrakenError::CostOutOfRange { price, volume };
+	let cost = price.checked_mul(volume).ok_or_else(out_of_range)?;
+
+	if cost.is_zero() && !price.is_zero() && !volume.is_zero() {
+		return Err(out_of_range());
+	}
+
+	Ok(cost)
+}
+
+#[derive(Debug)]
+pub struct PlacedBuy {
+	pub pair: String,
+	/// price the volume was computed from: the last trade, or the limit price
+	pub price: Decimal,
+	pub volume: Decimal,
+	pub order: AddOrderResponse,
+}
+
+impl PlacedBuy {
+	/// Checked with `safe_cost` before the buy was placed.
+	pub fn cost(&self) -> Decimal {
+		self.price * self.volume
+	}
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct OrderDescription {
+	/// asset pair
+	pub pair: String,
+	/// type of order (buy/sell)
+	#[serde(rename = "type", deserialize_with = "deserialize_from_str")]
+	pub order_direction: OrderDirection,
+	/// order type
+	#[serde(deserialize_with = "deserialize_from_str")]
+	pub ordertype: OrderType,
+	/// primary price ("0" when not set)
+	#[serde(default, deserialize_with = "deserialize_number_from_string_or_number")]
+	pub price: String,
+	/// secondary price ("0" when not set)
+	#[serde(default, deserialize_with = "deserialize_number_from_string_or_number")]
+	pub price2: String,
+	/// amount of leverage ("none" when not set)
+	#[serde(default)]
+	pub leverage: String,
+	/// order description, e.g. "buy 2.00000000 SOLUSD @ limit 154.00"
+	#[serde(default)]
+	pub order: String,
+	/// conditional close order description (if conditional close set)
+	pub close: Option<String>,
+}
+
+/// Description AddOrder returns for a placed order.
+#[derive(Deserialize, Debug, Clone)]
+pub struct AddOrderDescription {
+	/// order description, e.g. "buy 2.00000000 SOLUSD @ market"
+	pub order: String,
+	/// conditional close order description (if conditional close set)
+	pub close: Option<String>,
+}
+
+impl AddOrderDescription {
+	/// `order` read back into the typed description, `None` unless Kraken
+	/// worded it "<type> <volume> <pair> @ <ordertype> [<price>]".
+	fn to_description(&self) -> Option<OrderDescription> {
+		match self.order.split_whitespace().collect::<Vec<_>>().as_slice() {
+			[direction, _, pair, "@", ordertype, price @ ..] if price.len() <= 1 => Some(OrderDescription {
+				pair: (*pair).to_owned(),
+				order_direction: direction.parse().ok()?,
+				ordertype: ordertype.parse().ok()?,
+				price: price.first().map_or("0", |price| *price).to_owned(),
+				price2: String::from("0"),
+				leverage: String::from("none"),
+				order: self.order.clone(),
+				close: self.close.clone(),
+			}),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct OpenOrder {
+	/// the order's own txid, which Kraken sends as the key of the order; only
+	/// set by `open_orders_filtered`, empty in maps keyed by txid
+	#[serde(default)]
+	pub txid: TxId,
+	/// referral order transaction id that created this order
+	pub refid: Option<String>,
+	/// user reference id
+	pub userref: Option<i32>,
+	/// status of order (pending, open, closed, canceled, expired)
+	pub status: String,
+	/// unix timestamp of when order was placed
+	pub opentm: f64,
+	/// unix timestamp of order start time (or 0 if not set)
+	pub starttm: f64,
+	/// unix timestamp of order end time (or 0 if not set)
+	pub expiretm: f64,
+	pub descr: OrderDescription,
+	/// volume of order (base currency)
+	#[serde(deserialize_with = "deserialize_number_from_string_or_number")]
+	pub vol: String,
+	/// volume executed (base currency)
+	#[serde(deserialize_with = "deserialize_number_from_string_or_number")]
+	pub vol_exec: String,
+	/// total cost (quote currency)
+	#[serde(deserialize_with = "deserialize_number_from_string_or_number")]
+	pub cost: String,
+	/// total fee (quote currency)
+	#[serde(deserialize_with = "deserialize_number_from_string_or_number")]
+	pub fee: String,
+	/// average price (quote currency)
+	#[serde(deserialize_with = "deserialize_number_from_string_or_number")]
+	pub price: String,
+	/// stop price (quote currency)
+	pub stopprice: Option<String>,
+	/// triggered limit price (quote currency, when limit based order type triggered)
+	pub limitprice: Option<String>,
+	/// comma delimited list of miscellaneous info
+	pub misc: String,
+	/// comma delimited list of order flags
+	pub oflags: String,
+	/// unix timestamp of when order was closed (closed orders only)
+	pub closetm: Option<f64>,
+	/// additional info on status (closed orders only)
+	pub reason: Option<String>,
+	/// trade ids related to the order (only with `trades` requested)
+	pub trades: Option<Vec<TxId>>,
+}
+
+impl OpenOrder {
+	pub fn opened_at(&self) -> Option<crate::clock::Timestamp> {
+		crate::clock::from_unix_f64(self.opentm)
+	}
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenOrdersResponse {
+	open: HashMap<String, OpenOrder>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ClosedOrdersPage {
+	pub closed: HashMap<String, OpenOrder>,
+	/// total number of orders matching the query
+	#[serde(deserialize_with = "deserialize_count")]
+	pub count: u32,
+}
+
+impl ClosedOrdersPage {
+	/// Whether the page fetched at `ofs` is the last one.
+	pub fn is_last_page(&self, ofs: u32) -> bool {
+		is_last_page(self.count, ofs, self.closed.len())
+	}
+}
+
+/// A page of `page_len` items fetched at offset `ofs` is the last of `count`
+/// items when it reaches the end, or comes back empty short of it.
+fn is_last_page(count: u32, ofs: u32, page_len: usize) -> bool {
+	page_len == 0 || ofs as u64 + page_len as u64 >= count as u64
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LedgerEntry {
+	/// reference id
+	pub refid: String,
+	/// unix timestamp of ledger
+	pub time: f64,
+	/// type of ledger entry (trade, deposit, withdrawal, ...)
+	#[serde(rename = "type")]
+	pub entry_type: String,
+	/// additional info relating to the ledger entry type
+	pub subtype: String,
+	/// asset class
+	#[serde(default, deserialize_with = "deserialize_from_str")]
+	pub aclass: AssetClass,
+	pub asset: String,
+	/// transaction amount, negative for outgoing funds
+	pub amount: Decimal,
+	/// transaction fee
+	pub fee: Decimal,
+	/// resulting balance
+	pub balance: Decimal,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Trade {
+	/// order responsible for execution of trade
+	pub ordertxid: TxId,
+	/// position responsible for execution of trade
+	pub postxid: Option<TxId>,
+	pub pair: String,
+	/// unix timestamp of trade
+	pub time: f64,
+	/// type of order (buy/sell)
+	#[serde(rename = "type")]
+	pub order_direction: String,
+	/// order type
+	pub ordertype: String,
+	/// average price order was executed at (quote currency)
+	pub price: Decimal,
+	/// total cost of order (quote currency)
+	pub cost: Decimal,
+	/// total fee (quote currency)
+	pub fee: Decimal,
+	/// volume (base currency)
+	pub vol: Decimal,
+	/// initial margin (quote currency)
+	pub margin: Decimal,
+	/// comma delimited list of miscellaneous info
+	pub misc: String,
+	/// trades related to the position (only with `trades` requested)
+	pub trades: Option<Vec<TxId>>,
+}
+
+impl Trade {
+	pub fn executed_at(&self) -> Option<crate::clock::Timestamp> {
+		crate::clock::from_unix_f64(self.time)
+	}
+}
+
+/// Open margin position as reported by OpenPositions.
+#[derive(Deserialize, Debug, Clone)]
+pub struct OpenPosition {
+	/// order responsible for opening the position
+	pub ordertxid: TxId,
+	pub posstatus: String,
+	pub pair: String,
+	/// unix timestamp of the opening trade
+	pub time: f64,
+	/// direction of the position (buy/sell)
+	#[serde(rename = "type")]
+	pub order_direction: String,
+	pub ordertype: String,
+	/// opening cost of the position (quote currency)
+	pub cost: Decimal,
+	/// opening fee of the position (quote currency)
+	pub fee: Decimal,
+	/// position volume (base currency)
+	pub vol: Decimal,
+	/// volume closed so far (base currency)
+	pub vol_closed: Decimal,
+	/// initial margin (quote currency)
+	pub margin: Decimal,
+	/// current value of the remaining position (only with `docalcs`)
+	pub value: Option<Decimal>,
+	/// unrealized profit or loss of the remaining position (only with `docalcs`)
+	pub net: Option<Decimal>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TradesHistoryPage {
+	pub trades: HashMap<TxId, Trade>,
+	/// total number of trades matching the query
+	#[serde(deserialize_with = "deserialize_count")]
+	pub count: u32,
+}
+
+impl TradesHistoryPage {
+	/// Whether the page fetched at `ofs` is the last one.
+	pub fn is_last_page(&self, ofs: u32) -> bool {
+		is_last_page(self.count, ofs, self.trades.len())
+	}
+}
+
+/// Margin account summary, in the asset TradeBalance was asked for.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TradeBalance {
+	/// equivalent balance (combined balance of all currencies)
+	pub eb: Decimal,
+	/// trade balance (combined balance of all equity currencies)
+	pub tb: Decimal,
+	/// margin amount of open positions
+	#[serde(default)]
+	pub m: Decimal,
+	/// unrealized net profit/loss of open positions
+	#[serde(default)]
+	pub n: Decimal,
+	/// cost basis of open positions
+	#[serde(default)]
+	pub c: Decimal,
+	/// current floating valuation of open positions
+	#[serde(default)]
+	pub v: Decimal,
+	/// equity: trade balance + unrealized net profit/loss
+	#[serde(default)]
+	pub e: Decimal,
+	/// free margin: equity - initial margin
+	#[serde(default)]
+	pub mf: Decimal,
+	/// margin level: (equity / initial margin) * 100, not set without open positions
+	pub ml: Option<Decimal>,
+}
+
+/// 30-day trade volume and the fees it earns.
+#[derive(Deserialize, Debug)]
+pub struct TradeVolume {
+	/// asset id the volume is counted in
+	pub currency: String,
+	pub volume: Decimal,
+	/// taker fee tier by pair, for the pairs asked for
+	#[serde(default)]
+	pub fees: HashMap<String, FeeTier>,
+	/// maker fee tier by pair, for the pairs asked for
+	#[serde(default)]
+	pub fees_maker: HashMap<String, FeeTier>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct FeeTier {
+	/// current fee in percent
+	pub fee: Decimal,
+	pub minfee: Decimal,
+	pub maxfee: Decimal,
+	/// fee of the next tier, not set at the lowest fee tier
+	pub nextfee: Option<Decimal>,
+	/// volume the next tier starts at, not set at the lowest fee tier
+	pub nextvolume: Option<Decimal>,
+	/// volume the current tier starts at
+	pub tiervolume: Decimal,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LedgersPage {
+	pub ledger: HashMap<String, LedgerEntry>,
+	/// total number of entries matching the query
+	#[serde(deserialize_with = "deserialize_count")]
+	pub count: u32,
+}
+
+impl LedgersPage {
+	/// Whether the page fetched at `ofs` is the last one.
+	pub fn is_last_page(&self, ofs: u32) -> bool {
+		is_last_page(self.count, ofs, self.ledger.len())
+	}
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AddOrderResponse {
+	pub descr: AddOrderDescription,
+	pub txid: Option<Vec<String>>
+}
+
+impl AddOrderResponse {
+	/// Kraken only validated the order (`validate=true`): it has a description but no txid.
+	pub fn is_validation(&self) -> bool {
+		self.txid.is_none()
+	}
+}
+
+/// Result shared by the cancel endpoints.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct CancelResult {
+	/// number of orders canceled
+	#[serde(deserialize_with = "deserialize_count")]
+	pub count: u32,
+	/// if set, order(s) is/are pending cancellation; `None` when the endpoint doesn't say
+	pub pending: Option<bool>,
+}
+
+impl fmt::Display for CancelResult {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} order(s) canceled", self.count)?;
+		if self.pending == Some(true) {
+			write!(f, " (pending)")?;
+		}
+		Ok(())
+	}
+}
+
+/// Accepts the count either as a JSON number or as a numeric string.
+fn deserialize_count<'de, D>(deserializer: D) -> Result<u32, D::Error>
+	where
+		D: Deserializer<'de>
+{
+	#[derive(Deserialize)]
+	#[serde(untagged)]
+	enum Count {
+		Number(u32),
+		Text(String),
+	}
+
+	match Count::deserialize(deserializer)? {
+		Count::Number(count) => Ok(count),
+		Count::Text(count) => count.parse().map_err(de::Error::custom),
+	}
+}
+
+/// Failure to get a decodable response from Kraken.
+#[derive(Debug)]
+pub enum KrakenError {
+	/// HTTP status or body error of a response Kraken sent
+	Http(reqwest::Error),
+	/// DNS, connect or TLS failure, the request never reached Kraken
+	Connect(reqwest::Error),
+	/// no response in time, the request may still have been processed
+	Timeout(reqwest::Error),
+	/// sending the request failed after connecting, it may have been processed
+	Request(reqwest::Error),
+	/// the body wasn't the expected JSON, e.g. an HTML page during an outage
+	Deserialize {
+		error: String,
+		/// start of the body, never all of it as private responses are sensitive
+		body_snippet: String,
+	},
+	/// order for a pair outside the client's `allowed_pairs`
+	PairNotAllowed {
+		pair: String,
+	},
+	/// market buy refused as the price moved past the cap taken at planning time
+	SlippageExceeded {
+		pair: String,
+		price: Decimal,
+		max_price: Decimal,
+	},
+	/// free quote balance, less the reserve, doesn't cover an order's cost and fee
+	InsufficientFunds {
+		needed: Decimal,
+		available: Decimal,
+	},
+	/// sell order refused by a `DirectionPolicy::BuyOnly` client
+	SellNotAllowed {
+		pair: String,
+	},
+	/// history page larger than the client's `max_body_size`
+	BodyTooLarge {
+		limit: usize,
+	},
+	/// `price * volume` overflows `Decimal`, or is too small to be told from zero
+	CostOutOfRange {
+		price: Decimal,
+		volume: Decimal,
+	},
+	/// the client's `NonceSource` couldn't hand out a nonce
+	NonceSource {
+		error: String,
+	},
+	/// Kraken refused the credentials of a private call
+	Auth {
+		failure: AuthFailure,
+		error: String,
+	},
+	/// API errors of a response that has no more specific variant
+	Api {
+		errors: Vec<String>,
+	},
+	/// `result` null with no error, which Kraken sends now and then during transient issues
+	EmptyResult,
+}
+
+/// Part of the key, secret, nonce and signing chain Kraken refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthFailure {
+	InvalidKey,
+	InvalidSignature,
+	InvalidNonce,
+	PermissionDenied,
+}
+
+impl AuthFailure {
+	fn of(error: &str) -> Option<AuthFailure> {
+		match error {
+			"EAPI:Invalid key" => Some(AuthFailure::InvalidKey),
+			"EAPI:Invalid signature" => Some(AuthFailure::InvalidSignature),
+			"EAPI:Invalid nonce" => Some(AuthFailure::InvalidNonce),
+			PERMISSION_DENIED => Some(AuthFailure::PermissionDenied),
+			_ => None,
+		}
+	}
+}
+
+impl fmt::Display for AuthFailure {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			AuthFailure::InvalidKey => write!(f, "invalid API key"),
+			AuthFailure::InvalidSignature => write!(f, "invalid signature, check the API secret"),
+			AuthFailure::InvalidNonce => write!(f, "invalid nonce"),
+			AuthFailure::PermissionDenied => write!(f, "permission denied"),
+		}
+	}
+}
+
+impl fmt::Display for KrakenError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			KrakenError::Http(e) => write!(f, "{}", e),
+			KrakenError::Connect(e) => write!(f, "could not connect to Kraken: {}", e),
+			KrakenError::Timeout(e) => write!(f, "Kraken did not respond in time: {}", e),
+			KrakenError::Request(e) => write!(f, "request to Kraken failed: {}", e),
+			KrakenError::Deserialize { error, body_snippet } => {
+				write!(f, "unexpected response ({}): {}", error, body_snippet)
+			}
+			KrakenError::PairNotAllowed { pair } => write!(f, "pair not allowed: {}", pair),
+			KrakenError::SlippageExceeded { pair, price, max_price } => {
+				write!(f, "slippage exceeded for {}: price {} is above the cap of {}", pair, price, max_price)
+			}
+			KrakenError::InsufficientFunds { needed, available } => {
+				write!(f, "insufficient funds: needs {}, {} available", needed, available)
+			}
+			KrakenError::SellNotAllowed { pair } => {
+				write!(f, "sell order for {} refused, the direction policy is buy only", pair)
+			}
+			KrakenError::BodyTooLarge { limit } => write!(f, "response larger than {} bytes", limit),
+			KrakenError::CostOutOfRange { price, volume } => {
+				write!(f, "cost of {} at {} is out of range", volume, price)
+			}
+			KrakenError::NonceSource { error } => write!(f, "no nonce: {}", error),
+			KrakenError::Auth { failure, error } => write!(f, "{} ({})", failure, error),
+			KrakenError::Api { errors } => write!(f, "{}", errors.join(", ")),
+			KrakenError::EmptyResult => write!(f, "Kraken returned neither a result nor an error"),
+		}
+	}
+}
+
+impl From<reqwest::Error> for KrakenError {
+	fn from(e: reqwest::Error) -> KrakenError {
+		if e.is_connect() {
+			KrakenError::Connect(e)
+		} else if e.is_timeout() {
+			KrakenError::Timeout(e)
+		} else if e.is_request() {
+			KrakenError::Request(e)
+		} else {
+			KrakenError::Http(e)
+		}
+	}
+}
+
+/// characters of an undecodable body kept in `KrakenError::Deserialize`
+const BODY_SNIPPET_LEN: usize = 120;
+
+fn decode<T>(body: &str) -> Result<ApiResponse<T>, KrakenError>
+	where
+		T: DeserializeOwned
+{
+	serde_json::from_str(body).map_err(|e| KrakenError::Deserialize {
+		error: e.to_string(),
+		body_snippet: body.chars().take(BODY_SNIPPET_LEN).collect(),
+	})
+}
+
+/// Reads `response` chunk by chunk into one buffer and gives up as soon as it
+/// passes `limit` bytes, so an oversized page is never held whole. The body is
+/// decoded once complete, from the buffer rather than a second `String` copy.
+async fn decode_size_limited<T>(mut response: reqwest::Response, limit: usize) -> Result<ApiResponse<T>, KrakenError>
+	where
+		T: DeserializeOwned
+{
+	let mut body = Vec::new();
+
+	while let Some(chunk) = response.chunk().await? {
+		if body.len() + chunk.len() > limit {
+			return Err(KrakenError::BodyTooLarge { limit });
+		}
+		body.extend_from_slice(&chunk);
+	}
+
+	serde_json::from_slice(&body).map_err(|e| KrakenError::Deserialize {
+		error: e.to_string(),
+		body_snippet: String::from_utf8_lossy(&body).chars().take(BODY_SNIPPET_LEN).collect(),
+	})
+}
+
+fn into_result<T>(response: ApiResponse<T>) -> Result<T, String> {
+	if !response.error.is_empty() {
+		Err(format!("{:?}", response.error))
+	} else {
+		response.result.ok_or_else(|| KrakenError::EmptyResult.to_string())
+	}
+}
+
+/// Calls the public `method`, retrying per the client's `RetryPolicy`.
+async fn public<T>(client: &KrakenClient, method: &str, params: &HashMap<String, String>) -> Result<ApiResponse<T>, KrakenError>
+	where
+		T: DeserializeOwned
+{
+	let mut retries = HashMap::new();
+
+	loop {
+		let result = unsigned(client, method, params).await;
+
+		match retry_delay(client, method, &result, &mut retries) {
+			Some(delay) => tokio::time::sleep(delay).await,
+			None => return result,
+		}
+	}
+}
+
+async fn unsigned<T>(client: &KrakenClient, method: &str, params: &HashMap<String, String>) -> Result<ApiResponse<T>, KrakenError>
+	where
+		T: DeserializeOwned
+{
+	let url = format!("{}/0/public/{}", client.base_url, method);
+
+	let response = client.http
+		.get(url)
+		.query(params)
+		.timeout(client.timeouts.public)
+		.send()
+		.await?;
+	client.record_rate_limit(response.headers());
+
+	let response = response
+		.error_for_status()?
+		.text()
+		.await?;
+
+	#[cfg(feature = "record-raw")]
+	record_raw(client, method, false, params.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect(), &response).await;
+
+	decode(&response)
+}
+
+/// API-Sign of a private request: HMAC-SHA512 of the URI path and
+/// SHA256(nonce + POST data), keyed with the decoded API secret.
+fn sign(path: &str, nonce: &str, postdata: &[u8], secret: &[u8]) -> String {
+    let mut hmac = Hmac::new(Sha512::new(), secret);
+    let mut body_hasher = Sha256::new();
+
+    body_hasher.input(nonce.as_bytes());
+    body_hasher.input(postdata);
+
+    hmac.input(path.as_bytes());
+    let mut out: [u8; 32] = [0; 32];
+    body_hasher.result(&mut out);
+    hmac.input(&out);
+
+    base64::encode(hmac.result().code())
+}
+
+/// Form body of a private call. The nonce is always in `params`, so the body is
+/// never empty: a call without params of its own (e.g. `Balance`) sends just
+/// `nonce=<nonce>`, pairs are joined with `&` and nothing trails the last one.
+/// Values are percent-encoded as UTF-8 with spaces as `+` and a `+` as `%2B`,
+/// so a comment such as `dca run #3 + €` stays ASCII on the wire.
+fn post_data(params: &HashMap<String, String>) -> Vec<u8> {
+	// string pairs always encode
+	serde_urlencoded::to_string(params).unwrap().into_bytes()
+}
+
+#[cfg(feature = "record-raw")]
+async fn record_raw(client: &KrakenClient, method: &str, private: bool, params: HashMap<&str, &str>, body: &str) {
+	let sink = match &client.raw_sink {
+		Some(sink) => sink,
+		None => return,
+	};
+
+	let response = crate::raw::RawResponse {
+		method,
+		private,
+		at: Utc::now(),
+		params,
+		body,
+	};
+
+	if let Err(e) = sink.record(&response).await {
+		log::warn!("raw {} response not recorded: {}", method, e);
+	}
+}
+
+/// private methods returning pages of account history, read through
+/// `decode_size_limited` once `KrakenClient::with_max_body_size` is set
+const PAGED_HISTORY_METHODS: [&str; 4] = ["TradesHistory", "Ledgers", "ClosedOrders", "QueryLedgers"];
+
+/// params never logged in the clear
+const REDACTED_PARAMS: [&str; 2] = ["nonce", "otp"];
+
+fn redacted(params: &HashMap<String, String>) -> HashMap<&str, &str> {
+	params
+		.iter()
+		.map(|(name, value)| {
+			let value = if REDACTED_PARAMS.contains(&name.as_str()) { "***" } else { value.as_str() };
+			(name.as_str(), value)
+		})
+		.collect()
+}
+
+/// how far past the clock nonces jump after Kraken rejected one
+const NONCE_BUMP: Duration = Duration::from_secs(1);
+
+/// Kind of transient failure, each retried per its own `RetryPolicy` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ErrorClass {
+	RateLimit,
+	Nonce,
+	ServerError,
+	/// the exchange is congested or offline
+	Unavailable,
+	/// the request never reached Kraken
+	Connection,
+	/// timed out or cut off, Kraken may have processed the request
+	Interrupted,
+}
+
+fn classify_error(error: &str) -> Option<ErrorClass> {
+	match error {
+		"EAPI:Rate limit exceeded" | "EOrder:Rate limit exceeded" | "EGeneral:Too many requests" => Some(ErrorClass::RateLimit),
+		"EAPI:Invalid nonce" => Some(ErrorClass::Nonce),
+		"EService:Unavailable" | "EService:Busy" => Some(ErrorClass::Unavailable),
+		_ => None,
+	}
+}
+
+fn classify<T>(result: &Result<ApiResponse<T>, KrakenError>) -> Option<ErrorClass> {
+	match result {
+		// retried like a server error, and like one never for an order that may have gone through
+		Ok(response) if response.error.is_empty() && response.result.is_none() => Some(ErrorClass::ServerError),
+		Ok(response) => response.error.iter().find_map(|e| classify_error(e)),
+		Err(KrakenError::Http(e)) if e.status().is_some_and(|status| status.is_server_error()) => {
+			Some(ErrorClass::ServerError)
+		}
+		Err(KrakenError::Http(e)) if e.status() == Some(reqwest::StatusCode::TOO_MANY_REQUESTS) => Some(ErrorClass::RateLimit),
+		Err(KrakenError::Connect(_)) => Some(ErrorClass::Connection),
+		Err(KrakenError::Timeout(_)) | Err(KrakenError::Request(_)) => Some(ErrorClass::Interrupted),
+		Err(_) => None,
+	}
+}
+
+/// Points a private call adds to Kraken's API rate counter: 2 for the history
+/// endpoints, 1 for every other. Orders count against the matching engine's
+/// own limit, not this counter.
+pub fn api_cost(method: &str) -> u32 {
+	match method {
+		"Ledgers" | "QueryLedgers" | "TradesHistory" | "QueryTrades" | "ClosedOrders" => 2,
+		_ => 1,
+	}
+}
+
+/// private methods placing an order
+const ORDER_METHODS: [&str; 3] = ["AddOrder", "AddOrderBatch", "EditOrder"];
+
+/// Delay before retrying `method` after `result`, or `None` to return it as is.
+/// Counts the retries per class in `retries`.
+fn retry_delay<T>(
+	client: &KrakenClient,
+	method: &str,
+	result: &Result<ApiResponse<T>, KrakenError>,
+	retries: &mut HashMap<ErrorClass, u32>,
+) -> Option<Duration> {
+	let class = classify(result)?;
+
+	// an order may have gone through despite the error, retrying could place it twice
+	if (class == ErrorClass::ServerError || class == ErrorClass::Interrupted) && ORDER_METHODS.contains(&method) {
+		return None;
+	}
+
+	let settings = client.retry_policy.class(class);
+	let count = retries.entry(class).or_insert(0);
+	if *count >= settings.max_retries {
+		return None;
+	}
+	*count += 1;
+
+	log::warn!("{} failed ({:?}), retry {} of {}", method, class, count, settings.max_retries);
+
+	// the wait the response asked for beats the static backoff
+	let asked = client.rate_limit().and_then(|info| info.wait()).filter(|_| class == ErrorClass::RateLimit);
+
+	Some(asked.unwrap_or_else(|| settings.delay.saturating_mul(2u32.saturating_pow(*count - 1))))
+}
+
+/// Calls the private `method`, retrying per the client's `RetryPolicy`.
+async fn private<T>(client: &KrakenClient, method: &str, params: &mut HashMap<String, String>) -> Result<ApiResponse<T>, KrakenError>
+	where
+		T: DeserializeOwned
+{
+	let mut retries = HashMap::new();
+
+	loop {
+		let result = signed(client, method, params).await;
+
+		let delay = match retry_delay(client, method, &result, &mut retries) {
+			Some(delay) => delay,
+			None => return result,
+		};
+
+		// retried with nonces bumped past the clock
+		if classify(&result) == Some(ErrorClass::Nonce) {
+			client.nonces.bump(NONCE_BUMP).await.map_err(|error| KrakenError::NonceSource { error })?;
+		}
+
+		tokio::time::sleep(delay).await;
+	}
+}
+
+async fn signed<T>(client: &KrakenClient, method: &str, params: &mut HashMap<String, String>) -> Result<ApiResponse<T>, KrakenError> 
+	where
+		T: DeserializeOwned
+{
+	let account = &client.account;
+	let path = format!("/0/private/{}", method);
+    let url = format!("{}{}", client.base_url, path);
+    let nonce = client
+		.nonces
+		.next()
+		.await
+		.map_err(|error| KrakenError::NonceSource { error })?
+		.to_string();
+
+	params.insert("nonce".to_owned(), nonce.clone());
+
+	if let Some(otp) = &account.otp {
+		params.insert("otp".to_owned(), otp.clone());
+	}
+
+	// the key, secret and signature only ever go into headers, never into the log
+	if client.debug_log_requests {
+		log::debug!("POST {} {:?}", path, redacted(params));
+	}
+
+	// the very bytes signed are sent, so encoded values (an otp, comma separated
+	// oflags, spaces or non-ASCII text) can't make the signature and the body disagree
+	let body = post_data(params);
+
+    let secret = base64::decode(&account.secret).unwrap();
+    let sign = sign(&path, &nonce, &body, &secret);
+
+	let http_response = client.http
+		.post(url)
+		.header("API-Key", &account.key)
+		.header("API-Sign", sign)
+		.header(reqwest::header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+		.timeout(client.timeouts.private(method))
+		.body(body)
+		.send()
+		.await?;
+	client.record_rate_limit(http_response.headers());
+
+	let http_response = http_response.error_for_status()?;
+	
+	if let Some(limit) = client.max_body_size.filter(|_| PAGED_HISTORY_METHODS.contains(&method)) {
+		return decode_size_limited(http_response, limit).await;
+	}
+
+	let response = http_response
+        .text()
+        .await?;
+
+	#[cfg(feature = "record-raw")]
+	record_raw(client, method, true, redacted(params), &response).await;
+	
+	decode(&response)
+}
+
+/// Account balance, served from the client's cache while younger than its TTL.
+pub async fn balance(client: &KrakenClient) -> Result<HashMap<String, String>, String> {
+	if let Some((fetched, balance)) = client.balance.lock().unwrap().as_ref() {
+		if fetched.elapsed() < client.balance_ttl {
+			return Ok(balance.clone());
+		}
+	}
+
+	client.refresh_balance().await
+}
+
+/// Balance of one asset as reported by BalanceEx.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ExtendedBalance {
+	pub balance: Decimal,
+	/// credit available
+	#[serde(default)]
+	pub credit: Decimal,
+	/// amount of credit used
+	#[serde(default)]
+	pub credit_used: Decimal,
+	/// amount held by open orders
+	#[serde(default)]
+	pub hold_trade: Decimal,
+}
+
+impl ExtendedBalance {
+	/// balance not held by open orders
+	pub fn available(&self) -> Decimal {
+		self.balance - self.hold_trade
+	}
+}
+
+pub async fn balance_ex(client: &KrakenClient) -> Result<HashMap<String, ExtendedBalance>, String> {
+	let mut params = HashMap::new();
+
+	private(client, "BalanceEx", &mut params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)
+}
+
+/// Balance of every asset as a decimal. Assets with a zero balance, which Kraken
+/// keeps listing once an account held them, are left out unless `include_zero`.
+pub async fn balance_decimal(client: &KrakenClient, include_zero: bool) -> Result<HashMap<String, Decimal>, String> {
+	let mut balances = HashMap::new();
+
+	for (asset, amount) in balance(client).await? {
+		let amount = Decimal::from_str(&amount).map_err(|e| format!("{:?}", e))?;
+		if include_zero || !amount.is_zero() {
+			balances.insert(asset, amount);
+		}
+	}
+
+	Ok(balances)
+}
+
+/// decimals an amount is shown with when its asset's `display_decimals` is unknown
+const DEFAULT_DISPLAY_DECIMALS: u32 = 8;
+
+/// `value` of `asset` (asset id or altname) rounded to the asset's `display_decimals`,
+/// e.g. `1.2346` for `1.23456789012` of a 4 decimal asset.
+pub async fn format_amount(client: &KrakenClient, asset: &str, value: Decimal) -> String {
+	let decimals = match client.metadata().await {
+		Ok(metadata) => metadata
+			.asset_id(asset)
+			.and_then(|id| metadata.asset_info(&id).map(|info| info.display_decimals))
+			.unwrap_or(DEFAULT_DISPLAY_DECIMALS),
+		Err(_) => DEFAULT_DISPLAY_DECIMALS,
+	};
+
+	format!("{:.*}", decimals as usize, value.round_dp(decimals))
+}
+
+/// Values `balances` in `quote` (an asset id such as `ZUSD`) at the last trade price
+/// of each asset's pair against it. Assets without such a pair are left out.
+pub async fn valuation(client: &KrakenClient, balances: &HashMap<String, Decimal>, quote: &str) -> Result<Decimal, String> {
+	let metadata = client.metadata().await?;
+	let mut total = Decimal::ZERO;
+
+	for (asset, amount) in balances {
+		if amount.is_zero() {
+			continue;
+		}
+
+		if asset == quote {
+			total += *amount;
+			continue;
+		}
+
+		let pair = metadata
+			.pairs
+			.iter()
+			.find(|(_, pair)| pair.base == *asset && pair.quote == quote)
+			.map(|(key, _)| key.clone());
+
+		match pair {
+			Some(pair) => total += *amount * ticker(client, &pair).await?.last_price(),
+			None => log::warn!("no {} pair to value {}, leaving it out", quote, asset),
+		}
+	}
+
+	Ok(total)
+}
+
+pub async fn portfolio_value(client: &KrakenClient, quote: &str) -> Result<Decimal, String> {
+	let balances = balance_decimal(client, false).await?;
+	valuation(client, &balances, quote).await
+}
+
+/// Refuses flags that don't apply to `order`'s direction and type, which Kraken
+/// would otherwise reject or silently ignore.
+fn check_oflags(oflags: &[&str], order: &NewOrder) -> Result<(), String> {
+	let leveraged = order.leverage.as_deref().is_some_and(|leverage| leverage != "none");
+
+	for flag in oflags {
+		let applies = match *flag {
+			"post" => order.order_type == OrderType::Limit,
+			"nompp" => order.order_type == OrderType::Market,
+			"viqc" => order.order_direction == OrderDirection::Buy && order.order_type == OrderType::Market && !leveraged,
+			"fcib" => !oflags.contains(&"fciq"),
+			"fciq" => !oflags.contains(&"fcib"),
+			_ => return Err(format!("unknown order flag: {}", flag)),
+		};
+
+		if !applies {
+			return Err(format!(
+				"order flag {} doesn't apply to a {} {} order with flags {}",
+				flag,
+				order.order_direction,
+				order.order_type,
+				oflags.join(",")
+			));
+		}
+	}
+
+	Ok(())
+}
+
+pub async fn add_order(client: &KrakenClient, order: NewOrder) -> Result<AddOrderResponse, String> {
+	let mut params = order_params(client, order).await?;
+
+	let placed = private(client, "AddOrder", &mut params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result);
+
+	client.invalidate_balance();
+
+	placed
+}
+
+/// AddOrder params of `order`, once it passed the client's checks.
+async fn order_params(client: &KrakenClient, order: NewOrder) -> Result<HashMap<String, String>, String> {
+	client.check_direction(&order)?;
+	client.check_pair_allowed(&order.pair).await?;
+	client.check_order_leverage(&order).await?;
+
+	let mut params = HashMap::new();
+
+	params.insert("pair".to_owned(), order.pair.to_string());
+	params.insert("type".to_owned(), order.order_direction.to_string());
+	params.insert("ordertype".to_owned(), order.order_type.to_string());
+
+	if let Some(price) = order.price {
+		params.insert("price".to_owned(), price.to_string());
+	}
+
+	if let Some(price) = order.price2 {
+        params.insert("price2".to_owned(), price.to_string());
+    }
+
+    if let Some(trigger) = order.trigger {
+        if !order.order_type.is_triggered() {
+            return Err(format!("trigger only applies to stop and take profit orders, not {}", order.order_type));
+        }
+        params.insert("trigger".to_owned(), trigger.to_string());
+    }
+
+	if let Some(volume) = &order.volume {
+        params.insert("volume".to_owned(), volume.clone());
+    }	
+
+    if let Some(leverage) = &order.leverage {
+        params.insert("leverage".to_owned(), leverage.clone());
+    }
+
+    if order.reduce_only {
+        if order.leverage.is_none() {
+            return Err(String::from("reduce only applies to leveraged orders"));
+        }
+        params.insert("reduce_only".to_owned(), String::from("true"));
+    }
+
+    let mut oflags: Vec<&str> = order.oflags.as_deref().map_or(Vec::new(), |oflags| oflags.split(',').filter(|flag| !flag.is_empty()).collect());
+
+    if let Some(fee_currency) = order.fee_currency {
+        if oflags.iter().any(|flag| *flag == "viqc" || *flag == "fcib" || *flag == "fciq") {
+            return Err(format!("fee_currency conflicts with order flags {}", oflags.join(",")));
+        }
+        oflags.push(fee_currency.flag());
+    }
+
+    if !order.market_price_protection {
+        if order.order_type != OrderType::Market {
+            return Err(format!("market price protection only applies to market orders, not {}", order.order_type));
+        }
+        if !oflags.contains(&"nompp") {
+            oflags.push("nompp");
+        }
+    }
+
+    check_oflags(&oflags, &order)?;
+
+    if !oflags.is_empty() {
+        params.insert("oflags".to_owned(), oflags.join(","));
+    }
+
+    if let Some(userref) = order.userref {
+        params.insert("userref".to_owned(), userref);
+    }
+
+    if let Some(starttm) = order.starttm {
+        params.insert("starttm".to_owned(), format!("{}", starttm));
+    }
+
+    if let Some(expiretm) = order.expiretm {
+        params.insert("expiretm".to_owned(), expiretm);
+    }
+
+    #[cfg(feature = "timestamps")]
+    if let Some(deadline) = order.deadline {
+        // compared to Kraken's clock, ours may drift
+        let now = server_time(client).await?.unixtime;
+        if deadline.timestamp() <= now {
+            return Err(format!("order deadline {} is not in the future", deadline));
+        }
+
+        params.insert("deadline".to_owned(), deadline.to_rfc3339_opts(SecondsFormat::Millis, true));
+    }
+
+    if order.validate.is_some() {
+        params.insert("validate".to_owned(), String::from("1"));
+    }
+
+	Ok(params)
+}
+
+/// most orders AddOrderBatch takes in one call, and the fewest
+pub const MAX_BATCH_ORDERS: usize = 15;
+const MIN_BATCH_ORDERS: usize = 2;
+
+/// order params AddOrderBatch takes for the batch as a whole, not per order
+const BATCH_PARAMS: [&str; 3] = ["pair", "deadline", "validate"];
+
+#[derive(Deserialize, Debug)]
+struct AddOrderBatchResponse {
+	orders: Vec<BatchOrder>,
+}
+
+/// One order of an AddOrderBatch response, with a description when it was
+/// accepted and an error when it wasn't.
+#[derive(Deserialize, Debug)]
+struct BatchOrder {
+	descr: Option<AddOrderDescription>,
+	txid: Option<String>,
+	error: Option<String>,
+}
+
+/// Places 2 to `MAX_BATCH_ORDERS` orders for one pair in a single AddOrderBatch
+/// call, with one result per order in the same order. The orders must agree on
+/// `pair`, `deadline` and `validate`, which Kraken takes for the whole batch.
+pub async fn add_order_batch(client: &KrakenClient, orders: Vec<NewOrder>) -> Result<Vec<Result<AddOrderResponse, String>>, String> {
+	if orders.len() < MIN_BATCH_ORDERS || orders.len() > MAX_BATCH_ORDERS {
+		return Err(format!("a batch takes {} to {} orders, got {}", MIN_BATCH_ORDERS, MAX_BATCH_ORDERS, orders.len()));
+	}
+
+	let mut params = HashMap::new();
+	for (i, order) in orders.into_iter().enumerate() {
+		for (name, value) in order_params(client, order).await? {
+			if !BATCH_PARAMS.contains(&name.as_str()) {
+				params.insert(format!("orders[{}][{}]", i, name), value);
+				continue;
+			}
+
+			match params.get(&name) {
+				Some(batch_value) if *batch_value != value => {
+					return Err(format!("orders of a batch must share their {}", name));
+				}
+				Some(_) => {}
+				None => {
+					params.insert(name, value);
+				}
+			}
+		}
+	}
+
+	let placed: Result<AddOrderBatchResponse, String> = private(client, "AddOrderBatch", &mut params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result);
+
+	client.invalidate_balance();
+
+	Ok(placed?
+		.orders
+		.into_iter()
+		.map(|order| match (order.error, order.descr) {
+			(None, Some(descr)) => Ok(AddOrderResponse {
+				descr,
+				txid: order.txid.map(|txid| vec![txid]),
+			}),
+			(Some(error), _) => Err(error),
+			(None, None) => Err(String::from("order neither placed nor refused")),
+		})
+		.collect())
+}
+
+/// Fetches the pair table; `KrakenClient::metadata` serves a cached copy.
+pub async fn asset_pairs(client: &KrakenClient) -> Result<HashMap<String, AssetPair>, String> {
+	public(client, "AssetPairs", &HashMap::new())
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)
+}
+
+/// Fetches the asset table; `KrakenClient::metadata` serves a cached copy.
+pub async fn assets(client: &KrakenClient) -> Result<HashMap<String, AssetInfo>, String> {
+	public(client, "Assets", &HashMap::new())
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)
+}
+
+/// Way of depositing an asset, as listed by DepositMethods.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DepositMethod {
+	pub method: String,
+	/// most that can be deposited, `None` without a limit
+	#[serde(default, deserialize_with = "deserialize_limit")]
+	pub limit: Option<Decimal>,
+	pub fee: Option<Decimal>,
+	/// fee for setting up a new deposit address
+	#[serde(rename = "address-setup-fee")]
+	pub address_setup_fee: Option<Decimal>,
+	/// whether new addresses can be generated
+	#[serde(rename = "gen-address", default)]
+	pub gen_address: bool,
+}
+
+/// Kraken sends `false` for no limit, the amount as a string otherwise.
+fn deserialize_limit<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+	where
+		D: Deserializer<'de>
+{
+	#[derive(Deserialize)]
+	#[serde(untagged)]
+	enum Limit {
+		Unlimited(bool),
+		Amount(Decimal),
+	}
+
+	Ok(match Option::<Limit>::deserialize(deserializer)? {
+		Some(Limit::Amount(amount)) => Some(amount),
+		_ => None,
+	})
+}
+
+/// Deposit methods of `asset`, in asset class `aclass` (`currency` when not given).
+pub async fn deposit_methods(client: &KrakenClient, asset: &str, aclass: Option<AssetClass>) -> Result<Vec<DepositMethod>, String> {
+	let mut params = HashMap::new();
+	params.insert("asset".to_owned(), asset.to_owned());
+	params.insert("aclass".to_owned(), aclass.unwrap_or_default().to_string());
+
+	private(client, "DepositMethods", &mut params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)
+}
+
+/// Currency `pair`'s fee schedule is counted in, the pair's quote when Kraken
+/// doesn't name one, from the cached metadata.
+pub async fn fee_currency_for(client: &KrakenClient, pair: &str) -> Result<String, String> {
+	let metadata = client.metadata().await?;
+	let info = metadata.pair_info(pair).ok_or_else(|| format!("unknown asset pair: {}", pair))?;
+
+	Ok(info.fee_volume_currency.clone().unwrap_or_else(|| info.quote.clone()))
+}
+
+/// Maps a user supplied pair (`SOLUSD`, `SOL/USD`, `SOLZUSD`, ...) to the canonical
+/// AssetPairs key by matching the pair key, altname, wsname or base+quote.
+/// A pair Kraken renamed (`BTCUSD` for `XBTUSD`) still resolves, with a warning
+/// and the rename kept for `pair_renames`.
+pub async fn resolve_pair(client: &KrakenClient, input: &str) -> Result<String, String> {
+	let wanted = input.trim().to_uppercase();
+	let compact = wanted.replace('/', "");
+	let metadata = client.metadata().await?;
+
+	let found = metadata
+		.pairs
+		.iter()
+		.find(|(key, pair)| {
+			key.as_str() == compact
+				|| pair.altname == compact
+				|| pair.wsname.as_deref() == Some(wanted.as_str())
+				|| format!("{}{}", pair.base, pair.quote) == compact
+		})
+		.map(|(key, _)| key.clone());
+	if let Some(key) = found {
+		return Ok(key);
+	}
+
+	let key = renamed_pair(&metadata, &wanted).ok_or_else(|| format!("unknown asset pair: {}", input))?;
+	let altname = metadata.pair_info(&key).map_or(key.clone(), |info| info.altname.clone());
+	log::warn!("pair {} no longer resolves, using {} under its current name; update the config", input, altname);
+	client.renamed_pairs.lock().unwrap().insert(input.to_owned(), altname);
+
+	Ok(key)
+}
+
+/// Names Kraken used for the same asset over time.
+const ASSET_ALIASES: [(&str, &str); 2] = [("XBT", "BTC"), ("XDG", "DOGE")];
+
+fn asset_alias(code: &str) -> Option<&'static str> {
+	ASSET_ALIASES.iter().find_map(|&(a, b)| {
+		if code == a {
+			Some(b)
+		} else if code == b {
+			Some(a)
+		} else {
+			None
+		}
+	})
+}
+
+/// Key of the pair `wanted` (uppercase, `/` optional) now goes by: split into
+/// base and quote, each tried as an asset id, altname or alias, and matched on
+/// the assets' current ids.
+fn renamed_pair(metadata: &Metadata, wanted: &str) -> Option<String> {
+	let splits: Vec<(&str, &str)> = match wanted.split_once('/') {
+		Some(split) => vec![split],
+		None if wanted.is_ascii() => (1..wanted.len()).map(|i| wanted.split_at(i)).collect(),
+		None => Vec::new(),
+	};
+	let ids = |code: &str| -> Vec<String> {
+		std::iter::once(code).chain(asset_alias(code)).filter_map(|code| metadata.asset_id(code)).collect()
+	};
+
+	for (base, quote) in splits {
+		for base_id in ids(base) {
+			for quote_id in ids(quote) {
+				let found = metadata
+					.pairs
+					.iter()
+					.find(|(key, pair)| !key.ends_with(".d") && pair.base == base_id && pair.quote == quote_id);
+				if let Some((key, _)) = found {
+					return Some(key.clone());
+				}
+			}
+		}
+	}
+
+	None
+}
+
+/// Websocket name of `pair` (given in any form `resolve_pair` accepts), e.g.
+/// `SOL/USD`. Pairs without a wsname get `<base altname>/<quote altname>`.
+pub async fn to_wsname(client: &KrakenClient, pair: &str) -> Result<String, String> {
+	let key = resolve_pair(client, pair).await?;
+	let metadata = client.metadata().await?;
+	let info = metadata.pair_info(&key).ok_or_else(|| format!("unknown asset pair: {}", pair))?;
+
+	if let Some(wsname) = &info.wsname {
+		return Ok(wsname.clone());
+	}
+
+	let altname = |asset: &str| metadata.asset_info(asset).map_or(asset.to_owned(), |info| info.altname.clone());
+	Ok(format!("{}/{}", altname(&info.base), altname(&info.quote)))
+}
+
+/// Sorted keys of the pairs trading against `quote` (an asset id such as `ZUSD`
+/// or its altname `USD`), leaving out dark pool pairs and pairs that are not
+/// online. Cached on the client.
+pub async fn tradable_pairs(client: &KrakenClient, quote: &str) -> Result<Vec<String>, String> {
+	let wanted = quote.trim().to_uppercase();
+
+	if let Some(pairs) = client.quote_pairs.lock().unwrap().get(&wanted) {
+		return Ok(pairs.clone());
+	}
+
+	let metadata = client.metadata().await?;
+	let mut pairs: Vec<String> = metadata
+		.pairs
+		.iter()
+		.filter(|(key, pair)| {
+			let quote_altname = metadata.asset_info(&pair.quote).map(|asset| asset.altname.as_str());
+
+			!key.ends_with(".d")
+				&& pair.status.as_deref().is_none_or(|status| status == "online")
+				&& (pair.quote == wanted || quote_altname == Some(wanted.as_str()))
+		})
+		.map(|(key, _)| key.clone())
+		.collect();
+	pairs.sort();
+
+	client.quote_pairs.lock().unwrap().insert(wanted, pairs.clone());
+
+	Ok(pairs)
+}
+
+/// Tradable pairs quoted in US dollars, see `tradable_pairs`.
+pub async fn usd_pairs(client: &KrakenClient) -> Result<Vec<String>, String> {
+	tradable_pairs(client, "ZUSD").await
+}
+
+/// Kraken answers `Unknown order` when the order already filled, expired or was
+/// canceled; either way there is nothing left to cancel.
+fn is_unknown_order(errors: &[String]) -> bool {
+	errors.iter().any(|e| e.ends_with(":Unknown order"))
+}
+
+pub async fn cancel_order(client: &KrakenClient, txid: &str) -> Result<CancelResult, String> {
+	let mut params = HashMap::new();
+	params.insert("txid".to_owned(), txid.to_owned());
+
+	private(client, "CancelOrder", &mut params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(
+			|response| if is_unknown_order(&response.error) {
+				Ok(CancelResult { count: 0, pending: None })
+			} else if !response.error.is_empty() {
+				Err(format!("{:?}", response.error))
+			} else {
+				response.result.ok_or_else(|| KrakenError::EmptyResult.to_string())
+			}
+		)
+}
+
+/// Cancels every open order placed with `userref`, e.g. all orders of one
+/// scheduled run. `count` is the number of orders canceled.
+pub async fn cancel_by_userref(client: &KrakenClient, userref: i32) -> Result<CancelResult, String> {
+	// CancelOrder takes a user reference in place of a txid
+	cancel_order(client, &userref.to_string()).await
+}
+
+/// Cancels every open order `pred` holds for, one by one, and returns how many
+/// were canceled; `pred` sees each order with its `txid` set. An order already
+/// gone by its cancel counts as canceled; a cancel that fails is logged and the
+/// rest still go through.
+pub async fn cancel_where(client: &KrakenClient, pred: impl Fn(&OpenOrder) -> bool) -> Result<u32, String> {
+	let orders = open_orders_filtered(client, &OpenOrderFilter::default()).await?;
+	let mut canceled = 0;
+
+	for order in orders.iter().filter(|order| pred(order)) {
+		match cancel_order(client, &order.txid).await {
+			Ok(_) => canceled += 1,
+			Err(e) => log::warn!("order {} not canceled: {}", order.txid, e),
+		}
+	}
+
+	Ok(canceled)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EditOrderResponse {
+	/// "ok", or "err" with `error_message` set
+	pub status: String,
+	/// the edited order, which replaces `originaltxid`
+	pub txid: Option<TxId>,
+	pub originaltxid: TxId,
+	pub descr: Option<AddOrderDescription>,
+	pub error_message: Option<String>,
+}
+
+/// Moves the open limit order `txid` of `pair` to `price` in one call, and to
+/// `volume` when set; Kraken cancels it and places the edited order under a new
+/// txid, tagged with `userref`.
+pub async fn edit_order(
+	client: &KrakenClient,
+	txid: &str,
+	pair: &str,
+	price: Decimal,
+	volume: Option<Decimal>,
+	userref: Option<i32>,
+) -> Result<EditOrderResponse, String> {
+	let mut params = HashMap::new();
+	params.insert("txid".to_owned(), txid.to_owned());
+	params.insert("pair".to_owned(), pair.to_owned());
+	params.insert("price".to_owned(), price.to_string());
+
+	if let Some(volume) = volume {
+		params.insert("volume".to_owned(), volume.to_string());
+	}
+
+	if let Some(userref) = userref {
+		params.insert("userref".to_owned(), userref.to_string());
+	}
+
+	let edited: EditOrderResponse = private(client, "EditOrder", &mut params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)?;
+
+	client.invalidate_balance();
+
+	match (&edited.txid, &edited.error_message) {
+		(Some(_), None) => Ok(edited),
+		(_, Some(error)) => Err(format!("order {} not edited: {}", txid, error)),
+		(None, None) => Err(format!("order {} not edited: {}", txid, edited.status)),
+	}
+}
+
+pub async fn server_time(client: &KrakenClient) -> Result<ServerTime, String> {
+	public(client, "Time", &HashMap::new())
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)
+}
+
+/// Offset between the local clock and Kraken's. A large one breaks nonces,
+/// which Kraken reports as `EAPI:Invalid nonce`.
+pub async fn check_clock_skew(client: &KrakenClient) -> Result<Duration, String> {
+	let before = ::std::time::UNIX_EPOCH.elapsed().unwrap().as_secs_f64();
+	let server = server_time(client).await?.unixtime as f64;
+	let after = ::std::time::UNIX_EPOCH.elapsed().unwrap().as_secs_f64();
+
+	// server time is whole seconds, compare it to the middle of the request
+	let local = (before + after) / 2.0;
+	Ok(Duration::from_secs_f64((local - server).abs()))
+}
+
+/// API key permission, as named in Kraken's key settings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Permission {
+	QueryFunds,
+	QueryOpenOrders,
+	CreateOrders,
+}
+
+impl fmt::Display for Permission {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Permission::QueryFunds => write!(f, "Query Funds"),
+			Permission::QueryOpenOrders => write!(f, "Query Open Orders & Trades"),
+			Permission::CreateOrders => write!(f, "Create & Modify Orders"),
+		}
+	}
+}
+
+const PERMISSION_DENIED: &str = "EGeneral:Permission denied";
+
+/// Whether the client's key has `permission`, from the cheapest call needing
+/// it: Balance, OpenOrders, or a validate-only market buy of `pair`'s minimum.
+/// Only `EGeneral:Permission denied` counts as missing, other API errors
+/// (e.g. insufficient funds) mean the call was allowed.
+pub async fn has_permission(client: &KrakenClient, permission: Permission, pair: &str) -> Result<bool, String> {
+	let mut params = HashMap::new();
+
+	let method = match permission {
+		Permission::QueryFunds => "Balance",
+		Permission::QueryOpenOrders => "OpenOrders",
+		Permission::CreateOrders => {
+			let metadata = client.metadata().await?;
+			let info = metadata.pair_info(pair).ok_or_else(|| format!("unknown asset pair: {}", pair))?;
+
+			params.insert("pair".to_owned(), pair.to_owned());
+			params.insert("type".to_owned(), OrderDirection::Buy.to_string());
+			params.insert("ordertype".to_owned(), OrderType::Market.to_string());
+			params.insert("volume".to_owned(), info.ordermin.unwrap_or(Decimal::ONE).to_string());
+			params.insert("validate".to_owned(), "true".to_owned());
+			"AddOrder"
+		}
+	};
+
+	let response: ApiResponse<serde_json::Value> = private(client, method, &mut params).await.map_err(|e| format!("{:?}", e))?;
+
+	Ok(!response.error.iter().any(|error| error == PERMISSION_DENIED))
+}
+
+/// Confirms the client's key, secret, nonce and signing work with a single
+/// read-only `Balance` call, for setup and smoke tests against a staging key.
+/// Places and cancels nothing, and leaves the balance cache as it is.
+pub async fn verify_credentials(client: &KrakenClient) -> Result<(), KrakenError> {
+	let mut params = HashMap::new();
+	let response: ApiResponse<serde_json::Value> = private(client, "Balance", &mut params).await?;
+
+	if let Some((failure, error)) = response.error.iter().find_map(|error| AuthFailure::of(error).map(|failure| (failure, error))) {
+		return Err(KrakenError::Auth {
+			failure,
+			error: error.clone(),
+		});
+	}
+
+	if !response.error.is_empty() {
+		return Err(KrakenError::Api { errors: response.error });
+	}
+
+	Ok(())
+}
+
+pub async fn system_status(client: &KrakenClient) -> Result<SystemStatus, String> {
+	public(client, "SystemStatus", &HashMap::new())
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)
+}
+
+pub async fn ticker(client: &KrakenClient, pair: &str) -> Result<TickerInfo, String> {
+	let mut params = HashMap::new();
+	params.insert("pair".to_owned(), pair.to_owned());
+
+	let tickers: HashMap<String, TickerInfo> = public(client, "Ticker", &params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)?;
+
+	tickers
+		.into_iter()
+		.next()
+		.map(|(_, ticker)| ticker)
+		.ok_or_else(|| format!("no ticker for {}", pair))
+}
+
+/// Last trade price of `pair`, given in any form `resolve_pair` accepts, from
+/// a ticker served from the client's cache while younger than its TTL.
+pub async fn last_price(client: &KrakenClient, pair: &str) -> Result<Decimal, String> {
+	let key = resolve_pair(client, pair).await?;
+
+	if let Some((fetched, ticker)) = client.tickers.lock().unwrap().get(&key) {
+		if fetched.elapsed() < client.ticker_ttl {
+			return Ok(ticker.last_price());
+		}
+	}
+
+	let fetched = ticker(client, &key).await?;
+	let price = fetched.last_price();
+	client.tickers.lock().unwrap().insert(key, (Instant::now(), fetched));
+	Ok(price)
+}
+
+/// Units of `to` one unit of `from` buys at the last trade price, both given as
+/// asset id or altname. Uses the direct pair when there is one, else the inverse.
+pub async fn cross_rate(client: &KrakenClient, from: &str, to: &str) -> Result<Decimal, String> {
+	let metadata = client.metadata().await?;
+	let from_id = metadata.asset_id(from).ok_or_else(|| format!("unknown currency: {}", from))?;
+	let to_id = metadata.asset_id(to).ok_or_else(|| format!("unknown currency: {}", to))?;
+
+	if from_id == to_id {
+		return Ok(Decimal::ONE);
+	}
+
+	let find = |base: &str, quote: &str| {
+		metadata
+			.pairs
+			.iter()
+			.find(|(key, pair)| !key.ends_with(".d") && pair.base == base && pair.quote == quote)
+			.map(|(key, _)| key.clone())
+	};
+
+	if let Some(pair) = find(&from_id, &to_id) {
+		return Ok(ticker(client, &pair).await?.last_price());
+	}
+
+	match find(&to_id, &from_id) {
+		Some(pair) => {
+			let price = ticker(client, &pair).await?.last_price();
+			if price.is_zero() {
+				return Err(format!("no usable cross rate from {} to {}", from, to));
+			}
+			Ok(Decimal::ONE / price)
+		}
+		None => Err(format!("no cross rate from {} to {}: no pair between them", from, to)),
+	}
+}
+
+/// One OHLC candle.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Candle {
+	/// unix timestamp of the candle's start
+	pub time: i64,
+	pub open: Decimal,
+	pub high: Decimal,
+	pub low: Decimal,
+	pub close: Decimal,
+	/// volume weighted average price
+	pub vwap: Decimal,
+	pub volume: Decimal,
+	/// number of trades
+	pub count: u64,
+}
+
+/// Candles of `pair` with `interval` minutes each (1, 5, 15, 30, 60, 240,
+/// 1440, 10080 or 21600), oldest first. Kraken returns at most 720.
+pub async fn ohlc(client: &KrakenClient, pair: &str, interval: u32) -> Result<Vec<Candle>, String> {
+	let mut params = HashMap::new();
+	params.insert("pair".to_owned(), pair.to_owned());
+	params.insert("interval".to_owned(), interval.to_string());
+
+	// the candles sit under the pair key, next to a "last" cursor
+	let response: HashMap<String, serde_json::Value> = public(client, "OHLC", &params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)?;
+
+	response
+		.into_iter()
+		.find(|(key, _)| key != "last")
+		.ok_or_else(|| format!("no candles for {}", pair))
+		.and_then(|(_, candles)| serde_json::from_value(candles).map_err(|e| format!("{:?}", e)))
+}
+
+/// Price level of the order book.
+#[derive(Deserialize, Debug, Clone)]
+pub struct BookEntry {
+	pub price: Decimal,
+	pub volume: Decimal,
+	/// unix timestamp of the level's last update
+	pub timestamp: i64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct OrderBook {
+	/// lowest first
+	pub asks: Vec<BookEntry>,
+	/// highest first
+	pub bids: Vec<BookEntry>,
+}
+
+/// Top `count` levels (at most 500) on each side of `pair`'s book.
+pub async fn order_book(client: &KrakenClient, pair: &str, count: u32) -> Result<OrderBook, String> {
+	let mut params = HashMap::new();
+	params.insert("pair".to_owned(), pair.to_owned());
+	params.insert("count".to_owned(), count.to_string());
+
+	let books: HashMap<String, OrderBook> = public(client, "Depth", &params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)?;
+
+	books
+		.into_iter()
+		.next()
+		.map(|(_, book)| book)
+		.ok_or_else(|| format!("no order book for {}", pair))
+}
+
+/// Trade of the public trade history.
+#[derive(Deserialize, Debug, Clone)]
+pub struct PublicTrade {
+	pub price: Decimal,
+	pub volume: Decimal,
+	/// unix timestamp of the trade
+	pub time: f64,
+	/// "b" when a buyer took liquidity, "s" when a seller did
+	pub side: String,
+	/// "m" for market, "l" for limit
+	pub order_type: String,
+	pub misc: String,
+	/// not sent by older API versions
+	#[serde(default)]
+	pub trade_id: Option<u64>,
+}
+
+/// Most recent public trades of `pair` (up to 1000), oldest first.
+pub async fn recent_trades(client: &KrakenClient, pair: &str) -> Result<Vec<PublicTrade>, String> {
+	let mut params = HashMap::new();
+	params.insert("pair".to_owned(), pair.to_owned());
+
+	// the trades sit under the pair key, next to a "last" cursor
+	let response: HashMap<String, serde_json::Value> = public(client, "Trades", &params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)?;
+
+	response
+		.into_iter()
+		.find(|(key, _)| key != "last")
+		.ok_or_else(|| format!("no trades for {}", pair))
+		.and_then(|(_, trades)| serde_json::from_value(trades).map_err(|e| format!("{:?}", e)))
+}
+
+/// Rough time until a buy limit of `volume` at `price` on `pair` fills.
+///
+/// The order is assumed to wait behind every bid at or above `price`, and the
+/// queue to drain at the rate sellers took liquidity over the recent public
+/// trades. It ignores that the market has to come down to a price below the
+/// best bid, and that bids ahead may be canceled or added. A price at or above
+/// the best ask fills right away. `None` when the book or the trades give
+/// nothing to go on.
+pub async fn time_to_fill_estimate(client: &KrakenClient, pair: &str, price: Decimal, volume: Decimal) -> Result<Option<Duration>, String> {
+	let book = order_book(client, pair, MAX_BOOK_DEPTH).await?;
+
+	match book.asks.first() {
+		Some(best_ask) if price >= best_ask.price => return Ok(Some(Duration::ZERO)),
+		Some(_) => {}
+		None => return Ok(None),
+	}
+
+	let ahead: Decimal = book.bids.iter().filter(|bid| bid.price >= price).map(|bid| bid.volume).sum();
+
+	let trades = recent_trades(client, pair).await?;
+	let sold: Decimal = trades.iter().filter(|trade| trade.side == "s").map(|trade| trade.volume).sum();
+	let span = match (trades.first(), trades.last()) {
+		(Some(first), Some(last)) => last.time - first.time,
+		_ => return Ok(None),
+	};
+
+	if sold.is_zero() || span <= 0.0 {
+		return Ok(None);
+	}
+
+	// volume sold per second
+	let rate = match sold.to_f64() {
+		Some(sold) => sold / span,
+		None => return Ok(None),
+	};
+
+	Ok((ahead + volume).to_f64().map(|queue| Duration::from_secs_f64(queue / rate)))
+}
+
+/// Where a volume would fill walking the book.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct BookFill {
+	/// worst level the volume reaches, the limit price to fill all of it
+	pub price: Decimal,
+	/// volume weighted price the volume would fill at on average
+	pub average_price: Decimal,
+}
+
+/// most levels Depth returns per side
+const MAX_BOOK_DEPTH: u32 = 500;
+
+/// Limit price at which `target_volume` of `pair` (a canonical AssetPairs key)
+/// would fill right now: the ask level the cumulative volume reaches for a
+/// buy, the bid level for a sell. Both prices are rounded to the pair decimals,
+/// an error when the book isn't deep enough.
+pub async fn optimal_limit_price(client: &KrakenClient, pair: &str, target_volume: Decimal, side: OrderDirection) -> Result<BookFill, String> {
+	if target_volume <= Decimal::ZERO {
+		return Err(format!("target volume must be positive, not {}", target_volume));
+	}
+
+	let metadata = client.metadata().await?;
+	let info = metadata.pair_info(pair).ok_or_else(|| format!("unknown asset pair: {}", pair))?;
+	let book = order_book(client, pair, MAX_BOOK_DEPTH).await?;
+
+	let levels = match side {
+		OrderDirection::Buy => &book.asks,
+		OrderDirection::Sell => &book.bids,
+	};
+
+	let mut filled = Decimal::ZERO;
+	let mut cost = Decimal::ZERO;
+	for level in levels {
+		let taken = level.volume.min(target_volume - filled);
+		filled += taken;
+		cost += taken * level.price;
+
+		if filled >= target_volume {
+			return Ok(BookFill {
+				price: level.price.round_dp(info.pair_decimals),
+				average_price: (cost / filled).round_dp(info.pair_decimals),
+			});
+		}
+	}
+
+	Err(format!(
+		"book of {} only has {} of the {} volume wanted on the {} side",
+		pair, filled, target_volume, side
+	))
+}
+
+/// How a budget's volume is rounded to the pair's lot decimals.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+	/// never spends more than the budget, may leave a small residual
+	#[default]
+	Down,
+	/// closest lot, may spend slightly more or less than the budget
+	Nearest,
+	/// never leaves a residual, may spend more than the budget
+	Up,
+}
+
+impl RoundingMode {
+	fn strategy(self) -> RoundingStrategy {
+		match self {
+			RoundingMode::Down => RoundingStrategy::ToZero,
+			RoundingMode::Nearest => RoundingStrategy::MidpointAwayFromZero,
+			RoundingMode::Up => RoundingStrategy::AwayFromZero,
+		}
+	}
+}
+
+/// What a buy whose volume is above its `max_volume` gets.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum VolumeCapPolicy {
+	/// buy `max_volume` instead
+	#[default]
+	Clamp,
+	/// refuse the buy
+	Skip,
+}
+
+/// `volume` held to `max_volume` per `policy`, a guard against a mispriced
+/// ticker turning a budget into an enormous order.
+pub fn cap_volume(pair: &AssetPair, volume: Decimal, max_volume: Option<Decimal>, policy: VolumeCapPolicy) -> Result<Decimal, String> {
+	let max_volume = match max_volume {
+		Some(max_volume) if volume > max_volume => max_volume,
+		_ => return Ok(volume),
+	};
+
+	match policy {
+		VolumeCapPolicy::Clamp => {
+			let clamped = max_volume.round_dp_with_strategy(pair.lot_decimals, RoundingStrategy::ToZero);
+			log::warn!("volume {} for {} clamped to the cap of {}", volume, pair.altname, clamped);
+			Ok(clamped)
+		}
+		VolumeCapPolicy::Skip => Err(format!("volume {} for {} is above the cap of {}", volume, pair.altname, max_volume)),
+	}
+}
+
+/// Volume of `pair` that `budget` quote currency buys at `price`, rounded to
+/// the pair's lot decimals per `rounding` (`RoundingMode::Up` may exceed the
+/// budget). Fails when the result is below the pair's `ordermin` or `costmin`.
+pub fn volume_for_budget(pair: &AssetPair, price: Decimal, budget: Decimal, rounding: RoundingMode) -> Result<Decimal, String> {
+	if price <= Decimal::ZERO {
+		return Err(format!("invalid price {} for {}", price, pair.altname));
+	}
+
+	let volume = budget
+		.checked_div(price)
+		.ok_or_else(|| format!("volume for {} at {} is out of range", budget, price))?
+		.round_dp_with_strategy(pair.lot_decimals, rounding.strategy());
+
+	if let Some(ordermin) = pair.ordermin {
+		if volume < ordermin {
+			return Err(format!("volume {} for {} is below the minimum order of {}", volume, pair.altname, ordermin));
+		}
+	}
+
+	if let Some(costmin) = pair.costmin {
+		let cost = safe_cost(price, volume).map_err(|e| e.to_string())?;
+		if cost < costmin {
+			return Err(format!("cost {} for {} is below the minimum cost of {}", cost, pair.altname, costmin));
+		}
+	}
+
+	Ok(volume)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BuyOptions {
+	/// refuse the buy when the last trade (or limit price) is above this price
+	pub max_price: Option<Decimal>,
+	/// place a limit order at this price instead of a market order
+	pub limit_price: Option<Decimal>,
+	/// make the limit order post only
+	pub post_only: bool,
+	/// expire the limit order this long after placing it
+	pub limit_ttl: Option<Duration>,
+	pub fee_currency: Option<FeePreference>,
+	/// keep Kraken's price protection on a market buy, `false` trades it for an immediate fill
+	pub market_price_protection: bool,
+	/// quote amount the buy must leave free
+	pub reserve: Decimal,
+	pub rounding: RoundingMode,
+	/// most volume the buy may have, see `cap_volume`
+	pub max_volume: Option<Decimal>,
+	pub volume_cap: VolumeCapPolicy,
+	/// user reference the order is tagged with
+	pub userref: Option<i32>,
+	/// refuse the buy when its cost is further than this many percent from its amount, see `check_cost`
+	pub cost_tolerance_pct: Option<Decimal>,
+	/// validate inputs only, do not submit the order
+	pub validate: bool,
+}
+
+impl Default for BuyOptions {
+	fn default() -> BuyOptions {
+		BuyOptions {
+			max_price: None,
+			limit_price: None,
+			post_only: false,
+			limit_ttl: None,
+			fee_currency: None,
+			market_price_protection: true,
+			reserve: Decimal::ZERO,
+			rounding: RoundingMode::default(),
+			max_volume: None,
+			volume_cap: VolumeCapPolicy::default(),
+			userref: None,
+			cost_tolerance_pct: None,
+			validate: false,
+		}
+	}
+}
+
+/// Buys `pair` (a canonical AssetPairs key) spending about `amount` quote
+/// currency, with a market order sized at the last trade or a limit order at
+/// `options.limit_price`.
+pub async fn buy_for_amount(client: &KrakenClient, pair: &str, amount: Decimal, options: &BuyOptions) -> Result<PlacedBuy, String> {
+	let buy = buy_order(client, pair, amount, options, Decimal::ZERO).await?;
+	let placed = add_order(client, buy.order).await?;
+
+	Ok(PlacedBuy {
+		pair: pair.to_owned(),
+		price: buy.price,
+		volume: buy.volume,
+		order: placed,
+	})
+}
+
+/// Refuses a buy of `pair` whose `cost` is more than `tolerance_pct` percent off
+/// its `budget`, a safety net against a rounding or pricing bug spending an
+/// unexpected amount.
+pub fn check_cost(pair: &str, cost: Decimal, budget: Decimal, tolerance_pct: Decimal) -> Result<(), String> {
+	if budget <= Decimal::ZERO {
+		return Ok(());
+	}
+
+	let deviation_pct = (cost - budget) / budget * Decimal::ONE_HUNDRED;
+	if deviation_pct.abs() > tolerance_pct {
+		return Err(format!(
+			"cost {} of the {} buy is {}% off its amount of {}, beyond the cost tolerance of {}%",
+			cost, pair, deviation_pct.round_dp(2), budget, tolerance_pct
+		));
+	}
+
+	Ok(())
+}
+
+/// Buy of `buy_for_amount` ready to place.
+struct BuyOrder {
+	price: Decimal,
+	volume: Decimal,
+	/// cost plus the estimated fee
+	needed: Decimal,
+	order: NewOrder,
+}
+
+/// Order buying `pair` for `amount`, checked against the free quote balance
+/// less the reserve and the `committed` quote other orders of the run need.
+async fn buy_order(client: &KrakenClient, pair: &str, amount: Decimal, options: &BuyOptions, committed: Decimal) -> Result<BuyOrder, String> {
+	let metadata = client.metadata().await?;
+	let info = metadata.pair_info(pair).ok_or_else(|| format!("unknown asset pair: {}", pair))?;
+
+	let price = match options.limit_price {
+		Some(limit_price) => limit_price.round_dp(info.pair_decimals),
+		None => client.price_source.price(client, pair).await?,
+	};
+
+	if let Some(max_price) = options.max_price {
+		if price > max_price {
+			return Err(KrakenError::SlippageExceeded {
+				pair: pair.to_owned(),
+				price,
+				max_price,
+			}
+			.to_string());
+		}
+	}
+
+	let volume = volume_for_budget(info, price, amount, options.rounding)?;
+	if let Some(tolerance_pct) = options.cost_tolerance_pct {
+		// before the volume cap, which lowers the cost on purpose
+		check_cost(pair, safe_cost(price, volume).map_err(|e| e.to_string())?, amount, tolerance_pct)?;
+	}
+	let volume = cap_volume(info, volume, options.max_volume, options.volume_cap)?;
+	let cost = safe_cost(price, volume).map_err(|e| e.to_string())?;
+	let needed = cost
+		.checked_add(info.estimated_fee(cost))
+		.ok_or_else(|| KrakenError::CostOutOfRange { price, volume }.to_string())?;
+
+	// validate-only orders spend nothing
+	if !options.validate {
+		let available = balance_ex(client)
+			.await?
+			.get(&info.quote)
+			.map_or(Decimal::ZERO, |balance| balance.available())
+			- options.reserve
+			- committed;
+
+		if needed > available {
+			return Err(KrakenError::InsufficientFunds { needed, available }.to_string());
+		}
+	}
+
+	let order = NewOrder {
+		pair: pair.to_owned(),
+		order_direction: OrderDirection::Buy,
+		order_type: if options.limit_price.is_some() { OrderType::Limit } else { OrderType::Market },
+		price: options.limit_price.map(|_| Price::from(price)),
+		price2: None,
+		trigger: None,
+		volume: Some(volume.to_string()),
+		leverage: None,
+		reduce_only: false,
+		oflags: if options.post_only { Some(String::from("post")) } else { None },
+		fee_currency: options.fee_currency,
+		// a limit buy has no market price protection to turn off
+		market_price_protection: options.market_price_protection || options.limit_price.is_some(),
+		starttm: None,
+		expiretm: match (options.limit_price, options.limit_ttl) {
+			(Some(_), Some(ttl)) => Some(format!("+{}", ttl.as_secs())),
+			_ => None,
+		},
+		userref: options.userref.map(|userref| userref.to_string()),
+		#[cfg(feature = "timestamps")]
+		deadline: None,
+		validate: if options.validate { Some(true) } else { None },
+	};
+
+	Ok(BuyOrder {
+		price,
+		volume,
+		needed,
+		order,
+	})
+}
+
+/// Buys `pair` once for each of `buys`' amounts and options, in as few
+/// AddOrderBatch calls as `MAX_BATCH_ORDERS` allows. A single buy, or one left
+/// over by the split, goes through `add_order`. Returns one result per buy in
+/// the same order; a buy failing its checks leaves the others to be placed.
+pub async fn buy_batch(client: &KrakenClient, pair: &str, buys: &[(Decimal, BuyOptions)]) -> Vec<Result<PlacedBuy, String>> {
+	if let [(amount, options)] = buys {
+		return vec![buy_for_amount(client, pair, *amount, options).await];
+	}
+
+	let mut results: Vec<Option<Result<PlacedBuy, String>>> = Vec::new();
+	let mut ready = Vec::new();
+	let mut committed = Decimal::ZERO;
+
+	for (i, (amount, options)) in buys.iter().enumerate() {
+		match buy_order(client, pair, *amount, options, committed).await {
+			Ok(buy) => {
+				if !options.validate {
+					committed += buy.needed;
+				}
+				results.push(None);
+				ready.push((i, buy));
+			}
+			Err(e) => results.push(Some(Err(e))),
+		}
+	}
+
+	while !ready.is_empty() {
+		let chunk: Vec<_> = ready.drain(..ready.len().min(MAX_BATCH_ORDERS)).collect();
+		let sizes: Vec<_> = chunk.iter().map(|(i, buy)| (*i, buy.price, buy.volume)).collect();
+
+		let placed = if chunk.len() < MIN_BATCH_ORDERS {
+			let (_, buy) = chunk.into_iter().next().unwrap();
+			vec![add_order(client, buy.order).await]
+		} else {
+			let orders = chunk.into_iter().map(|(_, buy)| buy.order).collect();
+			match add_order_batch(client, orders).await {
+				Ok(placed) => placed,
+				Err(e) => sizes.iter().map(|_| Err(e.clone())).collect(),
+			}
+		};
+
+		for ((i, price, volume), order) in sizes.into_iter().zip(placed) {
+			results[i] = Some(order.map(|order| PlacedBuy {
+				pair: pair.to_owned(),
+				price,
+				volume,
+				order,
+			}));
+		}
+	}
+
+	results
+		.into_iter()
+		.map(|result| result.unwrap_or_else(|| Err(String::from("order missing from the batch response"))))
+		.collect()
+}
+
+fn capitalize(word: &str) -> String {
+	let mut chars = word.chars();
+	match chars.next() {
+		Some(first) => first.to_uppercase().collect::<String>() + chars.as_str(),
+		None => String::new(),
+	}
+}
+
+/// Readable one-line form of an order, e.g. "Buy 2.00000000 SOLUSD @ limit 154.00".
+/// Prices, leverage and the close order are left out when Kraken reports them unset.
+pub fn format_order(descr: &OrderDescription) -> String {
+	let mut text = capitalize(&descr.order_direction.to_string());
+
+	// the volume is only part of Kraken's own text: "<type> <volume> <pair> @ ..."
+	if let Some(volume) = descr.order.split_whitespace().nth(1) {
+		text += &format!(" {}", volume);
+	}
+
+	text += &format!(" {} @ {}", descr.pair, descr.ordertype);
+
+	let is_set = |price: &str| !price.is_empty() && price != "0";
+	match descr.ordertype {
+		OrderType::Market | OrderType::SettlePosition => {}
+		OrderType::StopLossLimit | OrderType::TakeProfitLimit | OrderType::TrailingStopLimit if is_set(&descr.price2) => {
+			text += &format!(" {} (limit {})", descr.price, descr.price2);
+		}
+		_ if is_set(&descr.price) => text += &format!(" {}", descr.price),
+		_ => {}
+	}
+
+	if descr.leverage != "none" && !descr.leverage.is_empty() {
+		text += &format!(" with {} leverage", descr.leverage);
+	}
+
+	if let Some(close) = &descr.close {
+		text += &format!(", close {}", close);
+	}
+
+	text
+}
+
+/// `format_order` of the description AddOrder returned, or Kraken's own
+/// wording when it can't be read back.
+pub fn format_placed(descr: &AddOrderDescription) -> String {
+	descr.to_description().map_or_else(|| descr.order.clone(), |typed| format_order(&typed))
+}
+
+pub async fn open_orders(client: &KrakenClient, trades: bool, userref: Option<i32>) -> Result<HashMap<String, OpenOrder>, String> {
+	let mut params = HashMap::new();
+
+	if trades {
+		params.insert("trades".to_owned(), String::from("true"));
+	}
+
+	if let Some(userref) = userref {
+		params.insert("userref".to_owned(), userref.to_string());
+	}
+
+	private(client, "OpenOrders", &mut params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)
+		.map(|response: OpenOrdersResponse| response.open)
+}
+
+/// Order `open_orders_filtered` returns the orders in.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OpenOrderSort {
+	#[default]
+	OldestFirst,
+	NewestFirst,
+	/// by pair, oldest first within a pair
+	Pair,
+}
+
+/// Which open orders `open_orders_filtered` returns; the default is all of them.
+#[derive(Debug, Clone, Default)]
+pub struct OpenOrderFilter {
+	/// pair in any form `resolve_pair` accepts
+	pub pair: Option<String>,
+	pub direction: Option<OrderDirection>,
+	/// only orders open at least this long
+	pub min_age: Option<Duration>,
+	pub userref: Option<i32>,
+	/// include the ids of the trades filling each order
+	pub trades: bool,
+	pub sort: OpenOrderSort,
+}
+
+/// The open orders matching `filter` in its `sort` order, each with its `txid` set.
+pub async fn open_orders_filtered(client: &KrakenClient, filter: &OpenOrderFilter) -> Result<Vec<OpenOrder>, String> {
+	// order descriptions name the pair by altname
+	let altname = match &filter.pair {
+		Some(pair) => {
+			let key = resolve_pair(client, pair).await?;
+			let metadata = client.metadata().await?;
+			Some(metadata.pair_info(&key).map_or(key.clone(), |info| info.altname.clone()))
+		}
+		None => None,
+	};
+	let now = ::std::time::UNIX_EPOCH.elapsed().unwrap().as_secs_f64();
+
+	let mut orders: Vec<OpenOrder> = open_orders(client, filter.trades, filter.userref)
+		.await?
+		.into_iter()
+		.map(|(txid, order)| OpenOrder { txid, ..order })
+		.filter(|order| altname.as_ref().is_none_or(|altname| order.descr.pair == *altname))
+		.filter(|order| filter.direction.is_none_or(|direction| order.descr.order_direction == direction))
+		.filter(|order| filter.min_age.is_none_or(|min_age| now - order.opentm >= min_age.as_secs_f64()))
+		.collect();
+
+	let by_age = |a: &OpenOrder, b: &OpenOrder| a.opentm.partial_cmp(&b.opentm).unwrap_or(std::cmp::Ordering::Equal);
+	match filter.sort {
+		OpenOrderSort::OldestFirst => orders.sort_by(by_age),
+		OpenOrderSort::NewestFirst => orders.sort_by(|a, b| by_age(b, a)),
+		OpenOrderSort::Pair => orders.sort_by(|a, b| a.descr.pair.cmp(&b.descr.pair).then_with(|| by_age(a, b))),
+	}
+
+	Ok(orders)
+}
+
+pub async fn closed_orders(client: &KrakenClient, trades: bool, userref: Option<i32>) -> Result<HashMap<String, OpenOrder>, String> {
+	let mut params = HashMap::new();
+
+	if trades {
+		params.insert("trades".to_owned(), String::from("true"));
+	}
+
+	if let Some(userref) = userref {
+		params.insert("userref".to_owned(), userref.to_string());
+	}
+
+	private(client, "ClosedOrders", &mut params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)
+		.map(|page: ClosedOrdersPage| page.closed)
+}
+
+/// Closed orders, newest first, starting at offset `ofs`.
+pub async fn closed_orders_page(client: &KrakenClient, userref: Option<i32>, ofs: u32) -> Result<ClosedOrdersPage, String> {
+	let mut params = HashMap::new();
+	params.insert("ofs".to_owned(), ofs.to_string());
+
+	if let Some(userref) = userref {
+		params.insert("userref".to_owned(), userref.to_string());
+	}
+
+	private(client, "ClosedOrders", &mut params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)
+}
+
+/// Every closed order tagged with `userref` (all of them without one) by
+/// txid, following the pages.
+pub async fn all_closed_orders(client: &KrakenClient, userref: Option<i32>) -> Result<HashMap<String, OpenOrder>, String> {
+	let mut orders = HashMap::new();
+
+	loop {
+		let ofs = orders.len() as u32;
+		let page = closed_orders_page(client, userref, ofs).await?;
+		let last = page.is_last_page(ofs);
+		orders.extend(page.closed);
+
+		if last {
+			return Ok(orders);
+		}
+	}
+}
+
+pub async fn query_orders(client: &KrakenClient, txids: &[&str], trades: bool, userref: Option<i32>) -> Result<HashMap<String, OpenOrder>, String> {
+	let mut params = HashMap::new();
+	params.insert("txid".to_owned(), txids.join(","));
+
+	if trades {
+		params.insert("trades".to_owned(), String::from("true"));
+	}
+
+	if let Some(userref) = userref {
+		params.insert("userref".to_owned(), userref.to_string());
+	}
+
+	private(client, "QueryOrders", &mut params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)
+}
+
+/// orders this close together with the same description are taken for one
+/// order placed twice by a retry
+const DUPLICATE_WINDOW_SECS: f64 = 60.0;
+
+/// Txids of orders tagged with `userref` that repeat an earlier one: the same
+/// description (pair, direction, type, price and volume) placed within a minute
+/// of it, as when a retried AddOrder went through twice because the first
+/// response was lost. The first of each such group is left out, as are repeats
+/// canceled or expired without a fill, which did no harm. Two plan entries
+/// ordering the very same thing in one run are reported too.
+pub async fn detect_duplicate_fills(client: &KrakenClient, userref: i32) -> Result<Vec<TxId>, String> {
+	let mut orders: Vec<(String, OpenOrder)> = open_orders(client, false, Some(userref)).await?.into_iter().collect();
+	orders.extend(all_closed_orders(client, Some(userref)).await?);
+	orders.sort_by(|a, b| a.1.opentm.partial_cmp(&b.1.opentm).unwrap_or(std::cmp::Ordering::Equal));
+
+	let mut duplicates = Vec::new();
+	for (i, (txid, order)) in orders.iter().enumerate() {
+		let repeats = orders[..i].iter().any(|(_, earlier)| {
+			earlier.descr.order == order.descr.order && order.opentm - earlier.opentm <= DUPLICATE_WINDOW_SECS
+		});
+		let unfilled = (order.status == "canceled" || order.status == "expired")
+			&& Decimal::from_str(&order.vol_exec).is_ok_and(|vol_exec| vol_exec.is_zero());
+
+		if repeats && !unfilled {
+			duplicates.push(txid.clone());
+		}
+	}
+
+	Ok(duplicates)
+}
+
+pub async fn ledgers(client: &KrakenClient, asset: Option<&str>, entry_type: Option<&str>, ofs: u32) -> Result<LedgersPage, String> {
+	ledgers_between(client, asset, entry_type, None, None, ofs).await
+}
+
+/// Ledger page of entries from `start` to `end` (unix time, both exclusive).
+pub async fn ledgers_between(
+	client: &KrakenClient,
+	asset: Option<&str>,
+	entry_type: Option<&str>,
+	start: Option<i64>,
+	end: Option<i64>,
+	ofs: u32,
+) -> Result<LedgersPage, String> {
+	let mut params = HashMap::new();
+	params.insert("ofs".to_owned(), ofs.to_string());
+
+	if let Some(start) = start {
+		params.insert("start".to_owned(), start.to_string());
+	}
+
+	if let Some(end) = end {
+		params.insert("end".to_owned(), end.to_string());
+	}
+
+	if let Some(asset) = asset {
+		params.insert("asset".to_owned(), asset.to_owned());
+	}
+
+	if let Some(entry_type) = entry_type {
+		params.insert("type".to_owned(), entry_type.to_owned());
+	}
+
+	private(client, "Ledgers", &mut params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)
+}
+
+/// Every ledger entry matching `asset` and `entry_type`, following the pages.
+pub async fn all_ledgers(client: &KrakenClient, asset: Option<&str>, entry_type: Option<&str>) -> Result<Vec<LedgerEntry>, String> {
+	all_ledgers_between(client, asset, entry_type, None, None).await
+}
+
+/// Every ledger entry matching `asset` and `entry_type` from `start` to `end`
+/// (unix time, both exclusive), following the pages.
+pub async fn all_ledgers_between(
+	client: &KrakenClient,
+	asset: Option<&str>,
+	entry_type: Option<&str>,
+	start: Option<i64>,
+	end: Option<i64>,
+) -> Result<Vec<LedgerEntry>, String> {
+	let mut entries = Vec::new();
+
+	loop {
+		let ofs = entries.len() as u32;
+		let page = ledgers_between(client, asset, entry_type, start, end, ofs).await?;
+		let last = page.is_last_page(ofs);
+		entries.extend(page.ledger.into_values());
+
+		if last {
+			return Ok(entries);
+		}
+	}
+}
+
+pub async fn trade_balance(client: &KrakenClient, asset: Option<&str>) -> Result<TradeBalance, String> {
+	let mut params = HashMap::new();
+
+	if let Some(asset) = asset {
+		params.insert("asset".to_owned(), asset.to_owned());
+	}
+
+	private(client, "TradeBalance", &mut params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)
+}
+
+/// 30-day trade volume, with the fee tiers of `pairs` when any are given.
+pub async fn trade_volume(client: &KrakenClient, pairs: &[&str]) -> Result<TradeVolume, String> {
+	let mut params = HashMap::new();
+
+	if !pairs.is_empty() {
+		params.insert("pair".to_owned(), pairs.join(","));
+	}
+
+	private(client, "TradeVolume", &mut params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)
+}
+
+/// Trades from `start` (unix time) on, newest first, starting at offset `ofs`.
+pub async fn trades_history(client: &KrakenClient, start: Option<i64>, ofs: u32) -> Result<TradesHistoryPage, String> {
+	let mut params = HashMap::new();
+	params.insert("ofs".to_owned(), ofs.to_string());
+
+	if let Some(start) = start {
+		params.insert("start".to_owned(), start.to_string());
+	}
+
+	private(client, "TradesHistory", &mut params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)
+}
+
+/// Open margin positions by position id, with their current value and
+/// unrealized profit or loss when `docalcs` is set.
+pub async fn open_positions(client: &KrakenClient, docalcs: bool) -> Result<HashMap<TxId, OpenPosition>, String> {
+	let mut params = HashMap::new();
+	params.insert("docalcs".to_owned(), docalcs.to_string());
+
+	private(client, "OpenPositions", &mut params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)
+}
+
+/// Closes all of position `txid`, or `volume` of it, with a reduce only market
+/// order against the position's direction at the leverage it was opened with.
+pub async fn close_position(client: &KrakenClient, txid: &str, volume: Option<Decimal>) -> Result<AddOrderResponse, String> {
+	let positions = open_positions(client, false).await?;
+	let position = positions.get(txid).ok_or_else(|| format!("no open position {}", txid))?;
+
+	let remaining = position.vol - position.vol_closed;
+	let volume = volume.unwrap_or(remaining);
+	if volume <= Decimal::ZERO || volume > remaining {
+		return Err(format!("cannot close {} of position {}, {} remains open", volume, txid, remaining));
+	}
+
+	let order_direction = match position.order_direction.parse()? {
+		OrderDirection::Buy => OrderDirection::Sell,
+		OrderDirection::Sell => OrderDirection::Buy,
+	};
+	if position.margin.is_zero() {
+		return Err(format!("position {} has no margin to derive its leverage from", txid));
+	}
+	let leverage = (position.cost / position.margin).round();
+
+	let order = NewOrder {
+		pair: position.pair.clone(),
+		order_direction,
+		order_type: OrderType::Market,
+		price: None,
+		price2: None,
+		trigger: None,
+		volume: Some(volume.to_string()),
+		leverage: Some(leverage.to_string()),
+		reduce_only: true,
+		oflags: None,
+		fee_currency: None,
+		market_price_protection: true,
+		starttm: None,
+		expiretm: None,
+		userref: None,
+		#[cfg(feature = "timestamps")]
+		deadline: None,
+		validate: None,
+	};
+
+	add_order(client, order).await
+}
+
+/// Every trade since `start` (unix time) by trade id, fetched page by page.
+pub async fn all_trades(client: &KrakenClient, start: Option<i64>) -> Result<HashMap<TxId, Trade>, String> {
+	let mut trades = HashMap::new();
+
+	loop {
+		let ofs = trades.len() as u32;
+		let page = trades_history(client, start, ofs).await?;
+		let last = page.is_last_page(ofs);
+		trades.extend(page.trades);
+
+		if last {
+			return Ok(trades);
+		}
+	}
+}
+
+/// Emergency stop: logs every open order before canceling them all at once
+/// with CancelAll, so the log shows what the count stands for. Orders placed
+/// between the listing and the cancel are canceled without being logged.
+pub async fn panic_cancel_all(client: &KrakenClient) -> Result<CancelResult, String> {
+	match open_orders(client, false, None).await {
+		Ok(orders) => {
+			for (txid, order) in &orders {
+				log::warn!("canceling {}: {} ({}, {} of {} executed)", txid, format_order(&order.descr), order.status, order.vol_exec, order.vol);
+			}
+			log::warn!("canceling all {} open orders", orders.len());
+		}
+		// the cancel matters more than the listing
+		Err(e) => log::error!("could not list the open orders, canceling them unlisted: {}", e),
+	}
+
+	let result = cancel_all(client).await?;
+	log::warn!("{}", result);
+
+	Ok(result)
+}
+
+pub async fn cancel_all(client: &KrakenClient) -> Result<CancelResult, String> {
+	let mut params = HashMap::new();
+
+	private(client, "CancelAll", &mut params)
+		.await
+		.map_err(|e| format!("{:?}", e))
+		.and_then(into_result)
+}
+
+/// Polls `txids` with backoff until every order reached a terminal state or
+/// `timeout` ran out, returning the fills seen last, partial ones included.
+pub async fn await_fills(client: &KrakenClient, txids: &[TxId], timeout: Duration) -> Result<Vec<ExecutedTrade>, String> {
+	if txids.is_empty() {
+		return Ok(Vec::new());
+	}
+
+	let ids: Vec<&str> = txids.iter().map(|txid| txid.as_str()).collect();
+	let started = Instant::now();
+	let mut delay = Duration::from_millis(500);
+
+	loop {
+		let orders = query_orders(client, &ids, false, None).await?;
+		let done = orders.values().all(|order| is_terminal(&order.status));
+
+		if done || started.elapsed() + delay > timeout {
+			return txids
+				.iter()
+				.filter_map(|txid| orders.get(txid).map(|order| ExecutedTrade::from_order(txid, order)))
+				.collect();
+		}
+
+		tokio::time::sleep(delay).await;
+		delay = (delay * 2).min(Duration::from_secs(4));
+	}
+}
+
+/// Where a submitted order stands, with its fill so far.
+#[derive(Debug, Clone)]
+pub enum OrderState {
+	/// pending or open
+	Open(ExecutedTrade),
+	Closed(ExecutedTrade),
+	Canceled(ExecutedTrade),
+	Expired(ExecutedTrade),
+	/// Kraken doesn't know the txid, the order never landed
+	Unknown,
+}
+
+impl OrderState {
+	/// The order's fill so far, `None` for an unknown order.
+	pub fn executed(self) -> Option<ExecutedTrade> {
+		match self {
+			OrderState::Open(executed)
+			| OrderState::Closed(executed)
+			| OrderState::Canceled(executed)
+			| OrderState::Expired(executed) => Some(executed),
+			OrderState::Unknown => None,
+		}
+	}
+}
+
+/// Queries `txid` to find out whether an order actually landed, e.g. after
+/// `add_order` failed in a way that leaves it unclear.
+pub async fn confirm_order(client: &KrakenClient, txid: &str) -> Result<OrderState, String> {
+	let orders = match query_orders(client, &[txid], false, None).await {
+		Ok(orders) => orders,
+		Err(e) if e.contains("Invalid order") => return Ok(OrderState::Unknown),
+		Err(e) => return Err(e),
+	};
+
+	let order = match orders.get(txid) {
+		Some(order) => order,
+		None => return Ok(OrderState::Unknown),
+	};
+	let executed = ExecutedTrade::from_order(txid, order)?;
+
+	Ok(match order.status.as_str() {
+		"closed" => OrderState::Closed(executed),
+		"canceled" => OrderState::Canceled(executed),
+		"expired" => OrderState::Expired(executed),
+		_ => OrderState::Open(executed),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{self, MockExchange};
+
+	fn dec(value: &str) -> Decimal {
+		value.parse().unwrap()
+	}
+
+	fn client() -> KrakenClient {
+		KrakenClient::new(Account {
+			key: String::from("key"),
+			secret: base64::encode("secret"),
+			otp: None,
+		})
+	}
+
+	async fn exchange() -> MockExchange {
+		let exchange = MockExchange::start().await;
+		exchange.add_pair("XXBTZUSD", "XBTUSD", "XXBT", "ZUSD", serde_json::json!({}));
+		exchange.set_price("XXBTZUSD", dec("40000"));
+		exchange.set_balance("ZUSD", dec("1000"));
+		exchange
+	}
+
+	fn xbtusd() -> AssetPair {
+		serde_json::from_value(serde_json::json!({
+			"altname": "XBTUSD",
+			"wsname": "XBT/USD",
+			"base": "XXBT",
+			"quote": "ZUSD",
+			"pair_decimals": 1,
+			"lot_decimals": 8,
+			"status": "online",
+			"ordermin": "0.0001",
+			"costmin": "0.5"
+		}))
+		.unwrap()
+	}
+
+	#[test]
+	fn sign_matches_the_documented_example() {
+		// the example of Kraken's REST authentication guide
+		let secret = base64::decode("kQH5HW/8p1uGOVjbgWA7FunAmGO8lsSUXNsu3eow76sz84Q18fWxnyRzBHCd3pd5nE9qa99HAZtuZuj6F1huXg==").unwrap();
+		let postdata = "nonce=1616492376594&ordertype=limit&pair=XBTUSD&price=37500&type=buy&volume=1.25";
+
+		assert_eq!(
+			sign("/0/private/AddOrder", "1616492376594", postdata.as_bytes(), &secret),
+			"4/dpxb3iT4tp/ZCVEwSnEsLxx0bqyhLpdfOpc6fn7OR8+UClSV5n9E6aSS8MPtnRfp32bAb0nmbRn6H8ndwLUQ=="
+		);
+	}
+
+	#[tokio::test]
+	async fn buys_are_sized_at_a_fixed_price() {
+		let mut prices = HashMap::new();
+		prices.insert(String::from("XXBTZUSD"), dec("40000"));
+		let client = client().with_price_source(Box::new(FixedPrice { prices }));
+
+		let price = client.price_source.price(&client, "XXBTZUSD").await.unwrap();
+		assert_eq!(price, dec("40000"));
+		assert_eq!(volume_for_budget(&xbtusd(), price, dec("100"), RoundingMode::default()), Ok(dec("0.0025")));
+		// a budget below the pair's ordermin at that price
+		assert!(volume_for_budget(&xbtusd(), price, dec("3"), RoundingMode::default()).is_err());
+
+		assert_eq!(client.price_source.price(&client, "XETHZUSD").await, Err(String::from("no fixed price for XETHZUSD")));
+	}
+
+	#[tokio::test]
+	async fn canceling_an_unknown_order_cancels_nothing() {
+		let exchange = exchange().await;
+		let client = exchange.client();
+
+		let canceled = cancel_order(&client, "OUNKNO-WNORD-ER0000").await.unwrap();
+		assert_eq!((canceled.count, canceled.pending), (0, None));
+
+		// an order that filled before the cancel is just as gone
+		let placed = add_order(&client, mock::market_buy("XXBTZUSD", dec("0.001"))).await.unwrap();
+		let txid = &placed.txid.unwrap()[0];
+		assert_eq!(cancel_order(&client, txid).await.unwrap().count, 0);
+	}
+
+	#[tokio::test]
+	async fn other_cancel_errors_propagate() {
+		let exchange = exchange().await;
+		exchange.script("CancelOrder", 200, r#"{"error": ["EGeneral:Permission denied"]}"#);
+		let client = exchange.client();
+
+		let refused = cancel_order(&client, "OABCDE-FGHIJ-KLMNOP").await;
+		assert!(refused.unwrap_err().contains("EGeneral:Permission denied"));
+	}
+
+	#[test]
+	fn budgets_round_to_the_lot_decimals_per_mode() {
+		let volume = |budget: &str, rounding| volume_for_budget(&xbtusd(), dec("30000"), dec(budget), rounding);
+
+		// 200 / 30000 = 0.0066666...
+		assert_eq!(volume("200", RoundingMode::Down), Ok(dec("0.00666666")));
+		assert_eq!(volume("200", RoundingMode::Nearest), Ok(dec("0.00666667")));
+		assert_eq!(volume("200", RoundingMode::Up), Ok(dec("0.00666667")));
+		// 100 / 30000 = 0.0033333...
+		assert_eq!(volume("100", RoundingMode::Nearest), Ok(dec("0.00333333")));
+		assert_eq!(volume("100", RoundingMode::Up), Ok(dec("0.00333334")));
+
+		// only up may spend more than the budget
+		assert!(dec("0.00666666") * dec("30000") <= dec("200"));
+		assert!(dec("0.00666667") * dec("30000") > dec("200"));
+	}
+
+	#[test]
+	fn rounding_up_still_respects_the_order_minimum() {
+		// 2.99 / 30000 = 0.0000996..., below the ordermin of 0.0001 either way
+		for rounding in [RoundingMode::Down, RoundingMode::Nearest, RoundingMode::Up] {
+			let refused = volume_for_budget(&xbtusd(), dec("30000"), dec("2.99"), rounding);
+			assert!(refused.unwrap_err().contains("below the minimum order of 0.0001"));
+		}
+		assert_eq!(volume_for_budget(&xbtusd(), dec("30000"), dec("3"), RoundingMode::Up), Ok(dec("0.0001")));
+	}
+
+	#[cfg(feature = "timestamps")]
+	#[tokio::test]
+	async fn deadlines_are_sent_as_rfc3339_millis() {
+		let exchange = exchange().await;
+		let client = exchange.client();
+
+		let mut order = mock::market_buy("XXBTZUSD", dec("0.001"));
+		order.deadline = Some("2100-12-25T09:30:59.123456Z".parse().unwrap());
+		let params = order_params(&client, order).await.unwrap();
+		assert_eq!(params["deadline"], "2100-12-25T09:30:59.123Z");
+
+		// checked against Kraken's clock, not ours
+		assert_eq!(exchange.calls("Time").len(), 1);
+	}
+
+	#[cfg(feature = "timestamps")]
+	#[tokio::test]
+	async fn past_deadlines_are_refused() {
+		let exchange = exchange().await;
+		let client = exchange.client();
+
+		let mut order = mock::market_buy("XXBTZUSD", dec("0.001"));
+		order.deadline = Some("2020-01-01T00:00:00Z".parse().unwrap());
+		let refused = add_order(&client, order).await;
+
+		assert!(refused.unwrap_err().contains("is not in the future"));
+		assert!(exchange.calls("AddOrder").is_empty());
+	}
+
+	fn nonces(exchange: &MockExchange, method: &str) -> Vec<u64> {
+		exchange.calls(method).iter().map(|call| call.params["nonce"].parse().unwrap()).collect()
+	}
+
+	#[tokio::test]
+	async fn a_rejected_nonce_is_bumped_and_retried_once() {
+		let exchange = exchange().await;
+		exchange.script("Balance", 200, r#"{"error": ["EAPI:Invalid nonce"]}"#);
+		let client = exchange.client();
+
+		let balances = balance(&client).await.unwrap();
+		assert_eq!(balances["ZUSD"], "1000");
+
+		let nonces = nonces(&exchange, "Balance");
+		assert_eq!(nonces.len(), 2);
+		assert!(nonces[1] - nonces[0] >= NONCE_BUMP.as_nanos() as u64);
+
+		// a second rejection in a row is returned
+		exchange.script("BalanceEx", 200, r#"{"error": ["EAPI:Invalid nonce"]}"#);
+		exchange.script("BalanceEx", 200, r#"{"error": ["EAPI:Invalid nonce"]}"#);
+		assert!(balance_ex(&client).await.unwrap_err().contains("EAPI:Invalid nonce"));
+		assert_eq!(exchange.calls("BalanceEx").len(), 2);
+	}
+
+	#[tokio::test]
+	async fn other_retries_keep_the_nonce_pace() {
+		let exchange = exchange().await;
+		exchange.script("Balance", 200, r#"{"error": ["EService:Unavailable"]}"#);
+		let client = exchange.client();
+
+		balance(&client).await.unwrap();
+
+		let nonces = nonces(&exchange, "Balance");
+		assert_eq!(nonces.len(), 2);
+		assert!(nonces[1] - nonces[0] < NONCE_BUMP.as_nanos() as u64);
+	}
+
+	#[tokio::test]
+	async fn buys_beyond_the_free_balance_fail_before_ordering() {
+		let exchange = exchange().await;
+		exchange.set_balance("ZUSD", dec("150"));
+		let client = exchange.client();
+		let options = BuyOptions {
+			reserve: dec("60"),
+			..BuyOptions::default()
+		};
+
+		// 100 and its 0.26 fee against the 90 left above the reserve
+		let refused = buy_for_amount(&client, "XXBTZUSD", dec("100"), &options).await;
+		assert_eq!(refused.unwrap_err(), "insufficient funds: needs 100.260000, 90 available");
+		assert!(exchange.calls("AddOrder").is_empty());
+
+		// a resting order holds part of the balance too
+		let resting = BuyOptions {
+			limit_price: Some(dec("20000")),
+			..BuyOptions::default()
+		};
+		buy_for_amount(&client, "XXBTZUSD", dec("60"), &resting).await.unwrap();
+		let refused = buy_for_amount(&client, "XXBTZUSD", dec("100"), &BuyOptions::default()).await;
+		assert_eq!(refused.unwrap_err(), "insufficient funds: needs 100.260000, 89.8440000 available");
+
+		buy_for_amount(&client, "XXBTZUSD", dec("80"), &BuyOptions::default()).await.unwrap();
+	}
+
+	fn order_json(vol: serde_json::Value, cost: serde_json::Value, price: serde_json::Value) -> serde_json::Value {
+		serde_json::json!({
+			"refid": null,
+			"userref": 0,
+			"status": "closed",
+			"opentm": 1688666559.8974,
+			"starttm": 0,
+			"expiretm": 0,
+			"descr": {"pair": "XBTUSD", "type": "buy", "ordertype": "market", "price": price, "price2": 0, "leverage": "none", "order": "buy 1.25000000 XBTUSD @ market", "close": ""},
+			"vol": vol,
+			"vol_exec": vol,
+			"cost": cost,
+			"fee": "0.0780",
+			"price": price,
+			"stopprice": "0",
+			"limitprice": "0",
+			"misc": "",
+			"oflags": "fciq",
+			"closetm": 1688666559.9
+		})
+	}
+
+	#[test]
+	fn numbers_decode_from_strings_and_numbers() {
+		let from_strings: OpenOrder = serde_json::from_value(order_json("1.25".into(), "46875.0".into(), "37500.0".into())).unwrap();
+		let from_numbers: OpenOrder = serde_json::from_value(order_json(1.25.into(), 46875.0.into(), 37500.into())).unwrap();
+
+		for order in [&from_strings, &from_numbers] {
+			assert_eq!(parse_decimal(&order.vol).unwrap(), dec("1.25"));
+			assert_eq!(parse_decimal(&order.cost).unwrap(), dec("46875"));
+			assert_eq!(parse_decimal(&order.price).unwrap(), dec("37500"));
+			assert_eq!(parse_decimal(&order.descr.price).unwrap(), dec("37500"));
+		}
+
+		// Decimal fields take either form on their own
+		let balance: ExtendedBalance = serde_json::from_str(r#"{"balance": 12.5, "hold_trade": "2.5"}"#).unwrap();
+		assert_eq!(balance.available(), dec("10"));
+	}
+
+	#[test]
+	fn numbers_of_other_types_fail_to_decode() {
+		let refused = serde_json::from_value::<OpenOrder>(order_json("1.25".into(), true.into(), "37500.0".into()));
+		assert!(refused.is_err());
+	}
+
+	#[tokio::test]
+	async fn otp_and_nonce_are_signed_with_the_body() {
+		let exchange = exchange().await;
+		let mut client = exchange.client();
+		client.account.otp = Some(String::from("123 456+"));
+
+		balance(&client).await.unwrap();
+
+		let calls = exchange.calls("Balance");
+		let call = &calls[0];
+		assert_eq!(call.params["otp"], "123 456+");
+		assert!(call.params["nonce"].parse::<u64>().is_ok());
+		assert!(call.body.contains("otp=123+456%2B"));
+
+		let secret = base64::decode(mock::SECRET).unwrap();
+		let expected = sign("/0/private/Balance", &call.params["nonce"], call.body.as_bytes(), &secret);
+		assert_eq!(call.headers["api-sign"], expected);
+	}
+
+	#[test]
+	fn paging_stops_at_the_final_page() {
+		// 120 items in pages of 50
+		let mut ofs = 0;
+		let mut pages = 0;
+		loop {
+			let page_len = (120 - ofs).min(50) as usize;
+			pages += 1;
+			if is_last_page(120, ofs, page_len) {
+				break;
+			}
+			ofs += page_len as u32;
+		}
+		assert_eq!((pages, ofs), (3, 100));
+
+		assert!(!is_last_page(100, 50, 49));
+		assert!(is_last_page(100, 50, 50));
+		// items gone since the count was taken
+		assert!(is_last_page(100, 50, 0));
+		assert!(is_last_page(0, 0, 0));
+		assert!(is_last_page(u32::MAX, u32::MAX, 1));
+	}
+
+	#[test]
+	fn safe_cost_refuses_overflow_and_underflow() {
+		assert_eq!(safe_cost(dec("40000"), dec("0.0025")).unwrap(), dec("100"));
+		assert_eq!(safe_cost(dec("40000"), Decimal::ZERO).unwrap(), Decimal::ZERO);
+
+		// too large for a Decimal
+		let overflow = safe_cost(Decimal::MAX, dec("2")).unwrap_err();
+		assert_eq!(overflow.to_string(), format!("cost of 2 at {} is out of range", Decimal::MAX));
+
+		// beyond 28 decimals the product rounds to zero
+		let underflow = safe_cost(dec("0.00000000000001"), dec("0.000000000000001")).unwrap_err();
+		assert!(matches!(underflow, KrakenError::CostOutOfRange { .. }));
+	}
+
+	#[tokio::test]
+	async fn a_call_without_params_sends_just_the_nonce() {
+		let exchange = exchange().await;
+
+		balance(&exchange.client()).await.unwrap();
+
+		let call = &exchange.calls("Balance")[0];
+		let nonce = &call.params["nonce"];
+		assert_eq!(call.body, format!("nonce={}", nonce));
+		assert_eq!(call.headers["content-type"], "application/x-www-form-urlencoded");
+
+		let secret = base64::decode(mock::SECRET).unwrap();
+		assert_eq!(call.headers["api-sign"], sign("/0/private/Balance", nonce, call.body.as_bytes(), &secret));
+	}
+
+	#[tokio::test]
+	async fn a_validated_order_has_no_txid() {
+		let exchange = exchange().await;
+		let client = exchange.client();
+
+		let validated = add_order(&client, NewOrder { validate: Some(true), ..mock::market_buy("XXBTZUSD", dec("0.0025")) }).await.unwrap();
+		assert!(validated.is_validation());
+		assert!(exchange.orders().is_empty());
+
+		let placed = add_order(&client, mock::market_buy("XXBTZUSD", dec("0.0025"))).await.unwrap();
+		assert!(!placed.is_validation());
+		assert_eq!(placed.txid.unwrap().len(), 1);
+	}
+
+	#[tokio::test]
+	async fn an_absurd_ticker_is_held_to_the_volume_cap() {
+		let exchange = exchange().await;
+		// a ticker 10000x off buys 0.25 BTC for 100
+		exchange.set_price("XXBTZUSD", dec("400"));
+		let client = exchange.client();
+		let options = BuyOptions {
+			max_volume: Some(dec("0.005")),
+			..BuyOptions::default()
+		};
+
+		let clamped = buy_for_amount(&client, "XXBTZUSD", dec("100"), &options).await.unwrap();
+		assert_eq!(clamped.volume, dec("0.005"));
+
+		let skipped = buy_for_amount(&client, "XXBTZUSD", dec("100"), &BuyOptions { volume_cap: VolumeCapPolicy::Skip, ..options }).await;
+		assert_eq!(skipped.unwrap_err(), "volume 0.25 for XBTUSD is above the cap of 0.005");
+		assert_eq!(exchange.orders().len(), 1);
+	}
+
+	#[test]
+	fn a_volume_within_the_cap_is_left_alone() {
+		let pair = xbtusd();
+
+		assert_eq!(cap_volume(&pair, dec("0.004"), Some(dec("0.005")), VolumeCapPolicy::Skip).unwrap(), dec("0.004"));
+		assert_eq!(cap_volume(&pair, dec("250"), None, VolumeCapPolicy::Skip).unwrap(), dec("250"));
+		// the cap is rounded down to the lot decimals
+		assert_eq!(cap_volume(&pair, dec("1"), Some(dec("0.123456789")), VolumeCapPolicy::Clamp).unwrap(), dec("0.12345678"));
+	}
+
+	#[tokio::test]
+	async fn an_unreachable_host_is_a_connect_error() {
+		let exchange = MockExchange::start().await;
+		let http = reqwest::Client::builder().no_proxy().connect_timeout(Duration::from_millis(200)).build().unwrap();
+		// TEST-NET-1, reserved for documentation and never routed
+		let client = exchange.client().with_base_url("http://192.0.2.1").with_http_client(http);
+
+		let error = private::<HashMap<String, String>>(&client, "Balance", &mut HashMap::new()).await.unwrap_err();
+		assert!(matches!(error, KrakenError::Connect(_)), "{:?}", error);
+		// retried as a connection failure
+		assert_eq!(classify::<()>(&Err(error)), Some(ErrorClass::Connection));
+		assert!(exchange.calls("Balance").is_empty());
+	}
+
+	#[test]
+	fn triggers_serialize_as_kraken_names_them() {
+		assert_eq!(serde_json::to_string(&Trigger::Index).unwrap(), r#""index""#);
+		assert_eq!(serde_json::from_str::<Trigger>(r#""last""#).unwrap(), Trigger::Last);
+		assert_eq!(Trigger::Index.to_string(), "index");
+	}
+
+	#[tokio::test]
+	async fn a_trigger_is_sent_only_with_triggered_order_types() {
+		let exchange = exchange().await;
+		let client = exchange.client();
+		let stop_loss = || NewOrder {
+			order_type: OrderType::StopLoss,
+			price: Some("38000".parse().unwrap()),
+			trigger: Some(Trigger::Index),
+			..mock::market_buy("XXBTZUSD", dec("0.0025"))
+		};
+
+		let params = order_params(&client, stop_loss()).await.unwrap();
+		assert_eq!((params["ordertype"].as_str(), params["trigger"].as_str()), ("stop-loss", "index"));
+
+		let untriggered = order_params(&client, NewOrder { trigger: None, ..stop_loss() }).await.unwrap();
+		assert!(!untriggered.contains_key("trigger"));
+
+		let refused = order_params(&client, NewOrder { order_type: OrderType::Limit, ..stop_loss() }).await;
+		assert_eq!(refused.unwrap_err(), "trigger only applies to stop and take profit orders, not limit");
+		assert!(exchange.calls("AddOrder").is_empty());
+	}
+
+	#[tokio::test]
+	async fn a_busy_exchange_is_retried_even_for_orders() {
+		let exchange = exchange().await;
+		exchange.script("AddOrder", 200, r#"{"error": ["EService:Busy"]}"#);
+		exchange.script("AddOrder", 200, r#"{"error": ["EService:Unavailable"]}"#);
+		let client = exchange.client();
+
+		let placed = add_order(&client, mock::market_buy("XXBTZUSD", dec("0.0025"))).await.unwrap();
+		assert!(!placed.is_validation());
+		assert_eq!(exchange.calls("AddOrder").len(), 3);
+		assert_eq!(exchange.orders().len(), 1);
+
+		// past the retries the error is returned
+		for _ in 0..4 {
+			exchange.script("Balance", 200, r#"{"error": ["EService:Busy"]}"#);
+		}
+		let refused = balance(&client).await.unwrap_err();
+		assert!(refused.to_string().contains("EService:Busy"), "{}", refused);
+		assert_eq!(exchange.calls("Balance").len(), 4);
+	}
+
+	#[test]
+	fn post_data_encodes_plus_spaces_and_unicode() {
+		let params: HashMap<String, String> = [("nonce", "1616492376594"), ("comment", "dca run #3 + €")]
+			.iter()
+			.map(|(name, value)| (name.to_string(), value.to_string()))
+			.collect();
+
+		let body = String::from_utf8(post_data(&params)).unwrap();
+		let mut pairs: Vec<&str> = body.split('&').collect();
+		pairs.sort_unstable();
+		assert_eq!(pairs, ["comment=dca+run+%233+%2B+%E2%82%AC", "nonce=1616492376594"]);
+		assert!(body.is_ascii());
+	}
+
+	#[tokio::test]
+	async fn the_signed_body_is_the_one_sent() {
+		let exchange = exchange().await;
+		let client = exchange.client();
+		let mut params: HashMap<String, String> = HashMap::new();
+		params.insert("comment".to_owned(), "dca run #3 + €".to_owned());
+
+		private::<HashMap<String, String>>(&client, "Balance", &mut params).await.unwrap();
+
+		let call = &exchange.calls("Balance")[0];
+		// the mock decodes the body the way Kraken does
+		assert_eq!(call.params["comment"], "dca run #3 + €");
+		let secret = base64::decode(mock::SECRET).unwrap();
+		assert_eq!(call.headers["api-sign"], sign("/0/private/Balance", &call.params["nonce"], call.body.as_bytes(), &secret));
+	}
+
+	#[tokio::test]
+	async fn an_order_placed_twice_under_the_userref_is_flagged() {
+		let exchange = exchange().await;
+		let client = exchange.client();
+		let tagged = |volume: &str, userref: i32| NewOrder {
+			userref: Some(userref.to_string()),
+			..mock::market_buy("XXBTZUSD", dec(volume))
+		};
+
+		add_order(&client, tagged("0.0025", 7)).await.unwrap();
+		let repeat = add_order(&client, tagged("0.0025", 7)).await.unwrap();
+		add_order(&client, tagged("0.003", 7)).await.unwrap();
+		// the same order under another userref is another run's
+		add_order(&client, tagged("0.0025", 8)).await.unwrap();
+
+		assert_eq!(detect_duplicate_fills(&client, 7).await.unwrap(), repeat.txid.unwrap());
+		assert!(detect_duplicate_fills(&client, 8).await.unwrap().is_empty());
+
+		// a minute apart it's another buy
+		let (first, _) = exchange.orders().remove(0);
+		exchange.backdate(&first, 120.0);
+		assert!(detect_duplicate_fills(&client, 7).await.unwrap().is_empty());
+	}
+
+	#[tokio::test]
+	async fn zero_balances_are_left_out_unless_asked_for() {
+		let exchange = exchange().await;
+		exchange.set_balance("XXBT", Decimal::ZERO);
+		exchange.set_balance("XETH", dec("0.5"));
+		let client = exchange.client();
+
+		let balances = balance_decimal(&client, false).await.unwrap();
+		let mut assets: Vec<&str> = balances.keys().map(String::as_str).collect();
+		assets.sort_unstable();
+		assert_eq!(assets, ["XETH", "ZUSD"]);
+
+		let balances = balance_decimal(&client, true).await.unwrap();
+		assert_eq!(balances.len(), 3);
+		assert_eq!(balances["XXBT"], Decimal::ZERO);
+		assert_eq!(balances["XETH"], dec("0.5"));
+	}
+
+	#[test]
+	fn prices_parse_as_kraken_takes_them() {
+		let price = |s: &str| s.parse::<Price>();
+
+		assert_eq!(price(" 154.20 ").unwrap(), Price { offset: None, value: dec("154.20"), percent: false });
+		assert_eq!(price("+2%").unwrap(), Price { offset: Some('+'), value: dec("2"), percent: true });
+		assert_eq!(price("#1.5").unwrap(), Price { offset: Some('#'), value: dec("1.5"), percent: false });
+		assert_eq!(price("-1.5").unwrap().to_string(), "-1.5");
+
+		assert_eq!(
+			price("154,20").unwrap_err(),
+			r#"invalid price "154,20": use '.' as the decimal separator, without thousands separators"#
+		);
+		assert!(price("40,000.5").unwrap_err().contains("decimal separator"));
+		for symbol in ["$154.20", "154.20€", "154.20 USD", "", "1.2.3"] {
+			assert!(price(symbol).unwrap_err().contains("without currency symbols"), "{:?}", symbol);
+		}
+		assert!(price("2%").unwrap_err().contains("only a relative price"));
+	}
+
+	#[tokio::test]
+	async fn a_null_result_without_errors_is_an_empty_result() {
+		let exchange = exchange().await;
+		let client = exchange.client();
+		let empty = r#"{"error": [], "result": null}"#;
+
+		// retried like a server error
+		exchange.script("Balance", 200, empty);
+		assert_eq!(balance(&client).await.unwrap()["ZUSD"], "1000");
+		assert_eq!(exchange.calls("Balance").len(), 2);
+
+		// but not for an order that may have gone through
+		exchange.script("AddOrder", 200, empty);
+		let refused = add_order(&client, mock::market_buy("XXBTZUSD", dec("0.0025"))).await;
+		assert_eq!(refused.unwrap_err(), "Kraken returned neither a result nor an error");
+		assert_eq!(exchange.calls("AddOrder").len(), 1);
+
+		let response: ApiResponse<HashMap<String, String>> = decode(empty).unwrap();
+		assert_eq!(into_result(response).unwrap_err(), KrakenError::EmptyResult.to_string());
+	}
+
+	#[test]
+	fn a_cost_beyond_the_tolerance_is_refused() {
+		assert!(check_cost("XBTUSD", dec("101"), dec("100"), dec("1")).is_ok());
+		assert!(check_cost("XBTUSD", dec("99"), dec("100"), dec("1")).is_ok());
+		assert_eq!(
+			check_cost("XBTUSD", dec("101.5"), dec("100"), dec("1")).unwrap_err(),
+			"cost 101.5 of the XBTUSD buy is 1.50% off its amount of 100, beyond the cost tolerance of 1%"
+		);
+		// a cost of ten times the amount, e.g. a misplaced decimal
+		assert!(check_cost("XBTUSD", dec("1000"), dec("100"), dec("5")).unwrap_err().contains("is 900% off"));
+		assert!(check_cost("XBTUSD", Decimal::ZERO, dec("100"), dec("5")).unwrap_err().contains("is -100% off"));
+		// nothing to compare with
+		assert!(check_cost("XBTUSD", dec("1000"), Decimal::ZERO, Decimal::ZERO).is_ok());
+	}
+
+	#[tokio::test]
+	async fn a_buy_off_its_amount_is_never_sent() {
+		let exchange = exchange().await;
+		// 33.33 buys 0.00047614 at 70000, for 33.3298
+		exchange.set_price("XXBTZUSD", dec("70000"));
+		let client = exchange.client();
+		let options = |tolerance: &str| BuyOptions {
+			cost_tolerance_pct: Some(dec(tolerance)),
+			..BuyOptions::default()
+		};
+
+		let refused = buy_for_amount(&client, "XXBTZUSD", dec("33.33"), &options("0")).await;
+		assert!(refused.unwrap_err().contains("beyond the cost tolerance of 0%"));
+		assert!(exchange.calls("AddOrder").is_empty());
+
+		let placed = buy_for_amount(&client, "XXBTZUSD", dec("33.33"), &options("0.01")).await.unwrap();
+		assert_eq!(placed.cost(), dec("33.32980000"));
+	}
+
+	#[tokio::test]
+	async fn clients_sharing_connections_keep_their_own_nonces() {
+		let exchange = exchange().await;
+		let http = reqwest::Client::builder().no_proxy().build().unwrap();
+		let first = exchange.client().with_http_client(http.clone());
+		let mut second = exchange.client().with_http_client(http);
+		second.account.key = String::from("other-key");
+		let nonces_of = |key: &str| -> Vec<u64> {
+			exchange
+				.calls("OpenOrders")
+				.iter()
+				.filter(|call| call.headers["api-key"] == key)
+				.map(|call| call.params["nonce"].parse().unwrap())
+				.collect()
+		};
+
+		// the first client's rejected nonce bumps only its own sequence
+		exchange.script("OpenOrders", 200, r#"{"error": ["EAPI:Invalid nonce"]}"#);
+		open_orders(&first, false, None).await.unwrap();
+		open_orders(&second, false, None).await.unwrap();
+		open_orders(&first, false, None).await.unwrap();
+		open_orders(&second, false, None).await.unwrap();
+
+		let (first_nonces, second_nonces) = (nonces_of(mock::KEY), nonces_of("other-key"));
+		assert_eq!((first_nonces.len(), second_nonces.len()), (3, 2));
+		assert!(first_nonces.windows(2).all(|pair| pair[0] < pair[1]));
+		assert!(second_nonces.windows(2).all(|pair| pair[0] < pair[1]));
+		let bump = NONCE_BUMP.as_nanos() as u64;
+		assert!(first_nonces[2] >= first_nonces[0] + bump);
+		assert!(second_nonces[1] < first_nonces[1]);
+	}
+
+	#[tokio::test]
+	async fn a_price_above_the_cap_is_never_bought() {
+		let exchange = exchange().await;
+		let client = exchange.client();
+		let capped = |max_price: &str| BuyOptions {
+			max_price: Some(dec(max_price)),
+			..BuyOptions::default()
+		};
+
+		let refused = buy_for_amount(&client, "XXBTZUSD", dec("100"), &capped("39999.9")).await.unwrap_err();
+		let expected = KrakenError::SlippageExceeded {
+			pair: String::from("XXBTZUSD"),
+			price: dec("40000"),
+			max_price: dec("39999.9"),
+		};
+		assert_eq!(refused, expected.to_string());
+		assert!(exchange.calls("AddOrder").is_empty());
+
+		// at the cap it still buys
+		buy_for_amount(&client, "XXBTZUSD", dec("100"), &capped("40000")).await.unwrap();
+		assert_eq!(exchange.orders().len(), 1);
+	}
+
+	#[test]
+	fn placed_orders_are_worded_like_open_ones() {
+		let placed = |order: &str| AddOrderDescription {
+			order: order.to_owned(),
+			close: None,
+		};
+
+		assert_eq!(format_placed(&placed("buy 0.50000000 XBTUSD @ limit 40000.0")), "Buy 0.50000000 XBTUSD @ limit 40000.0");
+		assert_eq!(format_placed(&placed("sell 1.25000000 ETHUSD @ market")), "Sell 1.25000000 ETHUSD @ market");
+		// anything else is kept as Kraken worded it
+		assert_eq!(format_placed(&placed("buy 1.0 XBTUSD @ stop loss 35000.0")), "buy 1.0 XBTUSD @ stop loss 35000.0");
+	}
+
+	#[tokio::test]
+	async fn history_pages_past_the_size_limit_are_refused() {
+		let exchange = exchange().await;
+		buy_for_amount(&exchange.client(), "XXBTZUSD", dec("100"), &BuyOptions::default()).await.unwrap();
+
+		let client = exchange.client().with_max_body_size(64);
+		let refused = closed_orders(&client, false, None).await.unwrap_err();
+		assert_eq!(refused, format!("{:?}", KrakenError::BodyTooLarge { limit: 64 }));
+
+		// other responses are read whole
+		assert_eq!(open_orders(&client, false, None).await.unwrap().len(), 0);
+	}
+
+	#[tokio::test]
+	async fn last_prices_are_served_from_the_ticker_cache() {
+		let exchange = exchange().await;
+		let client = exchange.client();
+
+		assert_eq!(last_price(&client, "XBTUSD").await.unwrap(), dec("40000"));
+		exchange.set_price("XXBTZUSD", dec("41000"));
+		assert_eq!(last_price(&client, "XXBTZUSD").await.unwrap(), dec("40000"));
+		assert_eq!(exchange.calls("Ticker").len(), 1);
+
+		let uncached = exchange.client().with_ticker_ttl(Duration::ZERO);
+		assert_eq!(last_price(&uncached, "XBTUSD").await.unwrap(), dec("41000"));
+
+		assert_eq!(last_price(&client, "DOGEUSD").await.unwrap_err(), "unknown asset pair: DOGEUSD");
+	}
+
+	#[tokio::test]
+	async fn closed_orders_are_fetched_page_by_page() {
+		let exchange = MockExchange::start().await;
+		let client = exchange.client();
+		let order = |status: &str| {
+			serde_json::json!({
+				"refid": null, "userref": 7, "status": status, "opentm": 1.0, "starttm": 0, "expiretm": 0,
+				"descr": { "pair": "XBTUSD", "type": "buy", "ordertype": "market", "price": "0", "price2": "0",
+					"leverage": "none", "order": "buy 0.1 XBTUSD @ market", "close": "" },
+				"vol": "0.1", "vol_exec": "0.1", "cost": "4000", "fee": "10", "price": "40000",
+				"stopprice": "0", "limitprice": "0", "misc": "", "oflags": "", "closetm": 2.0, "reason": null
+			})
+		};
+		let page = |closed: serde_json::Value| serde_json::json!({ "error": [], "result": { "closed": closed, "count": 3 } }).to_string();
+		exchange.script("ClosedOrders", 200, &page(serde_json::json!({ "O1": order("closed"), "O2": order("closed") })));
+		exchange.script("ClosedOrders", 200, &page(serde_json::json!({ "O3": order("canceled") })));
+
+		let orders = all_closed_orders(&client, Some(7)).await.unwrap();
+
+		assert_eq!(orders.len(), 3);
+		let offsets: Vec<String> = exchange.calls("ClosedOrders").iter().map(|call| call.params["ofs"].clone()).collect();
+		assert_eq!(offsets, vec!["0", "2"]);
+	}
+
+	#[tokio::test]
+	async fn open_orders_are_filtered_and_sorted() {
+		let exchange = exchange().await;
+		exchange.add_pair("XETHZUSD", "ETHUSD", "XETH", "ZUSD", serde_json::json!({}));
+		exchange.set_price("XETHZUSD", dec("2000"));
+		let client = exchange.client();
+		let resting = |price: &str| BuyOptions {
+			limit_price: Some(dec(price)),
+			..BuyOptions::default()
+		};
+
+		let old = buy_for_amount(&client, "XXBTZUSD", dec("50"), &resting("20000")).await.unwrap().order.txid.unwrap()[0].clone();
+		let new = buy_for_amount(&client, "XXBTZUSD", dec("50"), &resting("30000")).await.unwrap().order.txid.unwrap()[0].clone();
+		buy_for_amount(&client, "XETHZUSD", dec("50"), &resting("1000")).await.unwrap();
+		exchange.backdate(&old, 7200.0);
+
+		let newest_first = OpenOrderFilter {
+			pair: Some(String::from("XBTUSD")),
+			sort: OpenOrderSort::NewestFirst,
+			..OpenOrderFilter::default()
+		};
+		let txids: Vec<TxId> = open_orders_filtered(&client, &newest_first).await.unwrap().into_iter().map(|order| order.txid).collect();
+		assert_eq!(txids, vec![new, old.clone()]);
+
+		let stale = OpenOrderFilter {
+			min_age: Some(Duration::from_secs(3600)),
+			trades: true,
+			..OpenOrderFilter::default()
+		};
+		let txids: Vec<TxId> = open_orders_filtered(&client, &stale).await.unwrap().into_iter().map(|order| order.txid).collect();
+		assert_eq!(txids, vec![old]);
+		assert_eq!(exchange.calls("OpenOrders").last().unwrap().params.get("trades").map(String::as_str), Some("true"));
+
+		assert_eq!(open_orders_filtered(&client, &OpenOrderFilter::default()).await.unwrap().len(), 3);
+	}
+
+	#[tokio::test]
+	async fn tradable_pairs_leave_out_dark_pool_and_offline_pairs() {
+		let exchange = exchange().await;
+		exchange.add_pair("XXBTZUSD.d", "XBTUSD.d", "XXBT", "ZUSD", serde_json::json!({}));
+		exchange.add_pair("XETHZUSD", "ETHUSD", "XETH", "ZUSD", serde_json::json!({ "status": "reduce_only" }));
+		exchange.add_pair("SOLUSD", "SOLUSD", "SOL", "ZUSD", serde_json::json!({ "status": "online" }));
+		exchange.add_pair("XXBTZEUR", "XBTEUR", "XXBT", "ZEUR", serde_json::json!({}));
+		let client = exchange.client();
+
+		assert_eq!(usd_pairs(&client).await.unwrap(), vec!["SOLUSD", "XXBTZUSD"]);
+		assert_eq!(tradable_pairs(&client, "eur").await.unwrap(), vec!["XXBTZEUR"]);
+	}
+
+	#[tokio::test]
+	async fn pairs_without_a_wsname_get_one_from_their_assets() {
+		let exchange = exchange().await;
+		exchange.add_pair("SOLUSD", "SOLUSD", "SOL", "ZUSD", serde_json::json!({ "wsname": "SOL/USD" }));
+		let client = exchange.client();
+
+		assert_eq!(to_wsname(&client, "solusd").await.unwrap(), "SOL/USD");
+		assert_eq!(to_wsname(&client, "XBTUSD").await.unwrap(), "XBT/USD");
+		assert_eq!(resolve_pair(&client, "SOL/USD").await.unwrap(), "SOLUSD");
+	}
+
+	#[tokio::test]
+	async fn a_runs_open_orders_are_canceled_by_its_userref() {
+		let exchange = exchange().await;
+		let client = exchange.client();
+		let resting = |userref: i32| BuyOptions {
+			limit_price: Some(dec("20000")),
+			userref: Some(userref),
+			..BuyOptions::default()
+		};
+		buy_for_amount(&client, "XXBTZUSD", dec("50"), &resting(7)).await.unwrap();
+		buy_for_amount(&client, "XXBTZUSD", dec("50"), &resting(7)).await.unwrap();
+		buy_for_amount(&client, "XXBTZUSD", dec("50"), &resting(8)).await.unwrap();
+
+		assert_eq!(cancel_by_userref(&client, 7).await.unwrap().count, 2);
+		assert_eq!(open_orders(&client, false, None).await.unwrap().len(), 1);
+		// nothing left to cancel is no error
+		assert_eq!(cancel_by_userref(&client, 7).await.unwrap().count, 0);
+	}
+
+	#[tokio::test]
+	async fn deposit_limits_are_none_when_kraken_sends_false() {
+		let exchange = exchange().await;
+		let client = exchange.client();
+		exchange.script(
+			"DepositMethods",
+			200,
+			r#"{"error": [], "result": [
+				{"method": "Bitcoin", "limit": false, "fee": "0.0000000000", "gen-address": true},
+				{"method": "SWIFT", "limit": "1000000.00", "fee": "5", "address-setup-fee": "0"}
+			]}"#,
+		);
+
+		let methods = deposit_methods(&client, "XBT", Some(AssetClass::Forex)).await.unwrap();
+
+		assert_eq!(methods.iter().map(|method| method.limit).collect::<Vec<_>>(), vec![None, Some(dec("1000000"))]);
+		assert!(methods[0].gen_address && !methods[1].gen_address);
+		assert_eq!(exchange.calls("DepositMethods")[0].params["aclass"], "forex");
+	}
+
+	#[tokio::test]
+	async fn a_volume_is_priced_at_the_book_level_it_reaches() {
+		let exchange = exchange().await;
+		let client = exchange.client();
+		let book = r#"{"error": [], "result": {"XXBTZUSD": {"asks": [["40000", "1", 0], ["40100", "1", 0], ["40500", "5", 0]], "bids": [["39900", "0.5", 0], ["39800", "2", 0]]}}}"#;
+		for _ in 0..3 {
+			exchange.script("Depth", 200, book);
+		}
+
+		let buy = optimal_limit_price(&client, "XXBTZUSD", dec("1.5"), OrderDirection::Buy).await.unwrap();
+		let sell = optimal_limit_price(&client, "XXBTZUSD", dec("1"), OrderDirection::Sell).await.unwrap();
+		let too_much = optimal_limit_price(&client, "XXBTZUSD", dec("10"), OrderDirection::Sell).await;
+
+		assert_eq!((buy.price, buy.average_price), (dec("40100"), dec("40033.3")));
+		assert_eq!((sell.price, sell.average_price), (dec("39800"), dec("39850")));
+		assert!(too_much.unwrap_err().contains("only has 2.5"));
+	}
+
+	#[tokio::test]
+	async fn orders_are_canceled_by_what_the_predicate_sees() {
+		let exchange = exchange().await;
+		let client = exchange.client();
+		let resting = BuyOptions {
+			limit_price: Some(dec("20000")),
+			..BuyOptions::default()
+		};
+		let mut txids = Vec::new();
+		for _ in 0..3 {
+			let placed = buy_for_amount(&client, "XXBTZUSD", dec("50"), &resting).await.unwrap();
+			txids.extend(placed.order.txid.unwrap());
+		}
+
+		let canceled = cancel_where(&client, |order| order.txid != txids[1]).await.unwrap();
+
+		assert_eq!(canceled, 2);
+		let left: Vec<_> = open_orders(&client, false, None).await.unwrap().into_keys().collect();
+		assert_eq!(left, vec![txids[1].clone()]);
+	}
+
+	#[tokio::test]
+	async fn a_position_is_closed_against_its_direction_at_its_leverage() {
+		let exchange = exchange().await;
+		exchange.add_pair("XXBTZUSD", "XBTUSD", "XXBT", "ZUSD", serde_json::json!({ "leverage_buy": [2, 5], "leverage_sell": [2, 5] }));
+		// the mock settles a reduce only sell like a spot one
+		exchange.set_balance("XXBT", dec("2"));
+		// closing a long sells
+		let client = exchange.client().with_direction_policy(DirectionPolicy::Both);
+		let positions = r#"{"error": [], "result": {"TF5GVO-T7ZZ2-6NBKBI": {
+			"ordertxid": "OQCLML-BW3P3-BUCMWZ", "posstatus": "open", "pair": "XXBTZUSD", "time": 1605280097.8294,
+			"type": "buy", "ordertype": "limit", "cost": "40000", "fee": "64", "vol": "1", "vol_closed": "0.25", "margin": "8000"
+		}}}"#;
+		for _ in 0..3 {
+			exchange.script("OpenPositions", 200, positions);
+		}
+
+		close_position(&client, "TF5GVO-T7ZZ2-6NBKBI", Some(dec("0.5"))).await.unwrap();
+		let too_much = close_position(&client, "TF5GVO-T7ZZ2-6NBKBI", Some(dec("1"))).await;
+		close_position(&client, "TF5GVO-T7ZZ2-6NBKBI", None).await.unwrap();
+
+		let calls = exchange.calls("AddOrder");
+		assert_eq!(calls.len(), 2);
+		assert_eq!(calls[0].params["type"], "sell");
+		assert_eq!(calls[0].params["ordertype"], "market");
+		assert_eq!(calls[0].params["volume"], "0.5");
+		assert_eq!(calls[0].params["leverage"], "5");
+		assert_eq!(calls[0].params["reduce_only"], "true");
+		assert!(too_much.unwrap_err().contains("0.75 remains open"));
+		assert_eq!(calls[1].params["volume"], "0.75");
+	}
+
+	#[tokio::test]
+	async fn fees_are_in_the_fee_volume_currency_or_the_quote() {
+		let exchange = exchange().await;
+		exchange.add_pair("XXBTZEUR", "XBTEUR", "XXBT", "ZEUR", serde_json::json!({ "fee_volume_currency": null }));
+		let client = exchange.client();
+
+		assert_eq!(fee_currency_for(&client, "XXBTZUSD").await.unwrap(), "ZUSD");
+		assert_eq!(fee_currency_for(&client, "XXBTZEUR").await.unwrap(), "ZEUR");
+		assert!(fee_currency_for(&client, "XETHZUSD").await.is_err());
+	}
+}
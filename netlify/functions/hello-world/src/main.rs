@@ -1,70 +1,2205 @@
 use aws_lambda_events::event::apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse};
 use aws_lambda_events::encodings::Body;
-use http::header::HeaderMap;
+use http::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use journal::JournalSink;
 use lambda_runtime::{handler_fn, Context, Error};
 use log::LevelFilter;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 use simple_logger::SimpleLogger;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
 
+mod alert;
+mod backtest;
+mod clock;
+mod deadletter;
+mod email;
+mod fees;
+mod filter;
+mod journal;
 mod kraken;
+mod metadata;
+mod metrics;
+#[cfg(test)]
+mod mock;
+mod nonce;
+mod overview;
+mod pause;
+mod performance;
+mod plan;
+#[cfg(feature = "record-raw")]
+mod raw;
+mod reconcile;
+#[cfg(feature = "schedule")]
+mod schedule;
+mod snapshot;
+mod summary;
+
+/// no new order is started with less than this left before the Lambda deadline
+const DEADLINE_MARGIN: Duration = Duration::from_secs(10);
+
+/// time the run timeout leaves before the Lambda deadline to report the run
+const REPORT_MARGIN: Duration = Duration::from_secs(20);
+
+/// how long a placed order is watched for its fill
+const FILL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `/backtest` buys once a week over daily candles unless told otherwise
+const DEFAULT_BACKTEST_CANDLE_MINUTES: u32 = 1440;
+const DEFAULT_BACKTEST_EVERY: usize = 7;
+
+/// currency the run's spend is reported in as one total, per quote currency when not set
+const REPORT_CURRENCY: Option<&str> = option_env!("REPORT_CURRENCY");
+
+/// header carrying the `CONFIRM_TOKEN`, also accepted as the `confirm` query parameter
+const CONFIRM_HEADER: &str = "x-confirm-token";
+
+/// items `/activity` lists without a `limit`
+const DEFAULT_ACTIVITY_LIMIT: usize = 20;
+
+/// open limits `/stuck-orders` reports unless told otherwise: a day old and
+/// 5% from the market
+const DEFAULT_STUCK_AGE: Duration = Duration::from_secs(86_400);
+const DEFAULT_STUCK_DISTANCE_PCT: u32 = 5;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    SimpleLogger::new().with_level(LevelFilter::Info).init().unwrap();
+    let mut logger = SimpleLogger::new().with_level(LevelFilter::Info);
+    // the request log is written at debug level
+    if option_env!("DEBUG_LOG_REQUESTS") == Some("true") {
+        logger = logger.with_module_level("hello_world::kraken", LevelFilter::Debug);
+    }
+    logger.init().unwrap();
 
     let func = handler_fn(my_handler);
     lambda_runtime::run(func).await?;
     Ok(())
 }
 
-pub(crate) async fn my_handler(event: ApiGatewayProxyRequest, _ctx: Context) -> Result<ApiGatewayProxyResponse, Error> {
-    let path = event.path.unwrap();
+#[derive(Deserialize)]
+struct AccountConfig {
+    /// name the account's results are reported under
+    label: String,
+    #[serde(flatten)]
+    account: kraken::Account,
+    /// plan for this account, `DCA_PLAN` when not set
+    plan: Option<plan::DcaPlan>,
+}
+
+/// Accounts from the `KRAKEN_ACCOUNTS` JSON list (`[{"label": "main", "key": "...", "secret": "...", "plan": {...}}]`),
+/// or the single `KRAKEN_API_KEY`/`KRAKEN_API_SECRET` (and `KRAKEN_API_OTP`) account labelled "default".
+fn accounts() -> Result<Vec<AccountConfig>, String> {
+    match option_env!("KRAKEN_ACCOUNTS") {
+        Some(json) => serde_json::from_str(json).map_err(|e| format!("invalid KRAKEN_ACCOUNTS: {}", e)),
+        None => Ok(vec![AccountConfig {
+            label: String::from("default"),
+            account: kraken::Account {
+                key: String::from(option_env!("KRAKEN_API_KEY").unwrap()),
+                secret: String::from(option_env!("KRAKEN_API_SECRET").unwrap()),
+                otp: option_env!("KRAKEN_API_OTP").map(String::from),
+            },
+            plan: None,
+        }]),
+    }
+}
+
+/// HTTP client shared by every account's `KrakenClient`, so they reuse each
+/// other's connections. Without KRAKEN_PROXY reqwest still picks up HTTPS_PROXY.
+fn http_client() -> reqwest::Client {
+    static HTTP: OnceLock<reqwest::Client> = OnceLock::new();
+
+    HTTP.get_or_init(|| match option_env!("KRAKEN_PROXY") {
+        Some(proxy) => match kraken::proxied_http_client(proxy) {
+            Ok(http) => http,
+            Err(e) => panic!("KRAKEN_PROXY: {}", e),
+        },
+        None => reqwest::Client::new(),
+    })
+    .clone()
+}
+
+/// Client for `account`, limited to the pairs in `ALLOWED_PAIRS` (comma separated) when set,
+/// caching the balance for `BALANCE_TTL_SECS` when set, and taking its nonces from
+/// the `NONCE_TABLE` counter when set.
+async fn client_for(account: kraken::Account) -> kraken::KrakenClient {
+    let nonces = nonce::DynamoNonces::from_env(&account.key).await;
+    let mut client = kraken::KrakenClient::new(account);
+
+    if let Some(nonces) = nonces {
+        client = client.with_nonce_source(Box::new(nonces));
+    }
+
+    #[cfg(feature = "record-raw")]
+    if let Some(sink) = raw::from_env().await {
+        client = client.with_raw_sink(sink);
+    }
+
+    if let Some(secs) = option_env!("BALANCE_TTL_SECS").and_then(|secs| secs.parse::<u64>().ok()) {
+        client = client.with_balance_ttl(Duration::from_secs(secs));
+    }
+
+    // per class retry counts, the defaults for any not set
+    let retries = |count: Option<&str>| count.and_then(|count| count.parse::<u32>().ok());
+    let mut retry_policy = kraken::RetryPolicy::default();
+    if let Some(max_retries) = retries(option_env!("RATE_LIMIT_RETRIES")) {
+        retry_policy.rate_limit.max_retries = max_retries;
+    }
+    if let Some(max_retries) = retries(option_env!("NONCE_RETRIES")) {
+        retry_policy.nonce.max_retries = max_retries;
+    }
+    if let Some(max_retries) = retries(option_env!("SERVER_ERROR_RETRIES")) {
+        retry_policy.server_error.max_retries = max_retries;
+    }
+    if let Some(max_retries) = retries(option_env!("UNAVAILABLE_RETRIES")) {
+        retry_policy.unavailable.max_retries = max_retries;
+    }
+    if let Some(max_retries) = retries(option_env!("CONNECTION_RETRIES")) {
+        retry_policy.connection.max_retries = max_retries;
+    }
+    client = client.with_retry_policy(retry_policy);
+
+    // REQUEST_TIMEOUT_SECS for every request, the per category ones override it
+    let secs = |secs: Option<&str>| secs.and_then(|secs| secs.parse::<u64>().ok()).map(Duration::from_secs);
+    let mut timeouts = secs(option_env!("REQUEST_TIMEOUT_SECS")).map_or_else(kraken::Timeouts::default, kraken::Timeouts::uniform);
+    if let Some(timeout) = secs(option_env!("PUBLIC_TIMEOUT_SECS")) {
+        timeouts.public = timeout;
+    }
+    if let Some(timeout) = secs(option_env!("PRIVATE_READ_TIMEOUT_SECS")) {
+        timeouts.private_read = timeout;
+    }
+    if let Some(timeout) = secs(option_env!("PRIVATE_WRITE_TIMEOUT_SECS")) {
+        timeouts.private_write = timeout;
+    }
+    if let Some(timeout) = secs(option_env!("EXPORT_TIMEOUT_SECS")) {
+        timeouts.export = timeout;
+    }
+    client = client.with_timeouts(timeouts);
+
+    if let Some(bytes) = option_env!("MAX_HISTORY_BODY_BYTES").and_then(|bytes| bytes.parse::<usize>().ok()) {
+        client = client.with_max_body_size(bytes);
+    }
+
+    // sells are refused unless DIRECTION_POLICY is "both"
+    if let Some(policy) = option_env!("DIRECTION_POLICY").and_then(|policy| policy.parse().ok()) {
+        client = client.with_direction_policy(policy);
+    }
+
+    client = client.with_http_client(http_client());
+
+    // KRAKEN_API_URL sends the requests somewhere other than Kraken, e.g. a recording proxy
+    if let Some(url) = option_env!("KRAKEN_API_URL") {
+        client = client.with_base_url(url);
+    }
+
+    if let Some(pairs) = option_env!("ALLOWED_PAIRS") {
+        client = client.with_allowed_pairs(pairs.split(',').map(|pair| pair.trim().to_owned()).collect());
+    }
+
+    // leverage Kraken allows but AssetPairs doesn't list yet goes through with SKIP_LEVERAGE_CHECK
+    if option_env!("SKIP_LEVERAGE_CHECK") == Some("true") {
+        client = client.without_leverage_check();
+    }
+
+    // method and params of every private call at debug level, nonce and otp masked
+    if option_env!("DEBUG_LOG_REQUESTS") == Some("true") {
+        client = client.with_request_logging();
+    }
+
+    client
+}
+
+fn plan_for(account_plan: Option<plan::DcaPlan>, default_plan: &Option<Result<plan::DcaPlan, String>>) -> Result<plan::DcaPlan, String> {
+    match account_plan {
+        Some(account_plan) => account_plan.validate().map(|_| account_plan),
+        None => default_plan
+            .clone()
+            .unwrap_or_else(|| Err(String::from("no DCA plan configured"))),
+    }
+}
+
+/// Target weights of a `targets` query parameter, `<asset>:<percent>` pairs
+/// separated by commas, e.g. `XBT:60,ETH:30`.
+fn parse_targets(targets: &str) -> Result<HashMap<String, Decimal>, String> {
+    targets
+        .split(',')
+        .map(|target| match target.split_once(':') {
+            Some((asset, weight)) => weight
+                .trim()
+                .parse()
+                .map(|weight| (asset.trim().to_owned(), weight))
+                .map_err(|_| format!("invalid target weight: {}", target)),
+            None => Err(format!("invalid target: {}", target)),
+        })
+        .collect()
+}
+
+/// Random delay of up to `START_JITTER_MS` before a run so scheduled invocations
+/// don't all hit Kraken in the same second. Off unless set.
+fn start_jitter() -> Duration {
+    let max_ms = option_env!("START_JITTER_MS")
+        .and_then(|ms| ms.parse::<u64>().ok())
+        .unwrap_or(0);
+
+    if max_ms == 0 {
+        return Duration::ZERO;
+    }
+
+    let seed = ::std::time::UNIX_EPOCH.elapsed().unwrap().subsec_nanos() as u64;
+    Duration::from_millis(seed % (max_ms + 1))
+}
+
+/// Hours of the day (exchange time, UTC) runs may place orders in, from
+/// `RUN_HOURS` as `start-end` with `end` exclusive, e.g. `22-2`.
+fn run_hours() -> Option<(i64, i64)> {
+    let (start, end) = option_env!("RUN_HOURS")?.split_once('-')?;
+    Some((start.trim().parse().ok()?, end.trim().parse().ok()?))
+}
+
+/// Percent of a run's budget the free balance should cover on top of it, from
+/// `LOW_FUNDS_BUFFER_PCT`; below that the run warns that funds are running low.
+fn low_funds_buffer_pct() -> Option<Decimal> {
+    option_env!("LOW_FUNDS_BUFFER_PCT")?.trim().parse().ok()
+}
+
+/// Refuses the run unless Kraken is online or post only and, with `RUN_HOURS`
+/// set, the exchange clock is inside the allowed window. Returns the status.
+async fn schedule_guard(client: &kraken::KrakenClient) -> Result<kraken::ExchangeStatus, String> {
+    let status = kraken::system_status(client).await?.status;
+    if status != kraken::ExchangeStatus::Online && status != kraken::ExchangeStatus::PostOnly {
+        return Err(format!("exchange status is {:?}", status));
+    }
+
+    if let Some((start, end)) = run_hours() {
+        let hour = kraken::server_time(client).await?.unixtime % 86400 / 3600;
+        let allowed = if start <= end {
+            hour >= start && hour < end
+        } else {
+            hour >= start || hour < end
+        };
+
+        if !allowed {
+            return Err(format!("exchange hour {} is outside RUN_HOURS {}-{}", hour, start, end));
+        }
+    }
+
+    Ok(status)
+}
+
+/// Warns when the local clock is more than `MAX_CLOCK_SKEW_MS` off Kraken's,
+/// and refuses the run too with `ABORT_ON_CLOCK_SKEW=true`. Off unless set.
+async fn clock_guard(client: &kraken::KrakenClient) -> Result<(), String> {
+    let max_skew = match option_env!("MAX_CLOCK_SKEW_MS").and_then(|ms| ms.parse::<u64>().ok()) {
+        Some(ms) => Duration::from_millis(ms),
+        None => return Ok(()),
+    };
+
+    let skew = kraken::check_clock_skew(client).await?;
+    if skew > max_skew {
+        log::warn!("local clock is {:?} off the exchange clock", skew);
+
+        if option_env!("ABORT_ON_CLOCK_SKEW") == Some("true") {
+            return Err(format!("clock skew of {:?} exceeds {:?}", skew, max_skew));
+        }
+    }
+
+    Ok(())
+}
+
+/// Fails fast when the account's key lacks a permission the run needs, naming
+/// the missing ones. Skipped with `SKIP_PERMISSION_CHECK=true` for quicker cold starts.
+async fn permission_guard(client: &kraken::KrakenClient, dca_plan: &plan::DcaPlan) -> Result<(), String> {
+    if option_env!("SKIP_PERMISSION_CHECK") == Some("true") {
+        return Ok(());
+    }
 
-    let account = kraken::Account {
-        key: String::from(option_env!("KRAKEN_API_KEY").unwrap()),
-        secret: String::from(option_env!("KRAKEN_API_SECRET").unwrap()),
+    let entry = match dca_plan.entries.first() {
+        Some(entry) => entry,
+        None => return Ok(()),
     };
+    let pair = kraken::resolve_pair(client, &entry.pair).await?;
 
-    let tradable_asset_pair = vec![
-        "SOLUSD",
-        "DOTUSD"
-    ];
-
-    for asset_pair in tradable_asset_pair {
-        let order = kraken::NewOrder {
-            pair: asset_pair.to_string(),
-            order_direction: kraken::OrderDirection::Buy,
-            order_type: kraken::OrderType::Limit,
-            price: Some(String::from("154.00")),
-            price2: None,
-            volume: Some(String::from("2")),
-            leverage: None,
-            oflags: None,
-            starttm: None,
-            expiretm: None,
-            userref: None,
-            validate: Some(true)
-        };
-
-        let placed_order = kraken::add_order(&account, order)
-            .await
-            .expect("order not executed");
-    
-        println!("{:?}", placed_order);
-    }
-
-    let balance = kraken::balance(&account)
-        .await
-        .expect("could not get balance");
+    // validate-only runs place orders too, Kraken just doesn't execute them
+    let needed = [kraken::Permission::QueryFunds, kraken::Permission::QueryOpenOrders, kraken::Permission::CreateOrders];
 
-    print!("{:?}", balance);
-    
-    let resp = ApiGatewayProxyResponse {
-        status_code: 200,
-        headers: HeaderMap::new(),
+    let mut missing = Vec::new();
+    for permission in needed.iter().copied() {
+        if !kraken::has_permission(client, permission, &pair).await? {
+            missing.push(permission.to_string());
+        }
+    }
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        let noun = if missing.len() == 1 { "permission" } else { "permissions" };
+        Err(format!("API key lacks the {} {}", missing.join(", "), noun))
+    }
+}
+
+/// Response of `body` as `content_type`. A binary body is flagged base64, the
+/// encoding it is serialized with for API Gateway; text goes out as it is.
+fn response(status_code: i64, content_type: &'static str, body: Body) -> ApiGatewayProxyResponse {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    let is_base64_encoded = matches!(body, Body::Binary(_));
+
+    ApiGatewayProxyResponse {
+        status_code,
+        headers,
         multi_value_headers: HeaderMap::new(),
-        body: Some(Body::Text(format!("Account balance: {:?}", balance))),
-        is_base64_encoded: Some(false),
+        body: Some(body),
+        is_base64_encoded: Some(is_base64_encoded),
+    }
+}
+
+fn json_response<T: Serialize>(status_code: i64, value: &T) -> Result<ApiGatewayProxyResponse, Error> {
+    Ok(response(status_code, "application/json", Body::Text(serde_json::to_string(value)?)))
+}
+
+/// Plain text response, e.g. why a request was refused.
+fn text_response(status_code: i64, text: &str) -> ApiGatewayProxyResponse {
+    response(status_code, "text/plain; charset=utf-8", Body::Text(text.to_owned()))
+}
+
+fn remaining_time(ctx: &Context) -> Duration {
+    let now = ::std::time::UNIX_EPOCH.elapsed().unwrap();
+    Duration::from_millis(ctx.deadline)
+        .checked_sub(now)
+        .unwrap_or_default()
+}
+
+/// How long the accounts may run before the run is cut off and reported as it
+/// stands: `RUN_TIMEOUT_SECS`, at most the time left before the Lambda deadline
+/// less `REPORT_MARGIN`.
+fn run_timeout(ctx: &Context) -> Duration {
+    let left = remaining_time(ctx).checked_sub(REPORT_MARGIN).unwrap_or_default();
+
+    match option_env!("RUN_TIMEOUT_SECS").and_then(|secs| secs.parse::<u64>().ok()) {
+        Some(secs) => left.min(Duration::from_secs(secs)),
+        None => left,
+    }
+}
+
+struct AccountRun {
+    orders: Vec<summary::OrderOutcome>,
+    skipped: Vec<plan::Skipped>,
+    adjusted: Vec<plan::Adjustment>,
+    repriced: Vec<plan::Reprice>,
+    rebalanced: Vec<plan::Rebalance>,
+    errors: Vec<summary::RunError>,
+    decisions: Vec<journal::Decision>,
+    balance: Result<HashMap<String, String>, String>,
+}
+
+/// `balance` with every amount rounded to its asset's display decimals.
+async fn display_balance(client: &kraken::KrakenClient, balance: HashMap<String, String>) -> HashMap<String, String> {
+    let mut displayed = HashMap::new();
+
+    for (asset, amount) in balance {
+        let amount = match amount.parse() {
+            Ok(value) => kraken::format_amount(client, &asset, value).await,
+            Err(_) => amount,
+        };
+        displayed.insert(asset, amount);
+    }
+
+    displayed
+}
+
+/// Asset id of `pair`'s quote currency, empty when the pair is unknown.
+async fn pair_quote(client: &kraken::KrakenClient, pair: &str) -> String {
+    client
+        .metadata()
+        .await
+        .ok()
+        .and_then(|metadata| metadata.pair_info(pair).map(|info| info.quote.clone()))
+        .unwrap_or_default()
+}
+
+/// Safe mode: with `CONFIRM_TOKEN` set, only requests carrying it may place
+/// real orders. `Ok(false)` for a request without a token, which runs dry,
+/// and an error for one with the wrong token.
+fn confirmation(event: &ApiGatewayProxyRequest) -> Result<bool, &'static str> {
+    let expected = match option_env!("CONFIRM_TOKEN") {
+        Some(expected) => expected,
+        None => return Ok(true),
+    };
+
+    let token = event
+        .headers
+        .get(CONFIRM_HEADER)
+        .and_then(|token| token.to_str().ok())
+        .or_else(|| event.query_string_parameters.get("confirm").map(String::as_str));
+
+    match token {
+        Some(token) if crypto::util::fixed_time_eq(token.as_bytes(), expected.as_bytes()) => Ok(true),
+        Some(_) => Err("confirmation token does not match"),
+        None => Ok(false),
+    }
+}
+
+/// Whether a run's orders are only validated by Kraken: unless the build is
+/// `live` (`LIVE=true`) and the request `confirmed`, see `confirmation`.
+fn validate_only(live: bool, confirmed: bool) -> bool {
+    !live || !confirmed
+}
+
+/// The run plans are executed in: this invocation's own, or an interrupted one being resumed.
+struct RunInfo<'a> {
+    /// run id the journal and the orders' userref are keyed on
+    id: String,
+    /// leave out the entries the run already bought
+    resumed: bool,
+    /// only validate the orders, see `confirmation`
+    validate: bool,
+    journal_sink: Option<&'a dyn JournalSink>,
+    /// orders of the account in progress as they complete, reported when the
+    /// run timeout cuts the account off
+    completed: Mutex<Vec<summary::OrderOutcome>>,
+}
+
+/// Plan entry that passed the run's checks, ready to be bought.
+struct ReadyBuy<'a> {
+    entry: &'a plan::PlanEntry,
+    /// pair the entry resolved to
+    pair: String,
+    amount: Decimal,
+    options: kraken::BuyOptions,
+    spread_pct: Option<Decimal>,
+}
+
+/// Places `ready`'s buys and returns one result per buy in the same order. With
+/// `batch`, each pair's buys go through `kraken::buy_batch`, in as few calls as
+/// the batch limit allows; a pair with a single buy gets a plain AddOrder.
+async fn place_buys(client: &kraken::KrakenClient, batch: bool, ready: &[ReadyBuy<'_>]) -> Vec<Result<kraken::PlacedBuy, String>> {
+    if !batch {
+        let mut placed = Vec::new();
+        for buy in ready {
+            placed.push(kraken::buy_for_amount(client, &buy.pair, buy.amount, &buy.options).await);
+        }
+        return placed;
+    }
+
+    let mut pairs: Vec<&str> = Vec::new();
+    for buy in ready {
+        if !pairs.contains(&buy.pair.as_str()) {
+            pairs.push(&buy.pair);
+        }
+    }
+
+    let mut placed: Vec<Option<Result<kraken::PlacedBuy, String>>> = Vec::new();
+    placed.resize_with(ready.len(), || None);
+
+    for pair in pairs {
+        let indices: Vec<_> = (0..ready.len()).filter(|&i| ready[i].pair == pair).collect();
+        let buys: Vec<_> = indices.iter().map(|&i| (ready[i].amount, ready[i].options)).collect();
+
+        for (i, result) in indices.into_iter().zip(kraken::buy_batch(client, pair, &buys).await) {
+            placed[i] = Some(result);
+        }
+    }
+
+    placed
+        .into_iter()
+        .map(|result| result.unwrap_or_else(|| Err(String::from("buy missing from its pair's batch"))))
+        .collect()
+}
+
+async fn run_plan(ctx: &Context, run_info: &RunInfo<'_>, label: &str, client: &kraken::KrakenClient, dca_plan: &plan::DcaPlan) -> AccountRun {
+    let journal = journal::Recorder::new(&run_info.id, label, run_info.journal_sink);
+    let userref = journal::run_userref(&run_info.id);
+    let filters: Vec<_> = dca_plan.filters.iter().map(|filter| filter.build()).collect();
+
+    let reserved = match plan::apply_reserve(client, dca_plan).await {
+        Ok(reserved) => reserved,
+        Err(e) => {
+            log::error!("[{}] reserve check failed: {}", label, e);
+            plan::ReservedAmounts {
+                amounts: Vec::new(),
+                conversion_rate: None,
+                multipliers: HashMap::new(),
+                adjusted: Vec::new(),
+                skipped: dca_plan
+                    .entries
+                    .iter()
+                    .map(|entry| plan::Skipped {
+                        pair: entry.pair.clone(),
+                        reason: format!("reserve check failed: {}", e),
+                    })
+                    .collect(),
+            }
+        }
     };
 
-    Ok(resp)
-}
\ No newline at end of file
+    if let (Some(rate), Some(budget_currency)) = (reserved.conversion_rate, &dca_plan.budget_currency) {
+        log::info!("[{}] amounts converted from {} to {} at {}", label, budget_currency, dca_plan.quote, rate);
+    }
+
+    for (pair, multiplier) in &reserved.multipliers {
+        log::info!("[{}] {} amount multiplied by {} for the dip strategy", label, pair, multiplier);
+    }
+
+    for adjustment in &reserved.adjusted {
+        log::info!(
+            "[{}] {} lowered from {} to {} to keep the reserve",
+            label, adjustment.pair, adjustment.amount, adjustment.adjusted
+        );
+        journal.record(&adjustment.pair, journal::Action::Adjusted {
+            amount: adjustment.amount,
+            adjusted: adjustment.adjusted,
+        });
+    }
+
+    let mut prepared = Vec::new();
+    for (entry, amount) in plan::order_entries(client, dca_plan, reserved.amounts).await {
+        prepared.push((entry, amount, plan::prepare_entry(client, entry, &dca_plan.quote).await));
+    }
+
+    let mut buys = Vec::new();
+    let mut orders = Vec::new();
+    let mut repriced = Vec::new();
+    let mut errors = Vec::new();
+    let mut skipped = Vec::new();
+    let mut ready = Vec::new();
+
+    for reserve_skip in &reserved.skipped {
+        log::warn!("[{}] skipping {}: {}", label, reserve_skip.pair, reserve_skip.reason);
+        journal.record(&reserve_skip.pair, journal::Action::Skipped { reason: reserve_skip.reason.clone() });
+    }
+    skipped.extend(reserved.skipped);
+
+    let mut skip = |pair: &str, reason: String| {
+        log::warn!("[{}] skipping {}: {}", label, pair, reason);
+        journal.record(pair, journal::Action::Skipped { reason: reason.clone() });
+        skipped.push(plan::Skipped { pair: pair.to_owned(), reason });
+    };
+
+    for (entry, amount, prepared_entry) in prepared {
+        if remaining_time(ctx) < DEADLINE_MARGIN {
+            skip(&entry.pair, String::from("too close to the Lambda deadline"));
+            continue;
+        }
+
+        let prepared_entry = match prepared_entry {
+            Ok(prepared_entry) => prepared_entry,
+            Err(e) => {
+                metrics::order_failed();
+                log::error!("[{}] order for {} not executed: {}", label, entry.pair, e);
+                journal.record(&entry.pair, journal::Action::Failed { error: e.clone() });
+                errors.push(summary::RunError {
+                    pair: Some(entry.pair.clone()),
+                    error: e,
+                });
+                continue;
+            }
+        };
+
+        journal.record(&entry.pair, journal::Action::Prepared {
+            resolved_pair: prepared_entry.pair.clone(),
+            limit_price: prepared_entry.limit_price,
+            max_price: prepared_entry.max_price,
+        });
+
+        if let Ok(metadata) = client.metadata().await {
+            let status = metadata
+                .pair_info(&prepared_entry.pair)
+                .and_then(|info| plan::check_status(info, entry));
+
+            if let Some(reason) = status {
+                skip(&entry.pair, reason);
+                continue;
+            }
+        }
+
+        let mut filtered = None;
+        for (config, filter) in dca_plan.filters.iter().zip(&filters) {
+            filtered = filter
+                .check(client, &prepared_entry.pair)
+                .await
+                .unwrap_or_else(|e| Some(format!("check failed: {}", e)))
+                .map(|reason| format!("{} filter: {}", config.name(), reason));
+
+            if filtered.is_some() {
+                break;
+            }
+        }
+
+        if let Some(reason) = filtered {
+            skip(&entry.pair, reason);
+            continue;
+        }
+
+        let mut spread_pct = None;
+        if let Some(max_spread_pct) = dca_plan.max_spread_pct {
+            let spread = match plan::spread_pct(client, &prepared_entry.pair).await {
+                Ok(spread) if spread > max_spread_pct => Err(format!(
+                    "spread of {}% is wider than the {}% allowed",
+                    spread.round_dp(4),
+                    max_spread_pct
+                )),
+                Ok(spread) => Ok(spread),
+                Err(e) => Err(format!("spread check failed: {}", e)),
+            };
+
+            match spread {
+                Ok(spread) => spread_pct = Some(spread.round_dp(4)),
+                Err(reason) => {
+                    skip(&entry.pair, reason);
+                    continue;
+                }
+            }
+        }
+
+        if run_info.resumed {
+            let executed = plan::check_executed(client, &prepared_entry.pair, userref)
+                .await
+                .unwrap_or_else(|e| Some(format!("check for the run's orders failed: {}", e)));
+
+            if let Some(reason) = executed {
+                skip(&entry.pair, reason);
+                continue;
+            }
+        }
+
+        if let Some(min_interval) = entry.min_interval {
+            let cooldown = plan::check_cooldown(client, &prepared_entry.pair, min_interval)
+                .await
+                .unwrap_or_else(|e| Some(format!("cooldown check failed: {}", e)));
+
+            if let Some(reason) = cooldown {
+                skip(&entry.pair, reason);
+                continue;
+            }
+        }
+
+        if let Some(max_open_orders) = entry.max_open_orders {
+            let capped = plan::check_open_orders(client, &prepared_entry.pair, max_open_orders)
+                .await
+                .unwrap_or_else(|e| Some(format!("open order check failed: {}", e)));
+
+            if let Some(reason) = capped {
+                skip(&entry.pair, reason);
+                continue;
+            }
+        }
+
+        let options = kraken::BuyOptions {
+            max_price: prepared_entry.max_price,
+            limit_price: prepared_entry.limit_price,
+            post_only: entry.post_only,
+            limit_ttl: dca_plan.order_ttl,
+            fee_currency: dca_plan.fee_currency,
+            market_price_protection: true,
+            reserve: dca_plan.reserve,
+            rounding: dca_plan.rounding,
+            max_volume: entry.max_volume,
+            volume_cap: dca_plan.volume_cap,
+            userref: Some(userref),
+            cost_tolerance_pct: dca_plan.cost_tolerance_pct,
+            validate: run_info.validate,
+        };
+
+        ready.push(ReadyBuy {
+            entry,
+            pair: prepared_entry.pair,
+            amount,
+            options,
+            spread_pct,
+        });
+    }
+
+    let placed = place_buys(client, dca_plan.batch_orders, &ready).await;
+
+    for (ReadyBuy { entry, options, spread_pct, .. }, placed) in ready.into_iter().zip(placed) {
+        match placed {
+            Ok(buy) => {
+                // validate-only orders spend nothing
+                let spent = if buy.order.is_validation() { 0.0 } else { buy.cost().to_f64().unwrap_or(0.0) };
+                metrics::order_placed(spent);
+                if buy.order.is_validation() {
+                    log::info!("[{}] would place {} for {}", label, kraken::format_placed(&buy.order.descr), buy.cost());
+                } else {
+                    log::info!("[{}] placed {} for {}: {:?}", label, kraken::format_placed(&buy.order.descr), buy.cost(), buy.order.txid);
+                }
+
+                // on record before waiting on the fills, in case the run is cut short
+                journal
+                    .record_durably(&entry.pair, journal::Action::Ordered {
+                        price: buy.price,
+                        volume: buy.volume,
+                        txid: buy.order.txid.clone(),
+                    })
+                    .await;
+
+                let mut fills = Vec::new();
+                if let Some(txids) = &buy.order.txid {
+                    match kraken::await_fills(client, txids, FILL_TIMEOUT).await {
+                        Ok(watched) => {
+                            log::info!("[{}] fills for {}: {:?}", label, entry.pair, watched);
+                            fills = watched;
+                        }
+                        Err(e) => {
+                            log::warn!("[{}] could not confirm fills for {}: {}", label, entry.pair, e);
+
+                            for txid in txids {
+                                match kraken::confirm_order(client, txid).await {
+                                    Ok(state) => {
+                                        log::info!("[{}] order {} for {}: {:?}", label, txid, entry.pair, state);
+                                        fills.extend(state.executed());
+                                    }
+                                    Err(e) => log::warn!("[{}] order {} for {} unconfirmed: {}", label, txid, entry.pair, e),
+                                }
+                            }
+                        }
+                    }
+                }
+
+                // limits left open are moved after the market while the run has time
+                let mut market_buy = None;
+                if let (Some(policy), Some(_)) = (&dca_plan.reprice, options.limit_price) {
+                    if matches!(summary::Fill::of(&fills), summary::Fill::Open { .. }) {
+                        let time_left = remaining_time(ctx).checked_sub(DEADLINE_MARGIN).unwrap_or_default();
+
+                        match plan::reprice(client, policy, entry, &buy, &options, time_left).await {
+                            Ok(outcome) => {
+                                for reprice in &outcome.reprices {
+                                    log::info!("[{}] repriced {} from {} to {}", label, reprice.pair, reprice.from, reprice.to);
+                                    journal.record(&entry.pair, journal::Action::Repriced {
+                                        from: reprice.from,
+                                        to: reprice.to,
+                                        txid: reprice.txid.clone(),
+                                    });
+                                }
+                                if let Some(gave_up) = outcome.gave_up {
+                                    log::warn!("[{}] gave up repricing {}: {:?}", label, entry.pair, gave_up);
+                                }
+                                fills = outcome.fills;
+                                repriced.extend(outcome.reprices);
+                                market_buy = outcome.market_buy;
+                            }
+                            Err(e) => log::warn!("[{}] could not reprice the order for {}: {}", label, entry.pair, e),
+                        }
+                    }
+                }
+
+                let fill = summary::Fill::of(&fills);
+                if let summary::Fill::PartiallyFilled { exec_vol, remaining } = fill {
+                    log::warn!("[{}] order for {} {}", label, entry.pair, fill);
+                    journal.record(&entry.pair, journal::Action::PartiallyFilled { exec_vol, remaining });
+                }
+                let confirmed_fee = if fills.is_empty() { None } else { Some(fills.iter().map(|fill| fill.fee).sum()) };
+
+                // the estimate at the base taker tier when no fill could be confirmed
+                let (fee, fee_estimated) = match confirmed_fee {
+                    Some(fee) => (fee, false),
+                    None => {
+                        let estimated = client
+                            .metadata()
+                            .await
+                            .ok()
+                            .and_then(|metadata| metadata.pair_info(&buy.pair).map(|info| info.estimated_fee(buy.cost())))
+                            .unwrap_or_default();
+                        (estimated, true)
+                    }
+                };
+
+                let outcome = summary::OrderOutcome {
+                    pair: buy.pair.clone(),
+                    price: buy.price,
+                    volume: buy.volume,
+                    cost: buy.cost(),
+                    quote: pair_quote(client, &buy.pair).await,
+                    fee,
+                    fee_estimated,
+                    txid: buy.order.txid.clone(),
+                    validation: buy.order.is_validation(),
+                    description: kraken::format_placed(&buy.order.descr),
+                    fill,
+                    spread_pct,
+                };
+                run_info.completed.lock().unwrap().push(outcome.clone());
+                orders.push(outcome);
+                buys.push(buy);
+
+                match market_buy {
+                    Some(Ok(buy)) => {
+                        metrics::order_placed(buy.cost().to_f64().unwrap_or(0.0));
+                        log::info!("[{}] placed {} for {}: {:?}", label, kraken::format_placed(&buy.order.descr), buy.cost(), buy.order.txid);
+                        journal.record(&entry.pair, journal::Action::Ordered {
+                            price: buy.price,
+                            volume: buy.volume,
+                            txid: buy.order.txid.clone(),
+                        });
+
+                        let fee = client
+                            .metadata()
+                            .await
+                            .ok()
+                            .and_then(|metadata| metadata.pair_info(&buy.pair).map(|info| info.estimated_fee(buy.cost())))
+                            .unwrap_or_default();
+                        let outcome = summary::OrderOutcome {
+                            pair: buy.pair.clone(),
+                            price: buy.price,
+                            volume: buy.volume,
+                            cost: buy.cost(),
+                            quote: pair_quote(client, &buy.pair).await,
+                            fee,
+                            fee_estimated: true,
+                            txid: buy.order.txid.clone(),
+                            validation: false,
+                            description: kraken::format_placed(&buy.order.descr),
+                            fill: summary::Fill::Unconfirmed,
+                            spread_pct: None,
+                        };
+                        run_info.completed.lock().unwrap().push(outcome.clone());
+                        orders.push(outcome);
+                        buys.push(buy);
+                    }
+                    Some(Err(e)) => {
+                        metrics::order_failed();
+                        log::error!("[{}] market buy of the rest of {} not executed: {}", label, entry.pair, e);
+                        journal.record(&entry.pair, journal::Action::Failed { error: e.clone() });
+                        errors.push(summary::RunError {
+                            pair: Some(entry.pair.clone()),
+                            error: e,
+                        });
+                    }
+                    None => {}
+                }
+            }
+            Err(e) => {
+                metrics::order_failed();
+                log::error!("[{}] order for {} not executed: {}", label, entry.pair, e);
+                journal.record(&entry.pair, journal::Action::Failed { error: e.clone() });
+                errors.push(summary::RunError {
+                    pair: Some(entry.pair.clone()),
+                    error: e,
+                });
+            }
+        }
+    }
+
+    for (pair, percent) in plan::actual_split(&buys) {
+        log::info!("[{}] {}: {}% of the run's spend", label, pair, percent.round_dp(2));
+    }
+
+    // a retried order that went through twice is left for the operator to cancel
+    if !run_info.validate && !buys.is_empty() {
+        match kraken::detect_duplicate_fills(client, userref).await {
+            Ok(duplicates) => {
+                for txid in duplicates {
+                    log::error!("[{}] order {} duplicates an earlier order of the run", label, txid);
+                    errors.push(summary::RunError {
+                        pair: None,
+                        error: format!("order {} duplicates an earlier order of the run", txid),
+                    });
+                }
+            }
+            Err(e) => log::warn!("[{}] could not check the run's orders for duplicates: {}", label, e),
+        }
+    }
+
+    let mut rebalanced = Vec::new();
+    if dca_plan.entries.iter().any(|entry| entry.rebalance.is_some()) {
+        if !client.allows_sells() {
+            log::warn!("[{}] rebalance targets ignored, DIRECTION_POLICY doesn't allow sells", label);
+        } else {
+            match plan::rebalance(client, dca_plan, run_info.validate).await {
+                Ok(sells) => {
+                    for (entry, sold) in sells {
+                        match sold {
+                            Ok(Some(rebalance)) => {
+                                log::info!(
+                                    "[{}] {} at {}% of the portfolio, placed {}: {:?}",
+                                    label, entry.pair, rebalance.weight_pct, rebalance.description, rebalance.txid
+                                );
+                                rebalanced.push(rebalance);
+                            }
+                            Ok(None) => {}
+                            Err(e) => {
+                                metrics::order_failed();
+                                log::error!("[{}] rebalance of {} not executed: {}", label, entry.pair, e);
+                                errors.push(summary::RunError {
+                                    pair: Some(entry.pair.clone()),
+                                    error: format!("rebalance not executed: {}", e),
+                                });
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    log::error!("[{}] rebalance check failed: {}", label, e);
+                    errors.push(summary::RunError {
+                        pair: None,
+                        error: format!("rebalance check failed: {}", e),
+                    });
+                }
+            }
+        }
+    }
+
+    // reported in the summary's balance_after, not logged on its own
+    let balance = kraken::balance(client).await;
+
+    if let Some(store) = snapshot::BalanceSnapshotStore::from_env().await {
+        let quote = match client.metadata().await {
+            Ok(metadata) => metadata.asset_id(&dca_plan.quote).unwrap_or_else(|| dca_plan.quote.clone()),
+            Err(_) => dca_plan.quote.clone(),
+        };
+
+        let stored = match snapshot::BalanceSnapshot::capture(client, label, &quote).await {
+            Ok(balance_snapshot) => store.put(&balance_snapshot).await,
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = stored {
+            log::warn!("[{}] balance snapshot not written to {}: {}", label, store.table, e);
+        }
+    }
+
+    AccountRun {
+        orders,
+        skipped,
+        adjusted: reserved.adjusted,
+        repriced,
+        rebalanced,
+        errors,
+        decisions: journal.into_decisions(),
+        balance: match balance {
+            Ok(balance) => Ok(display_balance(client, balance).await),
+            Err(e) => Err(e),
+        },
+    }
+}
+
+/// Resumes run `run_info.id` for the account: entries the journal has an order
+/// for are left out, and `run_plan` checks the rest against the orders tagged
+/// with the run's userref before buying them.
+async fn resume_run(
+    ctx: &Context,
+    run_info: &RunInfo<'_>,
+    label: &str,
+    client: &kraken::KrakenClient,
+    dca_plan: &plan::DcaPlan,
+) -> Result<AccountRun, String> {
+    let journal_sink = run_info
+        .journal_sink
+        .ok_or_else(|| String::from("no journal configured to resume from"))?;
+
+    let ordered: HashSet<String> = journal_sink
+        .decisions(&run_info.id)
+        .await?
+        .into_iter()
+        .filter(|decision| decision.account == label && matches!(decision.action, journal::Action::Ordered { .. }))
+        .map(|decision| decision.pair)
+        .collect();
+
+    let mut remaining = dca_plan.clone();
+    remaining.entries.retain(|entry| !ordered.contains(&entry.pair));
+
+    let mut account_run = run_plan(ctx, run_info, label, client, &remaining).await;
+    account_run.skipped.extend(ordered.into_iter().map(|pair| plan::Skipped {
+        pair,
+        reason: format!("already ordered in run {}", run_info.id),
+    }));
+
+    Ok(account_run)
+}
+
+pub(crate) async fn my_handler(event: ApiGatewayProxyRequest, ctx: Context) -> Result<ApiGatewayProxyResponse, Error> {
+    let path = event.path.clone().unwrap();
+
+    if path.ends_with("/metrics") {
+        return Ok(response(200, "text/plain; version=0.0.4", Body::Text(metrics::render())));
+    }
+
+    // auth check of every account, for setup and smoke tests
+    if path.ends_with("/health") {
+        let mut health = serde_json::Map::new();
+        let mut healthy = true;
+
+        for config in accounts()? {
+            let client = client_for(config.account).await;
+            let auth = match kraken::verify_credentials(&client).await {
+                Ok(()) => serde_json::json!({ "auth": "ok" }),
+                Err(e) => {
+                    log::warn!("[{}] credentials not working: {}", config.label, e);
+                    healthy = false;
+                    serde_json::json!({ "auth": "failed", "error": e.to_string() })
+                }
+            };
+            health.insert(config.label, auth);
+        }
+
+        let pause_flag = pause::PauseFlag::from_env().await;
+        let paused = match pause::pause_state(pause_flag.as_ref()).await {
+            Ok(state) => serde_json::to_value(state)?,
+            Err(e) => {
+                log::warn!("pause state not read: {}", e);
+                healthy = false;
+                serde_json::json!({ "error": e })
+            }
+        };
+        health.insert(String::from("paused"), paused);
+
+        return json_response(if healthy { 200 } else { 503 }, &health);
+    }
+
+    if path.ends_with("/balance") {
+        let mut overviews = serde_json::Map::new();
+
+        for config in accounts()? {
+            let client = client_for(config.account).await;
+            let overview = overview::account_overview(&client).await;
+            overviews.insert(config.label, serde_json::to_value(overview)?);
+        }
+
+        return json_response(200, &overviews);
+    }
+
+    // `/activity?limit=20` lists every account's latest closed orders and ledger entries
+    if path.ends_with("/activity") {
+        let limit = match event.query_string_parameters.get("limit").map(|limit| limit.parse()) {
+            Some(Ok(limit)) => limit,
+            Some(Err(_)) => return Ok(text_response(400, "invalid limit")),
+            None => DEFAULT_ACTIVITY_LIMIT,
+        };
+
+        let mut feeds = serde_json::Map::new();
+
+        for config in accounts()? {
+            let client = client_for(config.account).await;
+
+            let feed = match overview::recent_activity(&client, limit).await {
+                Ok(activity) => serde_json::to_value(activity)?,
+                Err(e) => serde_json::json!({ "error": e }),
+            };
+            feeds.insert(config.label, feed);
+        }
+
+        return json_response(200, &feeds);
+    }
+
+    // `/book-price?pair=XBTUSD&volume=0.5&side=buy` walks the book for the limit price
+    // `volume` would fill at right now, and the average price it would fill at
+    if path.ends_with("/book-price") {
+        let params = &event.query_string_parameters;
+        let pair = match params.get("pair") {
+            Some(pair) => pair.clone(),
+            None => return Ok(text_response(400, "missing pair")),
+        };
+        let volume = match params.get("volume").map(|volume| volume.parse::<Decimal>()) {
+            Some(Ok(volume)) => volume,
+            Some(Err(_)) => return Ok(text_response(400, "invalid volume")),
+            None => return Ok(text_response(400, "missing volume")),
+        };
+        let side = match params.get("side").map(|side| side.parse()) {
+            Some(Ok(side)) => side,
+            Some(Err(_)) => return Ok(text_response(400, "invalid side")),
+            None => kraken::OrderDirection::Buy,
+        };
+
+        let config = match accounts()?.into_iter().next() {
+            Some(config) => config,
+            None => return Ok(text_response(500, "no account to read the book with")),
+        };
+        let client = client_for(config.account).await;
+
+        let fill = match kraken::resolve_pair(&client, &pair).await {
+            Ok(key) => kraken::optimal_limit_price(&client, &key, volume, side).await,
+            Err(e) => Err(e),
+        };
+        return match fill {
+            Ok(fill) => json_response(200, &fill),
+            Err(e) => Ok(text_response(502, &e)),
+        };
+    }
+
+    let default_plan = option_env!("DCA_PLAN").map(plan::DcaPlan::from_json);
+
+    // `/plan?account=main` exports the account's plan as the JSON `DCA_PLAN` loads back,
+    // the only account's without `account`
+    if path.ends_with("/plan") {
+        let configs = accounts()?;
+        let config = match event.query_string_parameters.get("account") {
+            Some(label) => configs.into_iter().find(|config| config.label == *label),
+            None if configs.len() == 1 => configs.into_iter().next(),
+            None => return Ok(text_response(400, "missing account")),
+        };
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(text_response(404, "unknown account")),
+        };
+
+        return Ok(match plan_for(config.plan, &default_plan).and_then(|dca_plan| dca_plan.to_json()) {
+            Ok(json) => response(200, "application/json", Body::Text(json)),
+            Err(e) => text_response(422, &e),
+        });
+    }
+
+    if path.ends_with("/preview") {
+        let mut previews = serde_json::Map::new();
+
+        for config in accounts()? {
+            let client = client_for(config.account).await;
+
+            let preview = match plan_for(config.plan, &default_plan) {
+                Ok(dca_plan) => plan::plan_preview(&client, &dca_plan)
+                    .await
+                    .map(|orders| (orders, plan::api_cost_estimate(&dca_plan))),
+                Err(e) => Err(e),
+            };
+
+            let preview = match preview {
+                Ok((orders, api_cost)) => serde_json::json!({
+                    "orders": serde_json::to_value(orders)?,
+                    "api_cost": serde_json::to_value(api_cost)?,
+                }),
+                Err(e) => serde_json::json!({ "error": e }),
+            };
+            previews.insert(config.label, preview);
+        }
+
+        return json_response(200, &previews);
+    }
+
+    // `/rebalance-preview?quote=ZUSD&targets=XBT:60,ETH:30` lists the buys and sells
+    // that would bring every account to the target weights, nothing is placed
+    if path.ends_with("/rebalance-preview") {
+        let quote = match event.query_string_parameters.get("quote") {
+            Some(quote) => quote.clone(),
+            None => return Ok(text_response(400, "missing quote")),
+        };
+        let targets = match event.query_string_parameters.get("targets").map(|targets| parse_targets(targets)) {
+            Some(Ok(targets)) => targets,
+            Some(Err(e)) => return Ok(text_response(400, &e)),
+            None => return Ok(text_response(400, "missing targets")),
+        };
+
+        let mut previews = serde_json::Map::new();
+
+        for config in accounts()? {
+            let client = client_for(config.account).await;
+
+            let preview = match plan::rebalance_preview(&client, &targets, &quote).await {
+                Ok(orders) => serde_json::to_value(orders)?,
+                Err(e) => serde_json::json!({ "error": e }),
+            };
+            previews.insert(config.label, preview);
+        }
+
+        return json_response(200, &previews);
+    }
+
+    // `/roi?quote=ZUSD` compares every account's net deposits with its value
+    if path.ends_with("/roi") {
+        let quote = match event.query_string_parameters.get("quote") {
+            Some(quote) => quote.clone(),
+            None => return Ok(text_response(400, "missing quote")),
+        };
+
+        let mut reports = serde_json::Map::new();
+
+        for config in accounts()? {
+            let client = client_for(config.account).await;
+
+            let report = match performance::roi(&client, &quote).await {
+                Ok(report) => serde_json::to_value(report)?,
+                Err(e) => serde_json::json!({ "error": e }),
+            };
+            reports.insert(config.label, report);
+        }
+
+        return json_response(200, &reports);
+    }
+
+    // `/pnl?quote=ZUSD` reports every account's profit and loss per asset
+    if path.ends_with("/pnl") {
+        let quote = match event.query_string_parameters.get("quote") {
+            Some(quote) => quote.clone(),
+            None => return Ok(text_response(400, "missing quote")),
+        };
+
+        let mut reports = serde_json::Map::new();
+
+        for config in accounts()? {
+            let client = client_for(config.account).await;
+
+            let report = match performance::pnl(&client, &quote).await {
+                Ok(report) => serde_json::to_value(report)?,
+                Err(e) => serde_json::json!({ "error": e }),
+            };
+            reports.insert(config.label, report);
+        }
+
+        return json_response(200, &reports);
+    }
+
+    // `/stuck-orders?max_age=86400&distance=5` lists every account's open limits older
+    // than `max_age` seconds and more than `distance` percent from the market
+    if path.ends_with("/stuck-orders") {
+        let max_age = match event.query_string_parameters.get("max_age").map(|secs| secs.parse()) {
+            Some(Ok(secs)) => Duration::from_secs(secs),
+            Some(Err(_)) => return Ok(text_response(400, "invalid max_age")),
+            None => DEFAULT_STUCK_AGE,
+        };
+        let distance = match event.query_string_parameters.get("distance").map(|pct| pct.parse()) {
+            Some(Ok(pct)) => pct,
+            Some(Err(_)) => return Ok(text_response(400, "invalid distance")),
+            None => Decimal::from(DEFAULT_STUCK_DISTANCE_PCT),
+        };
+
+        let mut reports = serde_json::Map::new();
+
+        for config in accounts()? {
+            let client = client_for(config.account).await;
+
+            let stuck = match plan::find_stuck_orders(&client, max_age, distance).await {
+                Ok(stuck) => serde_json::to_value(stuck)?,
+                Err(e) => serde_json::json!({ "error": e }),
+            };
+            reports.insert(config.label, stuck);
+        }
+
+        return json_response(200, &reports);
+    }
+
+    // `/pairs?quote=EUR` maps the pair keys a plan can name to their websocket names,
+    // quoted in USD without `quote`
+    if path.ends_with("/pairs") {
+        let config = match accounts()?.into_iter().next() {
+            Some(config) => config,
+            None => return Ok(text_response(500, "no account to read the pairs with")),
+        };
+        let client = client_for(config.account).await;
+
+        let pairs = match event.query_string_parameters.get("quote") {
+            Some(quote) => kraken::tradable_pairs(&client, quote).await,
+            None => kraken::usd_pairs(&client).await,
+        };
+
+        let pairs = match pairs {
+            Ok(pairs) => pairs,
+            Err(e) => return Ok(text_response(502, &e)),
+        };
+
+        let mut wsnames = serde_json::Map::new();
+        for pair in pairs {
+            match kraken::to_wsname(&client, &pair).await {
+                Ok(wsname) => wsnames.insert(pair, serde_json::Value::String(wsname)),
+                Err(e) => return Ok(text_response(502, &e)),
+            };
+        }
+
+        return json_response(200, &wsnames);
+    }
+
+    // `/backtest?pair=XBTUSD&amount=50&every=7&candle=1440` replays buying `amount`
+    // every `every` candles of `candle` minutes over Kraken's last 720 candles
+    if path.ends_with("/backtest") {
+        let params = &event.query_string_parameters;
+        let pair = match params.get("pair") {
+            Some(pair) => pair.clone(),
+            None => return Ok(text_response(400, "missing pair")),
+        };
+        let amount = match params.get("amount").map(|amount| amount.parse::<Decimal>()) {
+            Some(Ok(amount)) if amount > Decimal::ZERO => amount,
+            Some(_) => return Ok(text_response(400, "invalid amount")),
+            None => return Ok(text_response(400, "missing amount")),
+        };
+        let every = match params.get("every").map(|every| every.parse()) {
+            Some(Ok(every)) => every,
+            Some(Err(_)) => return Ok(text_response(400, "invalid every")),
+            None => DEFAULT_BACKTEST_EVERY,
+        };
+        let candle = match params.get("candle").map(|candle| candle.parse()) {
+            Some(Ok(candle)) => candle,
+            Some(Err(_)) => return Ok(text_response(400, "invalid candle")),
+            None => DEFAULT_BACKTEST_CANDLE_MINUTES,
+        };
+
+        let config = match accounts()?.into_iter().next() {
+            Some(config) => config,
+            None => return Ok(text_response(500, "no account to read the candles with")),
+        };
+        let client = client_for(config.account).await;
+
+        return match kraken::ohlc(&client, &pair, candle).await {
+            Ok(candles) => json_response(200, &backtest::backtest_dca(&candles, amount, every)),
+            Err(e) => Ok(text_response(502, &e)),
+        };
+    }
+
+    // Kraken's own verdict on every account's plan, nothing is executed
+    if path.ends_with("/validate") {
+        let mut validations = serde_json::Map::new();
+
+        for config in accounts()? {
+            let client = client_for(config.account).await;
+
+            let validation = match plan_for(config.plan, &default_plan) {
+                Ok(dca_plan) => plan::validate_plan(&client, &dca_plan).await,
+                Err(e) => Err(e),
+            };
+
+            let validation = match validation {
+                Ok(validation) => serde_json::to_value(validation)?,
+                Err(e) => serde_json::json!({ "error": e }),
+            };
+            validations.insert(config.label, validation);
+        }
+
+        return json_response(200, &validations);
+    }
+
+    // `/reconcile?since=<unix time>` checks the journal against every account's trades
+    if path.ends_with("/reconcile") {
+        let since = event
+            .query_string_parameters
+            .get("since")
+            .and_then(|since| since.parse::<i64>().ok())
+            .and_then(clock::from_unix);
+        let journal_sink = journal::S3Journal::from_env().await;
+
+        let (since, journal_sink) = match (since, &journal_sink) {
+            (Some(since), Some(journal_sink)) => (since, journal_sink),
+            (since, _) => {
+                return Ok(text_response(
+                    400,
+                    if since.is_none() { "missing or invalid since" } else { "no JOURNAL_BUCKET to reconcile" },
+                ));
+            }
+        };
+
+        let mut reports = serde_json::Map::new();
+
+        for config in accounts()? {
+            let client = client_for(config.account).await;
+
+            let report = match reconcile::reconcile(&client, journal_sink, &config.label, since).await {
+                Ok(report) => {
+                    if !report.is_clean() {
+                        log::warn!("[{}] journal and trades disagree: {:?}", config.label, report);
+                    }
+                    serde_json::to_value(report)?
+                }
+                Err(e) => serde_json::json!({ "error": e }),
+            };
+            reports.insert(config.label, report);
+        }
+
+        return json_response(200, &reports);
+    }
+
+    // `/deposit-methods?asset=XBT&aclass=currency` lists how every account can fund `asset`
+    if path.ends_with("/deposit-methods") {
+        let asset = match event.query_string_parameters.get("asset") {
+            Some(asset) => asset.clone(),
+            None => return Ok(text_response(400, "missing asset")),
+        };
+        let aclass = event.query_string_parameters.get("aclass").and_then(|aclass| aclass.parse().ok());
+
+        let mut methods = serde_json::Map::new();
+
+        for config in accounts()? {
+            let client = client_for(config.account).await;
+
+            let listed = match kraken::deposit_methods(&client, &asset, aclass.clone()).await {
+                Ok(listed) => serde_json::to_value(listed)?,
+                Err(e) => serde_json::json!({ "error": e }),
+            };
+            methods.insert(config.label, listed);
+        }
+
+        return json_response(200, &methods);
+    }
+
+    // `/fees?currency=ZUSD&since=<unix time>&until=<unix time>` totals every account's
+    // trading fees over the period, up to now without `until`
+    if path.ends_with("/fees") {
+        let params = &event.query_string_parameters;
+        let currency = match params.get("currency") {
+            Some(currency) => currency.clone(),
+            None => return Ok(text_response(400, "missing currency")),
+        };
+        let since = match params.get("since").and_then(|since| since.parse::<i64>().ok()).and_then(clock::from_unix) {
+            Some(since) => since,
+            None => return Ok(text_response(400, "missing or invalid since")),
+        };
+        let until = match params.get("until").map(|until| until.parse::<i64>().ok().and_then(clock::from_unix)) {
+            Some(Some(until)) => until,
+            Some(None) => return Ok(text_response(400, "invalid until")),
+            None => clock::now(),
+        };
+
+        let mut totals = serde_json::Map::new();
+
+        for config in accounts()? {
+            let client = client_for(config.account).await;
+
+            let total = match performance::fees_paid(&client, since, until, &currency).await {
+                Ok(total) => serde_json::json!({ "currency": currency, "fees": total }),
+                Err(e) => serde_json::json!({ "error": e }),
+            };
+            totals.insert(config.label, total);
+        }
+
+        return json_response(200, &totals);
+    }
+
+    // `/fee-tier?pair=XBTUSD` reports the volume every account needs for the next fee tier
+    if path.ends_with("/fee-tier") {
+        let pair = match event.query_string_parameters.get("pair") {
+            Some(pair) => pair.clone(),
+            None => return Ok(text_response(400, "missing pair")),
+        };
+
+        let mut upgrades = serde_json::Map::new();
+
+        for config in accounts()? {
+            let client = client_for(config.account).await;
+
+            let upgrade = match fees::fee_tier_upgrade(&client, &pair).await {
+                Ok(upgrade) => serde_json::to_value(upgrade)?,
+                Err(e) => serde_json::json!({ "error": e }),
+            };
+            upgrades.insert(config.label, upgrade);
+        }
+
+        return json_response(200, &upgrades);
+    }
+
+    // `/tax-lots?quote=ZUSD` estimates every account's FIFO lots from its trades
+    if path.ends_with("/tax-lots") {
+        let quote = match event.query_string_parameters.get("quote") {
+            Some(quote) => quote.clone(),
+            None => return Ok(text_response(400, "missing quote")),
+        };
+
+        let mut exports = serde_json::Map::new();
+
+        for config in accounts()? {
+            let client = client_for(config.account).await;
+
+            let lots = match performance::tax_lots(&client, &quote).await {
+                Ok(lots) => serde_json::to_value(lots)?,
+                Err(e) => serde_json::json!({ "error": e }),
+            };
+            exports.insert(config.label, lots);
+        }
+
+        return json_response(200, &exports);
+    }
+
+    let forbidden = |reason: &str| text_response(403, reason);
+
+    let confirmed = match confirmation(&event) {
+        Ok(confirmed) => confirmed,
+        Err(reason) => return Ok(forbidden(reason)),
+    };
+
+    // `/cancel-run?run_id=...` cancels the orders run `run_id` left open on every account
+    if path.ends_with("/cancel-run") {
+        if !confirmed {
+            return Ok(forbidden("canceling a run's orders needs the confirmation token"));
+        }
+        let run_id = match event.query_string_parameters.get("run_id") {
+            Some(run_id) => run_id.clone(),
+            None => return Ok(text_response(400, "missing run_id")),
+        };
+        let userref = journal::run_userref(&run_id);
+
+        let mut results = serde_json::Map::new();
+
+        for config in accounts()? {
+            let client = client_for(config.account).await;
+
+            let result = match kraken::cancel_by_userref(&client, userref).await {
+                Ok(result) => {
+                    log::info!("[{}] canceled {} open orders of run {}", config.label, result.count, run_id);
+                    serde_json::to_value(result)?
+                }
+                Err(e) => {
+                    log::error!("[{}] orders of run {} not canceled: {}", config.label, run_id, e);
+                    serde_json::json!({ "error": e })
+                }
+            };
+            results.insert(config.label, result);
+        }
+
+        return json_response(200, &results);
+    }
+
+    // the big red button, only with `PANIC_ROUTE=true` and the confirmation token
+    if path.ends_with("/panic-cancel-all") {
+        if option_env!("PANIC_ROUTE") != Some("true") {
+            return Ok(forbidden("the panic route is not enabled"));
+        }
+        if option_env!("CONFIRM_TOKEN").is_none() || !confirmed {
+            return Ok(forbidden("canceling all orders needs the confirmation token"));
+        }
+
+        let mut results = serde_json::Map::new();
+
+        for config in accounts()? {
+            let client = client_for(config.account).await;
+
+            let result = match kraken::panic_cancel_all(&client).await {
+                Ok(result) => serde_json::to_value(result)?,
+                Err(e) => {
+                    log::error!("[{}] open orders not canceled: {}", config.label, e);
+                    serde_json::json!({ "error": e })
+                }
+            };
+            results.insert(config.label, result);
+        }
+
+        return json_response(200, &results);
+    }
+
+    // `/cancel-stuck?max_age=86400&distance=5` cancels the open limits `/stuck-orders` lists
+    if path.ends_with("/cancel-stuck") {
+        if !confirmed {
+            return Ok(forbidden("canceling stuck orders needs the confirmation token"));
+        }
+        let max_age = match event.query_string_parameters.get("max_age").map(|secs| secs.parse()) {
+            Some(Ok(secs)) => Duration::from_secs(secs),
+            Some(Err(_)) => return Ok(text_response(400, "invalid max_age")),
+            None => DEFAULT_STUCK_AGE,
+        };
+        let distance = match event.query_string_parameters.get("distance").map(|pct| pct.parse()) {
+            Some(Ok(pct)) => pct,
+            Some(Err(_)) => return Ok(text_response(400, "invalid distance")),
+            None => Decimal::from(DEFAULT_STUCK_DISTANCE_PCT),
+        };
+
+        let mut results = serde_json::Map::new();
+
+        for config in accounts()? {
+            let client = client_for(config.account).await;
+
+            let canceled = match plan::find_stuck_orders(&client, max_age, distance).await {
+                Ok(stuck) => {
+                    let txids: Vec<_> = stuck.into_iter().map(|order| order.txid).collect();
+                    kraken::cancel_where(&client, |order| txids.contains(&order.txid)).await
+                }
+                Err(e) => Err(e),
+            };
+            let result = match canceled {
+                Ok(count) => {
+                    log::info!("[{}] canceled {} stuck orders", config.label, count);
+                    serde_json::json!({ "count": count })
+                }
+                Err(e) => {
+                    log::error!("[{}] stuck orders not canceled: {}", config.label, e);
+                    serde_json::json!({ "error": e })
+                }
+            };
+            results.insert(config.label, result);
+        }
+
+        return json_response(200, &results);
+    }
+
+    // `/close-position?account=main&txid=...&volume=0.5` closes `volume` of a margin
+    // position of the account (the only account's without `account`), all of it without `volume`
+    if path.ends_with("/close-position") {
+        if !confirmed {
+            return Ok(forbidden("closing a position needs the confirmation token"));
+        }
+        if option_env!("LIVE") != Some("true") {
+            return Ok(forbidden("closing a position needs a LIVE build"));
+        }
+        let params = &event.query_string_parameters;
+        let txid = match params.get("txid") {
+            Some(txid) => txid.clone(),
+            None => return Ok(text_response(400, "missing txid")),
+        };
+        let volume = match params.get("volume").map(|volume| volume.parse::<Decimal>()) {
+            Some(Ok(volume)) => Some(volume),
+            Some(Err(_)) => return Ok(text_response(400, "invalid volume")),
+            None => None,
+        };
+        let configs = accounts()?;
+        let config = match params.get("account") {
+            Some(label) => configs.into_iter().find(|config| config.label == *label),
+            None if configs.len() == 1 => configs.into_iter().next(),
+            None => return Ok(text_response(400, "missing account")),
+        };
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(text_response(404, "unknown account")),
+        };
+        let client = client_for(config.account).await;
+
+        return match kraken::close_position(&client, &txid, volume).await {
+            Ok(placed) => {
+                let order = kraken::format_placed(&placed.descr);
+                log::info!("[{}] closing position {}: {}", config.label, txid, order);
+                json_response(200, &serde_json::json!({ "txid": placed.txid, "order": order }))
+            }
+            Err(e) => {
+                log::error!("[{}] position {} not closed: {}", config.label, txid, e);
+                Ok(text_response(422, &e))
+            }
+        };
+    }
+
+    // `/pause` halts the runs until `/unpause`, `/resume` being taken by interrupted runs
+    if path.ends_with("/pause") || path.ends_with("/unpause") {
+        if !confirmed {
+            return Ok(forbidden("pausing or unpausing needs the confirmation token"));
+        }
+        let pause_flag = match pause::PauseFlag::from_env().await {
+            Some(pause_flag) => pause_flag,
+            None => return Ok(text_response(400, "no PAUSE_TABLE to keep the pause in")),
+        };
+
+        let paused = path.ends_with("/pause");
+        return match pause_flag.set(paused).await {
+            Ok(state) => {
+                log::info!("runs {}", if paused { "paused" } else { "unpaused" });
+                json_response(200, &state)
+            }
+            Err(e) => {
+                log::error!("pause flag not stored in {}: {}", pause_flag.table, e);
+                Ok(text_response(500, &e))
+            }
+        };
+    }
+
+    // a paused schedule places no orders, and an unreadable flag counts as paused
+    let pause_flag = pause::PauseFlag::from_env().await;
+    match pause::pause_state(pause_flag.as_ref()).await {
+        Ok(state) if state.paused => {
+            log::info!("paused{}", state.since.map(|since| format!(" since {}", clock::rfc3339(&since))).unwrap_or_default());
+            return json_response(200, &state);
+        }
+        Ok(_) => {}
+        Err(e) => {
+            log::error!("paused, the pause flag is not readable: {}", e);
+            return Ok(text_response(503, &e));
+        }
+    }
+
+    // `/resume?run_id=...` finishes the given run instead of starting a new one
+    if path.ends_with("/resume") && !confirmed {
+        return Ok(forbidden("resuming a run needs the confirmation token"));
+    }
+    let resumed_id = if path.ends_with("/resume") {
+        match event.query_string_parameters.get("run_id") {
+            Some(run_id) => Some(run_id.clone()),
+            None => {
+                return Ok(text_response(400, "missing run_id"));
+            }
+        }
+    } else {
+        None
+    };
+
+    let jitter = start_jitter();
+    if !jitter.is_zero() {
+        log::info!("waiting {:?} before the run", jitter);
+        tokio::time::sleep(jitter).await;
+    }
+
+    let live = option_env!("LIVE") == Some("true");
+    if !live {
+        log::info!("not a LIVE build, orders are only validated");
+    } else if !confirmed {
+        log::warn!("no confirmation token, orders are only validated");
+    }
+
+    let validate = validate_only(live, confirmed);
+    let mode = if validate { "validate" } else { "live" };
+    let journal_sink = journal::S3Journal::from_env().await;
+    let run_info = RunInfo {
+        resumed: resumed_id.is_some(),
+        validate,
+        id: resumed_id.unwrap_or_else(|| ctx.request_id.clone()),
+        journal_sink: journal_sink.as_ref().map(|sink| sink as &dyn JournalSink),
+        completed: Mutex::new(Vec::new()),
+    };
+    let mut run_summary = summary::DcaRunSummary::new(&run_info.id, mode);
+    let mut decisions = Vec::new();
+    // stays zero without orders, `None` once a cross rate was missing
+    let mut total_spent = Some(Decimal::ZERO);
+    let metadata_snapshots = metadata::MetadataSnapshots::from_env().await;
+    // pair metadata is public, so it is diffed once with the first account's client
+    let mut metadata_changes: Option<Vec<kraken::MetadataChange>> = None;
+
+    let configs = accounts()?;
+    let run_timeout = run_timeout(&ctx);
+    // label of the account being run, the one a timeout cuts short
+    let mut in_progress: Option<String> = None;
+
+    let accounts_run = async {
+        // every account gets its own client, so caches and nonces never mix
+        for config in configs {
+            in_progress = Some(config.label.clone());
+            run_info.completed.lock().unwrap().clear();
+
+            let client = client_for(config.account).await;
+            let label = config.label.as_str();
+
+            let ready = match plan_for(config.plan, &default_plan) {
+                Ok(dca_plan) => match schedule_guard(&client).await {
+                    Ok(kraken::ExchangeStatus::PostOnly) => clock_guard(&client).await.map(|_| {
+                        let (adapted_plan, adapted, skipped) = plan::for_post_only(&dca_plan);
+
+                        for pair in adapted {
+                            log::warn!("[{}] exchange is post only, buying {} with a post only limit at the bid", label, pair);
+                            run_summary.notes.push(summary::tagged(label, summary::Note {
+                                message: format!("exchange is post only: {} placed as a post only limit at the bid", pair),
+                            }));
+                        }
+                        run_summary.skipped.extend(skipped.into_iter().map(|item| summary::tagged(label, item)));
+
+                        adapted_plan
+                    }),
+                    Ok(_) => clock_guard(&client).await.map(|_| dca_plan),
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e),
+            };
+
+            let ready = match ready {
+                Ok(dca_plan) => permission_guard(&client, &dca_plan).await.map(|_| dca_plan),
+                Err(e) => Err(e),
+            };
+
+            let ready = match ready {
+                Ok(dca_plan) => plan::check_api_budget(&dca_plan).map(|_| dca_plan),
+                Err(e) => Err(e),
+            };
+
+            match ready {
+                Ok(dca_plan) if dca_plan.entries.is_empty() => {
+                    log::warn!("[{}] DCA plan has no entries, nothing to do", label);
+                    run_summary.notes.push(summary::tagged(label, summary::Note {
+                        message: String::from("nothing to do: the DCA plan has no entries"),
+                    }));
+                }
+                Ok(dca_plan) => {
+                    if let Some(snapshots) = &metadata_snapshots {
+                        if metadata_changes.is_none() {
+                            let changes = snapshots.check(&client).await.unwrap_or_else(|e| {
+                                log::warn!("[{}] pair metadata not checked for changes: {}", label, e);
+                                Vec::new()
+                            });
+                            for change in &changes {
+                                log::info!("pair metadata: {}", change);
+                            }
+                            metadata_changes = Some(changes);
+                        }
+
+                        let changes = metadata_changes.as_deref().unwrap_or_default();
+                        for alert in metadata::plan_alerts(&client, &dca_plan, changes).await {
+                            log::warn!("[{}] {}", label, alert);
+                            run_summary.notes.push(summary::tagged(label, summary::Note { message: alert }));
+                        }
+                    }
+
+                    match plan::fund_quote(&client, &dca_plan, run_info.validate).await {
+                        Ok(conversions) => {
+                            for conversion in &conversions {
+                                log::info!(
+                                    "[{}] converted {} to {} to fund the run, placed {}: {:?}",
+                                    label, conversion.from, conversion.to, conversion.description, conversion.txid
+                                );
+                            }
+                            let currencies = conversions.iter().map(|conversion| conversion.to.as_str());
+                            run_summary.cost_decimals.extend(summary::cost_decimals(&client, currencies).await);
+                            run_summary.converted.extend(conversions.into_iter().map(|item| summary::tagged(label, item)));
+                        }
+                        Err(e) => {
+                            log::error!("[{}] funding conversion failed: {}", label, e);
+                            run_summary.errors.push(summary::tagged(label, summary::RunError {
+                                pair: None,
+                                error: format!("funding conversion failed: {}", e),
+                            }));
+                        }
+                    }
+
+                    if let Some(buffer_pct) = low_funds_buffer_pct() {
+                        match plan::check_funds(&client, &dca_plan, buffer_pct).await {
+                            Ok(Some(low_funds)) => {
+                                log::warn!("[{}] {}", label, low_funds);
+                                run_summary.notes.push(summary::tagged(label, summary::Note {
+                                    message: low_funds.to_string(),
+                                }));
+
+                                if let Some(reporter) = email::EmailReporter::from_env().await {
+                                    if let Err(e) = reporter.send_low_funds(label, &low_funds).await {
+                                        log::warn!("[{}] low funds warning not mailed to {}: {}", label, reporter.to, e);
+                                    }
+                                }
+                            }
+                            Ok(None) => {}
+                            Err(e) => log::warn!("[{}] funds check failed: {}", label, e),
+                        }
+                    }
+
+                    let run = if run_info.resumed {
+                        resume_run(&ctx, &run_info, label, &client, &dca_plan).await
+                    } else {
+                        Ok(run_plan(&ctx, &run_info, label, &client, &dca_plan).await)
+                    };
+
+                    let run = match run {
+                        Ok(run) => run,
+                        Err(e) => {
+                            log::error!("[{}] not resumed: {}", label, e);
+                            run_summary.errors.push(summary::tagged(label, summary::RunError {
+                                pair: None,
+                                error: format!("not resumed: {}", e),
+                            }));
+                            continue;
+                        }
+                    };
+
+                    run_summary.record_balance_after(label, run.balance);
+                    if let (Some(currency), Some(total)) = (REPORT_CURRENCY, total_spent) {
+                        let spent = summary::spent_by_currency(&run.orders);
+                        total_spent = match summary::convert_spend(&client, &spent, currency).await {
+                            Ok(converted) => Some(total + converted),
+                            Err(e) => {
+                                log::warn!("[{}] spend not reported in {}, listing it per currency: {}", label, currency, e);
+                                None
+                            }
+                        };
+                    }
+                    let currencies = run.orders.iter().map(|order| order.quote.as_str()).chain(REPORT_CURRENCY);
+                    run_summary.cost_decimals.extend(summary::cost_decimals(&client, currencies).await);
+                    run_summary.orders.extend(run.orders.into_iter().map(|item| summary::tagged(label, item)));
+                    run_summary.skipped.extend(run.skipped.into_iter().map(|item| summary::tagged(label, item)));
+                    run_summary.adjusted.extend(run.adjusted.into_iter().map(|item| summary::tagged(label, item)));
+                    run_summary.repriced.extend(run.repriced.into_iter().map(|item| summary::tagged(label, item)));
+                    run_summary.rebalanced.extend(run.rebalanced.into_iter().map(|item| summary::tagged(label, item)));
+                    run_summary.errors.extend(run.errors.into_iter().map(|item| summary::tagged(label, item)));
+                    decisions.extend(run.decisions);
+                }
+                Err(e) => {
+                    log::error!("[{}] not run: {}", label, e);
+                    run_summary.errors.push(summary::tagged(label, summary::RunError {
+                        pair: None,
+                        error: format!("not run: {}", e),
+                    }));
+                }
+            }
+
+            for (given, current) in client.pair_renames() {
+                run_summary.notes.push(summary::tagged(label, summary::Note {
+                    message: format!("pair {} no longer exists under that name, update the plan to {}", given, current),
+                }));
+            }
+        }
+    };
+
+    if tokio::time::timeout(run_timeout, accounts_run).await.is_err() {
+        let label = in_progress.unwrap_or_default();
+        log::error!("[{}] run timed out after {:?}, reporting what completed", label, run_timeout);
+
+        let completed = std::mem::take(&mut *run_info.completed.lock().unwrap());
+        run_summary.timed_out = true;
+        run_summary.orders.extend(completed.into_iter().map(|item| summary::tagged(&label, item)));
+        run_summary.errors.push(summary::tagged(&label, summary::RunError {
+            pair: None,
+            error: format!("run timed out after {}s, cut short here and later accounts not run", run_timeout.as_secs()),
+        }));
+    }
+
+    #[cfg(feature = "schedule")]
+    match schedule::CronSchedule::from_env() {
+        Some(Ok(dca_schedule)) => run_summary.next_run = schedule::next_run(&dca_schedule, clock::now()),
+        Some(Err(e)) => log::warn!("next run unknown: {}", e),
+        None => {}
+    }
+
+    run_summary.total_up_fees();
+    run_summary.spent = summary::spent_by_currency(run_summary.orders.iter().map(|order| &order.item));
+    if let (Some(currency), Some(amount)) = (REPORT_CURRENCY, total_spent) {
+        run_summary.total_spent = Some(summary::ReportedSpend {
+            currency: currency.to_owned(),
+            amount,
+        });
+    }
+
+    // a pair skipped run after run for the same reason is most likely misconfigured
+    if let Some(counter) = alert::SkipCounter::from_env().await {
+        let threshold = alert::skip_threshold();
+        let reporter = email::EmailReporter::from_env().await;
+
+        for decision in &decisions {
+            let reason = match &decision.action {
+                journal::Action::Skipped { reason } => reason,
+                journal::Action::Ordered { .. } => {
+                    if let Err(e) = counter.reset(&decision.account, &decision.pair).await {
+                        log::warn!("[{}] skip count of {} not reset in {}: {}", decision.account, decision.pair, counter.table, e);
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+
+            let skips = match counter.record_skip(&decision.account, &decision.pair, reason).await {
+                Ok(skips) => skips,
+                Err(e) => {
+                    log::warn!("[{}] skip of {} not counted in {}: {}", decision.account, decision.pair, counter.table, e);
+                    continue;
+                }
+            };
+            if skips < threshold {
+                continue;
+            }
+
+            log::warn!("[{}] {} skipped {} runs in a row: {}", decision.account, decision.pair, skips, reason);
+            run_summary.notes.push(summary::tagged(&decision.account, summary::Note {
+                message: format!("{} skipped {} runs in a row, check its config: {}", decision.pair, skips, reason),
+            }));
+
+            // mailed once when the threshold is reached, then quiet until the pair is bought
+            if let (Some(reporter), true) = (&reporter, skips == threshold) {
+                if let Err(e) = reporter.send_repeated_skip(&decision.account, &decision.pair, skips, reason).await {
+                    log::warn!("[{}] repeated skip of {} not mailed to {}: {}", decision.account, decision.pair, reporter.to, e);
+                }
+            }
+        }
+    }
+
+
+    metrics::run_finished();
+    log::info!("{}", run_summary);
+
+    let failures = match alert::FailureCounter::from_env().await {
+        Some(counter) => match counter.record(run_summary.failed()).await {
+            Ok(failures) => Some(failures),
+            Err(e) => {
+                log::warn!("failure count in {} not updated: {}", counter.table, e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    if let Some(reporter) = email::EmailReporter::from_env().await {
+        let threshold = alert::failure_threshold();
+
+        // one escalation when the threshold is reached, then quiet until a run succeeds
+        let sent = match failures {
+            Some(failures) if failures == threshold => Some(reporter.send_outage(failures, &run_summary).await),
+            Some(failures) if failures > threshold => None,
+            _ => Some(reporter.send(&run_summary).await),
+        };
+
+        if let Some(Err(e)) = sent {
+            log::warn!("run summary not mailed to {}: {}", reporter.to, e);
+        }
+    }
+
+    if run_summary.failed() {
+        if let Some(sink) = deadletter::SqsDeadLetters::from_env().await {
+            if let Err(e) = deadletter::send_if_failed(&sink, &run_summary).await {
+                log::warn!("failed run {} not dead-lettered to {}: {}", run_summary.run_id, sink.queue_url, e);
+            }
+        }
+    }
+
+    if let Some(sink) = &journal_sink {
+        let entry = journal::JournalEntry {
+            run_id: run_summary.run_id.clone(),
+            started_at: run_summary.started_at,
+            finished_at: clock::now(),
+            decisions,
+        };
+
+        if let Err(e) = sink.append(&entry).await {
+            log::warn!("journal entry for run {} not written to {}: {}", entry.run_id, sink.bucket, e);
+        }
+    }
+
+    // multi-status when anything went wrong along the way
+    json_response(if run_summary.errors.is_empty() { 200 } else { 207 }, &run_summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockExchange;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    /// Context of an invocation with a minute left.
+    fn context() -> Context {
+        let mut ctx = Context::default();
+        ctx.deadline = (::std::time::UNIX_EPOCH.elapsed().unwrap() + Duration::from_secs(60)).as_millis() as u64;
+        ctx
+    }
+
+    /// XBTUSD at 40000, ETHUSD at 2000 and 1000 USD to buy them with.
+    async fn exchange() -> MockExchange {
+        let exchange = MockExchange::start().await;
+        exchange.add_pair("XXBTZUSD", "XBTUSD", "XXBT", "ZUSD", serde_json::json!({}));
+        exchange.add_pair("XETHZUSD", "ETHUSD", "XETH", "ZUSD", serde_json::json!({}));
+        exchange.set_price("XXBTZUSD", dec("40000"));
+        exchange.set_price("XETHZUSD", dec("2000"));
+        exchange.set_balance("ZUSD", dec("1000"));
+        exchange
+    }
+
+    fn run_info<'a>(journal_sink: Option<&'a dyn JournalSink>) -> RunInfo<'a> {
+        RunInfo {
+            id: String::from("run-1"),
+            resumed: false,
+            validate: false,
+            journal_sink,
+            completed: Mutex::new(Vec::new()),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_resumed_run_buys_only_what_the_journal_has_no_order_for() {
+        let exchange = exchange().await;
+        let client = exchange.client();
+
+        let journal = journal::MemoryJournal::default();
+        journal::Recorder::new("run-1", "main", Some(&journal))
+            .record_durably("XBTUSD", journal::Action::Ordered {
+                price: dec("40000"),
+                volume: dec("0.0005"),
+                txid: Some(vec![String::from("OABCDE-FGHIJ-KLMNOP")]),
+            })
+            .await;
+
+        let run_info = RunInfo {
+            resumed: true,
+            ..run_info(Some(&journal))
+        };
+        let dca_plan = plan::DcaPlan::from_json(
+            r#"{"mode": "fixed", "entries": [{"pair": "XBTUSD", "amount": "20"}, {"pair": "ETHUSD", "amount": "20"}]}"#,
+        )
+        .unwrap();
+
+        let account_run = resume_run(&context(), &run_info, "main", &client, &dca_plan).await.unwrap();
+
+        let bought: Vec<&str> = account_run.orders.iter().map(|order| order.pair.as_str()).collect();
+        assert_eq!(bought, ["XETHZUSD"]);
+        assert_eq!(account_run.skipped.len(), 1);
+        assert_eq!(account_run.skipped[0].pair, "XBTUSD");
+        assert_eq!(account_run.skipped[0].reason, "already ordered in run run-1");
+
+        let placed: Vec<String> = exchange.calls("AddOrder").iter().map(|call| call.params["pair"].clone()).collect();
+        assert_eq!(placed, ["XETHZUSD"]);
+    }
+
+    #[tokio::test]
+    async fn a_run_is_not_resumed_without_a_journal() {
+        let exchange = MockExchange::start().await;
+        let run_info = RunInfo {
+            resumed: true,
+            ..run_info(None)
+        };
+        let dca_plan = plan::DcaPlan::from_json(r#"{"mode": "fixed", "entries": []}"#).unwrap();
+
+        let refused = resume_run(&context(), &run_info, "main", &exchange.client(), &dca_plan).await;
+        assert_eq!(refused.err(), Some(String::from("no journal configured to resume from")));
+        assert!(exchange.calls("AddOrder").is_empty());
+    }
+
+    #[tokio::test]
+    async fn fees_come_from_the_fills_or_are_flagged_as_estimated() {
+        let exchange = exchange().await;
+        let client = exchange.client();
+        // 0.26% of 33.33 is 0.086658, the exchange charges 0.0867
+        let dca_plan = plan::DcaPlan::from_json(r#"{"mode": "fixed", "entries": [{"pair": "XBTUSD", "amount": "33.33"}]}"#).unwrap();
+
+        let confirmed = run_plan(&context(), &run_info(None), "main", &client, &dca_plan).await;
+        assert_eq!(confirmed.orders.len(), 1);
+        assert_eq!((confirmed.orders[0].fee, confirmed.orders[0].fee_estimated), (dec("0.0867"), false));
+
+        // no fill can be confirmed, the order is gone from the exchange's view
+        for _ in 0..2 {
+            exchange.script("QueryOrders", 200, r#"{"error": ["EOrder:Invalid order"]}"#);
+        }
+        let estimated = run_plan(&context(), &run_info(None), "main", &client, &dca_plan).await;
+        assert_eq!(estimated.orders.len(), 1);
+        assert_eq!((estimated.orders[0].fee, estimated.orders[0].fee_estimated), (dec("0.086658"), true));
+
+        let mut run_summary = summary::DcaRunSummary::new("run-1", "live");
+        run_summary.orders.extend(confirmed.orders.into_iter().map(|order| summary::tagged("main", order)));
+        run_summary.total_up_fees();
+        assert_eq!((run_summary.total_fees, run_summary.fees_estimated), (dec("0.0867"), false));
+
+        run_summary.orders.extend(estimated.orders.into_iter().map(|order| summary::tagged("main", order)));
+        run_summary.total_up_fees();
+        assert_eq!((run_summary.total_fees, run_summary.fees_estimated), (dec("0.173358"), true));
+    }
+
+    #[tokio::test]
+    async fn a_failed_balance_fetch_keeps_the_run_outcome() {
+        let exchange = exchange().await;
+        exchange.script("Balance", 200, r#"{"error": ["EGeneral:Permission denied"]}"#);
+        let client = exchange.client();
+        let dca_plan = plan::DcaPlan::from_json(r#"{"mode": "fixed", "entries": [{"pair": "XBTUSD", "amount": "20"}]}"#).unwrap();
+
+        let account_run = run_plan(&context(), &run_info(None), "main", &client, &dca_plan).await;
+        assert_eq!(account_run.orders.len(), 1);
+        assert!(account_run.errors.is_empty());
+
+        let mut run_summary = summary::DcaRunSummary::new("run-1", "live");
+        run_summary.orders.extend(account_run.orders.into_iter().map(|order| summary::tagged("main", order)));
+        run_summary.record_balance_after("main", account_run.balance);
+
+        assert!(!run_summary.failed());
+        assert!(run_summary.errors.is_empty());
+        assert_eq!(run_summary.balance_after["main"], None);
+        assert!(run_summary.notes[0].item.message.starts_with("balance after the run unavailable: "));
+        assert!(run_summary.to_string().contains("[main] Account balance: unavailable"));
+    }
+
+    #[test]
+    fn targets_parse_as_asset_weight_pairs() {
+        let targets = parse_targets("XBT:60, ETH:30").unwrap();
+        assert_eq!(targets, HashMap::from([(String::from("XBT"), dec("60")), (String::from("ETH"), dec("30"))]));
+
+        assert_eq!(parse_targets("XBT=60"), Err(String::from("invalid target: XBT=60")));
+        assert_eq!(parse_targets("XBT:sixty"), Err(String::from("invalid target weight: XBT:sixty")));
+    }
+
+    #[tokio::test]
+    async fn only_a_confirmed_live_run_executes_its_orders() {
+        let dca_plan = plan::DcaPlan::from_json(r#"{"mode": "fixed", "entries": [{"pair": "XBTUSD", "amount": "100"}]}"#).unwrap();
+
+        for (live, confirmed) in [(false, false), (false, true), (true, false)] {
+            let exchange = exchange().await;
+            let run_info = RunInfo {
+                validate: validate_only(live, confirmed),
+                ..run_info(None)
+            };
+
+            run_plan(&context(), &run_info, "main", &exchange.client(), &dca_plan).await;
+
+            let placed = exchange.calls("AddOrder");
+            assert_eq!(placed.len(), 1);
+            assert!(placed[0].params.contains_key("validate"));
+            assert_eq!(exchange.balance("XXBT"), Decimal::ZERO);
+        }
+
+        let exchange = exchange().await;
+        let run_info = RunInfo {
+            validate: validate_only(true, true),
+            ..run_info(None)
+        };
+
+        let account_run = run_plan(&context(), &run_info, "main", &exchange.client(), &dca_plan).await;
+
+        assert_eq!(account_run.orders.len(), 1);
+        assert!(!exchange.calls("AddOrder")[0].params.contains_key("validate"));
+        assert_eq!(exchange.balance("XXBT"), dec("0.0025"));
+    }
+}
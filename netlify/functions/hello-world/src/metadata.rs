@@ -0,0 +1,130 @@
+use crate::kraken::{self, KrakenClient, Metadata, MetadataChange};
+use crate::plan::{self, DcaPlan};
+
+const SNAPSHOT_KEY: &str = "metadata/latest.json";
+
+/// Asset-pair metadata of the previous run, kept as one object in `bucket` so
+/// a run can see what Kraken changed since.
+pub struct MetadataSnapshots {
+    pub bucket: String,
+    client: aws_sdk_s3::Client,
+}
+
+impl MetadataSnapshots {
+    /// Snapshots in `METADATA_SNAPSHOT_BUCKET`, `None` when it is not configured.
+    pub async fn from_env() -> Option<MetadataSnapshots> {
+        let bucket = option_env!("METADATA_SNAPSHOT_BUCKET")?;
+        let config = aws_config::load_from_env().await;
+
+        Some(MetadataSnapshots {
+            bucket: bucket.to_owned(),
+            client: aws_sdk_s3::Client::new(&config),
+        })
+    }
+
+    /// Diffs freshly fetched metadata against the stored snapshot and stores
+    /// it in its place. The first run has nothing to compare against and
+    /// reports no changes.
+    pub async fn check(&self, client: &KrakenClient) -> Result<Vec<MetadataChange>, String> {
+        let current = client.refresh().await?;
+        let changes = match self.load().await? {
+            Some(previous) => current.diff(&previous),
+            None => Vec::new(),
+        };
+
+        self.store(&current).await?;
+        Ok(changes)
+    }
+
+    async fn load(&self) -> Result<Option<Metadata>, String> {
+        let object = match self.client.get_object().bucket(&self.bucket).key(SNAPSHOT_KEY).send().await {
+            Ok(object) => object,
+            Err(aws_sdk_s3::SdkError::ServiceError { err, .. }) if err.is_no_such_key() => return Ok(None),
+            Err(e) => return Err(format!("{:?}", e)),
+        };
+        let body = object.body.collect().await.map_err(|e| format!("{:?}", e))?.into_bytes();
+
+        serde_json::from_slice(&body)
+            .map(Some)
+            .map_err(|e| format!("invalid metadata snapshot {}: {}", SNAPSHOT_KEY, e))
+    }
+
+    async fn store(&self, metadata: &Metadata) -> Result<(), String> {
+        let body = serde_json::to_vec(metadata).map_err(|e| format!("{:?}", e))?;
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(SNAPSHOT_KEY)
+            .content_type("application/json")
+            .body(aws_sdk_s3::ByteStream::from(body))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("{:?}", e))
+    }
+}
+
+/// Warnings for the `changes` that touch a pair `plan` buys, including an
+/// `ordermin` or `costmin` the entry's usual buy no longer reaches.
+pub async fn plan_alerts(client: &KrakenClient, plan: &DcaPlan, changes: &[MetadataChange]) -> Vec<String> {
+    if changes.is_empty() {
+        return Vec::new();
+    }
+
+    let rate = match plan::conversion_rate(client, plan).await {
+        Ok(rate) => rate,
+        Err(e) => return vec![format!("pair metadata changed, the plan was not checked against it: {}", e)],
+    };
+    let metadata = match client.metadata().await {
+        Ok(metadata) => metadata,
+        Err(e) => return vec![format!("pair metadata changed, the plan was not checked against it: {}", e)],
+    };
+    let mut alerts = Vec::new();
+
+    for (entry, amount) in plan::quote_amounts(plan, rate) {
+        let pair = match plan::resolve_entry_pair(client, entry, &plan.quote).await {
+            Ok(pair) => pair,
+            Err(e) => {
+                // a removed pair no longer resolves
+                if changes.iter().any(|change| matches!(change, MetadataChange::Removed { .. })) {
+                    alerts.push(format!("{} no longer resolves after a pair was removed: {}", entry.pair, e));
+                }
+                continue;
+            }
+        };
+
+        let pair_changes: Vec<_> = changes.iter().filter(|change| change.pair() == pair).collect();
+        if pair_changes.is_empty() {
+            continue;
+        }
+        for change in &pair_changes {
+            alerts.push(format!("{} (plan entry {})", change, entry.pair));
+        }
+
+        let info = match metadata.pair_info(&pair) {
+            Some(info) => info,
+            None => continue,
+        };
+        if let Some(costmin) = info.costmin.filter(|costmin| *costmin > amount) {
+            alerts.push(format!("{} costmin {} is now above the usual buy of {} {}", pair, costmin, amount, plan.quote));
+        }
+        if let Some(ordermin) = info.ordermin {
+            let volume = match kraken::ticker(client, &pair).await.map(|ticker| ticker.ask_price()) {
+                Ok(ask) if !ask.is_zero() => amount / ask,
+                _ => continue,
+            };
+            if ordermin > volume {
+                alerts.push(format!(
+                    "{} ordermin {} is now above the usual buy of {} {}",
+                    pair,
+                    ordermin,
+                    volume.round_dp(info.lot_decimals),
+                    info.base
+                ));
+            }
+        }
+    }
+
+    alerts
+}
@@ -0,0 +1,44 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+// process-global so the counters keep accumulating across warm invocations
+static ORDERS_PLACED: AtomicU64 = AtomicU64::new(0);
+static ORDERS_FAILED: AtomicU64 = AtomicU64::new(0);
+static QUOTE_SPENT: Mutex<f64> = Mutex::new(0.0);
+static LAST_RUN: AtomicU64 = AtomicU64::new(0);
+
+pub fn order_placed(quote_spent: f64) {
+    ORDERS_PLACED.fetch_add(1, Ordering::Relaxed);
+    *QUOTE_SPENT.lock().unwrap() += quote_spent;
+}
+
+pub fn order_failed() {
+    ORDERS_FAILED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn run_finished() {
+    let now = ::std::time::UNIX_EPOCH.elapsed().unwrap();
+    LAST_RUN.store(now.as_secs(), Ordering::Relaxed);
+}
+
+/// Renders the counters in the Prometheus text exposition format.
+pub fn render() -> String {
+    format!(
+        "# HELP dca_orders_placed_total Orders accepted by Kraken.\n\
+         # TYPE dca_orders_placed_total counter\n\
+         dca_orders_placed_total {}\n\
+         # HELP dca_orders_failed_total Orders rejected by Kraken or not sent.\n\
+         # TYPE dca_orders_failed_total counter\n\
+         dca_orders_failed_total {}\n\
+         # HELP dca_quote_spent_total Total quote currency spent by placed orders.\n\
+         # TYPE dca_quote_spent_total counter\n\
+         dca_quote_spent_total {}\n\
+         # HELP dca_last_run_timestamp_seconds Unix time of the last finished run.\n\
+         # TYPE dca_last_run_timestamp_seconds gauge\n\
+         dca_last_run_timestamp_seconds {}\n",
+        ORDERS_PLACED.load(Ordering::Relaxed),
+        ORDERS_FAILED.load(Ordering::Relaxed),
+        *QUOTE_SPENT.lock().unwrap(),
+        LAST_RUN.load(Ordering::Relaxed),
+    )
+}
@@ -0,0 +1,708 @@
+//! Kraken stand-in for tests: a local HTTP server answering the REST API from
+//! balances and prices held in memory. Market orders fill at the set price,
+//! limit orders rest until the price reaches them, and every call is recorded.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::kraken::{Account, KrakenClient, NewOrder, OrderDirection, OrderType};
+
+/// API key of `MockExchange::client`
+pub const KEY: &str = "mock-key";
+/// API secret of `MockExchange::client`, base64 like a real one
+pub const SECRET: &str = "bW9jay1zZWNyZXQ=";
+
+/// One request the exchange received.
+#[derive(Debug, Clone)]
+pub struct Call {
+    pub method: String,
+    /// query params of a public call, form params of a private one
+    pub params: HashMap<String, String>,
+    /// header names in lower case
+    pub headers: HashMap<String, String>,
+    /// body exactly as sent, empty for public calls
+    pub body: String,
+}
+
+/// Order as the exchange keeps it.
+#[derive(Debug, Clone)]
+pub struct MockOrder {
+    pub pair: String,
+    pub direction: String,
+    pub ordertype: String,
+    pub volume: Decimal,
+    /// limit price, `None` for market orders
+    pub price: Option<Decimal>,
+    pub userref: Option<i32>,
+    pub oflags: String,
+    /// open, closed or canceled
+    pub status: String,
+    pub vol_exec: Decimal,
+    pub cost: Decimal,
+    pub fee: Decimal,
+    /// unix time the order was placed at
+    pub opentm: f64,
+}
+
+#[derive(Default)]
+struct State {
+    balances: HashMap<String, Decimal>,
+    /// AssetPairs entries by pair key
+    pairs: HashMap<String, Value>,
+    prices: HashMap<String, Decimal>,
+    /// by txid, in the order they were placed
+    orders: BTreeMap<String, MockOrder>,
+    calls: Vec<Call>,
+    /// canned (status, body) answers by method, served before the simulation
+    scripted: HashMap<String, VecDeque<(u16, String)>>,
+}
+
+pub struct MockExchange {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockExchange {
+    /// Starts the exchange on a free local port, with no pairs, prices or balances.
+    pub async fn start() -> MockExchange {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let served = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, served.clone()));
+            }
+        });
+
+        MockExchange { addr, state }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Client of a test account talking to this exchange, without retry delays.
+    pub fn client(&self) -> KrakenClient {
+        let account = Account {
+            key: KEY.to_owned(),
+            secret: SECRET.to_owned(),
+            otp: None,
+        };
+
+        let mut retry_policy = crate::kraken::RetryPolicy::default();
+        for class in [
+            &mut retry_policy.rate_limit,
+            &mut retry_policy.nonce,
+            &mut retry_policy.server_error,
+            &mut retry_policy.unavailable,
+            &mut retry_policy.connection,
+        ] {
+            class.delay = std::time::Duration::ZERO;
+        }
+
+        KrakenClient::new(account)
+            .with_http_client(reqwest::Client::builder().no_proxy().build().unwrap())
+            .with_base_url(&self.url())
+            .with_retry_policy(retry_policy)
+    }
+
+    /// Lists `key` in AssetPairs, e.g. XXBTZUSD trading XXBT against ZUSD.
+    /// `extra` adds or overrides AssetPairs fields such as `ordermin`.
+    pub fn add_pair(&self, key: &str, altname: &str, base: &str, quote: &str, extra: Value) {
+        let mut pair = json!({
+            "altname": altname,
+            "wsname": format!("{}/{}", &altname[..altname.len() - 3], &altname[altname.len() - 3..]),
+            "base": base,
+            "quote": quote,
+            "pair_decimals": 1,
+            "lot_decimals": 8,
+            "status": "online",
+            "ordermin": "0.0001",
+            "costmin": "0.5",
+            "fees": [[0, 0.26], [50000, 0.24]],
+            "fees_maker": [[0, 0.16], [50000, 0.14]],
+            "fee_volume_currency": "ZUSD"
+        });
+        if let (Some(pair), Value::Object(extra)) = (pair.as_object_mut(), extra) {
+            pair.extend(extra);
+        }
+
+        self.state.lock().unwrap().pairs.insert(key.to_owned(), pair);
+    }
+
+    /// Sets the price of `pair` (ask, bid and last trade alike) and fills the
+    /// resting limit orders it reaches.
+    pub fn set_price(&self, pair: &str, price: Decimal) {
+        let mut state = self.state.lock().unwrap();
+        state.prices.insert(pair.to_owned(), price);
+
+        let reached: Vec<String> = state
+            .orders
+            .iter()
+            .filter(|(_, order)| order.pair == pair && order.status == "open")
+            .filter(|(_, order)| match (order.direction.as_str(), order.price) {
+                ("buy", Some(limit)) => price <= limit,
+                (_, Some(limit)) => price >= limit,
+                _ => false,
+            })
+            .map(|(txid, _)| txid.clone())
+            .collect();
+
+        for txid in reached {
+            let limit = state.orders[&txid].price.unwrap();
+            state.fill(&txid, limit);
+        }
+    }
+
+    pub fn set_balance(&self, asset: &str, amount: Decimal) {
+        self.state.lock().unwrap().balances.insert(asset.to_owned(), amount);
+    }
+
+    pub fn balance(&self, asset: &str) -> Decimal {
+        self.state.lock().unwrap().balances.get(asset).copied().unwrap_or_default()
+    }
+
+    /// Answers the next call of `method` with `body` and HTTP `status` instead
+    /// of simulating it. Several answers are served in the order given.
+    pub fn script(&self, method: &str, status: u16, body: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .scripted
+            .entry(method.to_owned())
+            .or_default()
+            .push_back((status, body.to_owned()));
+    }
+
+    /// Calls of `method` so far, oldest first.
+    pub fn calls(&self, method: &str) -> Vec<Call> {
+        self.state.lock().unwrap().calls.iter().filter(|call| call.method == method).cloned().collect()
+    }
+
+    pub fn orders(&self) -> Vec<(String, MockOrder)> {
+        self.state.lock().unwrap().orders.iter().map(|(txid, order)| (txid.clone(), order.clone())).collect()
+    }
+
+    /// Moves the open time of order `txid` `secs` into the past.
+    pub fn backdate(&self, txid: &str, secs: f64) {
+        if let Some(order) = self.state.lock().unwrap().orders.get_mut(txid) {
+            order.opentm -= secs;
+        }
+    }
+}
+
+/// Market buy of `volume` with none of the client side sizing, to reach the
+/// exchange's own checks.
+pub fn market_buy(pair: &str, volume: Decimal) -> NewOrder {
+    NewOrder {
+        pair: pair.to_owned(),
+        order_direction: OrderDirection::Buy,
+        order_type: OrderType::Market,
+        price: None,
+        price2: None,
+        trigger: None,
+        volume: Some(volume.to_string()),
+        leverage: None,
+        reduce_only: false,
+        oflags: None,
+        fee_currency: None,
+        market_price_protection: true,
+        starttm: None,
+        expiretm: None,
+        userref: None,
+        #[cfg(feature = "timestamps")]
+        deadline: None,
+        validate: None,
+    }
+}
+
+impl State {
+    fn price(&self, pair: &str) -> Result<Decimal, &'static str> {
+        self.prices.get(pair).copied().ok_or("EQuery:Unknown asset pair")
+    }
+
+    fn pair_field(&self, pair: &str, field: &str) -> Option<Decimal> {
+        self.pairs.get(pair)?.get(field)?.as_str()?.parse().ok()
+    }
+
+    fn pair_str(&self, pair: &str, field: &str) -> String {
+        self.pairs[pair][field].as_str().unwrap_or_default().to_owned()
+    }
+
+    /// Taker fee of the first tier, in percent.
+    fn fee_pct(&self, pair: &str) -> Decimal {
+        self.pairs[pair]["fees"][0][1]
+            .as_f64()
+            .and_then(Decimal::from_f64_retain)
+            .map_or(Decimal::ZERO, |fee| fee.round_dp(4))
+    }
+
+    /// Quote held by resting buys and base held by resting sells.
+    fn held(&self, asset: &str) -> Decimal {
+        self.orders
+            .values()
+            .filter(|order| order.status == "open")
+            .map(|order| {
+                let price = order.price.unwrap_or_default();
+                if order.direction == "buy" && self.pair_str(&order.pair, "quote") == asset {
+                    let cost = price * order.volume;
+                    cost + cost * self.fee_pct(&order.pair) / Decimal::ONE_HUNDRED
+                } else if order.direction == "sell" && self.pair_str(&order.pair, "base") == asset {
+                    order.volume
+                } else {
+                    Decimal::ZERO
+                }
+            })
+            .sum()
+    }
+
+    fn available(&self, asset: &str) -> Decimal {
+        self.balances.get(asset).copied().unwrap_or_default() - self.held(asset)
+    }
+
+    /// Executes the whole of order `txid` at `price`, moving the balances.
+    fn fill(&mut self, txid: &str, price: Decimal) {
+        let order = &self.orders[txid];
+        let (pair, direction, volume) = (order.pair.clone(), order.direction.clone(), order.volume);
+        let (base, quote) = (self.pair_str(&pair, "base"), self.pair_str(&pair, "quote"));
+        let cost = price * volume;
+        let fee = (cost * self.fee_pct(&pair) / Decimal::ONE_HUNDRED).round_dp(4);
+
+        let (base_change, quote_change) = match direction.as_str() {
+            "buy" => (volume, -(cost + fee)),
+            _ => (-volume, cost - fee),
+        };
+        *self.balances.entry(base).or_default() += base_change;
+        *self.balances.entry(quote).or_default() += quote_change;
+
+        let order = self.orders.get_mut(txid).unwrap();
+        order.status = String::from("closed");
+        order.vol_exec = volume;
+        order.cost = cost;
+        order.fee = fee;
+    }
+
+    fn order_json(&self, order: &MockOrder) -> Value {
+        let altname = self.pair_str(&order.pair, "altname");
+        let at = match order.price {
+            Some(price) => format!("limit {}", price),
+            None => String::from("market"),
+        };
+        let average = if order.vol_exec.is_zero() { Decimal::ZERO } else { order.cost / order.vol_exec };
+
+        json!({
+            "refid": null,
+            "userref": order.userref,
+            "status": order.status,
+            "opentm": order.opentm,
+            "starttm": 0,
+            "expiretm": 0,
+            "descr": {
+                "pair": altname,
+                "type": order.direction,
+                "ordertype": order.ordertype,
+                "price": order.price.unwrap_or_default().to_string(),
+                "price2": "0",
+                "leverage": "none",
+                "order": format!("{} {:.8} {} @ {}", order.direction, order.volume, altname, at),
+                "close": ""
+            },
+            "vol": order.volume.to_string(),
+            "vol_exec": order.vol_exec.to_string(),
+            "cost": order.cost.to_string(),
+            "fee": order.fee.to_string(),
+            "price": average.to_string(),
+            "stopprice": "0",
+            "limitprice": "0",
+            "misc": "",
+            "oflags": order.oflags,
+        })
+    }
+
+    fn add_order(&mut self, params: &HashMap<String, String>) -> Result<Value, &'static str> {
+        let pair = params.get("pair").cloned().unwrap_or_default();
+        if !self.pairs.contains_key(&pair) {
+            return Err("EQuery:Unknown asset pair");
+        }
+        let volume: Decimal = params.get("volume").and_then(|volume| volume.parse().ok()).ok_or("EGeneral:Invalid arguments:volume")?;
+        let ordertype = params.get("ordertype").cloned().unwrap_or_default();
+        let direction = params.get("type").cloned().unwrap_or_default();
+        let limit: Option<Decimal> = match ordertype.as_str() {
+            "market" => None,
+            "limit" => Some(params.get("price").and_then(|price| price.parse().ok()).ok_or("EGeneral:Invalid arguments:price")?),
+            _ => return Err("EGeneral:Invalid arguments:ordertype"),
+        };
+        let market = self.price(&pair)?;
+
+        if self.pair_field(&pair, "ordermin").is_some_and(|ordermin| volume < ordermin) {
+            return Err("EOrder:Order minimum not met");
+        }
+        let price = limit.unwrap_or(market);
+        if self.pair_field(&pair, "costmin").is_some_and(|costmin| price * volume < costmin) {
+            return Err("EOrder:Cost minimum not met");
+        }
+
+        let cost = price * volume;
+        let sufficient = match direction.as_str() {
+            "buy" => self.available(&self.pair_str(&pair, "quote")) >= cost + cost * self.fee_pct(&pair) / Decimal::ONE_HUNDRED,
+            _ => self.available(&self.pair_str(&pair, "base")) >= volume,
+        };
+        if !sufficient {
+            return Err("EOrder:Insufficient funds");
+        }
+
+        let order = MockOrder {
+            pair: pair.clone(),
+            direction,
+            ordertype,
+            volume,
+            price: limit,
+            userref: params.get("userref").and_then(|userref| userref.parse().ok()),
+            oflags: params.get("oflags").cloned().unwrap_or_default(),
+            status: String::from("open"),
+            vol_exec: Decimal::ZERO,
+            cost: Decimal::ZERO,
+            fee: Decimal::ZERO,
+            opentm: crate::clock::unix_nanos(&crate::clock::now()) as f64 / 1e9,
+        };
+        let description = self.order_json(&order)["descr"]["order"].clone();
+
+        if params.contains_key("validate") {
+            return Ok(json!({ "descr": { "order": description } }));
+        }
+
+        let txid = format!("O{:05}-MOCKX-{:06}", self.orders.len() + 1, self.orders.len() + 1);
+        let crosses = match (order.direction.as_str(), limit) {
+            (_, None) => true,
+            ("buy", Some(limit)) => market <= limit,
+            (_, Some(limit)) => market >= limit,
+        };
+        self.orders.insert(txid.clone(), order);
+        if crosses {
+            self.fill(&txid, market);
+        }
+
+        Ok(json!({ "descr": { "order": description }, "txid": [txid] }))
+    }
+
+    fn cancel_order(&mut self, params: &HashMap<String, String>) -> Result<Value, &'static str> {
+        let txid = params.get("txid").cloned().unwrap_or_default();
+
+        // a number is a user reference, canceling all of its open orders
+        if let Ok(userref) = txid.parse::<i32>() {
+            let mut count = 0;
+            for order in self.orders.values_mut().filter(|order| order.status == "open" && order.userref == Some(userref)) {
+                order.status = String::from("canceled");
+                count += 1;
+            }
+            return if count > 0 { Ok(json!({ "count": count })) } else { Err("EOrder:Unknown order") };
+        }
+
+        match self.orders.get_mut(&txid) {
+            Some(order) if order.status == "open" => {
+                order.status = String::from("canceled");
+                Ok(json!({ "count": 1 }))
+            }
+            _ => Err("EOrder:Unknown order"),
+        }
+    }
+
+    /// Txids of the orders `keep` accepts, of the `userref` in `params` if any.
+    fn orders_where(&self, params: &HashMap<String, String>, keep: impl Fn(&MockOrder) -> bool) -> Vec<String> {
+        let userref: Option<i32> = params.get("userref").and_then(|userref| userref.parse().ok());
+        self.orders
+            .iter()
+            .filter(|(_, order)| keep(order) && userref.is_none_or(|userref| order.userref == Some(userref)))
+            .map(|(txid, _)| txid.clone())
+            .collect()
+    }
+
+    fn orders_json<'a>(&self, txids: impl Iterator<Item = &'a String>) -> Value {
+        Value::Object(txids.filter_map(|txid| Some((txid.clone(), self.order_json(self.orders.get(txid)?)))).collect())
+    }
+
+    /// Simulated result of `method`, or Kraken's error for it.
+    fn answer(&mut self, method: &str, params: &HashMap<String, String>) -> Result<Value, &'static str> {
+        match method {
+            "Time" => Ok(json!({ "unixtime": crate::clock::unix(&crate::clock::now()), "rfc1123": "" })),
+            "AssetPairs" => Ok(json!(self.pairs)),
+            "Assets" => {
+                let mut assets = serde_json::Map::new();
+                for pair in self.pairs.values() {
+                    for asset in [&pair["base"], &pair["quote"]] {
+                        let asset = asset.as_str().unwrap_or_default();
+                        // XXBT is XBT and ZUSD is USD, newer assets have no prefix
+                        let altname = match asset.len() {
+                            4 if asset.starts_with(['X', 'Z']) => &asset[1..],
+                            _ => asset,
+                        };
+                        let decimals = if asset.starts_with('Z') { 4 } else { 10 };
+                        assets.insert(
+                            asset.to_owned(),
+                            json!({ "aclass": "currency", "altname": altname, "decimals": decimals, "display_decimals": decimals.min(5) }),
+                        );
+                    }
+                }
+                Ok(Value::Object(assets))
+            }
+            "Ticker" => {
+                let mut tickers = serde_json::Map::new();
+                for pair in params.get("pair").map_or("", |pair| pair.as_str()).split(',') {
+                    // keyed by the pair key, also when asked by altname
+                    let key = self
+                        .pairs
+                        .iter()
+                        .find(|(_, info)| info["altname"] == pair)
+                        .map_or(pair, |(key, _)| key.as_str());
+                    let price = self.price(key)?.to_string();
+                    tickers.insert(key.to_owned(), json!({ "a": [price, "1", "1.000"], "b": [price, "1", "1.000"], "c": [price, "0.1"] }));
+                }
+                Ok(Value::Object(tickers))
+            }
+            "Balance" => Ok(json!(self
+                .balances
+                .iter()
+                .map(|(asset, amount)| (asset.clone(), amount.to_string()))
+                .collect::<HashMap<_, _>>())),
+            "BalanceEx" => Ok(json!(self
+                .balances
+                .iter()
+                .map(|(asset, amount)| {
+                    (asset.clone(), json!({ "balance": amount.to_string(), "hold_trade": self.held(asset).to_string() }))
+                })
+                .collect::<HashMap<_, _>>())),
+            "AddOrder" => self.add_order(params),
+            "CancelOrder" => self.cancel_order(params),
+            "OpenOrders" => {
+                let open = self.orders_where(params, |order| order.status == "open");
+                Ok(json!({ "open": self.orders_json(open.iter()) }))
+            }
+            "ClosedOrders" => {
+                // newest first, all on one page
+                let mut closed = self.orders_where(params, |order| order.status != "open");
+                closed.reverse();
+                Ok(json!({ "closed": self.orders_json(closed.iter()), "count": closed.len() }))
+            }
+            "QueryOrders" => {
+                let txids: Vec<String> = params.get("txid").map_or("", |txid| txid.as_str()).split(',').map(str::to_owned).collect();
+                match self.orders_json(txids.iter()) {
+                    Value::Object(found) if found.is_empty() => Err("EOrder:Invalid order"),
+                    found => Ok(found),
+                }
+            }
+            _ => Err("EGeneral:Unknown method"),
+        }
+    }
+}
+
+/// Answers the one request of `stream`, which is closed afterwards.
+async fn serve(mut stream: TcpStream, state: Arc<Mutex<State>>) {
+    let mut request = Vec::new();
+    let mut chunk = [0; 4096];
+
+    let header_end = loop {
+        if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => request.extend_from_slice(&chunk[..read]),
+        }
+    };
+
+    let head = String::from_utf8_lossy(&request[..header_end]).into_owned();
+    let mut lines = head.lines();
+    let target = lines.next().and_then(|line| line.split(' ').nth(1)).unwrap_or_default().to_owned();
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_owned()))
+        .collect();
+
+    let length = headers.get("content-length").and_then(|length| length.parse().ok()).unwrap_or(0);
+    while request.len() < header_end + length {
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => request.extend_from_slice(&chunk[..read]),
+        }
+    }
+    let body = String::from_utf8_lossy(&request[header_end..header_end + length]).into_owned();
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let method = path.rsplit('/').next().unwrap_or_default().to_owned();
+    let params: HashMap<String, String> = serde_urlencoded::from_str(if body.is_empty() { query } else { &body }).unwrap_or_default();
+
+    let (status, response) = {
+        let mut state = state.lock().unwrap();
+        state.calls.push(Call {
+            method: method.clone(),
+            params: params.clone(),
+            headers,
+            body,
+        });
+
+        match state.scripted.get_mut(&method).and_then(VecDeque::pop_front) {
+            Some(scripted) => scripted,
+            None => match state.answer(&method, &params) {
+                Ok(result) => (200, json!({ "error": [], "result": result }).to_string()),
+                Err(error) => (200, json!({ "error": [error] }).to_string()),
+            },
+        }
+    };
+
+    let response = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        response.len(),
+        response
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kraken::{self, BuyOptions};
+    use crate::plan::{self, DcaPlan};
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    async fn exchange() -> MockExchange {
+        let exchange = MockExchange::start().await;
+        exchange.add_pair("XXBTZUSD", "XBTUSD", "XXBT", "ZUSD", json!({}));
+        exchange.set_price("XXBTZUSD", dec("40000"));
+        exchange
+    }
+
+    #[tokio::test]
+    async fn a_market_buy_fills_at_the_price() {
+        let exchange = exchange().await;
+        exchange.set_balance("ZUSD", dec("1000"));
+        let client = exchange.client();
+
+        let placed = kraken::buy_for_amount(&client, "XXBTZUSD", dec("100"), &BuyOptions::default()).await.unwrap();
+
+        assert_eq!((placed.price, placed.volume), (dec("40000"), dec("0.0025")));
+        assert_eq!(exchange.balance("XXBT"), dec("0.0025"));
+        // 100 spent and the 0.26% taker fee on it
+        assert_eq!(exchange.balance("ZUSD"), dec("899.74"));
+
+        let orders = exchange.orders();
+        assert_eq!(orders.len(), 1);
+        assert_eq!(placed.order.txid, Some(vec![orders[0].0.clone()]));
+        assert_eq!(orders[0].1.status, "closed");
+
+        let call = &exchange.calls("AddOrder")[0];
+        assert_eq!(call.headers["api-key"], KEY);
+        assert_eq!(call.params["volume"], "0.0025");
+        assert!(call.body.starts_with("nonce=") || call.body.contains("&nonce="));
+    }
+
+    #[tokio::test]
+    async fn scripted_answers_come_first() {
+        let exchange = exchange().await;
+        exchange.script("Ticker", 200, r#"{"error": [], "result": {"XXBTZUSD": {"a": ["41000.0", "1", "1.000"], "b": ["40990.0", "1", "1.000"], "c": ["41000.0", "0.1"]}}}"#);
+        let client = exchange.client();
+
+        assert_eq!(kraken::ticker(&client, "XXBTZUSD").await.unwrap().ask_price(), dec("41000"));
+        assert_eq!(kraken::ticker(&client, "XXBTZUSD").await.unwrap().ask_price(), dec("40000"));
+        assert_eq!(exchange.calls("Ticker").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_plan_buys_its_allocation() {
+        let exchange = exchange().await;
+        exchange.add_pair("XETHZUSD", "ETHUSD", "XETH", "ZUSD", json!({ "ordermin": "0.002" }));
+        exchange.set_price("XETHZUSD", dec("2000"));
+        exchange.set_balance("ZUSD", dec("1000"));
+        let client = exchange.client();
+        let plan = DcaPlan::from_json(
+            r#"{"mode": "allocation", "budget": "300", "entries": [{"pair": "XBTUSD", "percent": "60"}, {"pair": "ETHUSD", "percent": "40"}]}"#,
+        )
+        .unwrap();
+
+        for (entry, amount) in plan.amounts() {
+            let prepared = plan::prepare_entry(&client, entry, &plan.quote).await.unwrap();
+            kraken::buy_for_amount(&client, &prepared.pair, amount, &BuyOptions::default()).await.unwrap();
+        }
+
+        assert_eq!(exchange.balance("XXBT"), dec("0.0045"));
+        assert_eq!(exchange.balance("XETH"), dec("0.06"));
+        assert_eq!(exchange.balance("ZUSD"), dec("699.22"));
+        let pairs: Vec<String> = exchange.calls("AddOrder").iter().map(|call| call.params["pair"].clone()).collect();
+        assert_eq!(pairs, vec!["XXBTZUSD", "XETHZUSD"]);
+    }
+
+    #[tokio::test]
+    async fn a_limit_buy_rests_until_the_price_reaches_it() {
+        let exchange = exchange().await;
+        exchange.set_balance("ZUSD", dec("1000"));
+        let client = exchange.client();
+        let options = BuyOptions {
+            limit_price: Some(dec("38000")),
+            ..BuyOptions::default()
+        };
+
+        kraken::buy_for_amount(&client, "XXBTZUSD", dec("95"), &options).await.unwrap();
+        let (txid, order) = exchange.orders().remove(0);
+        assert_eq!((order.status.as_str(), order.volume), ("open", dec("0.0025")));
+        // the resting buy holds its cost and fee
+        let held = kraken::balance_ex(&client).await.unwrap()["ZUSD"].hold_trade;
+        assert_eq!(held, dec("95.247"));
+
+        exchange.set_price("XXBTZUSD", dec("39000"));
+        assert_eq!(exchange.orders()[0].1.status, "open");
+
+        exchange.set_price("XXBTZUSD", dec("37900"));
+        let open = kraken::open_orders(&client, false, None).await.unwrap();
+        assert!(open.is_empty());
+        let filled = &kraken::query_orders(&client, &[&txid], false, None).await.unwrap()[&txid];
+        assert_eq!((filled.status.as_str(), filled.cost.as_str()), ("closed", "95.0000"));
+        assert_eq!(exchange.balance("XXBT"), dec("0.0025"));
+    }
+
+    #[tokio::test]
+    async fn a_buy_beyond_the_balance_is_refused() {
+        let exchange = exchange().await;
+        exchange.set_balance("ZUSD", dec("50"));
+        let client = exchange.client();
+
+        // the client sees the shortfall in BalanceEx and never sends the order
+        let refused = kraken::buy_for_amount(&client, "XXBTZUSD", dec("100"), &BuyOptions::default()).await;
+        assert!(refused.unwrap_err().starts_with("insufficient funds"));
+        assert!(exchange.calls("AddOrder").is_empty());
+
+        // an order sent regardless, e.g. validated earlier, is refused by the exchange
+        let refused = kraken::add_order(&client, market_buy("XXBTZUSD", dec("0.0025"))).await;
+        assert!(refused.unwrap_err().contains("EOrder:Insufficient funds"));
+        assert_eq!(exchange.balance("ZUSD"), dec("50"));
+        assert!(exchange.orders().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_buy_below_the_order_minimum_is_refused() {
+        let exchange = exchange().await;
+        exchange.set_balance("ZUSD", dec("1000"));
+        let client = exchange.client();
+
+        // the client checks the pair's ordermin before sending anything
+        let refused = kraken::buy_for_amount(&client, "XXBTZUSD", dec("3"), &BuyOptions::default()).await;
+        assert!(refused.unwrap_err().contains("below the minimum order of 0.0001"));
+        assert!(exchange.calls("AddOrder").is_empty());
+
+        // and the exchange refuses one that gets past it
+        let refused = kraken::add_order(&client, market_buy("XXBTZUSD", dec("0.00005"))).await;
+        assert!(refused.unwrap_err().contains("EOrder:Order minimum not met"));
+        assert_eq!(exchange.balance("ZUSD"), dec("1000"));
+    }
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::error::UpdateItemErrorKind;
+use aws_sdk_dynamodb::model::{AttributeValue, ReturnValue};
+use aws_sdk_dynamodb::SdkError;
+
+use crate::kraken::NonceSource;
+
+/// Nonces of one API key from an atomic counter in DynamoDB, shared by every
+/// process using the key. The counter follows the clock in nanoseconds, like the
+/// in-process nonces, and only counts up past it when callers outpace the clock,
+/// so switching to it from those nonces (or back) never goes backwards.
+pub struct DynamoNonces {
+    pub table: String,
+    /// item the counter is kept in, one per API key
+    id: String,
+    client: aws_sdk_dynamodb::Client,
+}
+
+impl DynamoNonces {
+    /// Counter of API key `key` in the `NONCE_TABLE` table, `None` when it isn't configured.
+    pub async fn from_env(key: &str) -> Option<DynamoNonces> {
+        let table = option_env!("NONCE_TABLE")?;
+        let config = aws_config::load_from_env().await;
+
+        Some(DynamoNonces {
+            table: table.to_owned(),
+            id: key.to_owned(),
+            client: aws_sdk_dynamodb::Client::new(&config),
+        })
+    }
+
+    /// Moves the counter up to `value` unless it already is at or past it.
+    /// `Ok(false)` when it was.
+    async fn advance_to(&self, value: u64) -> Result<bool, String> {
+        let updated = self
+            .client
+            .update_item()
+            .table_name(&self.table)
+            .key("id", AttributeValue::S(self.id.clone()))
+            .update_expression("SET nonce = :value")
+            .condition_expression("attribute_not_exists(nonce) OR nonce < :value")
+            .expression_attribute_values(":value", AttributeValue::N(value.to_string()))
+            .send()
+            .await;
+
+        match updated {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError { err, .. }) if matches!(err.kind, UpdateItemErrorKind::ConditionalCheckFailedException(_)) => {
+                Ok(false)
+            }
+            Err(e) => Err(format!("{:?}", e)),
+        }
+    }
+}
+
+fn now_nanos() -> u64 {
+    ::std::time::UNIX_EPOCH.elapsed().unwrap().as_nanos() as u64
+}
+
+#[async_trait]
+impl NonceSource for DynamoNonces {
+    async fn next(&self) -> Result<u64, String> {
+        let now = now_nanos();
+        if self.advance_to(now).await? {
+            return Ok(now);
+        }
+
+        // another caller got this far already, take the value after the counter's
+        let updated = self
+            .client
+            .update_item()
+            .table_name(&self.table)
+            .key("id", AttributeValue::S(self.id.clone()))
+            .update_expression("ADD nonce :one")
+            .expression_attribute_values(":one", AttributeValue::N(String::from("1")))
+            .return_values(ReturnValue::UpdatedNew)
+            .send()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+
+        match updated.attributes.as_ref().and_then(|attributes| attributes.get("nonce")) {
+            Some(AttributeValue::N(nonce)) => nonce.parse().map_err(|e| format!("{:?}", e)),
+            _ => Err(String::from("nonce missing from the update")),
+        }
+    }
+
+    async fn bump(&self, ahead: Duration) -> Result<(), String> {
+        self.advance_to(now_nanos() + ahead.as_nanos() as u64).await.map(|_| ())
+    }
+}
@@ -0,0 +1,189 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::kraken::{self, KrakenClient, TradeBalance};
+
+/// Status of an account at a glance. A field Kraken couldn't provide is `None`
+/// and its error listed in `unavailable`.
+#[derive(Serialize, Debug)]
+pub struct AccountOverview {
+    pub balance: Option<HashMap<String, String>>,
+    pub trade_balance: Option<TradeBalance>,
+    pub open_orders: Option<usize>,
+    /// trades since midnight UTC
+    pub trades_today: Option<u32>,
+    /// field name and error of every field left out
+    pub unavailable: Vec<(String, String)>,
+}
+
+fn available<T>(field: &str, result: Result<T, String>, unavailable: &mut Vec<(String, String)>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(e) => {
+            log::warn!("account overview without {}: {}", field, e);
+            unavailable.push((field.to_owned(), e));
+            None
+        }
+    }
+}
+
+/// Gathers the overview. The calls are all private and run one after the
+/// other, as concurrent requests could reach Kraken with their nonces out of order.
+pub async fn account_overview(client: &KrakenClient) -> AccountOverview {
+    let mut unavailable = Vec::new();
+
+    let balance = available("balance", kraken::balance(client).await, &mut unavailable);
+    let trade_balance = available("trade_balance", kraken::trade_balance(client, None).await, &mut unavailable);
+    let open_orders = available(
+        "open_orders",
+        kraken::open_orders(client, false, None).await.map(|orders| orders.len()),
+        &mut unavailable,
+    );
+
+    let now = ::std::time::UNIX_EPOCH.elapsed().unwrap().as_secs() as i64;
+    let midnight = now - now % 86400;
+    let trades_today = available(
+        "trades_today",
+        kraken::trades_history(client, Some(midnight), 0).await.map(|page| page.count),
+        &mut unavailable,
+    );
+
+    AccountOverview {
+        balance,
+        trade_balance,
+        open_orders,
+        trades_today,
+        unavailable,
+    }
+}
+
+/// Entry of the account's activity feed.
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ActivityItem {
+    /// closed, canceled or expired order
+    Order {
+        txid: String,
+        /// unix time the order was closed, or placed if Kraken has no close time
+        time: f64,
+        status: String,
+        description: String,
+        cost: String,
+        fee: String,
+    },
+    /// ledger entry other than a trade, which its order already covers
+    Ledger {
+        id: String,
+        time: f64,
+        /// deposit, withdrawal, staking, ...
+        entry_type: String,
+        asset: String,
+        amount: Decimal,
+        fee: Decimal,
+    },
+}
+
+impl ActivityItem {
+    fn time(&self) -> f64 {
+        match self {
+            ActivityItem::Order { time, .. } | ActivityItem::Ledger { time, .. } => *time,
+        }
+    }
+}
+
+/// The account's latest closed orders and ledger entries, newest first and at most
+/// `limit` of them. Both come from the first history page, Kraken's 50 most recent.
+pub async fn recent_activity(client: &KrakenClient, limit: usize) -> Result<Vec<ActivityItem>, String> {
+    let orders = kraken::closed_orders_page(client, None, 0).await?;
+    let ledger = kraken::ledgers(client, None, None, 0).await?;
+
+    let mut activity: Vec<ActivityItem> = orders
+        .closed
+        .into_iter()
+        .map(|(txid, order)| ActivityItem::Order {
+            txid,
+            time: order.closetm.unwrap_or(order.opentm),
+            description: kraken::format_order(&order.descr),
+            status: order.status,
+            cost: order.cost,
+            fee: order.fee,
+        })
+        .collect();
+
+    activity.extend(
+        ledger
+            .ledger
+            .into_iter()
+            .filter(|(_, entry)| entry.entry_type != "trade")
+            .map(|(id, entry)| ActivityItem::Ledger {
+                id,
+                time: entry.time,
+                entry_type: entry.entry_type,
+                asset: entry.asset,
+                amount: entry.amount,
+                fee: entry.fee,
+            }),
+    );
+
+    activity.sort_by(|a, b| b.time().partial_cmp(&a.time()).unwrap_or(Ordering::Equal));
+    activity.truncate(limit);
+
+    Ok(activity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockExchange;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn activity_merges_orders_and_ledger_newest_first() {
+        let exchange = MockExchange::start().await;
+        exchange.add_pair("XXBTZUSD", "XBTUSD", "XXBT", "ZUSD", serde_json::json!({}));
+        exchange.set_price("XXBTZUSD", dec("40000"));
+        exchange.set_balance("ZUSD", dec("1000"));
+        let client = exchange.client();
+
+        let buy = kraken::buy_for_amount(&client, "XXBTZUSD", dec("100"), &kraken::BuyOptions::default()).await.unwrap();
+        let txid = buy.order.txid.unwrap()[0].clone();
+        let ledger = |kind: &str, time: u32, amount: &str| {
+            serde_json::json!({
+                "refid": "REF", "time": time, "type": kind, "subtype": "", "aclass": "currency",
+                "asset": "ZUSD", "amount": amount, "fee": "0", "balance": "1000"
+            })
+        };
+        let page = serde_json::json!({
+            "error": [],
+            "result": {
+                "ledger": {
+                    "L1": ledger("deposit", 1000, "1000"),
+                    "L2": ledger("trade", 2000, "-100"),
+                    "L3": ledger("withdrawal", 4_000_000_000, "-50"),
+                },
+                "count": 3
+            }
+        });
+        exchange.script("Ledgers", 200, &page.to_string());
+
+        let activity = recent_activity(&client, 10).await.unwrap();
+        let listed: Vec<String> = activity
+            .iter()
+            .map(|item| match item {
+                ActivityItem::Order { txid, .. } => txid.clone(),
+                ActivityItem::Ledger { id, .. } => id.clone(),
+            })
+            .collect();
+        // the trade entry is the order's own
+        assert_eq!(listed, vec![String::from("L3"), txid, String::from("L1")]);
+
+        exchange.script("Ledgers", 200, &page.to_string());
+        assert_eq!(recent_activity(&client, 1).await.unwrap().len(), 1);
+    }
+}
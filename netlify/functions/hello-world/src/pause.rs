@@ -0,0 +1,113 @@
+use aws_sdk_dynamodb::model::AttributeValue;
+use serde::Serialize;
+
+use crate::clock::{self, Timestamp};
+
+/// item of the pause table holding the flag
+const PAUSE_ID: &str = "paused";
+
+/// Whether scheduled runs are paused, and since when as far as it is known.
+#[derive(Debug, Serialize)]
+pub struct PauseState {
+    pub paused: bool,
+    pub since: Option<Timestamp>,
+}
+
+impl PauseState {
+    fn running() -> PauseState {
+        PauseState { paused: false, since: None }
+    }
+}
+
+/// Pause flag persisted across invocations, so runs can be halted without
+/// touching the EventBridge rule.
+pub struct PauseFlag {
+    pub table: String,
+    client: aws_sdk_dynamodb::Client,
+}
+
+impl PauseFlag {
+    /// Flag in the `PAUSE_TABLE` table, `None` when it isn't configured.
+    pub async fn from_env() -> Option<PauseFlag> {
+        let table = option_env!("PAUSE_TABLE")?;
+        let config = aws_config::load_from_env().await;
+
+        Some(PauseFlag {
+            table: table.to_owned(),
+            client: aws_sdk_dynamodb::Client::new(&config),
+        })
+    }
+
+    pub async fn get(&self) -> Result<PauseState, String> {
+        let stored = self
+            .client
+            .get_item()
+            .table_name(&self.table)
+            .key("id", AttributeValue::S(PAUSE_ID.to_owned()))
+            .send()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+
+        let since = match stored.item.as_ref().and_then(|item| item.get("since")) {
+            Some(AttributeValue::N(since)) => since,
+            _ => return Ok(PauseState::running()),
+        };
+        let since = since
+            .parse()
+            .ok()
+            .and_then(clock::from_unix)
+            .ok_or_else(|| format!("invalid pause time {}", since))?;
+
+        Ok(PauseState {
+            paused: true,
+            since: Some(since),
+        })
+    }
+
+    /// Pauses runs, keeping the time of an earlier pause, or lets them run again.
+    pub async fn set(&self, paused: bool) -> Result<PauseState, String> {
+        if !paused {
+            return self
+                .client
+                .delete_item()
+                .table_name(&self.table)
+                .key("id", AttributeValue::S(PAUSE_ID.to_owned()))
+                .send()
+                .await
+                .map(|_| PauseState::running())
+                .map_err(|e| format!("{:?}", e));
+        }
+
+        let current = self.get().await?;
+        if current.paused {
+            return Ok(current);
+        }
+
+        let since = clock::now();
+        self.client
+            .put_item()
+            .table_name(&self.table)
+            .item("id", AttributeValue::S(PAUSE_ID.to_owned()))
+            .item("since", AttributeValue::N(clock::unix(&since).to_string()))
+            .send()
+            .await
+            .map(|_| PauseState {
+                paused: true,
+                since: Some(since),
+            })
+            .map_err(|e| format!("{:?}", e))
+    }
+}
+
+/// Pause state of the runs: `DCA_PAUSED=true` pauses them for good, otherwise
+/// the persisted flag decides, and without one they run.
+pub async fn pause_state(flag: Option<&PauseFlag>) -> Result<PauseState, String> {
+    if option_env!("DCA_PAUSED") == Some("true") {
+        return Ok(PauseState { paused: true, since: None });
+    }
+
+    match flag {
+        Some(flag) => flag.get().await,
+        None => Ok(PauseState::running()),
+    }
+}
@@ -0,0 +1,511 @@
+use std::collections::{HashMap, VecDeque};
+
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::clock::{self, Timestamp};
+use crate::kraken::{self, KrakenClient};
+
+#[derive(Serialize, Debug)]
+pub struct RoiReport {
+    /// asset id everything is valued in
+    pub quote: String,
+    pub deposited: Decimal,
+    pub withdrawn: Decimal,
+    /// deposited minus withdrawn, negative when more was withdrawn than deposited
+    pub net_deposited: Decimal,
+    pub current_value: Decimal,
+    /// gain over the net deposit in percent, `None` when nothing is net deposited
+    pub return_pct: Option<Decimal>,
+    /// money-weighted yearly return in percent, see `annualized_return`
+    pub annualized_return_pct: Option<Decimal>,
+    /// unix time of the first and last deposit or withdrawal covered
+    pub from: Option<f64>,
+    pub to: Option<f64>,
+}
+
+/// Compares the net `quote` deposited (deposits minus withdrawals of `quote`
+/// itself, crypto transfers are not counted) with the current portfolio value.
+pub async fn roi(client: &KrakenClient, quote: &str) -> Result<RoiReport, String> {
+    let mut entries = kraken::all_ledgers(client, Some(quote), Some("deposit")).await?;
+    entries.extend(kraken::all_ledgers(client, Some(quote), Some("withdrawal")).await?);
+
+    let deposited: Decimal = entries
+        .iter()
+        .filter(|entry| entry.entry_type == "deposit")
+        .map(|entry| entry.amount.abs())
+        .sum();
+    let withdrawn: Decimal = entries
+        .iter()
+        .filter(|entry| entry.entry_type == "withdrawal")
+        .map(|entry| entry.amount.abs())
+        .sum();
+
+    let net_deposited = deposited - withdrawn;
+    let current_value = kraken::portfolio_value(client, quote).await?;
+
+    let return_pct = if net_deposited > Decimal::ZERO {
+        Some((current_value - net_deposited) / net_deposited * Decimal::ONE_HUNDRED)
+    } else {
+        None
+    };
+
+    let times = entries.iter().map(|entry| entry.time);
+
+    Ok(RoiReport {
+        quote: quote.to_owned(),
+        deposited,
+        withdrawn,
+        net_deposited,
+        current_value,
+        return_pct,
+        annualized_return_pct: annualized_return(&entries, current_value, clock::unix(&clock::now()) as f64),
+        from: times.clone().reduce(f64::min),
+        to: times.reduce(f64::max),
+    })
+}
+
+/// Profit or loss of one asset held against `quote`.
+#[derive(Serialize, Debug)]
+pub struct PnlReport {
+    pub asset: String,
+    /// asset id the profit and loss is valued in
+    pub quote: String,
+    /// bought minus sold as far as the trade history goes
+    pub held: Decimal,
+    /// average cost of `held`, fees included, `None` without trade history
+    pub average_cost: Option<Decimal>,
+    /// gains of the sells over the average cost at the time
+    pub realized: Decimal,
+    /// value of `held` at the last price over its cost, plus the open margin
+    /// positions' net; `None` without trade history to take a cost basis from
+    pub unrealized: Option<Decimal>,
+}
+
+/// Running average cost basis of one asset.
+#[derive(Default)]
+struct CostBasis {
+    held: Decimal,
+    cost: Decimal,
+    realized: Decimal,
+}
+
+impl CostBasis {
+    fn average_cost(&self) -> Option<Decimal> {
+        if self.held > Decimal::ZERO {
+            Some(self.cost / self.held)
+        } else {
+            None
+        }
+    }
+
+    fn apply(&mut self, trade: &kraken::Trade) {
+        if trade.order_direction == "buy" {
+            self.held += trade.vol;
+            self.cost += trade.cost + trade.fee;
+            return;
+        }
+
+        // sells beyond what the history bought came from elsewhere, e.g. a deposit
+        let sold = trade.vol.min(self.held);
+        let sold_cost = self.average_cost().unwrap_or_default() * sold;
+        let proceeds = if trade.vol.is_zero() { Decimal::ZERO } else { (trade.cost - trade.fee) * sold / trade.vol };
+
+        self.realized += proceeds - sold_cost;
+        self.cost -= sold_cost;
+        self.held -= sold;
+    }
+}
+
+/// Realized and unrealized profit or loss of every asset traded against or
+/// held besides `quote` (an asset id such as `ZUSD`), at average cost from the
+/// spot trade history. Trades against other quotes are left out, as are
+/// assets without a `quote` pair to price them. Held assets without any trade
+/// history are reported without a cost basis.
+pub async fn pnl(client: &KrakenClient, quote: &str) -> Result<Vec<PnlReport>, String> {
+    let metadata = client.metadata().await?;
+
+    let mut trades: Vec<_> = kraken::all_trades(client, None)
+        .await?
+        .into_values()
+        // margin trades are covered by the open positions
+        .filter(|trade| trade.margin.is_zero())
+        .collect();
+    trades.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut bases: HashMap<String, CostBasis> = HashMap::new();
+    for trade in &trades {
+        if let Some(pair) = metadata.pair_info(&trade.pair).filter(|pair| pair.quote == quote) {
+            bases.entry(pair.base.clone()).or_default().apply(trade);
+        }
+    }
+
+    let mut margin_net: HashMap<String, Decimal> = HashMap::new();
+    for position in kraken::open_positions(client, true).await?.values() {
+        if let Some(pair) = metadata.pair_info(&position.pair).filter(|pair| pair.quote == quote) {
+            *margin_net.entry(pair.base.clone()).or_default() += position.net.unwrap_or_default();
+        }
+    }
+
+    let mut assets: Vec<String> = kraken::balance_decimal(client, false)
+        .await?
+        .into_iter()
+        .filter(|(asset, _)| asset != quote)
+        .map(|(asset, _)| asset)
+        .chain(bases.keys().cloned())
+        .chain(margin_net.keys().cloned())
+        .collect();
+    assets.sort();
+    assets.dedup();
+
+    let mut reports = Vec::new();
+    for asset in assets {
+        let pair = metadata
+            .pairs
+            .iter()
+            .find(|(_, pair)| pair.base == asset && pair.quote == quote)
+            .map(|(key, _)| key.clone());
+
+        let pair = match pair {
+            Some(pair) => pair,
+            None => {
+                log::warn!("no {} pair to value {}, leaving it out", quote, asset);
+                continue;
+            }
+        };
+
+        let basis = bases.remove(&asset);
+        let net = margin_net.get(&asset).copied();
+
+        let unrealized = match &basis {
+            Some(basis) => {
+                let price = kraken::ticker(client, &pair).await?.last_price();
+                Some(basis.held * price - basis.cost + net.unwrap_or_default())
+            }
+            None => net,
+        };
+
+        reports.push(PnlReport {
+            quote: quote.to_owned(),
+            held: basis.as_ref().map(|basis| basis.held).unwrap_or_default(),
+            average_cost: basis.as_ref().and_then(CostBasis::average_cost),
+            realized: basis.as_ref().map(|basis| basis.realized).unwrap_or_default(),
+            unrealized,
+            asset,
+        });
+    }
+
+    Ok(reports)
+}
+
+/// Part of a buy matched first in, first out, either still held or sold.
+#[derive(Serialize, Debug)]
+pub struct TaxLot {
+    pub asset: String,
+    /// asset id the cost basis and proceeds are in
+    pub quote: String,
+    pub volume: Decimal,
+    /// time of the buy, `None` for a sell beyond what the history bought
+    pub acquired: Option<Timestamp>,
+    /// share of the buy's cost and fee, `None` along with `acquired`
+    pub cost_basis: Option<Decimal>,
+    /// time of the sell, `None` for a lot still held
+    pub disposed: Option<Timestamp>,
+    /// share of the sell's cost less its fee
+    pub proceeds: Option<Decimal>,
+    /// proceeds over the cost basis, `None` unless both are known
+    pub gain: Option<Decimal>,
+}
+
+/// Buys of one asset not sold yet, oldest first.
+#[derive(Default)]
+struct FifoLots {
+    open: VecDeque<OpenLot>,
+}
+
+struct OpenLot {
+    acquired: Option<Timestamp>,
+    volume: Decimal,
+    cost: Decimal,
+}
+
+impl FifoLots {
+    /// Adds a buy as a lot, or matches a sell against the oldest lots and
+    /// returns the lots it closed.
+    fn apply(&mut self, trade: &kraken::Trade, asset: &str, quote: &str) -> Vec<TaxLot> {
+        if trade.order_direction == "buy" {
+            self.open.push_back(OpenLot {
+                acquired: clock::from_unix_f64(trade.time),
+                volume: trade.vol,
+                cost: trade.cost + trade.fee,
+            });
+            return Vec::new();
+        }
+        if trade.vol.is_zero() {
+            return Vec::new();
+        }
+
+        let disposed = clock::from_unix_f64(trade.time);
+        let proceeds_per_unit = (trade.cost - trade.fee) / trade.vol;
+        let mut remaining = trade.vol;
+        let mut closed = Vec::new();
+
+        while remaining > Decimal::ZERO {
+            let lot = match self.open.front_mut() {
+                Some(lot) => lot,
+                None => break,
+            };
+            let acquired = lot.acquired;
+            let volume = lot.volume.min(remaining);
+            let cost_basis = lot.cost * volume / lot.volume;
+            let proceeds = proceeds_per_unit * volume;
+
+            lot.volume -= volume;
+            lot.cost -= cost_basis;
+            if lot.volume.is_zero() {
+                self.open.pop_front();
+            }
+            remaining -= volume;
+
+            closed.push(TaxLot {
+                asset: asset.to_owned(),
+                quote: quote.to_owned(),
+                volume,
+                acquired,
+                cost_basis: Some(cost_basis),
+                disposed,
+                proceeds: Some(proceeds),
+                gain: Some(proceeds - cost_basis),
+            });
+        }
+
+        // sold beyond what the history bought, e.g. a deposit, without a basis
+        if remaining > Decimal::ZERO {
+            closed.push(TaxLot {
+                asset: asset.to_owned(),
+                quote: quote.to_owned(),
+                volume: remaining,
+                acquired: None,
+                cost_basis: None,
+                disposed,
+                proceeds: Some(proceeds_per_unit * remaining),
+                gain: None,
+            });
+        }
+
+        closed
+    }
+
+    fn held(self, asset: &str, quote: &str) -> impl Iterator<Item = TaxLot> {
+        let (asset, quote) = (asset.to_owned(), quote.to_owned());
+
+        self.open.into_iter().map(move |lot| TaxLot {
+            asset: asset.clone(),
+            quote: quote.clone(),
+            volume: lot.volume,
+            acquired: lot.acquired,
+            cost_basis: Some(lot.cost),
+            disposed: None,
+            proceeds: None,
+            gain: None,
+        })
+    }
+}
+
+/// Tax lots of every asset traded against `quote` (an asset id such as
+/// `ZUSD`), matching sells with the oldest buys first over the spot trade
+/// history: one lot per part of a buy a sell closed, with its gain or loss,
+/// followed by the lots still held. Fees are added to the cost basis and taken
+/// off the proceeds. Margin trades and trades against other quotes are left
+/// out, and the figures are estimates, not tax advice.
+pub async fn tax_lots(client: &KrakenClient, quote: &str) -> Result<Vec<TaxLot>, String> {
+    let metadata = client.metadata().await?;
+
+    let mut trades: Vec<_> = kraken::all_trades(client, None)
+        .await?
+        .into_values()
+        .filter(|trade| trade.margin.is_zero())
+        .collect();
+    trades.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut lots: HashMap<String, FifoLots> = HashMap::new();
+    let mut closed = Vec::new();
+    for trade in &trades {
+        if let Some(pair) = metadata.pair_info(&trade.pair).filter(|pair| pair.quote == quote) {
+            closed.extend(lots.entry(pair.base.clone()).or_default().apply(trade, &pair.base, quote));
+        }
+    }
+
+    let mut assets: Vec<_> = lots.into_iter().collect();
+    assets.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (asset, lots) in assets {
+        closed.extend(lots.held(&asset, quote));
+    }
+
+    Ok(closed)
+}
+
+/// ledger entry types trading fees are charged on
+const FEE_ENTRY_TYPES: [&str; 3] = ["trade", "margin", "rollover"];
+
+/// Trading fees charged from `since` to `until`, converted to `currency` (asset
+/// id or altname) at the last cross rate of each asset they were paid in. Zero
+/// for a period without any.
+pub async fn fees_paid(client: &KrakenClient, since: Timestamp, until: Timestamp, currency: &str) -> Result<Decimal, String> {
+    let entries = kraken::all_ledgers_between(client, None, None, Some(clock::unix(&since)), Some(clock::unix(&until))).await?;
+
+    let mut by_asset: HashMap<String, Decimal> = HashMap::new();
+    for entry in entries.iter().filter(|entry| FEE_ENTRY_TYPES.contains(&entry.entry_type.as_str())) {
+        if !entry.fee.is_zero() {
+            *by_asset.entry(entry.asset.clone()).or_default() += entry.fee;
+        }
+    }
+
+    let mut total = Decimal::ZERO;
+    for (asset, fee) in by_asset {
+        total += fee * kraken::cross_rate(client, &asset, currency).await?;
+    }
+
+    Ok(total)
+}
+
+/// history shorter than this isn't annualized, a few days' return would be blown out of scale
+const MIN_RETURN_HISTORY_DAYS: i64 = 30;
+
+const SECS_PER_YEAR: f64 = 365.25 * 86400.0;
+
+/// yearly rates the money-weighted return is searched between, -99% to +1000%
+const RETURN_SEARCH_RANGE: (f64, f64) = (-0.99, 10.0);
+
+/// Money-weighted annualized return in percent, e.g. `12.5` for 12.5% a year,
+/// of the deposit and withdrawal ledger `entries` (as `roi` takes them) grown
+/// to `current_value` by unix time `now`.
+///
+/// The return is the internal rate of return of that timeline: the yearly rate
+/// `r` at which every deposit grown by `(1 + r)` per year since it was made,
+/// less every withdrawal grown the same way, adds up to the current value. Each
+/// cashflow is weighted by how long the money was invested, so buying through a
+/// dip after a large deposit counts for more than the same move on a small one.
+/// The rate is found by bisection, which stops at a hundredth of a basis point.
+///
+/// `None` without deposits, with less than 30 days since the first one, or
+/// when no rate between -99% and +1000% a year matches the value.
+fn annualized_return(entries: &[kraken::LedgerEntry], current_value: Decimal, now: f64) -> Option<Decimal> {
+    let first = entries.iter().filter(|entry| entry.entry_type == "deposit").map(|entry| entry.time).reduce(f64::min)?;
+    if now - first < (MIN_RETURN_HISTORY_DAYS * 86400) as f64 {
+        return None;
+    }
+
+    // (years invested until now, amount put in), withdrawals taking money out
+    let cashflows: Vec<(f64, Decimal)> = entries
+        .iter()
+        .map(|entry| {
+            let amount = if entry.entry_type == "deposit" { entry.amount.abs() } else { -entry.amount.abs() };
+            ((now - entry.time).max(0.0) / SECS_PER_YEAR, amount)
+        })
+        .collect();
+
+    // what the cashflows grow to at `rate` a year, less the current value;
+    // rises with the rate as long as more went in than came out early on, and
+    // growth past what a Decimal holds counts as the most there is
+    let excess = |rate: f64| -> Decimal {
+        cashflows
+            .iter()
+            .try_fold(Decimal::ZERO, |total, (years, amount)| {
+                let grown = amount.checked_mul(Decimal::from_f64((1.0 + rate).powf(*years))?)?;
+                total.checked_add(grown)
+            })
+            .map_or(Decimal::MAX, |grown| grown - current_value)
+    };
+
+    let (mut low, mut high) = RETURN_SEARCH_RANGE;
+    let (low_excess, high_excess) = (excess(low), excess(high));
+    if low_excess.is_sign_positive() == high_excess.is_sign_positive() {
+        return None;
+    }
+
+    while high - low > 1e-6 {
+        let mid = (low + high) / 2.0;
+        if excess(mid).is_sign_positive() == low_excess.is_sign_positive() {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+
+    Decimal::from_f64((low + high) / 2.0 * 100.0).map(|pct| pct.round_dp(2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    fn cashflow(entry_type: &str, time: f64, amount: &str) -> kraken::LedgerEntry {
+        serde_json::from_value(serde_json::json!({
+            "refid": "REF", "time": time, "type": entry_type, "subtype": "", "aclass": "currency",
+            "asset": "ZUSD", "amount": amount, "fee": "0", "balance": "0"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn returns_are_weighted_by_how_long_the_money_was_in() {
+        let now = 1_700_000_000.0;
+        let year_ago = now - SECS_PER_YEAR;
+
+        assert_eq!(annualized_return(&[cashflow("deposit", year_ago, "1000")], dec("1100"), now), Some(dec("10")));
+
+        // the same gain on money in for half the time is a better return
+        let entries = [cashflow("deposit", year_ago, "500"), cashflow("deposit", now - SECS_PER_YEAR / 2.0, "500")];
+        let weighted = annualized_return(&entries, dec("1100"), now).unwrap();
+        assert!(weighted > dec("13") && weighted < dec("14"), "{}", weighted);
+
+        let withdrawn = [cashflow("deposit", year_ago, "1000"), cashflow("withdrawal", now - 1.0, "-100")];
+        assert_eq!(annualized_return(&withdrawn, dec("1000"), now), Some(dec("10")));
+    }
+
+    #[test]
+    fn short_or_missing_histories_are_not_annualized() {
+        let now = 1_700_000_000.0;
+
+        assert_eq!(annualized_return(&[], dec("100"), now), None);
+        assert_eq!(annualized_return(&[cashflow("deposit", now - 86400.0, "1000")], dec("1100"), now), None);
+    }
+
+    #[tokio::test]
+    async fn fees_paid_in_any_asset_add_up_in_the_currency() {
+        let exchange = crate::mock::MockExchange::start().await;
+        exchange.add_pair("XXBTZUSD", "XBTUSD", "XXBT", "ZUSD", serde_json::json!({}));
+        exchange.set_price("XXBTZUSD", dec("40000"));
+        let client = exchange.client();
+        let fee = |entry_type: &str, asset: &str, fee: &str| {
+            serde_json::json!({
+                "refid": "REF", "time": 1000.0, "type": entry_type, "subtype": "", "aclass": "currency",
+                "asset": asset, "amount": "0", "fee": fee, "balance": "0"
+            })
+        };
+        let page = serde_json::json!({
+            "error": [],
+            "result": {
+                "ledger": {
+                    "L1": fee("trade", "ZUSD", "0.26"),
+                    "L2": fee("trade", "XXBT", "0.00001"),
+                    "L3": fee("withdrawal", "ZUSD", "5"),
+                },
+                "count": 3
+            }
+        });
+        exchange.script("Ledgers", 200, &page.to_string());
+        let (since, until) = (clock::from_unix(0).unwrap(), clock::from_unix(2000).unwrap());
+
+        assert_eq!(fees_paid(&client, since, until, "USD").await.unwrap(), dec("0.66"));
+
+        exchange.script("Ledgers", 200, r#"{"error": [], "result": {"ledger": {}, "count": 0}}"#);
+        assert_eq!(fees_paid(&client, since, until, "USD").await.unwrap(), Decimal::ZERO);
+    }
+}
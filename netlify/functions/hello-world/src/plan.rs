@@ -0,0 +1,2134 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::clock;
+use crate::filter::{FilterConfig, OHLC_INTERVALS};
+use crate::kraken::{self, FeePreference, KrakenClient, Metadata, OrderDirection, PlacedBuy, RoundingMode, TickerInfo, VolumeCapPolicy};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanMode {
+    /// every entry spends its own fixed `amount`
+    #[default]
+    Fixed,
+    /// every entry spends `percent` of the plan `budget`
+    Allocation,
+}
+
+/// How a run that would spend into the reserve is cut down.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ReservePolicy {
+    /// scale every entry down by the same factor
+    #[default]
+    Scale,
+    /// drop entries from the end of the plan until the rest fits
+    SkipLowest,
+}
+
+/// What a plan listing the same pair more than once gets.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicatePolicy {
+    /// refuse the plan
+    #[default]
+    Reject,
+    /// buy once, for the sum of the entries' amounts, with the first entry's settings
+    Merge,
+}
+
+/// What market entries get while the exchange only takes post only orders.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PostOnlyPolicy {
+    /// leave them out of the run
+    #[default]
+    Skip,
+    /// place them as post only limits at the bid
+    Adapt,
+}
+
+/// What a limit order the run left open gets once it ran out of reprices.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum GiveUp {
+    /// leave it open
+    Keep,
+    #[default]
+    Cancel,
+    /// cancel it and buy the rest at market
+    MarketBuy,
+}
+
+/// Moving the run's unfilled limit orders after the market.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RepricePolicy {
+    /// wait before every reprice (seconds in JSON), `DEFAULT_REPRICE_WAIT` when not set
+    #[serde(default, serialize_with = "serialize_secs", deserialize_with = "deserialize_secs")]
+    pub wait: Option<Duration>,
+    /// move the limit this many percent of the ask up each time
+    pub step_pct: Decimal,
+    pub max_reprices: u32,
+    #[serde(default)]
+    pub give_up: GiveUp,
+}
+
+/// wait before a reprice when the policy doesn't set one
+const DEFAULT_REPRICE_WAIT: Duration = Duration::from_secs(10);
+
+/// Order a run places its entries in.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderingStrategy {
+    /// as listed in the plan
+    #[default]
+    AsListed,
+    /// largest amount first
+    ByBudgetDesc,
+    /// widest recent price range first, see `volatility`
+    ByVolatilityDesc,
+}
+
+/// Kind of order an entry buys with.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanOrderType {
+    #[default]
+    Market,
+    /// priced by the entry's `price_strategy`
+    Limit,
+}
+
+/// Where a limit buy is priced, e.g. `"bid"` or `{"below_ask": "1.5"}`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceStrategy {
+    /// at the best bid
+    Bid,
+    /// at the current ask
+    Ask,
+    /// this many percent below the current ask
+    BelowAsk(Decimal),
+}
+
+impl PriceStrategy {
+    pub fn price(&self, ticker: &TickerInfo) -> Decimal {
+        match self {
+            PriceStrategy::Bid => ticker.bid_price(),
+            PriceStrategy::Ask => ticker.ask_price(),
+            PriceStrategy::BelowAsk(pct) => ticker.ask_price() * (Decimal::ONE_HUNDRED - *pct) / Decimal::ONE_HUNDRED,
+        }
+    }
+}
+
+/// Buying more while the price is below its trailing average, and optionally
+/// less while it is above.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DipMultiplier {
+    /// closed candles the average close is taken over
+    pub lookback: usize,
+    /// candle length in minutes
+    #[serde(default = "default_dip_interval")]
+    pub interval: u32,
+    /// amount multiplier below the average, e.g. 2
+    pub below: Decimal,
+    /// amount multiplier above the average, 1 when not set
+    pub above: Option<Decimal>,
+}
+
+/// Portfolio weight an entry's asset is held to by selling what it gains above it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RebalanceTarget {
+    /// share of the portfolio the asset should have, in percent
+    pub weight_pct: Decimal,
+    /// percentage points the weight may rise above `weight_pct` before the excess is sold
+    pub threshold_pct: Decimal,
+}
+
+/// one candle a day
+fn default_dip_interval() -> u32 {
+    1440
+}
+
+/// most candles one OHLC call returns
+const MAX_OHLC_CANDLES: usize = 720;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PlanEntry {
+    /// asset pair, in any form accepted by `kraken::resolve_pair`
+    pub pair: String,
+    /// quote amount to spend (fixed mode)
+    pub amount: Option<Decimal>,
+    /// share of the plan budget in percent (allocation mode)
+    pub percent: Option<Decimal>,
+    /// refuse a market buy when the ask rose more than this many percent
+    /// above the ask seen at planning time
+    pub max_slippage_pct: Option<Decimal>,
+    /// skip the pair while its most recent buy is younger than this (seconds in JSON)
+    #[serde(default, serialize_with = "serialize_secs", deserialize_with = "deserialize_secs")]
+    pub min_interval: Option<Duration>,
+    #[serde(default)]
+    pub order_type: PlanOrderType,
+    /// limit price of a limit entry
+    pub price_strategy: Option<PriceStrategy>,
+    /// set a limit entry's price this many percent below its `price_strategy` price
+    pub price_offset_pct: Option<Decimal>,
+    /// place a limit entry as post only
+    #[serde(default)]
+    pub post_only: bool,
+    /// skip the pair while it has this many open orders
+    pub max_open_orders: Option<u32>,
+    /// most base volume one buy may have, see `volume_cap`
+    pub max_volume: Option<Decimal>,
+    /// scale the amount by where the price is against its trailing average
+    pub dip: Option<DipMultiplier>,
+    /// sell the asset back to the plan quote when it outgrows this weight, needs
+    /// a client allowing sells
+    pub rebalance: Option<RebalanceTarget>,
+}
+
+impl PlanEntry {
+    /// Price of a limit entry: its `price_strategy` price, lowered by `price_offset_pct`.
+    pub fn limit_price(&self, ticker: &TickerInfo) -> Option<Decimal> {
+        let price = self.price_strategy?.price(ticker);
+
+        Some(match self.price_offset_pct {
+            Some(pct) => price * (Decimal::ONE_HUNDRED - pct) / Decimal::ONE_HUNDRED,
+            None => price,
+        })
+    }
+}
+
+/// Pairs are compared as written, aliases such as `XBTUSD` and `XXBTZUSD` aren't told apart.
+fn same_pair(a: &PlanEntry, b: &PlanEntry) -> bool {
+    a.pair.trim().eq_ignore_ascii_case(b.pair.trim())
+}
+
+fn serialize_secs<S>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    duration.map(|duration| duration.as_secs()).serialize(serializer)
+}
+
+fn deserialize_secs<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_secs))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DcaPlan {
+    #[serde(default)]
+    pub mode: PlanMode,
+    /// total quote amount to spend per run (allocation mode)
+    pub budget: Option<Decimal>,
+    /// rounding of each entry's volume to its lot decimals
+    #[serde(default)]
+    pub rounding: RoundingMode,
+    /// what a buy above its entry's `max_volume` gets
+    #[serde(default)]
+    pub volume_cap: VolumeCapPolicy,
+    /// expire unfilled limit orders this long after placing them (seconds in JSON)
+    #[serde(default, serialize_with = "serialize_secs", deserialize_with = "deserialize_secs")]
+    pub order_ttl: Option<Duration>,
+    /// currency every entry is bought with, as asset id or altname
+    #[serde(default = "default_quote")]
+    pub quote: String,
+    /// currency amounts and budget are given in, when not `quote`; converted to
+    /// `quote` at the cross rate every run
+    pub budget_currency: Option<String>,
+    /// quote amount every run leaves unspent
+    #[serde(default)]
+    pub reserve: Decimal,
+    #[serde(default)]
+    pub reserve_policy: ReservePolicy,
+    #[serde(default)]
+    pub ordering: OrderingStrategy,
+    #[serde(default)]
+    pub duplicates: DuplicatePolicy,
+    #[serde(default)]
+    pub post_only_policy: PostOnlyPolicy,
+    /// currency every order's fee is preferably charged in
+    pub fee_currency: Option<FeePreference>,
+    /// skip an entry while its pair's bid-ask spread is wider than this many percent of the mid price
+    pub max_spread_pct: Option<Decimal>,
+    /// move limit orders left open by the run after the market, not at all when not set
+    pub reprice: Option<RepricePolicy>,
+    /// conditions every entry must meet to be bought, none by default
+    #[serde(default)]
+    pub filters: Vec<FilterConfig>,
+    /// most orders a run may place, a plan resolving to more is refused as a whole
+    #[serde(default = "default_max_orders_per_run")]
+    pub max_orders_per_run: usize,
+    /// place each pair's orders in AddOrderBatch calls rather than one by one
+    #[serde(default)]
+    pub batch_orders: bool,
+    /// refuse a buy whose cost is more than this many percent off its amount,
+    /// e.g. after a rounding or pricing bug
+    pub cost_tolerance_pct: Option<Decimal>,
+    /// stablecoins (asset ids or altnames) converted to `quote`, in this order,
+    /// while the free quote balance falls short of the run's amounts plus the reserve
+    #[serde(default)]
+    pub fund_from: Vec<String>,
+    /// entries in priority order, highest first
+    pub entries: Vec<PlanEntry>,
+}
+
+/// shortest relative expiration Kraken accepts
+const MIN_ORDER_TTL: Duration = Duration::from_secs(5);
+
+fn default_quote() -> String {
+    String::from("USD")
+}
+
+fn default_max_orders_per_run() -> usize {
+    50
+}
+
+impl DcaPlan {
+    /// Parses and validates a plan such as
+    /// `{"mode": "allocation", "budget": "100", "entries": [{"pair": "XBTUSD", "percent": "60"}, {"pair": "ETHUSD", "percent": "40"}]}`
+    pub fn from_json(json: &str) -> Result<DcaPlan, String> {
+        let plan: DcaPlan = serde_json::from_str(json).map_err(|e| format!("invalid DCA plan: {}", e))?;
+        plan.validate()?;
+
+        Ok(plan)
+    }
+
+    /// The plan as JSON `from_json` loads back into an equal plan, e.g. to keep it
+    /// under version control. Refuses a plan that doesn't validate.
+    pub fn to_json(&self) -> Result<String, String> {
+        self.validate()?;
+        serde_json::to_string_pretty(self).map_err(|e| format!("{:?}", e))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.reserve.is_sign_negative() {
+            return Err(String::from("plan reserve is negative"));
+        }
+
+        if self.max_spread_pct.is_some_and(|pct| pct <= Decimal::ZERO) {
+            return Err(String::from("plan max_spread_pct must be positive"));
+        }
+
+        for filter in &self.filters {
+            filter.validate()?;
+        }
+
+        if self.fund_from.iter().any(|asset| asset.trim().eq_ignore_ascii_case(self.quote.trim())) {
+            return Err(format!("plan fund_from lists its own quote {}", self.quote));
+        }
+
+        if let Some(reprice) = &self.reprice {
+            if reprice.step_pct <= Decimal::ZERO || reprice.step_pct >= Decimal::ONE_HUNDRED {
+                return Err(format!("plan reprice has an invalid step_pct of {}%", reprice.step_pct));
+            }
+        }
+
+        if let Some(order_ttl) = self.order_ttl {
+            if order_ttl < MIN_ORDER_TTL {
+                return Err(format!("plan order_ttl must be at least {}s", MIN_ORDER_TTL.as_secs()));
+            }
+        }
+
+        if self.duplicates == DuplicatePolicy::Reject {
+            for (i, entry) in self.entries.iter().enumerate() {
+                if self.entries[..i].iter().any(|earlier| same_pair(earlier, entry)) {
+                    return Err(format!("plan lists {} more than once", entry.pair));
+                }
+            }
+        }
+
+        for entry in &self.entries {
+            if entry.max_volume.is_some_and(|max_volume| max_volume <= Decimal::ZERO) {
+                return Err(format!("plan entry {} has a non-positive max_volume", entry.pair));
+            }
+
+            if let Some(dip) = &entry.dip {
+                if dip.lookback == 0 || dip.lookback > MAX_OHLC_CANDLES - 1 {
+                    return Err(format!("plan entry {} has an invalid dip lookback of {}", entry.pair, dip.lookback));
+                }
+                if !OHLC_INTERVALS.contains(&dip.interval) {
+                    return Err(format!("plan entry {} has an invalid dip interval of {}m", entry.pair, dip.interval));
+                }
+                if dip.below <= Decimal::ZERO || dip.above.is_some_and(|above| above <= Decimal::ZERO) {
+                    return Err(format!("plan entry {} has a non-positive dip multiplier", entry.pair));
+                }
+            }
+
+            if let Some(target) = &entry.rebalance {
+                if target.weight_pct <= Decimal::ZERO || target.weight_pct >= Decimal::ONE_HUNDRED {
+                    return Err(format!("plan entry {} has an invalid rebalance weight of {}%", entry.pair, target.weight_pct));
+                }
+                if target.threshold_pct.is_sign_negative() {
+                    return Err(format!("plan entry {} has a negative rebalance threshold", entry.pair));
+                }
+            }
+
+            if entry.max_slippage_pct.is_some_and(|pct| pct.is_sign_negative()) {
+                return Err(format!("plan entry {} has a negative max_slippage_pct", entry.pair));
+            }
+
+            if let Some(pct) = entry.price_offset_pct {
+                if pct.is_sign_negative() || pct >= Decimal::ONE_HUNDRED {
+                    return Err(format!("plan entry {} has an invalid price_offset_pct of {}%", entry.pair, pct));
+                }
+            }
+
+            match (entry.order_type, entry.price_strategy) {
+                (PlanOrderType::Limit, None) => {
+                    return Err(format!("limit plan entry {} has no price_strategy", entry.pair));
+                }
+                (PlanOrderType::Limit, Some(PriceStrategy::BelowAsk(pct)))
+                    if pct.is_sign_negative() || pct >= Decimal::ONE_HUNDRED =>
+                {
+                    return Err(format!("plan entry {} has an invalid below_ask of {}%", entry.pair, pct));
+                }
+                (PlanOrderType::Market, Some(_)) => {
+                    return Err(format!("market plan entry {} has a price_strategy", entry.pair));
+                }
+                (PlanOrderType::Market, None) if entry.price_offset_pct.is_some() => {
+                    return Err(format!("market plan entry {} has a price_offset_pct", entry.pair));
+                }
+                (PlanOrderType::Market, None) if entry.post_only => {
+                    return Err(format!("market plan entry {} cannot be post_only", entry.pair));
+                }
+                _ => {}
+            }
+        }
+
+        match self.mode {
+            PlanMode::Fixed => {
+                for entry in &self.entries {
+                    match entry.amount {
+                        Some(amount) if amount <= Decimal::ZERO => {
+                            return Err(format!("plan entry {} has a non-positive amount of {}", entry.pair, amount));
+                        }
+                        Some(_) => {}
+                        None => return Err(format!("plan entry {} has no amount", entry.pair)),
+                    }
+                }
+            }
+            PlanMode::Allocation => {
+                match self.budget {
+                    Some(budget) if budget <= Decimal::ZERO => {
+                        return Err(format!("allocation plan has a non-positive budget of {}", budget));
+                    }
+                    Some(_) => {}
+                    None => return Err(String::from("allocation plan has no budget")),
+                }
+
+                let mut total = Decimal::ZERO;
+                for entry in &self.entries {
+                    match entry.percent {
+                        Some(percent) if percent <= Decimal::ZERO => {
+                            return Err(format!("plan entry {} has a non-positive percent of {}", entry.pair, percent));
+                        }
+                        Some(percent) => total += percent,
+                        None => return Err(format!("plan entry {} has no percent", entry.pair)),
+                    }
+                }
+
+                // an empty plan has nothing to allocate
+                if !self.entries.is_empty() && total != Decimal::ONE_HUNDRED {
+                    return Err(format!("plan allocations sum to {}%, expected 100%", total));
+                }
+            }
+        }
+
+        // merged duplicates place a single order
+        let orders = self.amounts().len();
+        if orders > self.max_orders_per_run {
+            return Err(format!(
+                "plan would place {} orders, more than the max_orders_per_run of {}",
+                orders, self.max_orders_per_run
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Amount each entry spends this run, in the budget currency. Entries for
+    /// the same pair are merged into the first one.
+    pub fn amounts(&self) -> Vec<(&PlanEntry, Decimal)> {
+        let mut amounts: Vec<(&PlanEntry, Decimal)> = Vec::new();
+
+        for entry in &self.entries {
+            let amount = match self.mode {
+                PlanMode::Fixed => entry.amount.unwrap_or(Decimal::ZERO),
+                PlanMode::Allocation => {
+                    self.budget.unwrap_or(Decimal::ZERO) * entry.percent.unwrap_or(Decimal::ZERO) / Decimal::ONE_HUNDRED
+                }
+            };
+
+            // only `DuplicatePolicy::Merge` plans get past validation with duplicates
+            match amounts.iter_mut().find(|(first, _)| same_pair(first, entry)) {
+                Some((_, merged)) => *merged += amount,
+                None => amounts.push((entry, amount)),
+            }
+        }
+
+        amounts
+    }
+}
+
+/// Rate `budget_currency` amounts are converted to `quote` at, `None` when
+/// the plan's amounts already are in `quote`.
+pub async fn conversion_rate(client: &KrakenClient, plan: &DcaPlan) -> Result<Option<Decimal>, String> {
+    match &plan.budget_currency {
+        Some(budget_currency) => {
+            let rate = kraken::cross_rate(client, budget_currency, &plan.quote).await?;
+            Ok(Some(rate).filter(|rate| *rate != Decimal::ONE))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Quote amount each entry spends this run, converted at `rate` when set.
+pub fn quote_amounts(plan: &DcaPlan, rate: Option<Decimal>) -> Vec<(&PlanEntry, Decimal)> {
+    plan.amounts()
+        .into_iter()
+        .map(|(entry, amount)| (entry, rate.map_or(amount, |rate| amount * rate)))
+        .collect()
+}
+
+/// Multiplier `dip` applies to `pair`'s amount now: `below` while the last
+/// price is under the average close of the last `lookback` closed candles,
+/// `above` (or 1) otherwise.
+pub async fn dip_multiplier(client: &KrakenClient, pair: &str, dip: &DipMultiplier) -> Result<Decimal, String> {
+    let candles = kraken::ohlc(client, pair, dip.interval).await?;
+
+    // the last candle is the period still in progress
+    let closed = &candles[..candles.len().saturating_sub(1)];
+    if closed.len() < dip.lookback {
+        return Err(format!("only {} closed {}m candles for {}, the dip lookback is {}", closed.len(), dip.interval, pair, dip.lookback));
+    }
+
+    let recent = &closed[closed.len() - dip.lookback..];
+    let average = recent.iter().map(|candle| candle.close).sum::<Decimal>() / Decimal::from(dip.lookback as u64);
+    let price = kraken::ticker(client, pair).await?.last_price();
+
+    Ok(if price < average { dip.below } else { dip.above.unwrap_or(Decimal::ONE) })
+}
+
+/// `amounts` with each entry's `dip` multiplier applied, and the multiplier
+/// applied to each entry that has one, by pair as written in the plan. An
+/// entry whose multiplier can't be worked out keeps its amount.
+pub async fn apply_dips<'a>(
+    client: &KrakenClient,
+    plan: &DcaPlan,
+    amounts: Vec<(&'a PlanEntry, Decimal)>,
+) -> (Vec<(&'a PlanEntry, Decimal)>, HashMap<String, Decimal>) {
+    let mut multipliers = HashMap::new();
+    let mut applied = Vec::new();
+
+    for (entry, amount) in amounts {
+        let dip = match &entry.dip {
+            Some(dip) => dip,
+            None => {
+                applied.push((entry, amount));
+                continue;
+            }
+        };
+
+        let multiplier = match resolve_entry_pair(client, entry, &plan.quote).await {
+            Ok(pair) => dip_multiplier(client, &pair, dip).await,
+            Err(e) => Err(e),
+        };
+
+        match multiplier {
+            Ok(multiplier) => {
+                multipliers.insert(entry.pair.clone(), multiplier);
+                applied.push((entry, amount * multiplier));
+            }
+            Err(e) => {
+                log::warn!("no dip multiplier for {}, keeping its amount: {}", entry.pair, e);
+                applied.push((entry, amount));
+            }
+        }
+    }
+
+    (applied, multipliers)
+}
+
+/// Entry resolved at planning time, ready to be bought.
+pub struct PreparedEntry {
+    /// canonical pair key
+    pub pair: String,
+    /// highest ask the buy may still execute at, from `max_slippage_pct`
+    pub max_price: Option<Decimal>,
+    /// price of a limit entry, from its `price_strategy` and `price_offset_pct`
+    pub limit_price: Option<Decimal>,
+}
+
+/// Canonical key of the pair `entry` buys with `quote`: the entry's own pair
+/// when it is quoted in `quote`, otherwise the pair of the same base against it.
+pub async fn resolve_entry_pair(client: &KrakenClient, entry: &PlanEntry, quote: &str) -> Result<String, String> {
+    let pair = kraken::resolve_pair(client, &entry.pair).await?;
+    let metadata = client.metadata().await?;
+
+    let quote_id = metadata
+        .asset_id(quote)
+        .ok_or_else(|| format!("unknown quote currency: {}", quote))?;
+    let info = metadata.pair_info(&pair).ok_or_else(|| format!("unknown asset pair: {}", pair))?;
+
+    if info.quote == quote_id {
+        return Ok(pair);
+    }
+
+    quote_pair(&metadata, &info.base, &quote_id)
+        .ok_or_else(|| format!("{} is not quoted in {} and has no {} pair", entry.pair, quote, quote))
+}
+
+/// Canonical key of the pair trading asset `base` against `quote_id`, dark pools left out.
+fn quote_pair(metadata: &Metadata, base: &str, quote_id: &str) -> Option<String> {
+    metadata
+        .pairs
+        .iter()
+        .find(|(key, info)| !key.ends_with(".d") && info.base == base && info.quote == quote_id)
+        .map(|(key, _)| key.clone())
+}
+
+pub async fn prepare_entry(client: &KrakenClient, entry: &PlanEntry, quote: &str) -> Result<PreparedEntry, String> {
+    let pair = resolve_entry_pair(client, entry, quote).await?;
+
+    let ticker = if entry.max_slippage_pct.is_some() || entry.price_strategy.is_some() {
+        Some(kraken::ticker(client, &pair).await?)
+    } else {
+        None
+    };
+
+    let max_price = match (entry.max_slippage_pct, &ticker) {
+        (Some(pct), Some(ticker)) => Some(ticker.ask_price() * (Decimal::ONE_HUNDRED + pct) / Decimal::ONE_HUNDRED),
+        _ => None,
+    };
+
+    let limit_price = ticker.as_ref().and_then(|ticker| entry.limit_price(ticker));
+
+    Ok(PreparedEntry {
+        pair,
+        max_price,
+        limit_price,
+    })
+}
+
+/// What a plan entry would place, computed without sending any order.
+#[derive(Serialize, Debug)]
+pub struct PlannedOrder {
+    /// pair as written in the plan
+    pub pair: String,
+    /// buy for plan entries, a rebalance may also sell
+    pub direction: OrderDirection,
+    /// quote amount the entry spends, or the sell receives
+    pub amount: Decimal,
+    /// rate the plan's budget currency was converted to the quote at
+    pub conversion_rate: Option<Decimal>,
+    /// `dip` multiplier included in `amount`
+    pub multiplier: Option<Decimal>,
+    /// canonical pair key
+    pub resolved_pair: Option<String>,
+    /// last trade, or limit price rounded to the pair decimals
+    pub price: Option<Decimal>,
+    /// volume rounded to the pair's lot decimals
+    pub volume: Option<Decimal>,
+    pub cost: Option<Decimal>,
+    /// fee at the pair's base taker tier
+    pub estimated_fee: Option<Decimal>,
+    /// currency `estimated_fee` is in, see `kraken::fee_currency_for`
+    pub fee_currency: Option<String>,
+    /// rough wait for a limit buy to fill (seconds in JSON), see `kraken::time_to_fill_estimate`
+    #[serde(serialize_with = "serialize_secs")]
+    pub fill_estimate: Option<Duration>,
+    /// why the entry could not be planned
+    pub error: Option<String>,
+}
+
+/// Resolves price, volume and fee of every plan entry as tonight's run would,
+/// without placing anything. Entries that fail validation carry the error
+/// instead of aborting the preview.
+pub async fn plan_preview(client: &KrakenClient, plan: &DcaPlan) -> Result<Vec<PlannedOrder>, String> {
+    let metadata = client.metadata().await?;
+    let rate = conversion_rate(client, plan).await?;
+    let (amounts, multipliers) = apply_dips(client, plan, quote_amounts(plan, rate)).await;
+    let mut preview = Vec::new();
+
+    for (entry, amount) in amounts {
+        let multiplier = multipliers.get(&entry.pair).copied();
+        let planned = match preview_entry(client, &metadata, plan, entry, amount).await {
+            Ok(planned) => PlannedOrder {
+                conversion_rate: rate,
+                multiplier,
+                ..planned
+            },
+            Err(e) => PlannedOrder {
+                pair: entry.pair.clone(),
+                direction: OrderDirection::Buy,
+                amount,
+                conversion_rate: rate,
+                multiplier,
+                resolved_pair: None,
+                price: None,
+                volume: None,
+                cost: None,
+                estimated_fee: None,
+                fee_currency: None,
+                fill_estimate: None,
+                error: Some(e),
+            },
+        };
+        preview.push(planned);
+    }
+
+    Ok(preview)
+}
+
+/// times `await_fills` is expected to poll an order before it is filled
+const FILL_POLLS: u32 = 3;
+
+/// API calls a run of a plan makes, estimated from the plan alone.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct ApiCostEstimate {
+    /// public calls: status, metadata, tickers and OHLC
+    pub public: u32,
+    /// points private calls add to the rate counter, see `kraken::api_cost`
+    pub private: u32,
+    /// AddOrder calls, including reprices, rebalance sells and funding conversions
+    pub orders: u32,
+    pub total: u32,
+}
+
+/// Estimates the API calls a run of `plan` makes: the run's status, clock,
+/// metadata and balance calls, then per entry its ticker, checks, order and
+/// fill confirmations, at the worst case of every limit being repriced.
+pub fn api_cost_estimate(plan: &DcaPlan) -> ApiCostEstimate {
+    // SystemStatus, Time, AssetPairs and Assets
+    let mut public = 4;
+    // permission checks, the reserve check and the balance after the run
+    let mut private = kraken::api_cost("Balance") * 3 + kraken::api_cost("OpenOrders");
+    let mut orders = 1 + plan.fund_from.len() as u32;
+
+    if plan.budget_currency.is_some() {
+        public += 1;
+    }
+
+    for entry in &plan.entries {
+        public += 1;
+        if entry.dip.is_some() {
+            public += 1;
+        }
+        if entry.min_interval.is_some() {
+            private += kraken::api_cost("ClosedOrders") + kraken::api_cost("OpenOrders");
+        }
+        if entry.max_open_orders.is_some() {
+            private += kraken::api_cost("OpenOrders");
+        }
+        if entry.rebalance.is_some() {
+            public += 1;
+            orders += 1;
+        }
+
+        let placed = match (&plan.reprice, entry.order_type) {
+            (Some(policy), PlanOrderType::Limit) => 1 + policy.max_reprices,
+            _ => 1,
+        };
+        private += kraken::api_cost("BalanceEx") + placed * FILL_POLLS * kraken::api_cost("QueryOrders");
+        orders += placed;
+    }
+
+    ApiCostEstimate {
+        public,
+        private,
+        orders,
+        total: public + private + orders,
+    }
+}
+
+/// Refuses `plan` when its estimated API cost is above `API_COST_BUDGET`, so a
+/// plan too large for the key's API tier fails up front rather than rate limited
+/// half way through.
+pub fn check_api_budget(plan: &DcaPlan) -> Result<ApiCostEstimate, String> {
+    let estimate = api_cost_estimate(plan);
+    let budget = match option_env!("API_COST_BUDGET").and_then(|budget| budget.parse::<u32>().ok()) {
+        Some(budget) => budget,
+        None => return Ok(estimate),
+    };
+
+    if estimate.total > budget {
+        return Err(format!(
+            "estimated API cost of {} ({} public calls, {} private points, {} orders) is above the API_COST_BUDGET of {}",
+            estimate.total, estimate.public, estimate.private, estimate.orders, budget
+        ));
+    }
+
+    Ok(estimate)
+}
+
+async fn preview_entry(
+    client: &KrakenClient,
+    metadata: &Metadata,
+    plan: &DcaPlan,
+    entry: &PlanEntry,
+    amount: Decimal,
+) -> Result<PlannedOrder, String> {
+    let pair = resolve_entry_pair(client, entry, &plan.quote).await?;
+    let info = metadata.pair_info(&pair).ok_or_else(|| format!("unknown asset pair: {}", pair))?;
+
+    if let Some(reason) = check_status(info, entry) {
+        return Err(reason);
+    }
+
+    // priced as `kraken::buy_for_amount` prices the run's buy
+    let ticker = kraken::ticker(client, &pair).await?;
+    let limit_price = entry.limit_price(&ticker).map(|limit_price| limit_price.round_dp(info.pair_decimals));
+    let price = match limit_price {
+        Some(limit_price) => limit_price,
+        None => kraken::last_price(client, &pair).await?,
+    };
+    let volume = kraken::volume_for_budget(info, price, amount, plan.rounding)?;
+    let volume = kraken::cap_volume(info, volume, entry.max_volume, plan.volume_cap)?;
+    let cost = kraken::safe_cost(price, volume).map_err(|e| e.to_string())?;
+
+    // a best effort, the preview stands without it
+    let fill_estimate = match limit_price {
+        Some(limit_price) => kraken::time_to_fill_estimate(client, &pair, limit_price, volume)
+            .await
+            .unwrap_or_else(|e| {
+                log::warn!("no fill estimate for {}: {}", entry.pair, e);
+                None
+            }),
+        None => None,
+    };
+
+    let fee_currency = kraken::fee_currency_for(client, &pair).await?;
+
+    Ok(PlannedOrder {
+        pair: entry.pair.clone(),
+        direction: OrderDirection::Buy,
+        amount,
+        conversion_rate: None,
+        multiplier: None,
+        resolved_pair: Some(pair),
+        price: Some(price),
+        volume: Some(volume),
+        cost: Some(cost),
+        estimated_fee: Some(info.estimated_fee(cost)),
+        fee_currency: Some(fee_currency),
+        fill_estimate,
+        error: None,
+    })
+}
+
+/// Kraken's verdict on the order a plan entry would place.
+#[derive(Serialize, Debug)]
+pub struct ValidationOutcome {
+    /// pair as written in the plan
+    pub pair: String,
+    /// canonical pair key
+    pub resolved_pair: Option<String>,
+    /// quote amount the entry spends
+    pub amount: Decimal,
+    pub price: Option<Decimal>,
+    pub volume: Option<Decimal>,
+    /// order description Kraken accepted the order with
+    pub description: Option<String>,
+    /// why Kraken, or a check before it, refused the order
+    pub error: Option<String>,
+}
+
+/// Submits the order of every plan entry with `validate` set, so Kraken checks
+/// price format, minimums and permissions without executing anything. Unlike
+/// `plan_preview` the verdict is the exchange's own; a refused entry carries
+/// Kraken's error instead of aborting the run of the others.
+pub async fn validate_plan(client: &KrakenClient, plan: &DcaPlan) -> Result<Vec<ValidationOutcome>, String> {
+    let rate = conversion_rate(client, plan).await?;
+    let (amounts, _) = apply_dips(client, plan, quote_amounts(plan, rate)).await;
+    let mut outcomes = Vec::new();
+
+    for (entry, amount) in amounts {
+        let mut outcome = ValidationOutcome {
+            pair: entry.pair.clone(),
+            resolved_pair: None,
+            amount,
+            price: None,
+            volume: None,
+            description: None,
+            error: None,
+        };
+
+        let prepared = match prepare_entry(client, entry, &plan.quote).await {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                outcome.error = Some(e);
+                outcomes.push(outcome);
+                continue;
+            }
+        };
+        let options = kraken::BuyOptions {
+            max_price: prepared.max_price,
+            limit_price: prepared.limit_price,
+            post_only: entry.post_only,
+            limit_ttl: plan.order_ttl,
+            fee_currency: plan.fee_currency,
+            reserve: plan.reserve,
+            rounding: plan.rounding,
+            max_volume: entry.max_volume,
+            volume_cap: plan.volume_cap,
+            cost_tolerance_pct: plan.cost_tolerance_pct,
+            validate: true,
+            ..kraken::BuyOptions::default()
+        };
+
+        match kraken::buy_for_amount(client, &prepared.pair, amount, &options).await {
+            Ok(buy) => {
+                outcome.price = Some(buy.price);
+                outcome.volume = Some(buy.volume);
+                outcome.description = Some(kraken::format_placed(&buy.order.descr));
+            }
+            Err(e) => outcome.error = Some(e),
+        }
+        outcome.resolved_pair = Some(prepared.pair);
+        outcomes.push(outcome);
+    }
+
+    Ok(outcomes)
+}
+
+/// Plan entry left out of a run, and why.
+#[derive(Serialize, Debug, Clone)]
+pub struct Skipped {
+    pub pair: String,
+    pub reason: String,
+}
+
+/// Entry amount lowered to keep the reserve.
+#[derive(Serialize, Debug, Clone)]
+pub struct Adjustment {
+    pub pair: String,
+    /// amount the plan asked for
+    #[serde(serialize_with = "crate::summary::serialize_amount")]
+    pub amount: Decimal,
+    /// amount the run spends instead
+    #[serde(serialize_with = "crate::summary::serialize_amount")]
+    pub adjusted: Decimal,
+}
+
+/// A run's amounts once the reserve is taken into account.
+pub struct ReservedAmounts<'a> {
+    /// quote amounts
+    pub amounts: Vec<(&'a PlanEntry, Decimal)>,
+    /// rate the budget currency was converted at, if it isn't the quote
+    pub conversion_rate: Option<Decimal>,
+    /// `dip` multipliers included in `amounts`, by pair as written in the plan
+    pub multipliers: HashMap<String, Decimal>,
+    pub adjusted: Vec<Adjustment>,
+    pub skipped: Vec<Skipped>,
+}
+
+/// The plan's amounts with their `dip` multipliers, cut down per `reserve_policy`
+/// when they would leave less than `reserve` of the plan's quote currency free
+/// (BalanceEx balance minus what open orders hold).
+pub async fn apply_reserve<'a>(client: &KrakenClient, plan: &'a DcaPlan) -> Result<ReservedAmounts<'a>, String> {
+    let rate = conversion_rate(client, plan).await?;
+    let (amounts, multipliers) = apply_dips(client, plan, quote_amounts(plan, rate)).await;
+    let mut reserved = ReservedAmounts {
+        amounts,
+        conversion_rate: rate,
+        multipliers,
+        adjusted: Vec::new(),
+        skipped: Vec::new(),
+    };
+
+    if plan.reserve.is_zero() {
+        return Ok(reserved);
+    }
+
+    let metadata = client.metadata().await?;
+    let quote = &metadata
+        .asset_id(&plan.quote)
+        .ok_or_else(|| format!("unknown quote currency: {}", plan.quote))?;
+
+    let available = kraken::balance_ex(client)
+        .await?
+        .get(quote)
+        .map_or(Decimal::ZERO, |balance| balance.available());
+    let spendable = (available - plan.reserve).max(Decimal::ZERO);
+    let mut total: Decimal = reserved.amounts.iter().map(|(_, amount)| *amount).sum();
+
+    if total <= spendable {
+        return Ok(reserved);
+    }
+
+    match plan.reserve_policy {
+        ReservePolicy::Scale => {
+            let decimals = metadata.asset_info(quote).map_or(2, |asset| asset.decimals);
+
+            for (entry, amount) in reserved.amounts.iter_mut() {
+                let adjusted = (*amount * spendable / total).round_dp_with_strategy(decimals, RoundingStrategy::ToZero);
+                reserved.adjusted.push(Adjustment {
+                    pair: entry.pair.clone(),
+                    amount: *amount,
+                    adjusted,
+                });
+                *amount = adjusted;
+            }
+        }
+        ReservePolicy::SkipLowest => {
+            while total > spendable {
+                let (entry, amount) = match reserved.amounts.pop() {
+                    Some(last) => last,
+                    None => break,
+                };
+
+                total -= amount;
+                reserved.skipped.push(Skipped {
+                    pair: entry.pair.clone(),
+                    reason: format!("would spend into the {} {} reserve", plan.reserve, quote),
+                });
+            }
+        }
+    }
+
+    Ok(reserved)
+}
+
+/// Free quote balance short of a run's budget plus buffer.
+#[derive(Serialize, Debug, Clone)]
+pub struct LowFunds {
+    /// asset id of the plan's quote
+    pub quote: String,
+    /// BalanceEx balance minus what open orders hold
+    pub available: Decimal,
+    /// what the run spends, the buffer and the reserve
+    pub required: Decimal,
+    pub shortfall: Decimal,
+}
+
+impl fmt::Display for LowFunds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "funds running low: {} {} free, {} wanted, {} short",
+            self.available, self.quote, self.required, self.shortfall
+        )
+    }
+}
+
+/// Funds check before a run: `Some` when the free quote balance is below the
+/// run's total budget plus `buffer_pct` percent of it, plus the reserve. The run
+/// may still go through, this only warns ahead of buys being cut or skipped.
+pub async fn check_funds(client: &KrakenClient, plan: &DcaPlan, buffer_pct: Decimal) -> Result<Option<LowFunds>, String> {
+    let rate = conversion_rate(client, plan).await?;
+    let budget: Decimal = quote_amounts(plan, rate).iter().map(|(_, amount)| *amount).sum();
+
+    let metadata = client.metadata().await?;
+    let quote = metadata
+        .asset_id(&plan.quote)
+        .ok_or_else(|| format!("unknown quote currency: {}", plan.quote))?;
+
+    let available = kraken::balance_ex(client)
+        .await?
+        .get(&quote)
+        .map_or(Decimal::ZERO, |balance| balance.available());
+    let required = budget * (Decimal::ONE_HUNDRED + buffer_pct) / Decimal::ONE_HUNDRED + plan.reserve;
+
+    if available >= required {
+        return Ok(None);
+    }
+
+    Ok(Some(LowFunds {
+        quote,
+        available,
+        required,
+        shortfall: required - available,
+    }))
+}
+
+/// Stablecoin converted to a plan's quote to fund its run.
+#[derive(Serialize, Debug, Clone)]
+pub struct Conversion {
+    /// asset id of the stablecoin
+    pub from: String,
+    /// asset id of the plan's quote
+    pub to: String,
+    pub pair: String,
+    /// base volume of the order, the stablecoin sold or the quote bought
+    #[serde(serialize_with = "crate::summary::serialize_amount")]
+    pub volume: Decimal,
+    /// quote the conversion brings in once its fee is paid, at the current price
+    #[serde(serialize_with = "crate::summary::serialize_amount")]
+    pub received: Decimal,
+    /// not set for validate-only orders
+    pub txid: Option<Vec<String>>,
+    pub description: String,
+}
+
+/// Funding step before a run: converts the plan's `fund_from` stablecoins to its
+/// quote at market, in order, until the free quote balance covers the run's
+/// amounts and the reserve. Nothing is converted when it already does. Each
+/// order is sized up for the pair's taker fee and to its `ordermin`, and capped
+/// at the free stablecoin balance; a stablecoin short of `ordermin` is passed over.
+pub async fn fund_quote(client: &KrakenClient, plan: &DcaPlan, validate: bool) -> Result<Vec<Conversion>, String> {
+    let mut conversions = Vec::new();
+    if plan.fund_from.is_empty() {
+        return Ok(conversions);
+    }
+
+    let mut shortfall = match check_funds(client, plan, Decimal::ZERO).await? {
+        Some(low_funds) => low_funds.shortfall,
+        None => return Ok(conversions),
+    };
+
+    let metadata = client.metadata().await?;
+    let quote = metadata
+        .asset_id(&plan.quote)
+        .ok_or_else(|| format!("unknown quote currency: {}", plan.quote))?;
+    let balances = kraken::balance_ex(client).await?;
+
+    for stablecoin in &plan.fund_from {
+        if shortfall <= Decimal::ZERO {
+            break;
+        }
+
+        let from = metadata
+            .asset_id(stablecoin)
+            .ok_or_else(|| format!("unknown funding currency: {}", stablecoin))?;
+        let available = balances.get(&from).map_or(Decimal::ZERO, |balance| balance.available());
+        if available <= Decimal::ZERO {
+            continue;
+        }
+
+        // the stablecoin is sold on a stablecoin/quote pair, or the quote bought on a quote/stablecoin one
+        let pair = metadata
+            .pairs
+            .iter()
+            .filter(|(key, _)| !key.ends_with(".d"))
+            .find(|(_, info)| (info.base == from && info.quote == quote) || (info.base == quote && info.quote == from));
+        let (pair, info) = match pair {
+            Some(pair) => pair,
+            None => {
+                log::warn!("no pair converts {} to {}, not funding from it", from, quote);
+                continue;
+            }
+        };
+
+        let fee_rate = info.estimated_fee(Decimal::ONE);
+        let ticker = kraken::ticker(client, pair).await?;
+        let selling = info.base == from;
+
+        // quote one unit of volume brings in, and the most volume the free stablecoin pays for
+        let (received_per_unit, affordable) = if selling {
+            (ticker.bid_price() * (Decimal::ONE - fee_rate), available)
+        } else {
+            (Decimal::ONE, available / (ticker.ask_price() * (Decimal::ONE + fee_rate)))
+        };
+        if received_per_unit <= Decimal::ZERO {
+            return Err(format!("no usable price to convert {} on {}", from, pair));
+        }
+
+        let mut volume = (shortfall / received_per_unit).round_dp_with_strategy(info.lot_decimals, RoundingStrategy::AwayFromZero);
+        if let Some(ordermin) = info.ordermin {
+            volume = volume.max(ordermin);
+        }
+        volume = volume.min(affordable.round_dp_with_strategy(info.lot_decimals, RoundingStrategy::ToZero));
+
+        if info.ordermin.map_or(volume <= Decimal::ZERO, |ordermin| volume < ordermin) {
+            log::info!("{} {} free is below the minimum order on {}, not funding from it", available, from, pair);
+            continue;
+        }
+
+        let order = kraken::NewOrder {
+            pair: pair.clone(),
+            order_direction: if selling { OrderDirection::Sell } else { OrderDirection::Buy },
+            order_type: kraken::OrderType::Market,
+            price: None,
+            price2: None,
+            trigger: None,
+            volume: Some(volume.to_string()),
+            leverage: None,
+            reduce_only: false,
+            oflags: None,
+            fee_currency: None,
+            market_price_protection: true,
+            starttm: None,
+            expiretm: None,
+            userref: None,
+            #[cfg(feature = "timestamps")]
+            deadline: None,
+            validate: if validate { Some(true) } else { None },
+        };
+        let placed = kraken::add_order(client, order).await?;
+
+        let received = volume * received_per_unit;
+        shortfall -= received;
+        conversions.push(Conversion {
+            from,
+            to: quote.clone(),
+            pair: pair.clone(),
+            volume,
+            received,
+            txid: placed.txid,
+            description: kraken::format_placed(&placed.descr),
+        });
+    }
+
+    Ok(conversions)
+}
+
+/// Reason to skip `entry` while its pair's trading status doesn't allow the
+/// entry's order: post only pairs still take post only limits, limit only pairs
+/// limits, online pairs anything.
+pub fn check_status(info: &kraken::AssetPair, entry: &PlanEntry) -> Option<String> {
+    let order_type = match entry.order_type {
+        PlanOrderType::Market => kraken::OrderType::Market,
+        PlanOrderType::Limit => kraken::OrderType::Limit,
+    };
+
+    if kraken::SupportedTypes::of(info).allows(order_type, entry.post_only) {
+        None
+    } else {
+        Some(format!("{} is {}", info.altname, info.status.as_deref().unwrap_or("online")))
+    }
+}
+
+/// The plan to run while the exchange is in post only mode, with the pairs of
+/// the market entries turned into post only limits and the entries left out.
+/// Bids never cross the book, so the adapted orders rest instead of being rejected.
+pub fn for_post_only(plan: &DcaPlan) -> (DcaPlan, Vec<String>, Vec<Skipped>) {
+    let mut entries = Vec::new();
+    let mut adapted = Vec::new();
+    let mut skipped = Vec::new();
+
+    for entry in &plan.entries {
+        if entry.order_type != PlanOrderType::Market {
+            entries.push(entry.clone());
+            continue;
+        }
+
+        match plan.post_only_policy {
+            PostOnlyPolicy::Adapt => {
+                entries.push(PlanEntry {
+                    order_type: PlanOrderType::Limit,
+                    price_strategy: Some(PriceStrategy::Bid),
+                    post_only: true,
+                    ..entry.clone()
+                });
+                adapted.push(entry.pair.clone());
+            }
+            PostOnlyPolicy::Skip => skipped.push(Skipped {
+                pair: entry.pair.clone(),
+                reason: String::from("exchange is post only, market orders are rejected"),
+            }),
+        }
+    }
+
+    (DcaPlan { entries, ..plan.clone() }, adapted, skipped)
+}
+
+/// Order descriptions name the pair by its altname.
+async fn altname(client: &KrakenClient, pair: &str) -> Result<String, String> {
+    Ok(client
+        .metadata()
+        .await?
+        .pair_info(pair)
+        .map(|info| info.altname.clone())
+        .unwrap_or_default())
+}
+
+/// Reason to skip `pair` while it already has `max_open_orders` open orders.
+pub async fn check_open_orders(client: &KrakenClient, pair: &str, max_open_orders: u32) -> Result<Option<String>, String> {
+    let filter = kraken::OpenOrderFilter {
+        pair: Some(pair.to_owned()),
+        ..Default::default()
+    };
+    let open = kraken::open_orders_filtered(client, &filter).await?.len();
+
+    Ok(if open >= max_open_orders as usize {
+        Some(format!("{} open orders reach the cap of {}", open, max_open_orders))
+    } else {
+        None
+    })
+}
+
+/// Open limit order that has sat far from the market for long.
+#[derive(Serialize, Debug)]
+pub struct StuckOrder {
+    pub txid: kraken::TxId,
+    /// pair altname
+    pub pair: String,
+    pub description: String,
+    pub price: Decimal,
+    /// ask a buy is below, or bid a sell is above
+    pub market: Decimal,
+    /// how far the limit is from `market`, in percent of it
+    pub distance_pct: Decimal,
+    /// seconds in JSON
+    #[serde(serialize_with = "serialize_age")]
+    pub age: Duration,
+    /// trades that filled part of it
+    pub trades: Vec<kraken::TxId>,
+}
+
+fn serialize_age<S>(age: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    age.as_secs().serialize(serializer)
+}
+
+/// Open limit orders older than `max_age` whose price is more than
+/// `min_distance_pct` away from the market, hinting at a limit set too
+/// aggressively for it to fill.
+pub async fn find_stuck_orders(client: &KrakenClient, max_age: Duration, min_distance_pct: Decimal) -> Result<Vec<StuckOrder>, String> {
+    let now = ::std::time::UNIX_EPOCH.elapsed().unwrap().as_secs_f64();
+    let mut tickers = HashMap::new();
+    let mut stuck = Vec::new();
+
+    let filter = kraken::OpenOrderFilter {
+        min_age: Some(max_age),
+        trades: true,
+        ..Default::default()
+    };
+
+    for order in kraken::open_orders_filtered(client, &filter).await? {
+        let age = Duration::from_secs_f64((now - order.opentm).max(0.0));
+        if order.descr.ordertype != kraken::OrderType::Limit {
+            continue;
+        }
+
+        let price: Decimal = order.descr.price.parse().map_err(|e| format!("{:?}", e))?;
+
+        if !tickers.contains_key(&order.descr.pair) {
+            let ticker = kraken::ticker(client, &order.descr.pair).await?;
+            tickers.insert(order.descr.pair.clone(), ticker);
+        }
+        let ticker = &tickers[&order.descr.pair];
+
+        let (market, distance) = match order.descr.order_direction {
+            OrderDirection::Buy => (ticker.ask_price(), ticker.ask_price() - price),
+            OrderDirection::Sell => (ticker.bid_price(), price - ticker.bid_price()),
+        };
+        if market.is_zero() {
+            continue;
+        }
+
+        let distance_pct = distance / market * Decimal::ONE_HUNDRED;
+        if distance_pct > min_distance_pct {
+            stuck.push(StuckOrder {
+                txid: order.txid.clone(),
+                pair: order.descr.pair.clone(),
+                description: kraken::format_order(&order.descr),
+                price,
+                market,
+                distance_pct: distance_pct.round_dp(4),
+                age,
+                trades: order.trades.clone().unwrap_or_default(),
+            });
+        }
+    }
+
+    Ok(stuck)
+}
+
+/// Limit order moved closer to the market.
+#[derive(Serialize, Debug, Clone)]
+pub struct Reprice {
+    /// pair as written in the plan
+    pub pair: String,
+    #[serde(serialize_with = "crate::summary::serialize_amount")]
+    pub from: Decimal,
+    #[serde(serialize_with = "crate::summary::serialize_amount")]
+    pub to: Decimal,
+    /// txid of the moved order, the old one is canceled
+    pub txid: kraken::TxId,
+}
+
+/// How repricing one order ended.
+#[derive(Debug)]
+pub struct Repriced {
+    pub reprices: Vec<Reprice>,
+    /// fills of the order and of every edited order replacing it
+    pub fills: Vec<kraken::ExecutedTrade>,
+    /// what the order got when it ran out of reprices, if it did
+    pub gave_up: Option<GiveUp>,
+    /// the market buy of the rest with `GiveUp::MarketBuy`
+    pub market_buy: Option<Result<PlacedBuy, String>>,
+}
+
+/// Moves the open limit `buy` of `entry` (placed with `options`) up by the
+/// policy's step, up to `max_reprices` times, while it stays open and the
+/// next wait fits into `time_left`. Post only limits stay at or below the bid.
+pub async fn reprice(
+    client: &KrakenClient,
+    policy: &RepricePolicy,
+    entry: &PlanEntry,
+    buy: &PlacedBuy,
+    options: &kraken::BuyOptions,
+    time_left: Duration,
+) -> Result<Repriced, String> {
+    let wait = policy.wait.unwrap_or(DEFAULT_REPRICE_WAIT);
+    let started = std::time::Instant::now();
+    let metadata = client.metadata().await?;
+    let info = metadata.pair_info(&buy.pair).ok_or_else(|| format!("unknown asset pair: {}", buy.pair))?;
+
+    let mut txid = match buy.order.txid.as_deref() {
+        Some([txid]) => txid.clone(),
+        _ => return Err(String::from("only single orders are repriced")),
+    };
+    let mut price = buy.price;
+    let mut reprices = Vec::new();
+    let mut fills = Vec::new();
+
+    loop {
+        if started.elapsed() + wait > time_left {
+            break;
+        }
+        tokio::time::sleep(wait).await;
+
+        let executed = match kraken::confirm_order(client, &txid).await? {
+            kraken::OrderState::Open(executed) => executed,
+            state => {
+                fills.extend(state.executed());
+                return Ok(Repriced {
+                    reprices,
+                    fills,
+                    gave_up: None,
+                    market_buy: None,
+                })
+            }
+        };
+
+        if reprices.len() as u32 >= policy.max_reprices {
+            let market_buy = give_up(client, policy, buy, options, &txid, &executed).await?;
+            fills.push(executed);
+            return Ok(Repriced {
+                reprices,
+                fills,
+                gave_up: Some(policy.give_up),
+                market_buy,
+            });
+        }
+
+        let ticker = kraken::ticker(client, &buy.pair).await?;
+        let ceiling = if options.post_only { ticker.bid_price() } else { ticker.ask_price() };
+        let to = (price + ticker.ask_price() * policy.step_pct / Decimal::ONE_HUNDRED)
+            .min(ceiling)
+            .round_dp_with_strategy(info.pair_decimals, RoundingStrategy::ToZero);
+
+        // already as close as it may get
+        if to <= price {
+            let market_buy = give_up(client, policy, buy, options, &txid, &executed).await?;
+            fills.push(executed);
+            return Ok(Repriced {
+                reprices,
+                fills,
+                gave_up: Some(policy.give_up),
+                market_buy,
+            });
+        }
+
+        // the edited order only carries what is still unfilled
+        let rest = if executed.volume > Decimal::ZERO { Some(executed.ordered - executed.volume) } else { None };
+        let edited = kraken::edit_order(client, &txid, &buy.pair, to, rest, options.userref).await?;
+        if executed.volume > Decimal::ZERO {
+            fills.push(executed);
+        }
+
+        let new_txid = edited.txid.unwrap_or_default();
+        reprices.push(Reprice {
+            pair: entry.pair.clone(),
+            from: price,
+            to,
+            txid: new_txid.clone(),
+        });
+        txid = new_txid;
+        price = to;
+    }
+
+    fills.extend(kraken::confirm_order(client, &txid).await?.executed());
+
+    Ok(Repriced {
+        reprices,
+        fills,
+        gave_up: None,
+        market_buy: None,
+    })
+}
+
+/// Cancels the order `txid` left of `buy` unless the policy keeps it, and buys
+/// what it didn't fill at market with `GiveUp::MarketBuy`.
+async fn give_up(
+    client: &KrakenClient,
+    policy: &RepricePolicy,
+    buy: &PlacedBuy,
+    options: &kraken::BuyOptions,
+    txid: &str,
+    executed: &kraken::ExecutedTrade,
+) -> Result<Option<Result<PlacedBuy, String>>, String> {
+    if policy.give_up == GiveUp::Keep {
+        return Ok(None);
+    }
+
+    kraken::cancel_order(client, txid).await?;
+
+    if policy.give_up != GiveUp::MarketBuy {
+        return Ok(None);
+    }
+
+    let ask = kraken::ticker(client, &buy.pair).await?.ask_price();
+    let rest = (executed.ordered - executed.volume) * ask;
+    let market = kraken::BuyOptions {
+        limit_price: None,
+        post_only: false,
+        limit_ttl: None,
+        ..*options
+    };
+
+    Ok(Some(kraken::buy_for_amount(client, &buy.pair, rest, &market).await))
+}
+
+/// hourly candles the volatility of a pair is measured over
+const VOLATILITY_CANDLES: usize = 24;
+
+/// High-low range of `pair` over the last day of hourly candles, in percent of the last close.
+pub async fn volatility(client: &KrakenClient, pair: &str) -> Result<Decimal, String> {
+    let candles = kraken::ohlc(client, pair, 60).await?;
+    let recent = &candles[candles.len().saturating_sub(VOLATILITY_CANDLES)..];
+
+    let high = recent.iter().map(|candle| candle.high).max();
+    let low = recent.iter().map(|candle| candle.low).min();
+
+    match (high, low, recent.last()) {
+        (Some(high), Some(low), Some(last)) if !last.close.is_zero() => Ok((high - low) / last.close * Decimal::ONE_HUNDRED),
+        _ => Err(format!("no candles to measure {} volatility", pair)),
+    }
+}
+
+/// Sorts a run's `amounts` per the plan's `ordering`. Entries whose volatility
+/// can't be measured go last, in plan order.
+pub async fn order_entries<'a>(
+    client: &KrakenClient,
+    plan: &DcaPlan,
+    mut amounts: Vec<(&'a PlanEntry, Decimal)>,
+) -> Vec<(&'a PlanEntry, Decimal)> {
+    match plan.ordering {
+        OrderingStrategy::AsListed => amounts,
+        OrderingStrategy::ByBudgetDesc => {
+            amounts.sort_by_key(|&(_, amount)| Reverse(amount));
+            amounts
+        }
+        OrderingStrategy::ByVolatilityDesc => {
+            let mut measured = Vec::new();
+            for (entry, amount) in amounts {
+                let measure = match resolve_entry_pair(client, entry, &plan.quote).await {
+                    Ok(pair) => volatility(client, &pair).await,
+                    Err(e) => Err(e),
+                };
+
+                if let Err(e) = &measure {
+                    log::warn!("volatility of {} unknown, ordering it last: {}", entry.pair, e);
+                }
+                measured.push((entry, amount, measure.ok()));
+            }
+
+            // stable, so ties and unmeasured entries keep their plan order
+            measured.sort_by_key(|&(_, _, measure)| Reverse(measure));
+            measured.into_iter().map(|(entry, amount, _)| (entry, amount)).collect()
+        }
+    }
+}
+
+/// Current bid-ask spread of `pair` in percent of the mid price.
+pub async fn spread_pct(client: &KrakenClient, pair: &str) -> Result<Decimal, String> {
+    let ticker = kraken::ticker(client, pair).await?;
+    let (ask, bid) = (ticker.ask_price(), ticker.bid_price());
+    let mid = (ask + bid) / Decimal::from(2);
+
+    if mid <= Decimal::ZERO {
+        return Err(format!("no usable bid and ask for {}", pair));
+    }
+
+    Ok((ask - bid) / mid * Decimal::ONE_HUNDRED)
+}
+
+/// Reason to skip `pair` when a buy placed with `userref` already went through
+/// or still rests; an order canceled or expired unfilled doesn't count.
+pub async fn check_executed(client: &KrakenClient, pair: &str, userref: i32) -> Result<Option<String>, String> {
+    let altname = altname(client, pair).await?;
+
+    let mut orders = kraken::open_orders(client, false, Some(userref)).await?;
+    orders.extend(kraken::all_closed_orders(client, Some(userref)).await?);
+
+    let executed = orders.iter().find(|(_, order)| {
+        let unfilled = order.vol_exec.parse::<Decimal>().is_ok_and(|vol_exec| vol_exec.is_zero());
+        let dropped = (order.status == "canceled" || order.status == "expired") && unfilled;
+
+        order.descr.order_direction == OrderDirection::Buy
+            && (order.descr.pair == pair || order.descr.pair == altname)
+            && !dropped
+    });
+
+    Ok(executed.map(|(txid, order)| format!("already bought in this run by {} ({})", txid, order.status)))
+}
+
+/// Reason to skip `pair` while its most recent buy (open or filled) is younger
+/// than `min_interval`.
+pub async fn check_cooldown(client: &KrakenClient, pair: &str, min_interval: Duration) -> Result<Option<String>, String> {
+    let altname = altname(client, pair).await?;
+
+    let mut orders = kraken::open_orders(client, false, None).await?;
+    orders.extend(
+        kraken::closed_orders(client, false, None)
+            .await?
+            .into_iter()
+            .filter(|(_, order)| order.status == "closed"),
+    );
+
+    let last_buy = orders
+        .values()
+        .filter(|order| {
+            order.descr.order_direction == OrderDirection::Buy && (order.descr.pair == pair || order.descr.pair == altname)
+        })
+        .filter_map(|order| order.opened_at())
+        .max();
+
+    let ago = last_buy.map(|opened| {
+        let nanos = clock::unix_nanos(&clock::now()) - clock::unix_nanos(&opened);
+        Duration::from_nanos(nanos.max(0) as u64)
+    });
+
+    Ok(match ago {
+        Some(ago) if ago < min_interval => Some(format!(
+            "last buy {}s ago is within the {}s cooldown",
+            ago.as_secs(),
+            min_interval.as_secs()
+        )),
+        _ => None,
+    })
+}
+
+/// Share of the total cost that went to each pair, in percent.
+pub fn actual_split(buys: &[PlacedBuy]) -> Vec<(String, Decimal)> {
+    let total: Decimal = buys.iter().map(|buy| buy.cost()).sum();
+
+    buys.iter()
+        .map(|buy| {
+            let percent = if total.is_zero() {
+                Decimal::ZERO
+            } else {
+                buy.cost() / total * Decimal::ONE_HUNDRED
+            };
+            (buy.pair.clone(), percent)
+        })
+        .collect()
+}
+
+/// Sell of an asset's excess over its rebalance target.
+#[derive(Serialize, Debug, Clone)]
+pub struct Rebalance {
+    /// pair as written in the plan
+    pub pair: String,
+    /// weight of the asset before the sell, in percent
+    #[serde(serialize_with = "crate::summary::serialize_amount")]
+    pub weight_pct: Decimal,
+    #[serde(serialize_with = "crate::summary::serialize_amount")]
+    pub target_pct: Decimal,
+    #[serde(serialize_with = "crate::summary::serialize_amount")]
+    pub price: Decimal,
+    #[serde(serialize_with = "crate::summary::serialize_amount")]
+    pub volume: Decimal,
+    /// not set for validate-only orders
+    pub txid: Option<Vec<String>>,
+    pub description: String,
+}
+
+/// Sells the excess of every entry whose asset weighs more than its rebalance
+/// target plus threshold in the portfolio valued in `plan.quote`, at market back
+/// to the quote. An excess below the pair's `ordermin` or `costmin` is left for a later run.
+/// Returns each rebalanced entry with the sell, `None` when nothing was sold.
+pub async fn rebalance<'a>(
+    client: &KrakenClient,
+    plan: &'a DcaPlan,
+    validate: bool,
+) -> Result<Vec<(&'a PlanEntry, Result<Option<Rebalance>, String>)>, String> {
+    let metadata = client.metadata().await?;
+    let quote = metadata
+        .asset_id(&plan.quote)
+        .ok_or_else(|| format!("unknown quote currency: {}", plan.quote))?;
+
+    let total = kraken::portfolio_value(client, &quote).await?;
+    let balances = kraken::balance_decimal(client, false).await?;
+
+    let mut rebalanced = Vec::new();
+    for entry in &plan.entries {
+        if let Some(target) = &entry.rebalance {
+            let sold = rebalance_entry(client, entry, target, &plan.quote, &balances, total, validate).await;
+            rebalanced.push((entry, sold));
+        }
+    }
+
+    Ok(rebalanced)
+}
+
+async fn rebalance_entry(
+    client: &KrakenClient,
+    entry: &PlanEntry,
+    target: &RebalanceTarget,
+    quote: &str,
+    balances: &HashMap<String, Decimal>,
+    total: Decimal,
+    validate: bool,
+) -> Result<Option<Rebalance>, String> {
+    if total <= Decimal::ZERO {
+        return Ok(None);
+    }
+
+    let pair = resolve_entry_pair(client, entry, quote).await?;
+    let metadata = client.metadata().await?;
+    let info = metadata.pair_info(&pair).ok_or_else(|| format!("unknown asset pair: {}", pair))?;
+
+    let held = balances.get(&info.base).copied().unwrap_or_default();
+    let price = rebalance_price(client, &pair).await?;
+
+    let weight_pct = held * price / total * Decimal::ONE_HUNDRED;
+    if weight_pct <= target.weight_pct + target.threshold_pct {
+        return Ok(None);
+    }
+
+    let planned = rebalance_order(info, &pair, &entry.pair, held, price, total, target.weight_pct)?;
+    let volume = match (planned.error, planned.volume) {
+        (None, Some(volume)) => volume,
+        (error, _) => {
+            log::info!(
+                "{} at {}% not rebalanced: {}",
+                entry.pair, weight_pct.round_dp(2), error.unwrap_or_default()
+            );
+            return Ok(None);
+        }
+    };
+
+    let order = kraken::NewOrder {
+        pair: pair.clone(),
+        order_direction: OrderDirection::Sell,
+        order_type: kraken::OrderType::Market,
+        price: None,
+        price2: None,
+        trigger: None,
+        volume: Some(volume.to_string()),
+        leverage: None,
+        reduce_only: false,
+        oflags: None,
+        fee_currency: None,
+        market_price_protection: true,
+        starttm: None,
+        expiretm: None,
+        // untagged, so a resumed run doesn't take the sell for the entry's buy
+        userref: None,
+        #[cfg(feature = "timestamps")]
+        deadline: None,
+        validate: if validate { Some(true) } else { None },
+    };
+    let placed = kraken::add_order(client, order).await?;
+
+    Ok(Some(Rebalance {
+        pair: entry.pair.clone(),
+        weight_pct: weight_pct.round_dp(2),
+        target_pct: target.weight_pct,
+        price,
+        volume,
+        txid: placed.txid,
+        description: kraken::format_placed(&placed.descr),
+    }))
+}
+
+/// Last trade price of `pair` (a canonical pair key) a rebalance is computed at.
+async fn rebalance_price(client: &KrakenClient, pair: &str) -> Result<Decimal, String> {
+    let price = kraken::last_price(client, pair).await?;
+    if price <= Decimal::ZERO {
+        return Err(format!("invalid price {} for {}", price, pair));
+    }
+    Ok(price)
+}
+
+/// Order that brings the `held` base of `pair` to `weight_pct` percent of a
+/// portfolio worth `total`, at `price`, listed under `label`. One below the
+/// pair's `ordermin` or `costmin` carries the reason as its error, as does an
+/// asset already at its target. Both `rebalance` and `rebalance_preview` go
+/// through here, so a preview shows what a run would sell.
+fn rebalance_order(
+    info: &kraken::AssetPair,
+    pair: &str,
+    label: &str,
+    held: Decimal,
+    price: Decimal,
+    total: Decimal,
+    weight_pct: Decimal,
+) -> Result<PlannedOrder, String> {
+    let difference = total * weight_pct / Decimal::ONE_HUNDRED - held * price;
+    let direction = if difference > Decimal::ZERO { OrderDirection::Buy } else { OrderDirection::Sell };
+
+    let mut volume = (difference.abs() / price).round_dp_with_strategy(info.lot_decimals, RoundingStrategy::ToZero);
+    if direction == OrderDirection::Sell {
+        volume = volume.min(held);
+    }
+    let cost = kraken::safe_cost(price, volume).map_err(|e| e.to_string())?;
+
+    let error = if volume.is_zero() {
+        Some(format!("{} is already at its {}% target", label, weight_pct))
+    } else {
+        let below_ordermin = info.ordermin.filter(|ordermin| volume < *ordermin).map(|ordermin| {
+            format!(
+                "{} {} of {} to reach {}% is below the minimum order of {}",
+                direction, volume, label, weight_pct, ordermin
+            )
+        });
+        below_ordermin.or_else(|| {
+            info.costmin.filter(|costmin| cost < *costmin).map(|costmin| {
+                format!(
+                    "{} of {} for {} to reach {}% is below the minimum cost of {}",
+                    direction, label, cost, weight_pct, costmin
+                )
+            })
+        })
+    };
+
+    Ok(PlannedOrder {
+        pair: label.to_owned(),
+        direction,
+        amount: difference.abs().round_dp(info.pair_decimals),
+        conversion_rate: None,
+        multiplier: None,
+        resolved_pair: Some(pair.to_owned()),
+        price: Some(price),
+        volume: Some(volume),
+        cost: Some(cost),
+        estimated_fee: Some(info.estimated_fee(cost)),
+        fee_currency: None,
+        fill_estimate: None,
+        error,
+    })
+}
+
+/// Buys and sells that would bring the portfolio valued in `quote` to the
+/// `targets` weights in percent (by asset id or altname), computed without
+/// placing anything. Targets are listed by asset; one that can't be met
+/// because its order falls below the pair's `ordermin` or `costmin` carries
+/// the reason as its error, as do assets already at their target.
+pub async fn rebalance_preview(
+    client: &KrakenClient,
+    targets: &HashMap<String, Decimal>,
+    quote: &str,
+) -> Result<Vec<PlannedOrder>, String> {
+    let metadata = client.metadata().await?;
+    let quote_id = metadata
+        .asset_id(quote)
+        .ok_or_else(|| format!("unknown quote currency: {}", quote))?;
+
+    let weights: Decimal = targets.values().sum();
+    if targets.values().any(|weight| *weight < Decimal::ZERO) || weights > Decimal::ONE_HUNDRED {
+        return Err(format!("target weights must be positive and add up to at most 100%, not {}%", weights));
+    }
+
+    let total = kraken::portfolio_value(client, &quote_id).await?;
+    if total <= Decimal::ZERO {
+        return Err(format!("nothing to rebalance, the portfolio is worth nothing in {}", quote));
+    }
+    let balances = kraken::balance_decimal(client, false).await?;
+
+    let mut targets: Vec<_> = targets.iter().collect();
+    targets.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut preview = Vec::new();
+    for (asset, weight_pct) in targets {
+        let planned = match target_order(client, &metadata, &balances, total, &quote_id, asset, *weight_pct).await {
+            Ok(Some(planned)) => planned,
+            // the quote's weight is whatever the other targets leave
+            Ok(None) => continue,
+            Err(e) => PlannedOrder {
+                pair: asset.clone(),
+                direction: OrderDirection::Buy,
+                amount: Decimal::ZERO,
+                conversion_rate: None,
+                multiplier: None,
+                resolved_pair: None,
+                price: None,
+                volume: None,
+                cost: None,
+                estimated_fee: None,
+                fee_currency: None,
+                fill_estimate: None,
+                error: Some(e),
+            },
+        };
+        preview.push(planned);
+    }
+
+    Ok(preview)
+}
+
+/// `rebalance_order` of one `rebalance_preview` target, `None` for the quote itself.
+async fn target_order(
+    client: &KrakenClient,
+    metadata: &Metadata,
+    balances: &HashMap<String, Decimal>,
+    total: Decimal,
+    quote_id: &str,
+    asset: &str,
+    weight_pct: Decimal,
+) -> Result<Option<PlannedOrder>, String> {
+    let asset_id = metadata.asset_id(asset).ok_or_else(|| format!("unknown asset: {}", asset))?;
+    if asset_id == quote_id {
+        return Ok(None);
+    }
+
+    let pair = quote_pair(metadata, &asset_id, quote_id).ok_or_else(|| format!("{} has no {} pair", asset, quote_id))?;
+    let info = metadata.pair_info(&pair).ok_or_else(|| format!("unknown asset pair: {}", pair))?;
+    let price = rebalance_price(client, &pair).await?;
+    let held = balances.get(&asset_id).copied().unwrap_or_default();
+
+    let planned = rebalance_order(info, &pair, asset, held, price, total, weight_pct)?;
+    Ok(Some(PlannedOrder {
+        fee_currency: Some(kraken::fee_currency_for(client, &pair).await?),
+        ..planned
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn an_empty_plan_is_valid_and_buys_nothing() {
+        for json in [r#"{"mode": "fixed", "entries": []}"#, r#"{"mode": "allocation", "budget": "100", "entries": []}"#] {
+            let plan = DcaPlan::from_json(json).unwrap();
+            assert!(plan.amounts().is_empty());
+        }
+    }
+
+    #[test]
+    fn zero_and_negative_amounts_are_refused() {
+        let fixed = |amount: &str| DcaPlan::from_json(&format!(r#"{{"mode": "fixed", "entries": [{{"pair": "XBTUSD", "amount": "{}"}}]}}"#, amount));
+
+        assert_eq!(fixed("0"), Err(String::from("plan entry XBTUSD has a non-positive amount of 0")));
+        assert_eq!(fixed("-5"), Err(String::from("plan entry XBTUSD has a non-positive amount of -5")));
+        assert!(fixed("5").is_ok());
+
+        let refused = DcaPlan::from_json(r#"{"mode": "allocation", "budget": "0", "entries": [{"pair": "XBTUSD", "percent": "100"}]}"#);
+        assert_eq!(refused, Err(String::from("allocation plan has a non-positive budget of 0")));
+
+        let refused = DcaPlan::from_json(
+            r#"{"mode": "allocation", "budget": "100", "entries": [{"pair": "XBTUSD", "percent": "100"}, {"pair": "ETHUSD", "percent": "0"}]}"#,
+        );
+        assert_eq!(refused, Err(String::from("plan entry ETHUSD has a non-positive percent of 0")));
+    }
+
+    #[test]
+    fn allocations_split_the_budget() {
+        let plan = DcaPlan::from_json(
+            r#"{"mode": "allocation", "budget": "250", "entries": [{"pair": "XBTUSD", "percent": "60"}, {"pair": "ETHUSD", "percent": "40"}]}"#,
+        )
+        .unwrap();
+
+        let amounts: Vec<(&str, Decimal)> = plan.amounts().into_iter().map(|(entry, amount)| (entry.pair.as_str(), amount)).collect();
+        assert_eq!(amounts, vec![("XBTUSD", dec("150")), ("ETHUSD", dec("100"))]);
+    }
+
+    #[test]
+    fn duplicate_entries_are_refused_or_merged() {
+        let entries = r#"[{"pair": "XBTUSD", "amount": "20", "max_volume": "0.01"}, {"pair": "ETHUSD", "amount": "10"}, {"pair": "xbtusd", "amount": "5"}]"#;
+
+        let refused = DcaPlan::from_json(&format!(r#"{{"mode": "fixed", "entries": {}}}"#, entries));
+        assert_eq!(refused, Err(String::from("plan lists xbtusd more than once")));
+
+        let plan = DcaPlan::from_json(&format!(r#"{{"mode": "fixed", "duplicates": "merge", "entries": {}}}"#, entries)).unwrap();
+        let amounts = plan.amounts();
+        let merged: Vec<(&str, Decimal)> = amounts.iter().map(|(entry, amount)| (entry.pair.as_str(), *amount)).collect();
+        assert_eq!(merged, vec![("XBTUSD", dec("25")), ("ETHUSD", dec("10"))]);
+        // the first entry's settings apply
+        assert_eq!(amounts[0].0.max_volume, Some(dec("0.01")));
+    }
+
+    #[test]
+    fn plans_beyond_the_order_cap_are_refused() {
+        let entries = |count: usize| {
+            let entries: Vec<String> = (0..count).map(|i| format!(r#"{{"pair": "PAIR{}USD", "amount": "10"}}"#, i)).collect();
+            entries.join(", ")
+        };
+
+        assert!(DcaPlan::from_json(&format!(r#"{{"mode": "fixed", "entries": [{}]}}"#, entries(50))).is_ok());
+        let refused = DcaPlan::from_json(&format!(r#"{{"mode": "fixed", "entries": [{}]}}"#, entries(51)));
+        assert_eq!(refused, Err(String::from("plan would place 51 orders, more than the max_orders_per_run of 50")));
+
+        let capped = |count: usize| format!(r#"{{"mode": "fixed", "max_orders_per_run": 2, "entries": [{}]}}"#, entries(count));
+        assert!(DcaPlan::from_json(&capped(2)).is_ok());
+        assert!(DcaPlan::from_json(&capped(3)).is_err());
+
+        // merged duplicates count as one order
+        let merged = r#"{"mode": "fixed", "max_orders_per_run": 1, "duplicates": "merge", "entries": [{"pair": "XBTUSD", "amount": "10"}, {"pair": "XBTUSD", "amount": "5"}]}"#;
+        assert!(DcaPlan::from_json(merged).is_ok());
+    }
+
+    #[test]
+    fn a_plan_survives_the_json_round_trip() {
+        let plan = DcaPlan::from_json(
+            r#"{
+                "mode": "allocation",
+                "budget": "250.50",
+                "quote": "EUR",
+                "order_ttl": 3600,
+                "reserve": "20",
+                "duplicates": "merge",
+                "max_spread_pct": "0.5",
+                "max_orders_per_run": 5,
+                "cost_tolerance_pct": "2",
+                "fund_from": ["USDT"],
+                "entries": [
+                    {"pair": "XBTEUR", "percent": "70", "max_slippage_pct": "1", "min_interval": 86400, "max_volume": "0.01"},
+                    {"pair": "ETHEUR", "percent": "30", "order_type": "limit", "price_strategy": {"below_ask": "1.5"}, "post_only": true}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let json = plan.to_json().unwrap();
+        assert_eq!(DcaPlan::from_json(&json), Ok(plan.clone()));
+        assert_eq!(plan.order_ttl, Some(Duration::from_secs(3600)));
+
+        let mut invalid = plan;
+        invalid.budget = None;
+        assert_eq!(invalid.to_json(), Err(String::from("allocation plan has no budget")));
+    }
+
+    async fn rebalancing_exchange(costmin: &str) -> crate::mock::MockExchange {
+        let exchange = crate::mock::MockExchange::start().await;
+        exchange.add_pair("XXBTZUSD", "XBTUSD", "XXBT", "ZUSD", serde_json::json!({ "costmin": costmin }));
+        exchange.set_price("XXBTZUSD", dec("40000"));
+        exchange.set_balance("ZUSD", dec("1000"));
+        exchange.set_balance("XXBT", dec("0.05"));
+        exchange
+    }
+
+    fn rebalancing_plan() -> DcaPlan {
+        DcaPlan::from_json(
+            r#"{"mode": "fixed", "entries": [{"pair": "XBTUSD", "amount": "20", "rebalance": {"weight_pct": "50", "threshold_pct": "5"}}]}"#,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_rebalance_sells_what_its_preview_shows() {
+        let exchange = rebalancing_exchange("0.5").await;
+        let client = exchange.client().with_direction_policy(kraken::DirectionPolicy::Both);
+        let targets = HashMap::from([(String::from("XBT"), dec("50"))]);
+
+        let plan = rebalancing_plan();
+
+        let preview = rebalance_preview(&client, &targets, "USD").await.unwrap();
+        let rebalanced = rebalance(&client, &plan, true).await.unwrap();
+        let sold = rebalanced[0].1.as_ref().unwrap().as_ref().unwrap();
+
+        // 2000 of 3000 in XBT, 500 above the 50% target
+        assert_eq!(preview[0].direction, OrderDirection::Sell);
+        assert_eq!(preview[0].volume, Some(dec("0.0125")));
+        assert_eq!(preview[0].volume, Some(sold.volume));
+        assert_eq!(preview[0].price, Some(sold.price));
+    }
+
+    #[tokio::test]
+    async fn a_rebalance_below_the_minimum_cost_is_left_for_later() {
+        let exchange = rebalancing_exchange("1000").await;
+        let client = exchange.client();
+        let targets = HashMap::from([(String::from("XBT"), dec("50"))]);
+
+        let preview = rebalance_preview(&client, &targets, "USD").await.unwrap();
+        assert_eq!(
+            preview[0].error.as_deref(),
+            Some("sell of XBT for 500.0000 to reach 50% is below the minimum cost of 1000")
+        );
+
+        let plan = rebalancing_plan();
+        let rebalanced = rebalance(&client, &plan, true).await.unwrap();
+        assert!(matches!(rebalanced[0].1, Ok(None)));
+        assert!(exchange.calls("AddOrder").is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_recent_buy_holds_the_pair_in_its_cooldown() {
+        let exchange = rebalancing_exchange("0.5").await;
+        let client = exchange.client();
+        let buy = kraken::buy_for_amount(&client, "XXBTZUSD", dec("100"), &kraken::BuyOptions::default()).await.unwrap();
+        let hour = Duration::from_secs(3600);
+
+        let reason = check_cooldown(&client, "XXBTZUSD", hour).await.unwrap().unwrap();
+        assert!(reason.ends_with("s ago is within the 3600s cooldown"), "{}", reason);
+
+        exchange.backdate(&buy.order.txid.unwrap()[0], 7200.0);
+        assert_eq!(check_cooldown(&client, "XXBTZUSD", hour).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn a_limit_preview_estimates_its_fill() {
+        let exchange = rebalancing_exchange("0.5").await;
+        let client = exchange.client();
+        let plan = DcaPlan::from_json(
+            r#"{"mode": "fixed", "entries": [{"pair": "XBTUSD", "amount": "100", "order_type": "limit", "price_strategy": "bid", "price_offset_pct": "5"}]}"#,
+        )
+        .unwrap();
+        // 3 ahead of the 38000 limit, sold at 1 per 100 seconds
+        exchange.script(
+            "Depth",
+            200,
+            r#"{"error": [], "result": {"XXBTZUSD": {"asks": [["40000", "1", 0]], "bids": [["39000", "2", 0], ["38000", "1", 0], ["37000", "5", 0]]}}}"#,
+        );
+        exchange.script(
+            "Trades",
+            200,
+            r#"{"error": [], "result": {"XXBTZUSD": [["40000", "0.5", 1000.0, "s", "m", ""], ["40000", "2", 1050.0, "b", "m", ""], ["40000", "0.5", 1100.0, "s", "m", ""]], "last": "1"}}"#,
+        );
+
+        let preview = plan_preview(&client, &plan).await.unwrap();
+
+        assert_eq!(preview[0].price, Some(dec("38000")));
+        assert_eq!(preview[0].fill_estimate.map(|estimate| estimate.as_secs()), Some(300));
+        assert_eq!(preview[0].fee_currency.as_deref(), Some("ZUSD"));
+    }
+
+    #[tokio::test]
+    async fn old_limits_far_from_the_market_are_stuck() {
+        let exchange = rebalancing_exchange("0.5").await;
+        let client = exchange.client();
+        async fn resting(client: &KrakenClient, price: &str) -> kraken::TxId {
+            let options = kraken::BuyOptions {
+                limit_price: Some(dec(price)),
+                ..kraken::BuyOptions::default()
+            };
+            kraken::buy_for_amount(client, "XXBTZUSD", dec("50"), &options).await.unwrap().order.txid.unwrap()[0].clone()
+        }
+        let far = resting(&client, "20000").await;
+        let near = resting(&client, "39500").await;
+        resting(&client, "20000").await;
+        exchange.backdate(&far, 7200.0);
+        exchange.backdate(&near, 7200.0);
+
+        let stuck = find_stuck_orders(&client, Duration::from_secs(3600), dec("5")).await.unwrap();
+
+        assert_eq!(stuck.len(), 1);
+        assert_eq!((stuck[0].txid.as_str(), stuck[0].distance_pct), (far.as_str(), dec("50")));
+        assert_eq!(stuck[0].age.as_secs() / 60, 120);
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Response body of one API call as Kraken sent it, for diagnosing decoding errors.
+#[derive(Serialize, Debug)]
+pub struct RawResponse<'a> {
+    pub method: &'a str,
+    pub private: bool,
+    pub at: DateTime<Utc>,
+    /// request params, `nonce` and `otp` masked for private calls; the key and
+    /// signature only ever go into headers and are never recorded
+    pub params: HashMap<&'a str, &'a str>,
+    pub body: &'a str,
+}
+
+impl RawResponse<'_> {
+    /// One name per response, in call order within a method.
+    fn name(&self) -> String {
+        format!("{}-{}.json", self.at.format("%Y%m%dT%H%M%S%.6fZ"), self.method)
+    }
+}
+
+/// Where raw responses are recorded.
+#[async_trait]
+pub trait RawSink: Send + Sync {
+    async fn record(&self, response: &RawResponse<'_>) -> Result<(), String>;
+}
+
+/// One JSON file per response in `dir`, e.g. `/tmp/raw` on Lambda.
+pub struct FileRawSink {
+    pub dir: PathBuf,
+}
+
+#[async_trait]
+impl RawSink for FileRawSink {
+    async fn record(&self, response: &RawResponse<'_>) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(response).map_err(|e| format!("{:?}", e))?;
+        std::fs::create_dir_all(&self.dir).map_err(|e| format!("{:?}", e))?;
+        std::fs::write(self.dir.join(response.name()), json).map_err(|e| format!("{:?}", e))
+    }
+}
+
+/// One object per response under `raw/` in `bucket`.
+pub struct S3RawSink {
+    pub bucket: String,
+    client: aws_sdk_s3::Client,
+}
+
+#[async_trait]
+impl RawSink for S3RawSink {
+    async fn record(&self, response: &RawResponse<'_>) -> Result<(), String> {
+        let body = serde_json::to_vec(response).map_err(|e| format!("{:?}", e))?;
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(format!("raw/{}", response.name()))
+            .content_type("application/json")
+            .body(aws_sdk_s3::ByteStream::from(body))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("{:?}", e))
+    }
+}
+
+/// Sink for `RAW_RESPONSE_BUCKET`, else for `RAW_RESPONSE_DIR`, `None` when neither is configured.
+pub async fn from_env() -> Option<Box<dyn RawSink>> {
+    if let Some(bucket) = option_env!("RAW_RESPONSE_BUCKET") {
+        let config = aws_config::load_from_env().await;
+
+        return Some(Box::new(S3RawSink {
+            bucket: bucket.to_owned(),
+            client: aws_sdk_s3::Client::new(&config),
+        }));
+    }
+
+    let dir = option_env!("RAW_RESPONSE_DIR")?;
+    Some(Box::new(FileRawSink { dir: PathBuf::from(dir) }))
+}
@@ -0,0 +1,135 @@
+use std::collections::{HashMap, HashSet};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::clock::{self, Timestamp};
+use crate::journal::{Action, JournalSink};
+use crate::kraken::{self, KrakenClient, TxId};
+
+/// most txids QueryOrders takes at once
+const QUERY_ORDERS_BATCH: usize = 50;
+
+/// Order the journal has as placed.
+#[derive(Serialize, Debug)]
+pub struct JournaledOrder {
+    pub run_id: String,
+    pub at: Timestamp,
+    /// pair as written in the plan
+    pub pair: String,
+    pub txid: TxId,
+}
+
+/// Fill of an order placed with a user reference, as the bot's orders are.
+#[derive(Serialize, Debug)]
+pub struct UnjournaledFill {
+    /// trade id
+    pub txid: TxId,
+    pub ordertxid: TxId,
+    pub userref: i32,
+    pub pair: String,
+    pub time: Option<Timestamp>,
+    pub volume: Decimal,
+    pub cost: Decimal,
+}
+
+/// Where the journal and Kraken's trade history disagree.
+#[derive(Serialize, Debug)]
+pub struct ReconcileReport {
+    pub account: String,
+    pub since: Timestamp,
+    /// journaled orders no trade filled: the bot thinks it bought but didn't
+    pub unfilled: Vec<JournaledOrder>,
+    /// fills the journal has no order for: the bot bought without knowing
+    pub unjournaled: Vec<UnjournaledFill>,
+}
+
+impl ReconcileReport {
+    pub fn is_clean(&self) -> bool {
+        self.unfilled.is_empty() && self.unjournaled.is_empty()
+    }
+}
+
+/// Compares the orders `account` journaled since `since` with the account's
+/// trades since then, matching on the order txid. Trades of orders without a
+/// user reference are taken as manual and left out.
+pub async fn reconcile(client: &KrakenClient, journal: &dyn JournalSink, account: &str, since: Timestamp) -> Result<ReconcileReport, String> {
+    let mut journaled: Vec<JournaledOrder> = Vec::new();
+    // orders a reprice replaced, not expected to fill
+    let mut replaced = HashSet::new();
+    for entry in journal.entries_since(since).await? {
+        let run_id = entry.run_id;
+        for decision in entry.decisions.into_iter().filter(|decision| decision.account == account) {
+            let (at, pair) = (decision.at, decision.pair);
+            match decision.action {
+                // validate-only orders have no txid
+                Action::Ordered { txid: Some(txids), .. } => {
+                    journaled.extend(txids.into_iter().map(|txid| JournaledOrder {
+                        run_id: run_id.clone(),
+                        at,
+                        pair: pair.clone(),
+                        txid,
+                    }));
+                }
+                Action::Repriced { txid, .. } => {
+                    let previous = journaled
+                        .iter()
+                        .rev()
+                        .find(|order| order.run_id == run_id && order.pair == pair);
+                    if let Some(previous) = previous {
+                        replaced.insert(previous.txid.clone());
+                    }
+                    journaled.push(JournaledOrder {
+                        run_id: run_id.clone(),
+                        at,
+                        pair,
+                        txid,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let trades = kraken::all_trades(client, Some(clock::unix(&since))).await?;
+
+    let filled: HashSet<&str> = trades.values().map(|trade| trade.ordertxid.as_str()).collect();
+    let journaled_txids: HashSet<&str> = journaled.iter().map(|order| order.txid.as_str()).collect();
+
+    let unknown_orders: Vec<&str> = filled.iter().copied().filter(|txid| !journaled_txids.contains(txid)).collect();
+    let mut userrefs = HashMap::new();
+    for batch in unknown_orders.chunks(QUERY_ORDERS_BATCH) {
+        for (txid, order) in kraken::query_orders(client, batch, false, None).await? {
+            if let Some(userref) = order.userref.filter(|userref| *userref != 0) {
+                userrefs.insert(txid, userref);
+            }
+        }
+    }
+
+    let unjournaled = trades
+        .iter()
+        .filter_map(|(txid, trade)| {
+            userrefs.get(&trade.ordertxid).map(|userref| UnjournaledFill {
+                txid: txid.clone(),
+                ordertxid: trade.ordertxid.clone(),
+                userref: *userref,
+                pair: trade.pair.clone(),
+                time: trade.executed_at(),
+                volume: trade.vol,
+                cost: trade.cost,
+            })
+        })
+        .collect();
+
+    let unfilled = journaled
+        .into_iter()
+        .filter(|order| !filled.contains(order.txid.as_str()) && !replaced.contains(&order.txid))
+        .collect();
+
+    Ok(ReconcileReport {
+        account: account.to_owned(),
+        since,
+        unfilled,
+        unjournaled,
+    })
+}
@@ -0,0 +1,46 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, FixedOffset, Utc};
+
+/// Cron schedule the DCA runs on, mirroring the EventBridge rule.
+pub struct CronSchedule {
+    schedule: cron::Schedule,
+    /// timezone the expression is written in
+    offset: FixedOffset,
+}
+
+impl CronSchedule {
+    /// Parses an EventBridge cron expression such as `0 18 ? * MON *`
+    /// (minutes, hours, day of month, month, day of week, year), evaluated
+    /// `utc_offset_minutes` east of UTC.
+    pub fn parse(expression: &str, utc_offset_minutes: i32) -> Result<CronSchedule, String> {
+        let offset = FixedOffset::east_opt(utc_offset_minutes * 60)
+            .ok_or_else(|| format!("invalid schedule UTC offset: {} minutes", utc_offset_minutes))?;
+
+        // the cron crate wants a leading seconds field and doesn't know `?`
+        let expression = format!("0 {}", expression.trim().replace('?', "*"));
+        let schedule = cron::Schedule::from_str(&expression).map_err(|e| format!("invalid schedule {}: {}", expression, e))?;
+
+        Ok(CronSchedule { schedule, offset })
+    }
+
+    /// Schedule in `DCA_SCHEDULE`, in the timezone `DCA_SCHEDULE_UTC_OFFSET`
+    /// minutes east of UTC (UTC when unset). `None` when no schedule is configured.
+    pub fn from_env() -> Option<Result<CronSchedule, String>> {
+        let expression = option_env!("DCA_SCHEDULE")?;
+        let offset = option_env!("DCA_SCHEDULE_UTC_OFFSET")
+            .and_then(|offset| offset.parse().ok())
+            .unwrap_or(0);
+
+        Some(CronSchedule::parse(expression, offset))
+    }
+}
+
+/// First time `schedule` fires after `now`, `None` when it never fires again.
+pub fn next_run(schedule: &CronSchedule, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    schedule
+        .schedule
+        .after(&now.with_timezone(&schedule.offset))
+        .next()
+        .map(|next| next.with_timezone(&Utc))
+}
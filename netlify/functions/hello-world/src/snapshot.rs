@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use aws_sdk_dynamodb::model::AttributeValue;
+use rust_decimal::Decimal;
+
+use crate::kraken;
+
+#[derive(Debug)]
+pub struct BalanceSnapshot {
+    /// label of the account the balances belong to
+    pub account: String,
+    /// unix time the snapshot was taken
+    pub timestamp: u64,
+    pub balances: HashMap<String, Decimal>,
+    /// asset id the balances are valued in
+    pub quote: String,
+    pub total_value: Decimal,
+}
+
+impl BalanceSnapshot {
+    pub async fn capture(client: &kraken::KrakenClient, account: &str, quote: &str) -> Result<BalanceSnapshot, String> {
+        // the full account, so a snapshot also records an asset that was sold off
+        let balances = kraken::balance_decimal(client, true).await?;
+        let total_value = kraken::valuation(client, &balances, quote).await?;
+
+        Ok(BalanceSnapshot {
+            account: account.to_owned(),
+            timestamp: ::std::time::UNIX_EPOCH.elapsed().unwrap().as_secs(),
+            balances,
+            quote: quote.to_owned(),
+            total_value,
+        })
+    }
+}
+
+pub struct BalanceSnapshotStore {
+    pub table: String,
+    client: aws_sdk_dynamodb::Client,
+}
+
+impl BalanceSnapshotStore {
+    /// Store for the `BALANCE_SNAPSHOT_TABLE` table, `None` when it isn't configured.
+    pub async fn from_env() -> Option<BalanceSnapshotStore> {
+        let table = option_env!("BALANCE_SNAPSHOT_TABLE")?;
+        let config = aws_config::load_from_env().await;
+
+        Some(BalanceSnapshotStore {
+            table: table.to_owned(),
+            client: aws_sdk_dynamodb::Client::new(&config),
+        })
+    }
+
+    pub async fn put(&self, snapshot: &BalanceSnapshot) -> Result<(), String> {
+        let balances = snapshot
+            .balances
+            .iter()
+            .map(|(asset, amount)| (asset.clone(), AttributeValue::N(amount.to_string())))
+            .collect();
+
+        self.client
+            .put_item()
+            .table_name(&self.table)
+            .item("account", AttributeValue::S(snapshot.account.clone()))
+            .item("timestamp", AttributeValue::N(snapshot.timestamp.to_string()))
+            .item("quote", AttributeValue::S(snapshot.quote.clone()))
+            .item("total_value", AttributeValue::N(snapshot.total_value.to_string()))
+            .item("balances", AttributeValue::M(balances))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("{:?}", e))
+    }
+}
@@ -0,0 +1,589 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use rust_decimal::Decimal;
+use serde::ser::Error as _;
+use serde::{Serialize, Serializer};
+
+use crate::clock::{self, Timestamp};
+use crate::kraken::{self, ExecutedTrade, KrakenClient};
+use crate::plan;
+
+/// How the summary writes amounts in JSON, per `AMOUNT_FORMAT` at build time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AmountFormat {
+    /// `"0.00012000"`, the default: no consumer can parse it into a float by accident
+    String,
+    /// `0.00012000`, a JSON number written digit for digit, never through a float
+    Number,
+}
+
+impl AmountFormat {
+    pub fn from_env() -> AmountFormat {
+        match option_env!("AMOUNT_FORMAT") {
+            Some("number") => AmountFormat::Number,
+            _ => AmountFormat::String,
+        }
+    }
+
+    fn serialize<S>(self, amount: &Decimal, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            AmountFormat::String => serializer.collect_str(amount),
+            AmountFormat::Number => serde_json::value::RawValue::from_string(amount.to_string())
+                .map_err(S::Error::custom)?
+                .serialize(serializer),
+        }
+    }
+}
+
+/// Serializes `amount` in the build's `AmountFormat`.
+pub fn serialize_amount<S>(amount: &Decimal, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    AmountFormat::from_env().serialize(amount, serializer)
+}
+
+pub fn serialize_amount_opt<S>(amount: &Option<Decimal>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match amount {
+        Some(amount) => serialize_amount(amount, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Serializes the amounts of `amounts` in the build's `AmountFormat`.
+pub fn serialize_amounts<S>(amounts: &HashMap<String, Decimal>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    #[derive(Serialize)]
+    struct Amount<'a>(#[serde(serialize_with = "serialize_amount")] &'a Decimal);
+
+    serializer.collect_map(amounts.iter().map(|(key, amount)| (key, Amount(amount))))
+}
+
+/// Entry of a run summary, tagged with the account it belongs to.
+#[derive(Serialize, Debug)]
+pub struct AccountItem<T> {
+    pub account: String,
+    #[serde(flatten)]
+    pub item: T,
+}
+
+pub fn tagged<T>(account: &str, item: T) -> AccountItem<T> {
+    AccountItem {
+        account: account.to_owned(),
+        item,
+    }
+}
+
+/// How far an order filled, as far as the run saw.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "fill", rename_all = "snake_case")]
+pub enum Fill {
+    /// validate-only, or the fills couldn't be queried
+    Unconfirmed,
+    Filled,
+    /// closed, canceled or expired short of the ordered volume
+    PartiallyFilled {
+        #[serde(serialize_with = "serialize_amount")]
+        exec_vol: Decimal,
+        #[serde(serialize_with = "serialize_amount")]
+        remaining: Decimal,
+    },
+    /// still resting when the run stopped watching
+    Open {
+        #[serde(serialize_with = "serialize_amount")]
+        exec_vol: Decimal,
+    },
+}
+
+impl Fill {
+    pub fn of(trades: &[ExecutedTrade]) -> Fill {
+        if trades.is_empty() {
+            return Fill::Unconfirmed;
+        }
+
+        let exec_vol: Decimal = trades.iter().map(|trade| trade.volume).sum();
+        let ordered: Decimal = trades.iter().map(|trade| trade.ordered).sum();
+
+        if !trades.iter().all(|trade| trade.is_terminal()) {
+            Fill::Open { exec_vol }
+        } else if exec_vol < ordered {
+            Fill::PartiallyFilled {
+                exec_vol,
+                remaining: ordered - exec_vol,
+            }
+        } else {
+            Fill::Filled
+        }
+    }
+}
+
+impl fmt::Display for Fill {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fill::Unconfirmed => write!(f, "unconfirmed"),
+            Fill::Filled => write!(f, "filled"),
+            Fill::PartiallyFilled { exec_vol, remaining } => {
+                write!(f, "partially filled, {} executed and {} left", exec_vol, remaining)
+            }
+            Fill::Open { exec_vol } => write!(f, "open, {} executed so far", exec_vol),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OrderOutcome {
+    pub pair: String,
+    #[serde(serialize_with = "serialize_amount")]
+    pub price: Decimal,
+    #[serde(serialize_with = "serialize_amount")]
+    pub volume: Decimal,
+    #[serde(serialize_with = "serialize_amount")]
+    pub cost: Decimal,
+    /// asset id of the pair's quote, the currency of `cost` and `fee`
+    pub quote: String,
+    /// fee of the confirmed fills, or the estimate when there are none
+    #[serde(serialize_with = "serialize_amount")]
+    pub fee: Decimal,
+    pub fee_estimated: bool,
+    /// not set for validate-only orders
+    pub txid: Option<Vec<String>>,
+    /// only validated by Kraken, nothing was placed
+    pub validation: bool,
+    pub description: String,
+    #[serde(flatten)]
+    pub fill: Fill,
+    /// bid-ask spread in percent when it was checked before the order
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "serialize_amount_opt")]
+    pub spread_pct: Option<Decimal>,
+}
+
+pub type SkipReason = plan::Skipped;
+
+#[derive(Serialize, Debug)]
+pub struct RunError {
+    /// plan entry the error is about, if any
+    pub pair: Option<String>,
+    pub error: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Note {
+    pub message: String,
+}
+
+/// Spend of a run in a single reporting currency.
+#[derive(Serialize, Debug)]
+pub struct ReportedSpend {
+    pub currency: String,
+    #[serde(serialize_with = "serialize_amount")]
+    pub amount: Decimal,
+}
+
+/// decimals a cost is shown with when its currency's aren't known
+pub const DEFAULT_COST_DECIMALS: u32 = 2;
+
+/// `amount` of a quote currency rounded and padded to `decimals`, e.g. `25.00`.
+pub fn format_cost(amount: Decimal, decimals: u32) -> String {
+    format!("{:.*}", decimals as usize, amount.round_dp(decimals))
+}
+
+/// Name `asset` (asset id or altname) is shown with in reports: its entry in the
+/// `ASSET_DISPLAY_NAMES` JSON map, e.g. `{"XXBT": "Bitcoin"}`, else its short
+/// code with Kraken's legacy `X`/`Z` prefix dropped (`XXBT` -> `XBT`).
+pub fn asset_display_name(asset: &str) -> String {
+    let names: HashMap<String, String> = match option_env!("ASSET_DISPLAY_NAMES").map(serde_json::from_str) {
+        Some(Ok(names)) => names,
+        Some(Err(e)) => {
+            log::warn!("ASSET_DISPLAY_NAMES is not a JSON map of asset codes to names: {}", e);
+            HashMap::new()
+        }
+        None => HashMap::new(),
+    };
+
+    display_name(&names, asset)
+}
+
+/// `asset`'s entry in `names`, by asset id or short code, else the short code.
+fn display_name(names: &HashMap<String, String>, asset: &str) -> String {
+    if let Some(name) = names.get(asset) {
+        return name.clone();
+    }
+
+    let short = short_code(asset);
+    names.get(short).cloned().unwrap_or_else(|| short.to_owned())
+}
+
+/// `XXBT` -> `XBT`, `ZUSD` -> `USD`, newer ids such as `SOL` as they are.
+fn short_code(asset: &str) -> &str {
+    if asset.len() == 4 && (asset.starts_with('X') || asset.starts_with('Z')) {
+        &asset[1..]
+    } else {
+        asset
+    }
+}
+
+/// Display decimals of each of `currencies` (asset ids or altnames), keyed as
+/// given. Currencies the asset metadata doesn't know are left out.
+pub async fn cost_decimals<'a>(client: &KrakenClient, currencies: impl IntoIterator<Item = &'a str>) -> HashMap<String, u32> {
+    let metadata = match client.metadata().await {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            log::warn!("asset metadata unavailable, showing costs with {} decimals: {}", DEFAULT_COST_DECIMALS, e);
+            return HashMap::new();
+        }
+    };
+
+    currencies
+        .into_iter()
+        .filter_map(|currency| {
+            let asset = metadata.asset_id(currency)?;
+            let info = metadata.asset_info(&asset)?;
+            Some((currency.to_owned(), info.display_decimals))
+        })
+        .collect()
+}
+
+/// Cost of the placed `orders` by quote currency.
+pub fn spent_by_currency<'a>(orders: impl IntoIterator<Item = &'a OrderOutcome>) -> HashMap<String, Decimal> {
+    let mut spent = HashMap::new();
+
+    for order in orders.into_iter().filter(|order| !order.validation) {
+        *spent.entry(order.quote.clone()).or_insert(Decimal::ZERO) += order.cost;
+    }
+
+    spent
+}
+
+/// `spent` converted to `currency` at the last cross rates, failing when one isn't available.
+pub async fn convert_spend(client: &KrakenClient, spent: &HashMap<String, Decimal>, currency: &str) -> Result<Decimal, String> {
+    let mut total = Decimal::ZERO;
+
+    for (quote, amount) in spent {
+        total += *amount * kraken::cross_rate(client, quote, currency).await?;
+    }
+
+    Ok(total)
+}
+
+/// Outcome of a run, the one source every output (HTTP body, logs) is rendered from.
+#[derive(Serialize, Debug)]
+pub struct DcaRunSummary {
+    pub run_id: String,
+    /// "validate" while orders are validate-only, "live" otherwise
+    pub mode: &'static str,
+    pub started_at: Timestamp,
+    /// the run timeout cut the run off, only what completed before it is reported
+    pub timed_out: bool,
+    pub orders: Vec<AccountItem<OrderOutcome>>,
+    pub skipped: Vec<AccountItem<SkipReason>>,
+    pub adjusted: Vec<AccountItem<plan::Adjustment>>,
+    /// open limit orders moved after the market
+    pub repriced: Vec<AccountItem<plan::Reprice>>,
+    /// stablecoins converted to the plan's quote before the run
+    pub converted: Vec<AccountItem<plan::Conversion>>,
+    /// excess of assets above their rebalance target sold back to the quote
+    pub rebalanced: Vec<AccountItem<plan::Rebalance>>,
+    pub errors: Vec<AccountItem<RunError>>,
+    pub notes: Vec<AccountItem<Note>>,
+    /// fees of all orders in their quote currency
+    #[serde(serialize_with = "serialize_amount")]
+    pub total_fees: Decimal,
+    /// some of `total_fees` is estimated, not taken from confirmed fills
+    pub fees_estimated: bool,
+    /// cost of the placed orders by quote currency, validate-only orders left out
+    #[serde(serialize_with = "serialize_amounts")]
+    pub spent: HashMap<String, Decimal>,
+    /// `spent` converted to the `REPORT_CURRENCY`, when set and every cross rate was available
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_spent: Option<ReportedSpend>,
+    /// display decimals of the quote and reporting currencies, for the text summary
+    #[serde(skip)]
+    pub cost_decimals: HashMap<String, u32>,
+    /// balances after the run by account label, `None` for an account whose
+    /// balance couldn't be fetched after its orders
+    pub balance_after: HashMap<String, Option<HashMap<String, String>>>,
+    /// when the schedule fires next, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_run: Option<Timestamp>,
+}
+
+impl DcaRunSummary {
+    /// Nothing was ordered and something went wrong, e.g. every account failed to authenticate.
+    pub fn failed(&self) -> bool {
+        self.orders.is_empty() && !self.errors.is_empty()
+    }
+
+    pub fn new(run_id: &str, mode: &'static str) -> DcaRunSummary {
+        DcaRunSummary {
+            run_id: run_id.to_owned(),
+            mode,
+            started_at: clock::now(),
+            timed_out: false,
+            orders: Vec::new(),
+            skipped: Vec::new(),
+            adjusted: Vec::new(),
+            repriced: Vec::new(),
+            converted: Vec::new(),
+            rebalanced: Vec::new(),
+            errors: Vec::new(),
+            notes: Vec::new(),
+            total_fees: Decimal::ZERO,
+            fees_estimated: false,
+            spent: HashMap::new(),
+            total_spent: None,
+            cost_decimals: HashMap::new(),
+            balance_after: HashMap::new(),
+            next_run: None,
+        }
+    }
+
+    /// Sums the orders' fees into `total_fees`, flagged as estimated when any
+    /// order's fee is.
+    pub fn total_up_fees(&mut self) {
+        self.total_fees = self.orders.iter().map(|order| order.item.fee).sum();
+        self.fees_estimated = self.orders.iter().any(|order| order.item.fee_estimated);
+    }
+
+    /// Records `account`'s balance after its orders. A balance that couldn't be
+    /// fetched is only noted: the orders are the outcome that matters, and a
+    /// missing balance doesn't fail the run.
+    pub fn record_balance_after(&mut self, account: &str, balance: Result<HashMap<String, String>, String>) {
+        match balance {
+            Ok(balance) => {
+                self.balance_after.insert(account.to_owned(), Some(balance));
+            }
+            Err(e) => {
+                log::warn!("[{}] could not get the balance after the run: {}", account, e);
+                self.balance_after.insert(account.to_owned(), None);
+                self.notes.push(tagged(account, Note {
+                    message: format!("balance after the run unavailable: {}", e),
+                }));
+            }
+        }
+    }
+
+    /// `amount` of `currency` as the text summary shows it.
+    fn cost(&self, amount: Decimal, currency: &str) -> String {
+        let decimals = self.cost_decimals.get(currency).copied().unwrap_or(DEFAULT_COST_DECIMALS);
+        format_cost(amount, decimals)
+    }
+}
+
+impl fmt::Display for DcaRunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Run {} ({}) started {}", self.run_id, self.mode, clock::rfc3339(&self.started_at))?;
+        if self.timed_out {
+            writeln!(f, "Timed out, only what completed before the run timeout is reported")?;
+        }
+
+        for conversion in &self.converted {
+            let converted = if conversion.item.txid.is_none() { "Would convert" } else { "Converted" };
+            writeln!(
+                f,
+                "[{}] {} {} {} to {} with {} to fund the run",
+                conversion.account,
+                converted,
+                asset_display_name(&conversion.item.from),
+                self.cost(conversion.item.received, &conversion.item.to),
+                asset_display_name(&conversion.item.to),
+                conversion.item.description
+            )?;
+        }
+        for order in &self.orders {
+            if order.item.validation {
+                writeln!(
+                    f,
+                    "[{}] Would place {} for {}",
+                    order.account,
+                    order.item.description,
+                    self.cost(order.item.cost, &order.item.quote)
+                )?;
+            } else {
+                writeln!(
+                    f,
+                    "[{}] Ordered {} for {} ({})",
+                    order.account,
+                    order.item.description,
+                    self.cost(order.item.cost, &order.item.quote),
+                    order.item.fill
+                )?;
+            }
+        }
+        if !self.orders.is_empty() {
+            let estimated = if self.fees_estimated { " (partly estimated)" } else { "" };
+            let paid = if self.orders.iter().all(|order| order.item.validation) { "Would pay" } else { "Paid" };
+            writeln!(f, "{} {} in fees{}", paid, self.total_fees, estimated)?;
+        }
+        match &self.total_spent {
+            Some(total) => writeln!(f, "Spent {} {}", self.cost(total.amount, &total.currency), asset_display_name(&total.currency))?,
+            None => {
+                let mut spent: Vec<_> = self.spent.iter().collect();
+                spent.sort_by(|a, b| a.0.cmp(b.0));
+                for (quote, amount) in spent {
+                    writeln!(f, "Spent {} {}", self.cost(*amount, quote), asset_display_name(quote))?;
+                }
+            }
+        }
+        for adjustment in &self.adjusted {
+            writeln!(
+                f,
+                "[{}] Lowered {} from {} to {} to keep the reserve",
+                adjustment.account, adjustment.item.pair, adjustment.item.amount, adjustment.item.adjusted
+            )?;
+        }
+        for reprice in &self.repriced {
+            writeln!(
+                f,
+                "[{}] Repriced {} from {} to {}",
+                reprice.account, reprice.item.pair, reprice.item.from, reprice.item.to
+            )?;
+        }
+        for rebalance in &self.rebalanced {
+            let sold = if rebalance.item.txid.is_none() { "Would sell" } else { "Sold" };
+            writeln!(
+                f,
+                "[{}] {} {} to bring {} from {}% toward {}%",
+                rebalance.account, sold, rebalance.item.description, rebalance.item.pair, rebalance.item.weight_pct, rebalance.item.target_pct
+            )?;
+        }
+        for skipped in &self.skipped {
+            writeln!(f, "[{}] Skipped {}: {}", skipped.account, skipped.item.pair, skipped.item.reason)?;
+        }
+        for error in &self.errors {
+            match &error.item.pair {
+                Some(pair) => writeln!(f, "[{}] Error for {}: {}", error.account, pair, error.item.error)?,
+                None => writeln!(f, "[{}] Error: {}", error.account, error.item.error)?,
+            }
+        }
+        for note in &self.notes {
+            writeln!(f, "[{}] {}", note.account, note.item.message)?;
+        }
+
+        let mut accounts: Vec<_> = self.balance_after.iter().collect();
+        accounts.sort_by(|a, b| a.0.cmp(b.0));
+        for (account, balance) in accounts {
+            match balance {
+                Some(balance) => {
+                    let named: BTreeMap<_, _> = balance.iter().map(|(asset, amount)| (asset_display_name(asset), amount)).collect();
+                    writeln!(f, "[{}] Account balance: {:?}", account, named)?
+                }
+                None => writeln!(f, "[{}] Account balance: unavailable", account)?,
+            }
+        }
+
+        if let Some(next_run) = self.next_run {
+            writeln!(f, "Next buy at {}", clock::rfc3339(&next_run))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    fn trade(status: &str, volume: &str, ordered: &str) -> ExecutedTrade {
+        ExecutedTrade {
+            txid: String::from("OABCDE-FGHIJ-KLMNOP"),
+            pair: String::from("XBTUSD"),
+            status: status.to_owned(),
+            volume: dec(volume),
+            ordered: dec(ordered),
+            price: dec("40000"),
+            cost: dec(volume) * dec("40000"),
+            fee: Decimal::ZERO,
+        }
+    }
+
+    #[test]
+    fn an_order_ending_short_of_its_volume_is_partially_filled() {
+        for status in ["closed", "canceled", "expired"] {
+            match Fill::of(&[trade(status, "0.0003", "0.001")]) {
+                Fill::PartiallyFilled { exec_vol, remaining } => assert_eq!((exec_vol, remaining), (dec("0.0003"), dec("0.0007"))),
+                fill => panic!("{} order taken for {:?}", status, fill),
+            }
+        }
+
+        let fill = Fill::of(&[trade("closed", "0.001", "0.001"), trade("canceled", "0", "0.002")]);
+        assert_eq!(fill.to_string(), "partially filled, 0.001 executed and 0.002 left");
+
+        assert!(matches!(Fill::of(&[trade("closed", "0.001", "0.001")]), Fill::Filled));
+        assert!(matches!(Fill::of(&[trade("open", "0.0003", "0.001")]), Fill::Open { .. }));
+        assert!(matches!(Fill::of(&[]), Fill::Unconfirmed));
+    }
+
+    #[test]
+    fn costs_are_rounded_and_padded_to_their_decimals() {
+        assert_eq!(format_cost(dec("25"), 2), "25.00");
+        assert_eq!(format_cost(dec("24.996"), 2), "25.00");
+        assert_eq!(format_cost(dec("0.123456"), 4), "0.1235");
+        assert_eq!(format_cost(dec("1234.6"), 0), "1235");
+        assert_eq!(format_cost(dec("0.00012"), 8), "0.00012000");
+    }
+
+    #[tokio::test]
+    async fn costs_take_their_currency_decimals() {
+        let exchange = crate::mock::MockExchange::start().await;
+        exchange.add_pair("XXBTZUSD", "XBTUSD", "XXBT", "ZUSD", serde_json::json!({}));
+        let decimals = cost_decimals(&exchange.client(), ["ZUSD", "USD", "XBT", "DOGE"]).await;
+
+        assert_eq!(decimals.get("ZUSD"), Some(&4));
+        assert_eq!(decimals.get("USD"), Some(&4));
+        assert_eq!(decimals.get("XBT"), Some(&5));
+        assert_eq!(decimals.get("DOGE"), None);
+
+        let mut run_summary = DcaRunSummary::new("run-1", "live");
+        run_summary.cost_decimals = decimals;
+        assert_eq!(run_summary.cost(dec("25"), "ZUSD"), "25.0000");
+        assert_eq!(run_summary.cost(dec("25"), "DOGE"), "25.00");
+    }
+
+    #[test]
+    fn amounts_are_written_as_strings_or_exact_numbers() {
+        let write = |format: AmountFormat, amount: &str| {
+            let mut json = Vec::new();
+            format.serialize(&dec(amount), &mut serde_json::Serializer::new(&mut json)).unwrap();
+            String::from_utf8(json).unwrap()
+        };
+
+        assert_eq!(write(AmountFormat::String, "0.00012000"), r#""0.00012000""#);
+        assert_eq!(write(AmountFormat::Number, "0.00012000"), "0.00012000");
+        // digit for digit, beyond what an f64 holds
+        assert_eq!(write(AmountFormat::Number, "12345678901234567.123456789"), "12345678901234567.123456789");
+
+        // strings are the default
+        let run_summary = serde_json::to_value(DcaRunSummary::new("run-1", "live")).unwrap();
+        assert_eq!(run_summary["total_fees"], "0");
+    }
+
+    #[test]
+    fn assets_show_their_display_names() {
+        // no ASSET_DISPLAY_NAMES in test builds
+        assert_eq!(asset_display_name("XXBT"), "XBT");
+        assert_eq!(asset_display_name("ZUSD"), "USD");
+        assert_eq!(asset_display_name("SOL"), "SOL");
+        assert_eq!(asset_display_name("USDT"), "USDT");
+        assert_eq!(asset_display_name("XTZ"), "XTZ");
+
+        let names: HashMap<String, String> = serde_json::from_str(r#"{"XXBT": "Bitcoin", "ETH": "Ether", "USD": "US dollar"}"#).unwrap();
+        assert_eq!(display_name(&names, "XXBT"), "Bitcoin");
+        assert_eq!(display_name(&names, "XBT"), "XBT");
+        assert_eq!(display_name(&names, "XETH"), "Ether");
+        assert_eq!(display_name(&names, "ZUSD"), "US dollar");
+        assert_eq!(display_name(&names, "ZEUR"), "EUR");
+    }
+}